@@ -0,0 +1,93 @@
+use crate::room::{RoomTheme, WALL_THICKNESS};
+
+use ilattice3::Extent;
+use serde::{Deserialize, Serialize};
+
+/// How fog and gas fill rooms, so renderers and survival mechanics can follow the generator's
+/// intent without analyzing voxels.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AtmosphereSpec {
+    /// The fog density of cave rooms next to the entrance.
+    pub cave_fog: f32,
+    /// The fog density of hall rooms next to the entrance.
+    pub hall_fog: f32,
+    /// Added to the fog density for each door between a room and the entrance.
+    pub fog_per_door: f32,
+    /// Rooms at least this many doors from the entrance are filled with gas, unless they're safe.
+    /// If `None`, no rooms have gas.
+    #[serde(default)]
+    pub gas_min_doors: Option<usize>,
+}
+
+/// The air inside of one room.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AtmosphereVolume {
+    /// The interior of the room's bounding extent.
+    pub extent: Extent,
+    /// In `[0, 1]`, where 0 is clear air.
+    pub fog_density: f32,
+    pub gas_hazard: bool,
+}
+
+impl AtmosphereSpec {
+    /// Fog thickens with each door away from the entrance, starting from the density of the
+    /// room's theme.
+    pub fn volume_for_room(
+        &self,
+        room: &Extent,
+        theme: RoomTheme,
+        doors_from_entrance: usize,
+        safe: bool,
+    ) -> AtmosphereVolume {
+        let base = match theme {
+            RoomTheme::Cave => self.cave_fog,
+            RoomTheme::Hall => self.hall_fog,
+        };
+        let fog_density = base + self.fog_per_door * doors_from_entrance as f32;
+
+        AtmosphereVolume {
+            extent: room.radial_grow(-WALL_THICKNESS),
+            fog_density: fog_density.clamp(0.0, 1.0),
+            gas_hazard: !safe && self.gas_min_doors.is_some_and(|n| doors_from_entrance >= n),
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fog_thickens_and_gas_fills_deep_rooms() {
+        let spec = AtmosphereSpec {
+            cave_fog: 0.3,
+            hall_fog: 0.0,
+            fog_per_door: 0.2,
+            gas_min_doors: Some(3),
+        };
+        let room = Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 20, 20].into());
+
+        let entrance = spec.volume_for_room(&room, RoomTheme::Hall, 0, true);
+        assert_eq!(entrance.fog_density, 0.0);
+        assert!(!entrance.gas_hazard);
+        assert_eq!(entrance.extent, room.radial_grow(-WALL_THICKNESS));
+
+        let deep_cave = spec.volume_for_room(&room, RoomTheme::Cave, 4, false);
+        assert_eq!(deep_cave.fog_density, 1.0);
+        assert!(deep_cave.gas_hazard);
+
+        // Safe rooms never have gas.
+        assert!(
+            !spec
+                .volume_for_room(&room, RoomTheme::Cave, 4, true)
+                .gas_hazard
+        );
+    }
+}
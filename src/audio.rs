@@ -0,0 +1,186 @@
+use crate::{
+    corridor::Corridor,
+    extent::{bounding_extent, extent_center},
+};
+
+use ilattice3::{Extent, Point};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ReverbZoneKind {
+    Room,
+    /// The bounding box of a corridor's segments, walls included.
+    Corridor,
+    /// The passage carved through the walls between two rooms, or between a room and a corridor.
+    Doorway,
+}
+
+/// A coarse box volume suitable for setting up reverb zones in audio middleware.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReverbZone {
+    pub kind: ReverbZoneKind,
+    pub center: [f32; 3],
+    pub dimensions: Point,
+    /// The fraction of the zone's boundary surface that opens into neighboring zones, in `[0, 1]`.
+    pub openness: f32,
+}
+
+/// Derives one reverb zone per room, per corridor and per doorway directly from the layout extents,
+/// so no voxel scan is required. `doors` are the doors between rooms. The doors at both ends of
+/// each corridor are doorways too, and open into the rooms and the corridor.
+pub fn reverb_zones(rooms: &[Extent], corridors: &[Corridor], doors: &[Extent]) -> Vec<ReverbZone> {
+    let mut doors = doors.to_vec();
+    for door in corridors.iter().flat_map(end_doors) {
+        if !doors.contains(door) {
+            doors.push(*door);
+        }
+    }
+
+    let mut zones = Vec::with_capacity(rooms.len() + corridors.len() + doors.len());
+    for room in rooms.iter() {
+        let open_area: i32 = doors
+            .iter()
+            .filter(|d| !d.intersection(room).is_empty())
+            .map(opening_area)
+            .sum();
+        zones.push(ReverbZone {
+            kind: ReverbZoneKind::Room,
+            center: extent_center(room),
+            dimensions: *room.get_local_supremum(),
+            openness: (open_area as f32 / surface_area(room) as f32).min(1.0),
+        });
+    }
+    for corridor in corridors.iter() {
+        let bounds = match bounding_extent(&corridor.segments) {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+        let open_area: i32 = end_doors(corridor).map(opening_area).sum();
+        zones.push(ReverbZone {
+            kind: ReverbZoneKind::Corridor,
+            center: extent_center(&bounds),
+            dimensions: *bounds.get_local_supremum(),
+            openness: (open_area as f32 / surface_area(&bounds) as f32).min(1.0),
+        });
+    }
+    for door in doors.iter() {
+        // A doorway is open on both of the faces that lead into the adjacent rooms.
+        zones.push(ReverbZone {
+            kind: ReverbZoneKind::Doorway,
+            center: extent_center(door),
+            dimensions: *door.get_local_supremum(),
+            openness: (2 * opening_area(door)) as f32 / surface_area(door) as f32,
+        });
+    }
+
+    zones
+}
+
+/// The doors from the rooms at either end into `corridor`. The doors between its segments don't
+/// lead out of it.
+fn end_doors(corridor: &Corridor) -> impl Iterator<Item = &Extent> {
+    let last = corridor.doors.len().saturating_sub(1);
+
+    corridor
+        .doors
+        .iter()
+        .enumerate()
+        .filter(move |(i, _)| *i == 0 || *i == last)
+        .map(|(_, d)| d)
+}
+
+fn surface_area(extent: &Extent) -> i32 {
+    let s = extent.get_local_supremum();
+
+    2 * (s.x * s.y + s.y * s.z + s.z * s.x)
+}
+
+/// Doors are thin slabs, so the area of the opening is the product of the two largest dimensions.
+fn opening_area(door: &Extent) -> i32 {
+    let s = door.get_local_supremum();
+    let mut dims = [s.x, s.y, s.z];
+    dims.sort_unstable();
+
+    dims[1] * dims[2]
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(min: [i32; 3], sup: [i32; 3]) -> Extent {
+        Extent::from_min_and_local_supremum(min.into(), sup.into())
+    }
+
+    /// Two rooms that share a wall, and a third room down a corridor from the second. Returns the
+    /// rooms, the corridor and the doors between rooms, which include the corridor's first door
+    /// like the dungeon's doors do.
+    fn layout() -> (Vec<Extent>, Vec<Corridor>, Vec<Extent>) {
+        let rooms = vec![
+            extent([0, 0, 0], [10, 10, 10]),
+            extent([10, 0, 0], [10, 10, 10]),
+            extent([40, 0, 0], [10, 10, 10]),
+        ];
+        let corridor = Corridor {
+            segments: vec![extent([20, 0, 2], [20, 8, 6])],
+            doors: vec![extent([19, 5, 4], [2, 3, 2]), extent([39, 5, 4], [2, 3, 2])],
+        };
+        let doors = vec![extent([9, 5, 4], [2, 3, 2]), corridor.doors[0]];
+
+        (rooms, vec![corridor], doors)
+    }
+
+    fn zones_of(zones: &[ReverbZone], kind: ReverbZoneKind) -> Vec<&ReverbZone> {
+        zones.iter().filter(|z| z.kind == kind).collect()
+    }
+
+    #[test]
+    fn test_zones_cover_rooms_corridors_and_doorways() {
+        let (rooms, corridors, doors) = layout();
+        let zones = reverb_zones(&rooms, &corridors, &doors);
+
+        let room_zones = zones_of(&zones, ReverbZoneKind::Room);
+        assert_eq!(room_zones.len(), 3);
+        assert_eq!(room_zones[0].center, [5.0, 5.0, 5.0]);
+        assert_eq!(room_zones[0].dimensions, [10, 10, 10].into());
+
+        let corridor_zones = zones_of(&zones, ReverbZoneKind::Corridor);
+        assert_eq!(corridor_zones.len(), 1);
+        assert_eq!(corridor_zones[0].center, [30.0, 4.0, 5.0]);
+        assert_eq!(corridor_zones[0].dimensions, [20, 8, 6].into());
+
+        // The corridor's first door is only listed once.
+        let doorway_zones = zones_of(&zones, ReverbZoneKind::Doorway);
+        assert_eq!(doorway_zones.len(), 3);
+        assert_eq!(doorway_zones[0].center, [10.0, 6.5, 5.0]);
+        assert_eq!(doorway_zones[0].dimensions, [2, 3, 2].into());
+        assert_eq!(doorway_zones[2].center, [40.0, 6.5, 5.0]);
+    }
+
+    #[test]
+    fn test_openness_is_the_open_fraction_of_the_surface() {
+        let (rooms, corridors, doors) = layout();
+        let zones = reverb_zones(&rooms, &corridors, &doors);
+
+        // Each door opens 3 x 2 voxels. A room has 600 voxels of surface, and the corridor 656.
+        let room_zones = zones_of(&zones, ReverbZoneKind::Room);
+        assert_eq!(room_zones[0].openness, 6.0 / 600.0);
+        assert_eq!(room_zones[1].openness, 12.0 / 600.0);
+        assert_eq!(room_zones[2].openness, 6.0 / 600.0);
+        assert_eq!(
+            zones_of(&zones, ReverbZoneKind::Corridor)[0].openness,
+            12.0 / 656.0
+        );
+        // A doorway is open on both sides: 2 * 6 of its 2 * (6 + 6 + 4) voxels of surface.
+        for zone in zones_of(&zones, ReverbZoneKind::Doorway) {
+            assert_eq!(zone.openness, 12.0 / 32.0);
+        }
+    }
+}
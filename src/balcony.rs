@@ -0,0 +1,255 @@
+use crate::{
+    extent::{component, with_component},
+    room::{fill_map_with_steps, FLOOR_VOXEL, WALL_THICKNESS},
+    Voxel, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+/// A second level in tall rooms: a platform along one wall, halfway up, with stairs up to it from
+/// the floor.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BalconySpec {
+    /// Rooms at least this tall, walls included, get a balcony.
+    pub min_room_height: u32,
+    /// How far the platform reaches out from the wall.
+    pub depth: u32,
+    pub thickness: u32,
+    pub railing_height: u32,
+    /// The most each step of the stairs rises above the one below it.
+    pub step_rise: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Balcony {
+    pub platform: Extent,
+    /// From the step next to the platform down to the floor.
+    pub steps: Vec<Extent>,
+    /// Along the open edge of the platform.
+    pub railings: Vec<Extent>,
+}
+
+impl Balcony {
+    /// The layer of the platform that's walked on.
+    pub fn walkable_surface(&self) -> Extent {
+        let min = self.platform.get_minimum();
+        let sup = self.platform.get_local_supremum();
+
+        Extent::from_min_and_local_supremum(
+            with_component(min, 1, min.y + sup.y - 1),
+            with_component(sup, 1, 1),
+        )
+    }
+}
+
+/// Builds a balcony against a random wall of `room`, with the stairs at a random end. Balconies
+/// never come within a voxel of `obstacles` like doors and spawn points. Returns `None` if the
+/// room is too short, or no wall has space for the platform and the stairs.
+pub fn try_generate_balcony(
+    room: &Extent,
+    obstacles: &[Extent],
+    spawn_points: &[Point],
+    spec: &BalconySpec,
+    rng: &mut impl Rng,
+) -> Option<Balcony> {
+    if room.get_local_supremum().y < spec.min_room_height as i32 {
+        return None;
+    }
+    let interior = room.radial_grow(-WALL_THICKNESS);
+    if interior.is_empty() {
+        return None;
+    }
+
+    let mut options: Vec<(usize, bool, bool)> = [0, 2]
+        .iter()
+        .flat_map(|axis| {
+            [(false, false), (false, true), (true, false), (true, true)]
+                .iter()
+                .map(move |(far_wall, far_stairs)| (*axis, *far_wall, *far_stairs))
+        })
+        .collect();
+    options.shuffle(rng);
+
+    options
+        .into_iter()
+        .filter_map(|(axis, far_wall, far_stairs)| {
+            balcony_against_wall(&interior, axis, far_wall, far_stairs, spec)
+        })
+        .find(|b| {
+            let pieces = || {
+                std::iter::once(&b.platform)
+                    .chain(b.steps.iter())
+                    .chain(b.railings.iter())
+            };
+            let blocks_obstacle = pieces().any(|e| {
+                let grown = e.radial_grow(1);
+                obstacles.iter().any(|o| !grown.intersection(o).is_empty())
+                    || spawn_points.iter().any(|p| grown.contains_world(p))
+            });
+
+            !blocks_obstacle
+        })
+}
+
+/// The balcony against the wall of `interior` that's normal to `axis`, with the stairs running
+/// along the wall. Returns `None` if it doesn't fit.
+fn balcony_against_wall(
+    interior: &Extent,
+    axis: usize,
+    far_wall: bool,
+    far_stairs: bool,
+    spec: &BalconySpec,
+) -> Option<Balcony> {
+    let along = 2 - axis;
+    let (min, sup) = (interior.get_minimum(), interior.get_world_supremum());
+    let floor_y = min.y;
+    let depth = spec.depth.max(1) as i32;
+    let thickness = spec.thickness.max(1) as i32;
+    let rise = spec.step_rise.max(1) as i32;
+    // Halfway up, with headroom above and below.
+    let level = (sup.y - floor_y) / 2;
+    if level <= thickness || component(&sup, axis) - component(min, axis) <= depth {
+        return None;
+    }
+
+    let (wall_min, wall_sup) = if far_wall {
+        (component(&sup, axis) - depth, component(&sup, axis))
+    } else {
+        (component(min, axis), component(min, axis) + depth)
+    };
+    let num_steps = (level - 1) / rise;
+    let (along_min, along_sup) = (component(min, along), component(&sup, along));
+    // The platform must be at least as long as it is deep.
+    if along_sup - along_min < num_steps + depth {
+        return None;
+    }
+    let strip = |a_min: i32, a_sup: i32, y_min: i32, y_sup: i32| {
+        let strip_min = with_component(&with_component(min, axis, wall_min), along, a_min);
+        let strip_sup = with_component(&with_component(&sup, axis, wall_sup), along, a_sup);
+
+        Extent::from_min_and_world_supremum(
+            with_component(&strip_min, 1, y_min),
+            with_component(&strip_sup, 1, y_sup),
+        )
+    };
+
+    let top = floor_y + level;
+    // The stairs are at the `along_min` end unless `far_stairs`, with a step for each row.
+    let (platform, steps): (Extent, Vec<Extent>) = if far_stairs {
+        let start = along_sup - num_steps;
+        (
+            strip(along_min, start, top - thickness, top),
+            (0..num_steps)
+                .map(|i| strip(start + i, start + i + 1, floor_y, top - rise * (i + 1)))
+                .collect(),
+        )
+    } else {
+        let start = along_min + num_steps;
+        (
+            strip(start, along_sup, top - thickness, top),
+            (0..num_steps)
+                .map(|i| strip(start - i - 1, start - i, floor_y, top - rise * (i + 1)))
+                .collect(),
+        )
+    };
+
+    // The row of the platform farthest from the wall.
+    let edge = if far_wall { wall_min } else { wall_sup - 1 };
+    let railings = if spec.railing_height > 0 {
+        let p_min = platform.get_minimum();
+        let p_sup = platform.get_world_supremum();
+        vec![Extent::from_min_and_world_supremum(
+            with_component(&with_component(p_min, axis, edge), 1, top),
+            with_component(
+                &with_component(&p_sup, axis, edge + 1),
+                1,
+                (top + spec.railing_height as i32).min(sup.y),
+            ),
+        )]
+    } else {
+        Vec::new()
+    };
+
+    Some(Balcony {
+        platform,
+        steps,
+        railings,
+    })
+}
+
+/// Writes each platform with `FLOOR_VOXEL`, the stairs like `fill_map_with_steps`, and the
+/// railings with `railing_voxel_type`.
+pub fn fill_map_with_balconies(
+    balconies: &[Balcony],
+    railing_voxel_type: u8,
+    encoder: &mut impl VoxelEncoder,
+) {
+    let railing = Voxel {
+        distance: -1.0,
+        voxel_type: railing_voxel_type,
+    };
+    for b in balconies.iter() {
+        let top = b.platform.get_world_supremum().y - 1;
+        for p in &b.platform {
+            encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            if p.y == top {
+                encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+            }
+        }
+        fill_map_with_steps(&b.steps, encoder);
+        for r in b.railings.iter() {
+            for p in r {
+                encoder.encode_voxel(&p, &railing);
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::small_rng;
+
+    #[test]
+    fn test_stairs_climb_to_balcony_inside_tall_rooms() {
+        let spec = BalconySpec {
+            min_room_height: 30,
+            depth: 3,
+            thickness: 2,
+            railing_height: 2,
+            step_rise: 1,
+        };
+        let short = Extent::from_min_and_local_supremum([0, 0, 0].into(), [40, 20, 40].into());
+        assert!(try_generate_balcony(&short, &[], &[], &spec, &mut small_rng([1; 4])).is_none());
+
+        let tall = Extent::from_min_and_local_supremum([0, 0, 0].into(), [40, 30, 40].into());
+        let interior = tall.radial_grow(-WALL_THICKNESS);
+        for seed in 0..8 {
+            let balcony =
+                try_generate_balcony(&tall, &[], &[], &spec, &mut small_rng([seed; 4])).unwrap();
+
+            // Halfway up the 20 voxel interior, which starts at 5.
+            assert_eq!(balcony.walkable_surface().get_minimum().y, 14);
+            assert!(balcony.platform.is_subset(&interior));
+            assert_eq!(balcony.steps.len(), 9);
+            for (i, s) in balcony.steps.iter().enumerate() {
+                assert!(s.is_subset(&interior));
+                assert_eq!(s.get_world_supremum().y, 14 - i as i32);
+            }
+            // The top step is next to the platform.
+            assert!(!balcony.steps[0]
+                .radial_grow(1)
+                .intersection(&balcony.platform)
+                .is_empty());
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::extent::extent_center;
+
+use ilattice3::Extent;
+use serde::{Deserialize, Serialize};
+
+/// The vertical field of view, in radians, that the overview poses in map metadata are fitted to.
+pub const OVERVIEW_VERTICAL_FOV: f32 = std::f32::consts::FRAC_PI_3;
+
+/// A suggested camera for previews and loading screens that frames the whole map.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CameraPose {
+    pub position: [f32; 3],
+    /// The volume-weighted centroid of the map, which the camera looks at.
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+}
+
+impl CameraPose {
+    /// Looks down at the centroid of `extents` from above one corner, far enough back that a
+    /// camera with `vertical_fov` sees every extent. Returns `None` if there are no extents.
+    pub fn overview(extents: &[Extent], vertical_fov: f32) -> Option<CameraPose> {
+        let total_volume: f32 = extents.iter().map(volume).sum();
+        if extents.is_empty() || total_volume <= 0.0 {
+            return None;
+        }
+
+        let mut target = [0.0; 3];
+        for e in extents.iter() {
+            let (c, w) = (extent_center(e), volume(e) / total_volume);
+            for (t, c) in target.iter_mut().zip(c.iter()) {
+                *t += c * w;
+            }
+        }
+
+        // The radius of a sphere around the target that holds every extent.
+        let radius = extents
+            .iter()
+            .map(|e| {
+                let (min, sup) = (e.get_minimum(), e.get_world_supremum());
+                let farthest =
+                    |lo: i32, hi: i32, t: f32| (lo as f32 - t).abs().max((hi as f32 - t).abs());
+                let d = [
+                    farthest(min.x, sup.x, target[0]),
+                    farthest(min.y, sup.y, target[1]),
+                    farthest(min.z, sup.z, target[2]),
+                ];
+
+                (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+            })
+            .fold(0.0, f32::max);
+        let distance = radius / (vertical_fov / 2.0).sin();
+
+        // Elevated 60 degrees above the horizon, along the diagonal between +X and +Z.
+        let (elevation, azimuth) = (std::f32::consts::FRAC_PI_3, std::f32::consts::FRAC_PI_4);
+        let direction = [
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        ];
+
+        Some(CameraPose {
+            position: [
+                target[0] + direction[0] * distance,
+                target[1] + direction[1] * distance,
+                target[2] + direction[2] * distance,
+            ],
+            target,
+            up: [0.0, 1.0, 0.0],
+        })
+    }
+}
+
+fn volume(e: &Extent) -> f32 {
+    let sup = e.get_local_supremum();
+
+    sup.x as f32 * sup.y as f32 * sup.z as f32
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+        [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+    }
+
+    fn angle_between(a: [f32; 3], b: [f32; 3]) -> f32 {
+        let dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let len = |v: [f32; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+
+        (dot / (len(a) * len(b))).clamp(-1.0, 1.0).acos()
+    }
+
+    #[test]
+    fn test_overview_frames_the_bounds_and_looks_at_the_center() {
+        let extents = [
+            Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 10, 20].into()),
+            Extent::from_min_and_local_supremum([40, 0, 0].into(), [10, 10, 10].into()),
+        ];
+        let pose = CameraPose::overview(&extents, OVERVIEW_VERTICAL_FOV).unwrap();
+
+        // The first extent has 4 times the volume of the second.
+        assert_eq!(pose.target, [17.0, 5.0, 9.0]);
+        assert_eq!(pose.up, [0.0, 1.0, 0.0]);
+        assert!(pose.position[1] > 10.0);
+
+        let forward = sub(pose.target, pose.position);
+        for e in extents.iter() {
+            let (min, sup) = (e.get_minimum(), e.get_world_supremum());
+            for x in [min.x, sup.x].iter() {
+                for y in [min.y, sup.y].iter() {
+                    for z in [min.z, sup.z].iter() {
+                        let corner = [*x as f32, *y as f32, *z as f32];
+                        let angle = angle_between(sub(corner, pose.position), forward);
+                        assert!(angle <= OVERVIEW_VERTICAL_FOV / 2.0 + 1e-4);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_overview_of_nothing() {
+        assert_eq!(CameraPose::overview(&[], OVERVIEW_VERTICAL_FOV), None);
+    }
+}
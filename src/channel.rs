@@ -0,0 +1,54 @@
+use crate::{
+    extent::{component, with_component},
+    Voxel, VoxelEncoder, VoxelFlags, VoxelLayer,
+};
+
+use ilattice3::Extent;
+
+/// Not solid, so engines should render it as a liquid surface rather than a wall.
+pub const WATER_VOXEL: Voxel = Voxel {
+    distance: 1.0,
+    voxel_type: 4,
+};
+
+/// A solid but see-through cover over an opening, like a sewer grate.
+pub const GRATE_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 5,
+};
+
+/// The trench sunk `depth` voxels into the floor along the middle of `tunnel`, which runs along
+/// the horizontal `axis`. `tunnel` is the empty space of the tunnel, so its minimum is on the floor.
+pub fn channel_below(tunnel: &Extent, axis: usize, width: i32, depth: i32) -> Extent {
+    let across = 2 - axis;
+    let (min, sup) = (tunnel.get_minimum(), tunnel.get_local_supremum());
+    let width = width.min(component(sup, across));
+    let across_min = component(min, across) + (component(sup, across) - width) / 2;
+
+    Extent::from_min_and_local_supremum(
+        with_component(&with_component(min, across, across_min), 1, min.y - depth),
+        with_component(&with_component(sup, across, width), 1, depth),
+    )
+}
+
+/// Fills each channel with water up to one voxel below the floor.
+pub fn fill_map_with_water(channels: &[Extent], encoder: &mut impl VoxelEncoder) {
+    for c in channels.iter() {
+        let surface_y = c.get_world_supremum().y - 1;
+        for p in c {
+            if p.y < surface_y {
+                encoder.encode_layer_voxel(VoxelLayer::Liquid, &p, &WATER_VOXEL);
+                encoder.encode_flags(&p, VoxelFlags::WATER);
+            }
+        }
+    }
+}
+
+pub fn fill_map_with_grates(grates: &[Extent], encoder: &mut impl VoxelEncoder) {
+    for g in grates.iter() {
+        for p in g {
+            encoder.encode_voxel(&p, &GRATE_VOXEL);
+            encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    extent::{component, touching_axis, with_component},
+    layout::Layout,
+    movement::MovementSpec,
+    room::{CLIMBABLE_VOXEL, LADDER_VOXEL, WALL_THICKNESS},
+    VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::Extent;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClimbingSpec {
+    /// Climbs up to this height are left to the player's jump.
+    pub jump_height: u32,
+    /// The width of a climbable strip. Strips are never wider than the door they lead to.
+    pub strip_width: u32,
+}
+
+/// Narrows `strip` along `axis` to at most `width`, keeping it centered.
+fn center_strip(strip: Extent, axis: usize, width: i32) -> Extent {
+    let len = component(strip.get_local_supremum(), axis);
+    if len <= width {
+        return strip;
+    }
+    let min = component(strip.get_minimum(), axis) + (len - width) / 2;
+
+    Extent::from_min_and_local_supremum(
+        with_component(strip.get_minimum(), axis, min),
+        with_component(strip.get_local_supremum(), axis, width),
+    )
+}
+
+/// Finds the doors whose bottom is too high to jump to from the floor of an adjacent room, and
+/// returns the strips that should be made climbable to reach them. Doors in walls get a strip on the
+/// wall face below the door. Doors in floors get a ladder below the door's edge. With a `movement`
+/// spec, doors are also climbable when they're too high to step onto or drop down from safely.
+pub fn climbable_strips(
+    layout: &Layout,
+    spec: &ClimbingSpec,
+    movement: Option<&MovementSpec>,
+) -> Vec<Extent> {
+    let jump_height = match movement {
+        Some(m) => spec.jump_height.min(m.max_ledge_height()),
+        None => spec.jump_height,
+    } as i32;
+    let width = spec.strip_width.max(1) as i32;
+
+    let mut strips = Vec::new();
+    for door in layout.doors.iter() {
+        let (r1, r2) = (&layout.rooms[door.rooms.0], &layout.rooms[door.rooms.1]);
+        let (axis, r2_is_positive) = match touching_axis(r1, r2) {
+            Some(t) => t,
+            None => continue,
+        };
+        let door_min = door.extent.get_minimum();
+        let door_sup = door.extent.get_local_supremum();
+
+        if axis == 1 {
+            let lower = if r2_is_positive { r1 } else { r2 };
+            let floor_y = lower.get_minimum().y + WALL_THICKNESS;
+            let height = door_min.y - floor_y;
+            if height > jump_height {
+                let ladder = Extent::from_min_and_local_supremum(
+                    [door_min.x, floor_y, door_min.z].into(),
+                    [door_sup.x, height, 1].into(),
+                );
+                strips.push(center_strip(ladder, 0, width));
+            }
+
+            continue;
+        }
+
+        for (room, other_is_positive) in [(r1, r2_is_positive), (r2, !r2_is_positive)].iter() {
+            let floor_y = room.get_minimum().y + WALL_THICKNESS;
+            let height = door_min.y - floor_y;
+            if height <= jump_height {
+                continue;
+            }
+
+            // The layer of wall facing the room's interior.
+            let interior = room.radial_grow(-WALL_THICKNESS);
+            let face = if *other_is_positive {
+                component(&interior.get_world_supremum(), axis)
+            } else {
+                component(interior.get_minimum(), axis) - 1
+            };
+            let strip = Extent::from_min_and_local_supremum(
+                with_component(&with_component(door_min, 1, floor_y), axis, face),
+                with_component(&with_component(door_sup, 1, height), axis, 1),
+            );
+            strips.push(center_strip(strip, 2 - axis, width));
+        }
+    }
+
+    strips
+}
+
+/// A ladder on one wall of the shaft through a door in a floor.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Ladder {
+    /// Index into `Layout::doors`.
+    pub door: usize,
+    /// From the floor of the room below up to the top of the door.
+    pub extent: Extent,
+}
+
+/// Puts a ladder against one side of each door in a floor of `layout`, so the door can be reached
+/// from the room below however high it is. The ladder goes on the first side where it stands
+/// inside the room below and stays clear of the other doors; doors without such a side are
+/// skipped.
+pub fn shaft_ladders(layout: &Layout) -> Vec<Ladder> {
+    let mut ladders = Vec::new();
+    for (i, door) in layout.doors.iter().enumerate() {
+        let (r1, r2) = (&layout.rooms[door.rooms.0], &layout.rooms[door.rooms.1]);
+        let lower = match touching_axis(r1, r2) {
+            Some((1, true)) => r1,
+            Some((1, false)) => r2,
+            _ => continue,
+        };
+        let interior = lower.radial_grow(-WALL_THICKNESS);
+        let (door_min, door_sup) = (door.extent.get_minimum(), door.extent.get_world_supremum());
+        let floor_y = interior.get_minimum().y;
+
+        let sides = [(2, false), (2, true), (0, false), (0, true)];
+        let ladder = sides.iter().find_map(|(axis, positive)| {
+            let c = if *positive {
+                component(&door_sup, *axis)
+            } else {
+                component(door_min, *axis) - 1
+            };
+            let extent = Extent::from_min_and_world_supremum(
+                with_component(&with_component(door_min, *axis, c), 1, floor_y),
+                with_component(&door_sup, *axis, c + 1),
+            );
+            let fits = [0, 2].iter().all(|a| {
+                component(extent.get_minimum(), *a) >= component(interior.get_minimum(), *a)
+                    && component(&extent.get_world_supremum(), *a)
+                        <= component(&interior.get_world_supremum(), *a)
+            });
+            let grown = extent.radial_grow(1);
+            let blocks_door = layout
+                .doors
+                .iter()
+                .enumerate()
+                .any(|(j, d)| j != i && !grown.intersection(&d.extent).is_empty());
+
+            if fits && !blocks_door {
+                Some(Ladder { door: i, extent })
+            } else {
+                None
+            }
+        });
+        ladders.extend(ladder);
+    }
+
+    ladders
+}
+
+pub fn fill_map_with_ladders(ladders: &[Ladder], encoder: &mut impl VoxelEncoder) {
+    for l in ladders.iter() {
+        for p in &l.extent {
+            encoder.encode_voxel(&p, &LADDER_VOXEL);
+            encoder.encode_flags(&p, VoxelFlags::CLIMBABLE);
+        }
+    }
+}
+
+pub fn fill_map_with_climbables(strips: &[Extent], encoder: &mut impl VoxelEncoder) {
+    for s in strips.iter() {
+        for p in s {
+            encoder.encode_voxel(&p, &CLIMBABLE_VOXEL);
+            encoder.encode_flags(&p, VoxelFlags::CLIMBABLE);
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::layout::LayoutDoor;
+
+    #[test]
+    fn test_raised_door_gets_strip_below_it_only_in_the_lower_room() {
+        let low_room = Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 20, 20].into());
+        let high_room =
+            Extent::from_min_and_local_supremum([20, 10, 0].into(), [20, 20, 20].into());
+        // Sits just above the high room's floor.
+        let door = Extent::from_min_and_local_supremum([19, 15, 8].into(), [2, 4, 4].into());
+        let layout = Layout {
+            rooms: vec![low_room, high_room],
+            doors: vec![LayoutDoor::new(0, 1, door)],
+        };
+        let spec = ClimbingSpec {
+            jump_height: 3,
+            strip_width: 2,
+        };
+
+        let strips = climbable_strips(&layout, &spec, None);
+
+        assert_eq!(
+            strips,
+            vec![Extent::from_min_and_local_supremum(
+                [15, 5, 9].into(),
+                [1, 10, 2].into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_ladder_climbs_a_shaft_wall_clear_of_other_doors() {
+        let lower = Extent::from_min_and_local_supremum([0, 0, 0].into(), [30, 20, 30].into());
+        let upper = Extent::from_min_and_local_supremum([0, 20, 0].into(), [30, 20, 30].into());
+        let door = Extent::from_min_and_local_supremum([10, 19, 10].into(), [3, 2, 3].into());
+        // Right next to the -Z side of the first door.
+        let other_door = Extent::from_min_and_local_supremum([10, 19, 6].into(), [3, 2, 3].into());
+        let layout = Layout {
+            rooms: vec![lower, upper],
+            doors: vec![
+                LayoutDoor::new(0, 1, door),
+                LayoutDoor::new(0, 1, other_door),
+            ],
+        };
+
+        let ladders = shaft_ladders(&layout);
+
+        assert_eq!(
+            ladders[0],
+            Ladder {
+                door: 0,
+                extent: Extent::from_min_and_world_supremum(
+                    [10, 5, 13].into(),
+                    [13, 21, 14].into()
+                ),
+            }
+        );
+    }
+}
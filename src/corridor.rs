@@ -0,0 +1,507 @@
+use crate::{
+    extent::{component, extent_center, with_component},
+    room::{
+        generate_door_graph, try_generate_door_big_enough_between_rooms, DoorSelectionSpec, Room,
+        WALL_THICKNESS,
+    },
+    sampling::SubspanSampler,
+    symmetric_map::SymmetricMap,
+};
+
+use ilattice3::{Extent, Point};
+use petgraph::{stable_graph::StableGraph, Undirected};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+/// Corridors are built from axis-aligned segments that have walls like rooms do.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CorridorSpec {
+    /// The horizontal width of the open space inside a segment. The walls are added on both sides.
+    pub width: u32,
+    /// The height of the open space inside a segment. The floor and ceiling are added to it.
+    pub height: u32,
+    /// Rooms are only connected if the total length of the segments is at most this.
+    pub max_length: u32,
+}
+
+impl CorridorSpec {
+    /// The horizontal width of a segment's extent, including its walls.
+    pub fn outer_width(&self) -> i32 {
+        self.width as i32 + 2 * WALL_THICKNESS
+    }
+
+    /// The height of a segment's extent, including its floor and ceiling.
+    pub fn outer_height(&self) -> i32 {
+        self.height as i32 + 2 * WALL_THICKNESS
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Corridor {
+    /// One segment for a straight corridor, or two for an L-shaped corridor.
+    pub segments: Vec<Extent>,
+    /// The doors from the first room into the corridor, between segments, and into the second
+    /// room, in that order.
+    pub doors: Vec<Extent>,
+}
+
+/// Short dead ends off the walls of rooms that lead nowhere, so the rooms look like part of a
+/// larger complex.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CorridorStubSpec {
+    /// The chance that a wall without doors gets a stub.
+    pub probability: f32,
+    /// The horizontal width of the open space inside a stub. The walls are added on both sides.
+    pub width: u32,
+    /// The height of the open space inside a stub. The floor and ceiling are added to it.
+    pub height: u32,
+    /// Stubs are 1 to 3 voxels deep, so values above 3 are treated as 3.
+    pub max_depth: u32,
+}
+
+impl CorridorStubSpec {
+    /// The horizontal width of a stub's extent, including its walls.
+    pub fn outer_width(&self) -> i32 {
+        self.width as i32 + 2 * WALL_THICKNESS
+    }
+
+    /// The height of a stub's extent, including its floor and ceiling.
+    pub fn outer_height(&self) -> i32 {
+        self.height as i32 + 2 * WALL_THICKNESS
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CorridorStub {
+    /// Index into the rooms of the map of the room that the stub opens into.
+    pub room: usize,
+    /// Sealed by its own wall at the far end.
+    pub segment: Extent,
+    /// The door from the room into the stub.
+    pub door: Extent,
+}
+
+fn min_along(e: &Extent, axis: usize) -> i32 {
+    component(e.get_minimum(), axis)
+}
+
+fn sup_along(e: &Extent, axis: usize) -> i32 {
+    component(&e.get_world_supremum(), axis)
+}
+
+fn sample_in(lo: i32, hi: i32, rng: &mut impl Rng) -> Option<i32> {
+    if hi < lo {
+        None
+    } else {
+        Some(rng.gen_range(lo, hi + 1))
+    }
+}
+
+/// The segment spanning `[a_min, a_sup)` along `a` and `[b_min, b_min + width)` along `b`.
+fn segment(a: usize, (a_min, a_sup): (i32, i32), b_min: i32, y: (i32, i32), width: i32) -> Extent {
+    let b = 2 - a;
+    let min = with_component(&with_component(&[0, y.0, 0].into(), a, a_min), b, b_min);
+    let sup = with_component(
+        &with_component(&[0, y.1, 0].into(), a, a_sup - a_min),
+        b,
+        width,
+    );
+
+    Extent::from_min_and_local_supremum(min, sup)
+}
+
+/// The range along `axis` between the faces of `r1` and `r2`, or `None` if they overlap along
+/// `axis`.
+fn gap_between(r1: &Extent, r2: &Extent, axis: usize) -> Option<(i32, i32)> {
+    if sup_along(r1, axis) <= min_along(r2, axis) {
+        Some((sup_along(r1, axis), min_along(r2, axis)))
+    } else if sup_along(r2, axis) <= min_along(r1, axis) {
+        Some((sup_along(r2, axis), min_along(r1, axis)))
+    } else {
+        None
+    }
+}
+
+/// Plans the segments of a corridor that leaves `r1` along the horizontal axis `a` and, if needed,
+/// turns once to enter `r2` along the other horizontal axis.
+fn plan_segments(
+    r1: &Extent,
+    r2: &Extent,
+    a: usize,
+    spec: &CorridorSpec,
+    rng: &mut impl Rng,
+) -> Option<Vec<Extent>> {
+    let b = 2 - a;
+    let width = spec.outer_width();
+
+    // Line up the corridor's floor with the higher of the two floors.
+    let y_min = r1.get_minimum().y.max(r2.get_minimum().y);
+    let y_sup = y_min + spec.outer_height();
+    if y_sup > r1.get_world_supremum().y.min(r2.get_world_supremum().y) {
+        return None;
+    }
+
+    // A segment must stay off of the edges of the walls it opens into.
+    let b_lo = min_along(r1, b) + 1;
+    let b_hi = sup_along(r1, b) - 1 - width;
+
+    // Straight, if the rooms face each other along `a`.
+    let (gap_min, gap_sup) = gap_between(r1, r2, a)?;
+    if let Some(b_min) = sample_in(
+        b_lo.max(min_along(r2, b) + 1),
+        b_hi.min(sup_along(r2, b) - 1 - width),
+        rng,
+    ) {
+        return Some(vec![segment(
+            a,
+            (gap_min, gap_sup),
+            b_min,
+            (y_min, y_sup),
+            width,
+        )]);
+    }
+
+    // Otherwise, the first segment runs along `a` until it's in front of `r2`, where the second
+    // segment turns along `b` into `r2`.
+    let b_min = sample_in(b_lo, b_hi, rng)?;
+    let elbow_min = sample_in(min_along(r2, a) + 1, sup_along(r2, a) - 1 - width, rng)?;
+    let first_a_range = if sup_along(r1, a) <= min_along(r2, a) {
+        (sup_along(r1, a), elbow_min + width)
+    } else {
+        (elbow_min, min_along(r1, a))
+    };
+    let second_b_range = if b_min + width <= min_along(r2, b) {
+        (b_min + width, min_along(r2, b))
+    } else if sup_along(r2, b) <= b_min {
+        (sup_along(r2, b), b_min)
+    } else {
+        return None;
+    };
+    if first_a_range.1 <= first_a_range.0 || second_b_range.1 <= second_b_range.0 {
+        return None;
+    }
+
+    Some(vec![
+        segment(a, first_a_range, b_min, (y_min, y_sup), width),
+        segment(b, second_b_range, elbow_min, (y_min, y_sup), width),
+    ])
+}
+
+/// Tries to connect `r1` and `r2` with a straight or L-shaped corridor that doesn't intersect any
+/// of the `obstacles`.
+pub fn try_route_corridor(
+    r1: &Extent,
+    r2: &Extent,
+    spec: &CorridorSpec,
+    door_dims: &SubspanSampler,
+    obstacles: &[Extent],
+    rng: &mut impl Rng,
+) -> Option<Corridor> {
+    let mut axes = [0, 2];
+    axes.shuffle(rng);
+    for a in axes.iter() {
+        let segments = match plan_segments(r1, r2, *a, spec, rng) {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let length: i32 = segments
+            .iter()
+            .map(|s| {
+                let sup = s.get_local_supremum();
+                sup.x.max(sup.z)
+            })
+            .sum();
+        if length > spec.max_length as i32 {
+            continue;
+        }
+        if segments
+            .iter()
+            .any(|s| obstacles.iter().any(|o| !s.intersection(o).is_empty()))
+        {
+            continue;
+        }
+
+        // Doors from r1, through each segment, into r2.
+        let mut chain = vec![*r1];
+        chain.extend(segments.iter().cloned());
+        chain.push(*r2);
+        let doors: Option<Vec<_>> = chain
+            .windows(2)
+            .map(|pair| {
+                try_generate_door_big_enough_between_rooms(door_dims, &pair[0], &pair[1], rng)
+            })
+            .collect();
+        if let Some(doors) = doors {
+            return Some(Corridor { segments, doors });
+        }
+    }
+
+    None
+}
+
+/// Like `generate_door_graph`, but rooms that don't share a wall can also be connected by a
+/// corridor. Corridors are only attempted between rooms that aren't already connected by a door,
+/// and they never pass through rooms or earlier corridors.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_connection_graph(
+    rooms: &[Extent],
+    min_door_dim: u32,
+    max_door_dim: u32,
+    selection: &DoorSelectionSpec,
+    corridor_spec: &CorridorSpec,
+    rng: &mut impl Rng,
+    doors: &mut SymmetricMap<Vec<Extent>>,
+    corridors: &mut SymmetricMap<Corridor>,
+) -> StableGraph<usize, (), Undirected> {
+    let mut graph = generate_door_graph(rooms, min_door_dim, max_door_dim, selection, rng, doors);
+
+    let door_dims = SubspanSampler::new(min_door_dim as i32, max_door_dim as i32);
+    let mut obstacles = rooms.to_vec();
+    let max_distance = corridor_spec.max_length as f32;
+    let all_node_indices: Vec<_> = graph.node_indices().collect();
+    for i in all_node_indices.iter() {
+        for j in all_node_indices.iter() {
+            let (i_idx, j_idx) = (graph[*i], graph[*j]);
+            if j_idx <= i_idx || graph.contains_edge(*i, *j) {
+                continue;
+            }
+
+            // Skip pairs that are obviously too far apart before doing any routing.
+            let (c1, c2) = (extent_center(&rooms[i_idx]), extent_center(&rooms[j_idx]));
+            let (dx, dz) = ((c1[0] - c2[0]).abs(), (c1[2] - c2[2]).abs());
+            if dx + dz > max_distance + rooms_half_span(&rooms[i_idx], &rooms[j_idx]) {
+                continue;
+            }
+
+            if let Some(corridor) = try_route_corridor(
+                &rooms[i_idx],
+                &rooms[j_idx],
+                corridor_spec,
+                &door_dims,
+                &obstacles,
+                rng,
+            ) {
+                obstacles.extend(corridor.segments.iter().cloned());
+                // The edge's door is the one into the first room, so consumers that expect one
+                // door per edge still have something to work with.
+                doors.insert(i_idx, j_idx, vec![corridor.doors[0]]);
+                corridors.insert(i_idx, j_idx, corridor);
+                graph.add_edge(*i, *j, ());
+            }
+        }
+    }
+
+    graph
+}
+
+/// Gives each side wall of each part of `room` that none of `doors` touch a stub with
+/// `spec.probability`. Stubs don't intersect any of the `obstacles`, which should include the
+/// bounding extent of `room`, so they only leave from outer walls. Each stub is added to the
+/// `obstacles`.
+pub fn generate_corridor_stubs(
+    room_index: usize,
+    room: &Room,
+    doors: &[Extent],
+    spec: &CorridorStubSpec,
+    door_dims: &SubspanSampler,
+    obstacles: &mut Vec<Extent>,
+    rng: &mut impl Rng,
+) -> Vec<CorridorStub> {
+    let width = spec.outer_width();
+    let max_depth = spec.max_depth.clamp(1, 3) as i32;
+    let mut stubs = Vec::new();
+    for part in room.parts.iter() {
+        let y_min = part.get_minimum().y;
+        let y_sup = y_min + spec.outer_height();
+        if y_sup > part.get_world_supremum().y {
+            continue;
+        }
+        for a in [0, 2].iter().cloned() {
+            let b = 2 - a;
+            for face in [min_along(part, a), sup_along(part, a)].iter().cloned() {
+                // The doors in a wall straddle its face.
+                let wall = segment(
+                    a,
+                    (face - 1, face + 1),
+                    min_along(part, b),
+                    (y_min, part.get_world_supremum().y),
+                    sup_along(part, b) - min_along(part, b),
+                );
+                if doors.iter().any(|d| !d.intersection(&wall).is_empty())
+                    || rng.gen::<f32>() >= spec.probability
+                {
+                    continue;
+                }
+
+                let b_min =
+                    match sample_in(min_along(part, b) + 1, sup_along(part, b) - 1 - width, rng) {
+                        Some(b_min) => b_min,
+                        None => continue,
+                    };
+                let length = rng.gen_range(1, max_depth + 1) + 2 * WALL_THICKNESS;
+                let a_range = if face == min_along(part, a) {
+                    (face - length, face)
+                } else {
+                    (face, face + length)
+                };
+                let stub = segment(a, a_range, b_min, (y_min, y_sup), width);
+                if obstacles.iter().any(|o| !stub.intersection(o).is_empty()) {
+                    continue;
+                }
+                if let Some(door) =
+                    try_generate_door_big_enough_between_rooms(door_dims, part, &stub, rng)
+                {
+                    obstacles.push(stub);
+                    stubs.push(CorridorStub {
+                        room: room_index,
+                        segment: stub,
+                        door,
+                    });
+                }
+            }
+        }
+    }
+
+    stubs
+}
+
+/// The largest horizontal distance from the centers of two rooms to their walls.
+fn rooms_half_span(r1: &Extent, r2: &Extent) -> f32 {
+    let half = |p: &Point| (p.x + p.z) as f32 / 2.0;
+
+    half(r1.get_local_supremum()) + half(r2.get_local_supremum())
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sampling::small_rng;
+
+    const SPEC: CorridorSpec = CorridorSpec {
+        width: 3,
+        height: 4,
+        max_length: 100,
+    };
+
+    /// The shape of the space inside `segment` that its walls leave open.
+    fn open_shape(segment: &Extent) -> Point {
+        *segment.radial_grow(-WALL_THICKNESS).get_local_supremum()
+    }
+
+    fn room(min: [i32; 3]) -> Extent {
+        Extent::from_min_and_local_supremum(min.into(), [20, 20, 20].into())
+    }
+
+    #[test]
+    fn test_route_straight_corridor() {
+        let (r1, r2) = (room([0, 0, 0]), room([30, 0, 0]));
+        let door_dims = SubspanSampler::new(2, 4);
+        let corridor = try_route_corridor(
+            &r1,
+            &r2,
+            &SPEC,
+            &door_dims,
+            &[r1, r2],
+            &mut small_rng([1, 2, 3, 4]),
+        )
+        .unwrap();
+
+        assert_eq!(corridor.segments.len(), 1);
+        assert_eq!(corridor.doors.len(), 2);
+        let s = corridor.segments[0];
+        assert_eq!((s.get_minimum().x, s.get_world_supremum().x), (20, 30));
+        let open = open_shape(&s);
+        assert_eq!((open.y, open.z), (SPEC.height as i32, SPEC.width as i32));
+    }
+
+    #[test]
+    fn test_route_l_shaped_corridor_around_obstacle_free_corner() {
+        let (r1, r2) = (room([0, 0, 0]), room([40, 0, 40]));
+        let door_dims = SubspanSampler::new(2, 4);
+        let corridor = try_route_corridor(
+            &r1,
+            &r2,
+            &SPEC,
+            &door_dims,
+            &[r1, r2],
+            &mut small_rng([1, 2, 3, 4]),
+        )
+        .unwrap();
+
+        assert_eq!(corridor.segments.len(), 2);
+        assert_eq!(corridor.doors.len(), 3);
+    }
+
+    #[test]
+    fn test_corridor_avoids_obstacles() {
+        let (r1, r2) = (room([0, 0, 0]), room([60, 0, 0]));
+        let blocker = room([30, 0, 0]);
+        let door_dims = SubspanSampler::new(2, 4);
+
+        assert!(try_route_corridor(
+            &r1,
+            &r2,
+            &SPEC,
+            &door_dims,
+            &[r1, r2, blocker],
+            &mut small_rng([1, 2, 3, 4]),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_corridor_stubs_only_leave_free_walls() {
+        let r = room([0, 0, 0]);
+        // A door in the wall at X = 0 and a neighbor past the wall at Z = 20.
+        let door = Extent::from_min_and_local_supremum([-1, 5, 8].into(), [2, 4, 3].into());
+        let neighbor = room([0, 0, 20]);
+        let spec = CorridorStubSpec {
+            probability: 1.0,
+            width: 3,
+            height: 4,
+            max_depth: 3,
+        };
+        let mut obstacles = vec![r, neighbor];
+
+        let stubs = generate_corridor_stubs(
+            7,
+            &Room::from(r),
+            &[door],
+            &spec,
+            &SubspanSampler::new(2, 4),
+            &mut obstacles,
+            &mut small_rng([1, 2, 3, 4]),
+        );
+
+        // Only the walls at X = 20 and Z = 0 are free.
+        assert_eq!(stubs.len(), 2);
+        for stub in stubs.iter() {
+            assert_eq!(stub.room, 7);
+            assert!(stub.segment.intersection(&r).is_empty());
+            let (sup, open) = (stub.segment.get_local_supremum(), open_shape(&stub.segment));
+            // Stubs off the wall at X = 20 run along X, and the others along Z.
+            let (along, across, open_across) = if stub.segment.get_minimum().x >= 20 {
+                (sup.x, sup.z, open.z)
+            } else {
+                (sup.z, sup.x, open.x)
+            };
+            assert_eq!(across, spec.outer_width());
+            assert!((1..=3).contains(&(along - 2 * WALL_THICKNESS)));
+            // The stub is open inside, not a solid block.
+            assert_eq!(
+                (open_across, open.y),
+                (spec.width as i32, spec.height as i32)
+            );
+        }
+        assert_eq!(obstacles.len(), 4);
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{
+    extent::wall_face_slabs,
+    room::{RoomTheme, FLOOR_VOXEL, WALL_THICKNESS},
+    SpawnArea, Voxel, VoxelEncoder, VoxelFlags, VoxelLayer,
+};
+
+use ilattice3::{normal::Direction, Extent, Point};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum CeilingFeatureKind {
+    /// Written into the map as a column of rock.
+    Stalactite,
+    /// Only reported, so the engine can hang its own chandelier model.
+    ChandelierAnchor,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CeilingFeature {
+    pub kind: CeilingFeatureKind,
+    /// The ceiling voxel that the feature hangs from.
+    pub anchor: Point,
+    /// How far the feature hangs below the ceiling.
+    pub length: i32,
+}
+
+impl CeilingFeature {
+    /// The space that the feature occupies below its anchor.
+    pub fn extent(&self) -> Extent {
+        Extent::from_min_and_local_supremum(
+            self.anchor - [0, self.length, 0].into(),
+            [1, self.length, 1].into(),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CeilingThemeSpec {
+    /// Expected number of features per voxel of ceiling area.
+    pub density: f32,
+    pub min_length: u32,
+    pub max_length: u32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CeilingDecorationSpec {
+    pub cave: CeilingThemeSpec,
+    pub hall: CeilingThemeSpec,
+    /// Features stay at least this far from any door, so they don't block the headroom.
+    pub door_clearance: u32,
+    /// Features don't hang within this horizontal distance of a spawn point.
+    pub spawn_clearance: u32,
+}
+
+impl CeilingDecorationSpec {
+    fn theme_spec(&self, theme: RoomTheme) -> (&CeilingThemeSpec, CeilingFeatureKind) {
+        match theme {
+            RoomTheme::Cave => (&self.cave, CeilingFeatureKind::Stalactite),
+            RoomTheme::Hall => (&self.hall, CeilingFeatureKind::ChandelierAnchor),
+        }
+    }
+}
+
+/// Chooses the features that hang from the ceiling of `room`. `doors` and `spawn_area` may include
+/// doors and spawn points outside of the room; they are only used as obstacles.
+pub fn ceiling_features_for_room(
+    room: &Extent,
+    theme: RoomTheme,
+    doors: &[Extent],
+    spawn_area: &SpawnArea,
+    spec: &CeilingDecorationSpec,
+    rng: &mut impl Rng,
+) -> Vec<CeilingFeature> {
+    let (theme_spec, kind) = spec.theme_spec(theme);
+    let interior = room.radial_grow(-WALL_THICKNESS);
+    if interior.is_empty() {
+        return Vec::new();
+    }
+
+    let int_sup = interior.get_local_supremum();
+    // Features hang from the bottom layer of the ceiling, away from the edges where it meets the
+    // walls.
+    let ceiling = match wall_face_slabs(room, WALL_THICKNESS)
+        .into_iter()
+        .find(|(d, _)| *d == Direction::PosY)
+    {
+        Some((_, face)) => face,
+        None => return Vec::new(),
+    };
+    let (c_min, c_sup) = (*ceiling.get_minimum(), *ceiling.get_local_supremum());
+    // Always leave at least half of the room's height open.
+    let max_length = (theme_spec.max_length as i32).min(int_sup.y / 2);
+    let min_length = (theme_spec.min_length.max(1) as i32).min(max_length);
+    if max_length < 1 {
+        return Vec::new();
+    }
+
+    let area = int_sup.x * int_sup.z;
+    let num_features = (theme_spec.density * area as f32).round() as usize;
+    let anchors: Vec<Point> = (c_min.z..c_min.z + c_sup.z)
+        .flat_map(|z| (c_min.x..c_min.x + c_sup.x).map(move |x| [x, c_min.y, z].into()))
+        .collect();
+
+    let door_clearance = spec.door_clearance as i32;
+    let spawn_clearance = spec.spawn_clearance as i32;
+    let mut features = Vec::new();
+    for anchor in anchors.choose_multiple(rng, anchors.len()) {
+        if features.len() >= num_features {
+            break;
+        }
+
+        let feature = CeilingFeature {
+            kind,
+            anchor: *anchor,
+            length: rng.gen_range(min_length, max_length + 1),
+        };
+        // Keep the whole column above each door clear, since that's where the player's head goes
+        // when walking through it.
+        let blocks_door = doors.iter().any(|d| {
+            let grown = d.radial_grow(door_clearance);
+            let (min, sup) = (grown.get_minimum(), grown.get_world_supremum());
+
+            anchor.x >= min.x && anchor.x < sup.x && anchor.z >= min.z && anchor.z < sup.z
+        });
+        let over_spawn = spawn_area.valid_spawn_points.iter().any(|p| {
+            (p.x - anchor.x).abs() <= spawn_clearance && (p.z - anchor.z).abs() <= spawn_clearance
+        });
+        if !blocks_door && !over_spawn {
+            features.push(feature);
+        }
+    }
+
+    features
+}
+
+pub fn fill_map_with_stalactites(features: &[CeilingFeature], encoder: &mut impl VoxelEncoder) {
+    for f in features.iter() {
+        if f.kind != CeilingFeatureKind::Stalactite {
+            continue;
+        }
+        for p in &f.extent() {
+            encoder.encode_layer_voxel(VoxelLayer::Decoration, &p, &FLOOR_VOXEL);
+        }
+    }
+}
+
+pub const PILLAR_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 6,
+};
+
+pub const RUBBLE_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 7,
+};
+
+pub const CRATE_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 8,
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum FloorFeatureKind {
+    /// A square column from the floor to the ceiling.
+    Pillar,
+    /// A low pile that can be climbed over.
+    Rubble,
+    /// A cube that can be stood on or destroyed.
+    Crate,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FloorFeature {
+    pub kind: FloorFeatureKind,
+    /// The space that the feature occupies, starting on the floor.
+    pub extent: Extent,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FloorFeatureSpec {
+    /// Expected number of features per voxel of floor area.
+    pub density: f32,
+    /// Only rooms whose interior is at least this long along both horizontal axes get the feature.
+    pub min_room_dim: u32,
+    /// The range of the side length of the feature's square footprint.
+    pub min_size: u32,
+    pub max_size: u32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct InteriorDecorationSpec {
+    pub pillars: FloorFeatureSpec,
+    pub rubble: FloorFeatureSpec,
+    pub crates: FloorFeatureSpec,
+    /// Features stay at least this far from any door, so they don't block the way in.
+    pub door_clearance: u32,
+    /// Features stay at least this far horizontally from any spawn point.
+    pub spawn_clearance: u32,
+}
+
+/// Scatters features over the floor of `room`. Features don't overlap each other and keep clear of
+/// `doors` and `spawn_area`, which may include doors and spawn points outside of the room.
+pub fn floor_features_for_room(
+    room: &Extent,
+    doors: &[Extent],
+    spawn_area: &SpawnArea,
+    spec: &InteriorDecorationSpec,
+    rng: &mut impl Rng,
+) -> Vec<FloorFeature> {
+    let interior = room.radial_grow(-WALL_THICKNESS);
+    if interior.is_empty() {
+        return Vec::new();
+    }
+
+    let int_min = *interior.get_minimum();
+    let int_sup = *interior.get_local_supremum();
+    let door_clearance = spec.door_clearance as i32;
+    let spawn_clearance = spec.spawn_clearance as i32;
+    let mut features: Vec<FloorFeature> = Vec::new();
+    for (kind, feature_spec) in [
+        (FloorFeatureKind::Pillar, &spec.pillars),
+        (FloorFeatureKind::Rubble, &spec.rubble),
+        (FloorFeatureKind::Crate, &spec.crates),
+    ]
+    .iter()
+    {
+        let min_room_dim = feature_spec.min_room_dim as i32;
+        let max_size = (feature_spec.max_size as i32).min(int_sup.x.min(int_sup.z));
+        let min_size = (feature_spec.min_size.max(1) as i32).min(max_size);
+        if int_sup.x < min_room_dim || int_sup.z < min_room_dim || max_size < 1 {
+            continue;
+        }
+
+        let num_features = (feature_spec.density * (int_sup.x * int_sup.z) as f32).round() as usize;
+        let corners: Vec<Point> = (int_min.z..int_min.z + int_sup.z)
+            .flat_map(|z| (int_min.x..int_min.x + int_sup.x).map(move |x| [x, int_min.y, z].into()))
+            .collect();
+        let mut placed = 0;
+        for corner in corners.choose_multiple(rng, corners.len()) {
+            if placed >= num_features {
+                break;
+            }
+
+            let size = rng.gen_range(min_size, max_size + 1);
+            let height = match kind {
+                FloorFeatureKind::Pillar => int_sup.y,
+                FloorFeatureKind::Rubble => (size / 2).max(1),
+                FloorFeatureKind::Crate => size,
+            };
+            let extent = Extent::from_min_and_local_supremum(
+                *corner,
+                [size, height.min(int_sup.y), size].into(),
+            );
+            if !extent.is_subset(&interior) {
+                continue;
+            }
+
+            let blocks_door = doors.iter().any(|d| {
+                !d.radial_grow(door_clearance)
+                    .intersection(&extent)
+                    .is_empty()
+            });
+            let near_spawn = spawn_area.valid_spawn_points.iter().any(|p| {
+                let (min, sup) = (extent.get_minimum(), extent.get_world_supremum());
+
+                p.x >= min.x - spawn_clearance
+                    && p.x < sup.x + spawn_clearance
+                    && p.z >= min.z - spawn_clearance
+                    && p.z < sup.z + spawn_clearance
+            });
+            let overlaps = features
+                .iter()
+                .any(|f| !f.extent.intersection(&extent).is_empty());
+            if !blocks_door && !near_spawn && !overlaps {
+                features.push(FloorFeature {
+                    kind: *kind,
+                    extent,
+                });
+                placed += 1;
+            }
+        }
+    }
+
+    features
+}
+
+/// Columns from the floor to the ceiling on a regular grid, to hold up the ceilings of large rooms.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SupportColumnSpec {
+    /// Only rooms with at least this many voxels of interior floor get columns.
+    pub min_floor_area: u32,
+    /// The distance between the centers of neighboring columns, along both X and Z.
+    pub spacing: u32,
+    /// The side length of each column's square footprint.
+    pub width: u32,
+    /// Columns stay at least this far from any door, and this far horizontally from any spawn
+    /// point.
+    pub clearance: u32,
+}
+
+/// Spreads columns evenly over the interior of `room`, with the grid centered so the margins to
+/// the walls are equal. Grid points whose column would come too close to `doors` or `spawn_area`
+/// are left empty.
+pub fn support_columns_for_room(
+    room: &Extent,
+    doors: &[Extent],
+    spawn_area: &SpawnArea,
+    spec: &SupportColumnSpec,
+) -> Vec<Extent> {
+    let interior = room.radial_grow(-WALL_THICKNESS);
+    let (min, sup) = (*interior.get_minimum(), *interior.get_local_supremum());
+    if interior.is_empty() || ((sup.x * sup.z) as u32) < spec.min_floor_area {
+        return Vec::new();
+    }
+
+    let spacing = spec.spacing.max(1) as i32;
+    let width = spec.width.max(1) as i32;
+    let clearance = spec.clearance as i32;
+    // The offsets of the column centers from the minimum of the interior along one axis.
+    let centers = |len: i32| {
+        let n = len / spacing;
+        let margin = (len - (n - 1) * spacing) / 2;
+
+        (0..n).map(move |i| margin + i * spacing)
+    };
+
+    let mut columns = Vec::new();
+    for x in centers(sup.x) {
+        for z in centers(sup.z) {
+            let column = Extent::from_min_and_local_supremum(
+                [min.x + x - width / 2, min.y, min.z + z - width / 2].into(),
+                [width, sup.y, width].into(),
+            );
+            if !column.is_subset(&interior) {
+                continue;
+            }
+
+            let blocks_door = doors
+                .iter()
+                .any(|d| !d.radial_grow(clearance).intersection(&column).is_empty());
+            let near_spawn = spawn_area.valid_spawn_points.iter().any(|p| {
+                let (c_min, c_sup) = (column.get_minimum(), column.get_world_supremum());
+
+                p.x >= c_min.x - clearance
+                    && p.x < c_sup.x + clearance
+                    && p.z >= c_min.z - clearance
+                    && p.z < c_sup.z + clearance
+            });
+            if !blocks_door && !near_spawn {
+                columns.push(column);
+            }
+        }
+    }
+
+    columns
+}
+
+/// Columns are part of the structure, so they're written with `voxel_type` rather than a
+/// decoration type.
+pub fn fill_map_with_support_columns(
+    columns: &[Extent],
+    voxel_type: u8,
+    encoder: &mut impl VoxelEncoder,
+) {
+    let voxel = Voxel {
+        distance: -1.0,
+        voxel_type,
+    };
+    for c in columns.iter() {
+        for p in c {
+            encoder.encode_voxel(&p, &voxel);
+        }
+    }
+}
+
+/// Crates are destructible, and the tops of rubble and crates are walkable.
+pub fn fill_map_with_floor_features(features: &[FloorFeature], encoder: &mut impl VoxelEncoder) {
+    for f in features.iter() {
+        let (voxel, flags) = match f.kind {
+            FloorFeatureKind::Pillar => (&PILLAR_VOXEL, VoxelFlags::NONE),
+            FloorFeatureKind::Rubble => (&RUBBLE_VOXEL, VoxelFlags::NONE),
+            FloorFeatureKind::Crate => (&CRATE_VOXEL, VoxelFlags::DESTRUCTIBLE),
+        };
+        let top = f.extent.get_world_supremum().y - 1;
+        for p in &f.extent {
+            encoder.encode_layer_voxel(VoxelLayer::Decoration, &p, voxel);
+            let mut p_flags = flags;
+            if p.y == top && f.kind != FloorFeatureKind::Pillar {
+                p_flags |= VoxelFlags::WALKABLE;
+            }
+            if !p_flags.is_empty() {
+                encoder.encode_flags(&p, p_flags);
+            }
+        }
+    }
+}
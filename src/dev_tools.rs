@@ -0,0 +1,139 @@
+use crate::{
+    encoder::NullEncoder,
+    layout::{Layout, LayoutDiff},
+    map_types::dungeon::{DungeonMapSpec, DungeonMeta},
+    sampling::small_rng,
+};
+
+use std::{
+    fs, io,
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug)]
+pub enum SpecWatchError {
+    Io(io::Error),
+    /// The spec file isn't a valid `DungeonMapSpec`, e.g. because it was saved halfway through an
+    /// edit.
+    Parse(ron::Error),
+    /// Every attempt to generate a dungeon from the spec failed, e.g. because it asks for more
+    /// rooms than fit.
+    Generation,
+}
+
+impl From<io::Error> for SpecWatchError {
+    fn from(e: io::Error) -> Self {
+        SpecWatchError::Io(e)
+    }
+}
+
+impl From<ron::Error> for SpecWatchError {
+    fn from(e: ron::Error) -> Self {
+        SpecWatchError::Parse(e)
+    }
+}
+
+/// Regenerates a dungeon whenever its RON spec file is saved, so the effect of an edit can be seen
+/// without restarting anything. Every dungeon is generated with the seed of the first spec that
+/// was loaded, so the differences between layouts only come from the edits.
+pub struct SpecWatcher {
+    path: PathBuf,
+    seed: Option<[u32; 4]>,
+    modified: Option<SystemTime>,
+    previous: Option<Layout>,
+}
+
+impl SpecWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SpecWatcher {
+            path: path.into(),
+            seed: None,
+            modified: None,
+            previous: None,
+        }
+    }
+
+    /// If the spec file was modified since the last call, regenerates the dungeon and calls
+    /// `on_change` with it and its differences from the previous layout. The first call always
+    /// regenerates, and compares against an empty layout. Returns whether the dungeon was
+    /// regenerated.
+    pub fn poll(
+        &mut self,
+        mut on_change: impl FnMut(&DungeonMeta, &LayoutDiff),
+    ) -> Result<bool, SpecWatchError> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        self.modified = Some(modified);
+
+        let mut spec: DungeonMapSpec = ron::de::from_str(&fs::read_to_string(&self.path)?)?;
+        spec.seed = *self.seed.get_or_insert(spec.seed);
+        let meta = spec
+            .try_generate_with_retries(&mut small_rng(spec.seed), &mut NullEncoder)
+            .ok_or(SpecWatchError::Generation)?;
+
+        let diff = self.previous.take().unwrap_or_default().diff(&meta.layout);
+        on_change(&meta, &diff);
+        self.previous = Some(meta.layout);
+
+        Ok(true)
+    }
+
+    /// Polls every `interval` until `on_change` returns false. Errors are passed to `on_error`, and
+    /// watching continues, since the next save may fix them.
+    pub fn watch(
+        &mut self,
+        interval: Duration,
+        mut on_change: impl FnMut(&DungeonMeta, &LayoutDiff) -> bool,
+        mut on_error: impl FnMut(SpecWatchError),
+    ) {
+        let mut keep_watching = true;
+        while keep_watching {
+            if let Err(e) = self.poll(|meta, diff| keep_watching = on_change(meta, diff)) {
+                on_error(e);
+            }
+            if keep_watching {
+                thread::sleep(interval);
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_types::dungeon::FailureInjectionSpec;
+
+    #[test]
+    fn test_poll_reports_generation_failure() {
+        let mut spec = DungeonMapSpec::default();
+        spec.room_graph.num_rooms = 4;
+        spec.room_graph.entrance_to_objective_path_length = 3;
+        spec.failure_injection = Some(FailureInjectionSpec {
+            candidate_rejection: 1.0,
+            ..Default::default()
+        });
+        let path = std::env::temp_dir().join("spec_watcher_generation_failure.ron");
+        fs::write(&path, ron::ser::to_string(&spec).unwrap()).unwrap();
+
+        let mut watcher = SpecWatcher::new(&path);
+        let mut calls = 0;
+        let result = watcher.poll(|_, _| calls += 1);
+        assert!(matches!(result, Err(SpecWatchError::Generation)));
+        assert_eq!(calls, 0);
+
+        // Nothing changed, so the failure isn't repeated.
+        assert!(!watcher.poll(|_, _| calls += 1).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
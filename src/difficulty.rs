@@ -0,0 +1,226 @@
+use crate::layout::Layout;
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// How difficulty rises along the main path, as a function of how far along it a room is, from 0
+/// at the entrance to 1 at the objective.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum DifficultyCurve {
+    Linear,
+    /// `t^exponent`: above 1, the map stays easy for longer and ramps up near the objective.
+    Power(f32),
+    /// Flattens out near the entrance and the objective.
+    SmoothStep,
+}
+
+impl DifficultyCurve {
+    pub fn eval(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            DifficultyCurve::Linear => t,
+            DifficultyCurve::Power(exponent) => t.powf(*exponent),
+            DifficultyCurve::SmoothStep => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DifficultySpec {
+    /// The difficulty of the entrance.
+    pub entrance: f32,
+    /// The difficulty of the objective.
+    pub objective: f32,
+    pub curve: DifficultyCurve,
+    /// Added for each door between a room off the main path and the nearest room on it.
+    pub branch_step: f32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BranchRewardSpec {
+    /// The reward tier of a branch goes up by one for every this many doors of depth.
+    pub doors_per_tier: usize,
+    #[serde(default)]
+    pub max_tier: Option<usize>,
+}
+
+impl BranchRewardSpec {
+    pub fn tier(&self, depth: usize) -> usize {
+        let tier = depth.div_ceil(self.doors_per_tier.max(1));
+
+        self.max_tier.map_or(tier, |max| tier.min(max))
+    }
+}
+
+/// A detour off the main path.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Branch {
+    /// Index into `Layout::rooms` of the room on the main path that the branch leaves from.
+    pub from: usize,
+    /// Indices into `Layout::rooms`, nearest to the main path first.
+    pub rooms: Vec<usize>,
+    /// The room of the branch farthest from the main path, where its reward goes.
+    pub terminal: usize,
+    /// The number of doors from `from` to `terminal`.
+    pub depth: usize,
+    pub reward_tier: usize,
+}
+
+/// Scores every room of `layout`. Rooms on `main_path`, which runs from the entrance to the
+/// objective, follow `spec.curve` from `spec.entrance` to `spec.objective`. Every other room gets
+/// the score of the nearest room on the main path, plus `spec.branch_step` for each door in
+/// between. Rooms that can't be reached from the main path get `spec.entrance`.
+pub fn room_difficulties(layout: &Layout, main_path: &[usize], spec: &DifficultySpec) -> Vec<f32> {
+    let last_step = main_path.len().saturating_sub(1).max(1) as f32;
+    let along_path = |step: usize| {
+        spec.entrance + (spec.objective - spec.entrance) * spec.curve.eval(step as f32 / last_step)
+    };
+
+    nearest_on_main_path(layout, main_path)
+        .into_iter()
+        .map(|nearest| match nearest {
+            Some(n) => along_path(n.step) + spec.branch_step * n.doors as f32,
+            None => spec.entrance,
+        })
+        .collect()
+}
+
+/// Splits the rooms of `layout` that are off `main_path` into branches. Each room belongs to the
+/// branch of the room it's reached through on the shortest way from the main path, so a branch
+/// starts at a room next to the main path and is a tree, even where the map has loops. Rooms that
+/// can't be reached from the main path aren't in any branch.
+pub fn branches(layout: &Layout, main_path: &[usize], spec: &BranchRewardSpec) -> Vec<Branch> {
+    let nearest = nearest_on_main_path(layout, main_path);
+    // Parents come before their children.
+    let mut off_path: Vec<(usize, Nearest)> = nearest
+        .iter()
+        .enumerate()
+        .filter_map(|(room, n)| n.filter(|n| n.doors > 0).map(|n| (room, n)))
+        .collect();
+    off_path.sort_by_key(|(_, n)| n.doors);
+
+    let mut branch_of = vec![None; layout.rooms.len()];
+    let mut branches: Vec<Branch> = Vec::new();
+    for (room, n) in off_path.into_iter() {
+        let b = match branch_of[n.parent] {
+            Some(b) => b,
+            None => {
+                branches.push(Branch {
+                    from: n.parent,
+                    rooms: Vec::new(),
+                    terminal: room,
+                    depth: 0,
+                    reward_tier: 0,
+                });
+                branches.len() - 1
+            }
+        };
+        branch_of[room] = Some(b);
+        let branch = &mut branches[b];
+        branch.rooms.push(room);
+        if n.doors > branch.depth {
+            branch.depth = n.doors;
+            branch.terminal = room;
+        }
+    }
+    for b in branches.iter_mut() {
+        b.reward_tier = spec.tier(b.depth);
+    }
+
+    branches
+}
+
+#[derive(Clone, Copy)]
+struct Nearest {
+    /// Index into `main_path` of the nearest room on it.
+    step: usize,
+    /// The number of doors to that room.
+    doors: usize,
+    /// The next room on the way there, or the room itself if it's on the main path.
+    parent: usize,
+}
+
+/// Searches outward from every room of `main_path` at once. Ties go to the room closer to the
+/// entrance.
+fn nearest_on_main_path(layout: &Layout, main_path: &[usize]) -> Vec<Option<Nearest>> {
+    let mut nearest = vec![None; layout.rooms.len()];
+    let mut queue = VecDeque::new();
+    for (step, room) in main_path.iter().enumerate() {
+        if nearest[*room].is_none() {
+            nearest[*room] = Some(Nearest {
+                step,
+                doors: 0,
+                parent: *room,
+            });
+            queue.push_back(*room);
+        }
+    }
+    while let Some(room) = queue.pop_front() {
+        let Nearest { step, doors, .. } = nearest[room].unwrap();
+        for door in layout.doors.iter() {
+            let next = match door.rooms {
+                (a, b) if a == room => b,
+                (a, b) if b == room => a,
+                _ => continue,
+            };
+            if nearest[next].is_none() {
+                nearest[next] = Some(Nearest {
+                    step,
+                    doors: doors + 1,
+                    parent: room,
+                });
+                queue.push_back(next);
+            }
+        }
+    }
+
+    nearest
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::row_layout;
+
+    #[test]
+    fn test_difficulty_rises_along_main_path_and_branches() {
+        // A main path 0-1-2 with a branch 3-4 off of 1, and room 5 on its own.
+        let layout = row_layout(6, &[(0, 1), (1, 2), (1, 3), (3, 4)]);
+        let spec = DifficultySpec {
+            entrance: 1.0,
+            objective: 9.0,
+            curve: DifficultyCurve::Power(2.0),
+            branch_step: 0.5,
+        };
+
+        let d = room_difficulties(&layout, &[0, 1, 2], &spec);
+
+        assert_eq!(d, vec![1.0, 3.0, 9.0, 3.5, 4.0, 1.0]);
+    }
+
+    #[test]
+    fn test_longer_branches_get_better_rewards() {
+        // A main path 0-1-2 with branches 3-4-5 and 6 off of 1, and 7 off of 2.
+        let layout = row_layout(8, &[(0, 1), (1, 2), (1, 3), (3, 4), (4, 5), (1, 6), (2, 7)]);
+        let spec = BranchRewardSpec {
+            doors_per_tier: 2,
+            max_tier: None,
+        };
+
+        let found = branches(&layout, &[0, 1, 2], &spec);
+
+        let summary: Vec<(usize, usize, usize, usize)> = found
+            .iter()
+            .map(|b| (b.from, b.terminal, b.depth, b.reward_tier))
+            .collect();
+        assert_eq!(summary, vec![(1, 5, 3, 2), (1, 6, 1, 1), (2, 7, 1, 1)]);
+        assert_eq!(found[0].rooms, vec![3, 4, 5]);
+    }
+}
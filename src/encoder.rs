@@ -0,0 +1,509 @@
+use crate::{extent::bounding_extent, Voxel, VoxelEncoder, VoxelFlags, VoxelLayer, VoxelSource};
+
+use fnv::FnvHashMap;
+use ilattice3::{
+    normal::{Normal, ALL_DIRECTIONS},
+    Extent, Point,
+};
+
+/// Stages all writes in memory so they can be applied to the wrapped encoder all at once, or not at
+/// all. Dropping the adapter without calling `commit` discards the staged writes.
+pub struct BufferedEncoder<'a, E> {
+    encoder: &'a mut E,
+    writes: Vec<StagedWrite>,
+    /// The last voxel staged at each point of each layer, if reading back is enabled.
+    read_back: Option<FnvHashMap<(VoxelLayer, Point), Voxel>>,
+    /// The flags staged for each structure voxel since it was last staged, if reading back is
+    /// enabled.
+    read_back_flags: FnvHashMap<Point, VoxelFlags>,
+    /// Whether flags belong to a voxel of a layer other than the structure.
+    last_write_layered: bool,
+    /// If set, writes outside of this extent are dropped instead of staged.
+    clip: Option<Extent>,
+    /// The bounding extent of every write, including the ones outside of `clip`.
+    bounds: Option<Extent>,
+}
+
+enum StagedWrite {
+    Voxel(Point, Voxel),
+    LayerVoxel(VoxelLayer, Point, Voxel),
+    Flags(Point, VoxelFlags),
+}
+
+impl<'a, E: VoxelEncoder> BufferedEncoder<'a, E> {
+    pub fn new(encoder: &'a mut E) -> Self {
+        BufferedEncoder {
+            encoder,
+            writes: Vec::new(),
+            read_back: None,
+            read_back_flags: FnvHashMap::default(),
+            last_write_layered: false,
+            clip: None,
+            bounds: None,
+        }
+    }
+
+    /// Like `new`, but also remembers the last voxel and flags staged at each point, so the staged
+    /// writes can be read back through `VoxelSource`.
+    pub fn with_read_back(encoder: &'a mut E) -> Self {
+        BufferedEncoder {
+            encoder,
+            writes: Vec::new(),
+            read_back: Some(FnvHashMap::default()),
+            read_back_flags: FnvHashMap::default(),
+            last_write_layered: false,
+            clip: None,
+            bounds: None,
+        }
+    }
+
+    /// Only stages the writes inside of `extent`, so no more than that much of a map is ever held
+    /// in memory. Reading back outside of `extent` finds nothing.
+    pub fn clip_to(mut self, extent: Extent) -> Self {
+        self.clip = Some(extent);
+
+        self
+    }
+
+    pub fn num_staged_writes(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// The bounding extent of every voxel written through the adapter, including the writes that
+    /// `clip_to` dropped. `None` if nothing was written.
+    pub fn bounds_of_writes(&self) -> Option<Extent> {
+        self.bounds
+    }
+
+    /// Flushes the staged writes to the wrapped encoder in the order they were made.
+    pub fn commit(self) {
+        for write in self.writes.iter() {
+            match write {
+                StagedWrite::Voxel(p, voxel) => self.encoder.encode_voxel(p, voxel),
+                StagedWrite::LayerVoxel(layer, p, voxel) => {
+                    self.encoder.encode_layer_voxel(*layer, p, voxel)
+                }
+                StagedWrite::Flags(p, flags) => self.encoder.encode_flags(p, *flags),
+            }
+        }
+    }
+}
+
+impl<'a, E> BufferedEncoder<'a, E> {
+    fn keeps(&self, point: &Point) -> bool {
+        self.clip.as_ref().is_none_or(|c| c.contains_world(point))
+    }
+
+    fn grow_bounds(&mut self, point: &Point) {
+        let voxel = Extent::from_min_and_local_supremum(*point, [1, 1, 1].into());
+        self.bounds = match self.bounds {
+            Some(bounds) => bounding_extent(&[bounds, voxel]),
+            None => Some(voxel),
+        };
+    }
+}
+
+impl<'a, E> VoxelEncoder for BufferedEncoder<'a, E> {
+    fn encode_voxel(&mut self, point: &Point, data: &Voxel) {
+        self.grow_bounds(point);
+        if !self.keeps(point) {
+            return;
+        }
+        if let Some(read_back) = &mut self.read_back {
+            read_back.insert((VoxelLayer::Structure, *point), *data);
+            self.read_back_flags.remove(point);
+        }
+        self.last_write_layered = false;
+        self.writes.push(StagedWrite::Voxel(*point, *data));
+    }
+
+    fn encode_layer_voxel(&mut self, layer: VoxelLayer, point: &Point, data: &Voxel) {
+        self.grow_bounds(point);
+        if !self.keeps(point) {
+            return;
+        }
+        if let Some(read_back) = &mut self.read_back {
+            read_back.insert((layer, *point), *data);
+            if layer == VoxelLayer::Structure {
+                self.read_back_flags.remove(point);
+            }
+        }
+        self.last_write_layered = layer != VoxelLayer::Structure;
+        self.writes
+            .push(StagedWrite::LayerVoxel(layer, *point, *data));
+    }
+
+    fn encode_flags(&mut self, point: &Point, flags: VoxelFlags) {
+        if !self.keeps(point) {
+            return;
+        }
+        if self.read_back.is_some() && !self.last_write_layered {
+            self.read_back_flags.insert(*point, flags);
+        }
+        self.writes.push(StagedWrite::Flags(*point, flags));
+    }
+}
+
+/// Only knows about the staged writes if the adapter was created with `with_read_back`.
+impl<'a, E> VoxelSource for BufferedEncoder<'a, E> {
+    fn get_voxel(&self, layer: VoxelLayer, point: &Point) -> Option<Voxel> {
+        self.read_back.as_ref()?.get(&(layer, *point)).cloned()
+    }
+
+    fn get_flags(&self, point: &Point) -> Option<VoxelFlags> {
+        self.read_back_flags.get(point).cloned()
+    }
+}
+
+/// Collects all writes and only passes on the voxels at the boundary between solid and air, for
+/// engines that reconstruct solid interiors themselves. Voxels that were never written count as
+/// air. Writes to layers other than the structure are sparse, so they're all passed on after the
+/// boundary. Call `finish` to flush the writes to the wrapped encoder.
+///
+/// The boundary voxels are passed on with the distances they were written with. A mesher that
+/// places the surface between them relies on those distances being exact, so the shell is only as
+/// accurate as the SDF of the source that wrote them.
+pub struct ShellEncoder<'a, E> {
+    encoder: &'a mut E,
+    voxels: FnvHashMap<Point, (Voxel, VoxelFlags)>,
+    layered: Vec<(VoxelLayer, Point, Voxel, VoxelFlags)>,
+    /// Whether flags belong to the last entry of `layered`.
+    last_write_layered: bool,
+}
+
+impl<'a, E: VoxelEncoder> ShellEncoder<'a, E> {
+    pub fn new(encoder: &'a mut E) -> Self {
+        ShellEncoder {
+            encoder,
+            voxels: FnvHashMap::default(),
+            layered: Vec::new(),
+            last_write_layered: false,
+        }
+    }
+
+    /// Writes the last voxel and flags written at each point, if the point is solid with an air
+    /// neighbor or is air with a solid neighbor.
+    pub fn finish(self) {
+        let is_solid = |p: &Point| self.voxels.get(p).is_some_and(|(v, _)| v.distance < 0.0);
+        let mut boundary: Vec<_> = self
+            .voxels
+            .iter()
+            .filter(|(p, (v, _))| {
+                let solid = v.distance < 0.0;
+                ALL_DIRECTIONS
+                    .iter()
+                    .any(|d| is_solid(&(**p + Point::from(Normal::Axis(*d)))) != solid)
+            })
+            .collect();
+        // Keep the output deterministic despite the hash map.
+        boundary.sort_by_key(|(p, _)| (p.x, p.y, p.z));
+
+        for (p, (voxel, flags)) in boundary {
+            self.encoder.encode_voxel(p, voxel);
+            if !flags.is_empty() {
+                self.encoder.encode_flags(p, *flags);
+            }
+        }
+        for (layer, p, voxel, flags) in self.layered.iter() {
+            self.encoder.encode_layer_voxel(*layer, p, voxel);
+            if !flags.is_empty() {
+                self.encoder.encode_flags(p, *flags);
+            }
+        }
+    }
+}
+
+impl<'a, E> VoxelEncoder for ShellEncoder<'a, E> {
+    fn encode_voxel(&mut self, point: &Point, data: &Voxel) {
+        // Flags belong to the voxel they were encoded after.
+        self.voxels.insert(*point, (*data, VoxelFlags::NONE));
+        self.last_write_layered = false;
+    }
+
+    fn encode_layer_voxel(&mut self, layer: VoxelLayer, point: &Point, data: &Voxel) {
+        if layer == VoxelLayer::Structure {
+            return self.encode_voxel(point, data);
+        }
+        self.layered.push((layer, *point, *data, VoxelFlags::NONE));
+        self.last_write_layered = true;
+    }
+
+    fn encode_flags(&mut self, point: &Point, flags: VoxelFlags) {
+        if self.last_write_layered {
+            if let Some((_, p, _, f)) = self.layered.last_mut() {
+                if p == point {
+                    *f = flags;
+                    return;
+                }
+            }
+        }
+        if let Some((_, f)) = self.voxels.get_mut(point) {
+            *f = flags;
+        }
+    }
+}
+
+/// Passes on only the writes inside of an extent.
+pub struct ClipEncoder<'a, E> {
+    encoder: &'a mut E,
+    extent: Extent,
+}
+
+impl<'a, E: VoxelEncoder> ClipEncoder<'a, E> {
+    pub fn new(encoder: &'a mut E, extent: Extent) -> Self {
+        ClipEncoder { encoder, extent }
+    }
+}
+
+impl<'a, E: VoxelEncoder> VoxelEncoder for ClipEncoder<'a, E> {
+    fn encode_voxel(&mut self, point: &Point, data: &Voxel) {
+        if self.extent.contains_world(point) {
+            self.encoder.encode_voxel(point, data);
+        }
+    }
+
+    fn encode_layer_voxel(&mut self, layer: VoxelLayer, point: &Point, data: &Voxel) {
+        if self.extent.contains_world(point) {
+            self.encoder.encode_layer_voxel(layer, point, data);
+        }
+    }
+
+    fn encode_flags(&mut self, point: &Point, flags: VoxelFlags) {
+        if self.extent.contains_world(point) {
+            self.encoder.encode_flags(point, flags);
+        }
+    }
+}
+
+/// Discards all writes, for when only the generated metadata is needed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullEncoder;
+
+impl VoxelEncoder for NullEncoder {
+    fn encode_voxel(&mut self, _point: &Point, _data: &Voxel) {}
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ilattice3::Extent;
+
+    #[derive(Default)]
+    struct PointRecorder {
+        points: Vec<Point>,
+    }
+
+    impl VoxelEncoder for PointRecorder {
+        fn encode_voxel(&mut self, point: &Point, _data: &Voxel) {
+            self.points.push(*point);
+        }
+    }
+
+    const VOXEL: Voxel = Voxel {
+        distance: -1.0,
+        voxel_type: 1,
+    };
+
+    #[test]
+    fn test_buffered_encoder_commit_flushes_in_order() {
+        let mut recorder = PointRecorder::default();
+        let mut staged = BufferedEncoder::new(&mut recorder);
+        staged.encode_voxel(&[1, 2, 3].into(), &VOXEL);
+        staged.encode_voxel(&[4, 5, 6].into(), &VOXEL);
+        staged.commit();
+
+        assert_eq!(recorder.points, vec![[1, 2, 3].into(), [4, 5, 6].into()]);
+    }
+
+    #[test]
+    fn test_buffered_encoder_drop_discards_writes() {
+        let mut recorder = PointRecorder::default();
+        {
+            let mut staged = BufferedEncoder::new(&mut recorder);
+            staged.encode_voxel(&[1, 2, 3].into(), &VOXEL);
+        }
+
+        assert!(recorder.points.is_empty());
+    }
+
+    #[test]
+    fn test_clipped_buffered_encoder_only_stages_inside() {
+        let clip = Extent::from_min_and_local_supremum([0, 0, 0].into(), [2, 2, 2].into());
+        let mut recorder = PointRecorder::default();
+        let mut staged = BufferedEncoder::with_read_back(&mut recorder).clip_to(clip);
+        staged.encode_voxel(&[1, 1, 1].into(), &VOXEL);
+        staged.encode_voxel(&[2, 1, 1].into(), &VOXEL);
+        staged.encode_voxel(&[-1, 0, 0].into(), &VOXEL);
+
+        assert_eq!(staged.num_staged_writes(), 1);
+        assert!(staged
+            .get_voxel(VoxelLayer::Structure, &[2, 1, 1].into())
+            .is_none());
+        staged.commit();
+        assert_eq!(recorder.points, vec![[1, 1, 1].into()]);
+    }
+
+    #[derive(Default)]
+    struct FlagRecorder {
+        flags: Vec<(Point, VoxelFlags)>,
+    }
+
+    impl VoxelEncoder for FlagRecorder {
+        fn encode_voxel(&mut self, _point: &Point, _data: &Voxel) {}
+
+        fn encode_flags(&mut self, point: &Point, flags: VoxelFlags) {
+            self.flags.push((*point, flags));
+        }
+    }
+
+    #[test]
+    fn test_buffered_encoder_stages_flags() {
+        let mut recorder = FlagRecorder::default();
+        let mut staged = BufferedEncoder::new(&mut recorder);
+        staged.encode_voxel(&[1, 2, 3].into(), &VOXEL);
+        staged.encode_flags(&[1, 2, 3].into(), VoxelFlags::WALKABLE | VoxelFlags::WATER);
+        assert_eq!(staged.num_staged_writes(), 2);
+        staged.commit();
+
+        let flags = recorder.flags[0].1;
+        assert_eq!(recorder.flags.len(), 1);
+        assert!(flags.contains(VoxelFlags::WALKABLE));
+        assert!(flags.contains(VoxelFlags::WATER));
+        assert!(!flags.contains(VoxelFlags::CLIMBABLE));
+    }
+
+    #[test]
+    fn test_shell_encoder_skips_buried_voxels() {
+        let mut recorder = PointRecorder::default();
+        let mut shell = ShellEncoder::new(&mut recorder);
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    shell.encode_voxel(&[x, y, z].into(), &VOXEL);
+                }
+            }
+        }
+        shell.finish();
+
+        assert_eq!(recorder.points.len(), 26);
+        assert!(!recorder.points.contains(&[1, 1, 1].into()));
+    }
+
+    #[derive(Default)]
+    struct VoxelRecorder {
+        voxels: Vec<(Point, Voxel)>,
+    }
+
+    impl VoxelEncoder for VoxelRecorder {
+        fn encode_voxel(&mut self, point: &Point, data: &Voxel) {
+            self.voxels.push((*point, *data));
+        }
+    }
+
+    const SPHERE_RADIUS: f32 = 4.0;
+
+    /// The exact SDF of a sphere at the origin.
+    fn sphere_distance(p: &Point) -> f32 {
+        ((p.x * p.x + p.y * p.y + p.z * p.z) as f32).sqrt() - SPHERE_RADIUS
+    }
+
+    /// Writes the sphere through a shell, with air all around it.
+    fn sphere_shell() -> Vec<(Point, Voxel)> {
+        let mut recorder = VoxelRecorder::default();
+        let mut shell = ShellEncoder::new(&mut recorder);
+        for p in &Extent::from_min_and_local_supremum([-6, -6, -6].into(), [13, 13, 13].into()) {
+            let voxel = Voxel {
+                distance: sphere_distance(&p),
+                voxel_type: 1,
+            };
+            shell.encode_voxel(&p, &voxel);
+        }
+        shell.finish();
+
+        recorder.voxels
+    }
+
+    #[test]
+    fn test_shell_encoder_passes_on_exact_distances() {
+        let voxels = sphere_shell();
+
+        assert!(!voxels.is_empty());
+        for (p, voxel) in voxels.iter() {
+            assert_eq!(voxel.distance, sphere_distance(p));
+            let solid = voxel.distance < 0.0;
+            assert!(ALL_DIRECTIONS.iter().any(|d| {
+                let neighbor = *p + Point::from(Normal::Axis(*d));
+
+                (sphere_distance(&neighbor) < 0.0) != solid
+            }));
+        }
+    }
+
+    #[test]
+    fn test_shell_encoder_keeps_both_sides_of_the_boundary() {
+        let points: Vec<Point> = sphere_shell().into_iter().map(|(p, _)| p).collect();
+
+        // Along the X axis, the surface is between x = 3, the last solid voxel, and x = 4.
+        assert!(points.contains(&[3, 0, 0].into()));
+        assert!(points.contains(&[4, 0, 0].into()));
+        assert!(!points.contains(&[2, 0, 0].into()));
+        assert!(!points.contains(&[5, 0, 0].into()));
+        assert!(!points.contains(&[0, 0, 0].into()));
+        assert!(!points.contains(&[-6, -6, -6].into()));
+    }
+
+    #[derive(Default)]
+    struct LayerRecorder {
+        writes: Vec<(VoxelLayer, Point)>,
+    }
+
+    impl VoxelEncoder for LayerRecorder {
+        fn encode_voxel(&mut self, point: &Point, _data: &Voxel) {
+            self.writes.push((VoxelLayer::Structure, *point));
+        }
+
+        fn encode_layer_voxel(&mut self, layer: VoxelLayer, point: &Point, _data: &Voxel) {
+            self.writes.push((layer, *point));
+        }
+    }
+
+    #[test]
+    fn test_adapters_preserve_layers() {
+        let mut recorder = LayerRecorder::default();
+        let mut staged = BufferedEncoder::new(&mut recorder);
+        staged.encode_voxel(&[0, 0, 0].into(), &VOXEL);
+        staged.encode_layer_voxel(VoxelLayer::Liquid, &[0, 1, 0].into(), &VOXEL);
+        staged.commit();
+
+        assert_eq!(
+            recorder.writes,
+            vec![
+                (VoxelLayer::Structure, [0, 0, 0].into()),
+                (VoxelLayer::Liquid, [0, 1, 0].into()),
+            ]
+        );
+
+        // Decoration inside of a solid is still passed on by the shell.
+        let mut recorder = LayerRecorder::default();
+        let mut shell = ShellEncoder::new(&mut recorder);
+        for p in &Extent::from_min_and_local_supremum([0, 0, 0].into(), [3, 3, 3].into()) {
+            shell.encode_voxel(&p, &VOXEL);
+        }
+        shell.encode_layer_voxel(VoxelLayer::Decoration, &[1, 1, 1].into(), &VOXEL);
+        shell.finish();
+
+        assert_eq!(recorder.writes.len(), 27);
+        assert_eq!(
+            recorder.writes.last(),
+            Some(&(VoxelLayer::Decoration, [1, 1, 1].into()))
+        );
+    }
+}
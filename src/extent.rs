@@ -1,7 +1,150 @@
-use ilattice3::Extent;
+use ilattice3::{
+    normal::{Direction, Normal},
+    Extent, Point,
+};
+use serde::{Deserialize, Serialize};
 
-pub fn push_extents_apart(r1: Extent, r2: Extent) -> (Extent, Extent) {
-    let (push_v, direction) = Extent::penetrations(&r1, &r2).min_vector();
+/// The coordinate of `p` along `axis`, where 0, 1, 2 are X, Y, Z.
+pub fn component(p: &Point, axis: usize) -> i32 {
+    match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    }
+}
+
+pub fn with_component(p: &Point, axis: usize, value: i32) -> Point {
+    let mut p = *p;
+    match axis {
+        0 => p.x = value,
+        1 => p.y = value,
+        _ => p.z = value,
+    }
+
+    p
+}
+
+/// Returns the axis along which `a` and `b` touch, and whether `b` is on the positive side of `a`.
+pub fn touching_axis(a: &Extent, b: &Extent) -> Option<(usize, bool)> {
+    (0..3).find_map(|axis| {
+        if component(&a.get_world_supremum(), axis) == component(b.get_minimum(), axis) {
+            Some((axis, true))
+        } else if component(&b.get_world_supremum(), axis) == component(a.get_minimum(), axis) {
+            Some((axis, false))
+        } else {
+            None
+        }
+    })
+}
+
+/// The center of `extent` in world coordinates.
+pub fn extent_center(extent: &Extent) -> [f32; 3] {
+    let min = extent.get_minimum();
+    let sup = extent.get_local_supremum();
+
+    [
+        min.x as f32 + sup.x as f32 / 2.0,
+        min.y as f32 + sup.y as f32 / 2.0,
+        min.z as f32 + sup.z as f32 / 2.0,
+    ]
+}
+
+pub fn distance_squared(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+/// Keeps extents in order, dropping any whose center is within `min_distance` of the center of an
+/// extent that was already kept.
+pub fn filter_close_extents(extents: Vec<Extent>, min_distance: f32) -> Vec<Extent> {
+    if min_distance <= 0.0 {
+        return extents;
+    }
+
+    let min_dist_sq = min_distance * min_distance;
+    let mut kept_centers: Vec<[f32; 3]> = Vec::with_capacity(extents.len());
+    let mut kept = Vec::with_capacity(extents.len());
+    for e in extents.into_iter() {
+        let center = extent_center(&e);
+        if kept_centers
+            .iter()
+            .all(|c| distance_squared(c, &center) >= min_dist_sq)
+        {
+            kept_centers.push(center);
+            kept.push(e);
+        }
+    }
+
+    kept
+}
+
+/// How willing `resolve_extent_overlaps` is to push extents apart along each axis. Overlapping
+/// extents are separated along the axis where the penetration divided by the weight is smallest,
+/// so a larger weight favors an axis and a zero weight forbids it. If every weight is zero, the
+/// axes are treated equally.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct SeparationWeights {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Default for SeparationWeights {
+    fn default() -> Self {
+        SeparationWeights {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        }
+    }
+}
+
+impl SeparationWeights {
+    /// Only separates extents horizontally, so layouts spread out instead of stacking.
+    pub fn horizontal() -> Self {
+        SeparationWeights {
+            y: 0.0,
+            ..Default::default()
+        }
+    }
+
+    fn along(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+}
+
+fn direction_axis(d: Direction) -> usize {
+    match d.positive() {
+        Direction::PosX => 0,
+        Direction::PosY => 1,
+        _ => 2,
+    }
+}
+
+pub fn push_extents_apart(r1: Extent, r2: Extent, weights: &SeparationWeights) -> (Extent, Extent) {
+    let penetrations = Extent::penetrations(&r1, &r2);
+    let weighted = penetrations
+        .iter()
+        .filter_map(|(d, p)| {
+            let w = weights.along(direction_axis(d));
+            if w > 0.0 {
+                Some((d, *p, *p as f32 / w))
+            } else {
+                None
+            }
+        })
+        // Ties go to the earliest direction.
+        .fold(None, |best: Option<(_, i32, f32)>, candidate| match best {
+            Some(b) if b.2 <= candidate.2 => Some(b),
+            _ => Some(candidate),
+        });
+    let (push_v, direction) = match weighted {
+        Some((d, p, _)) => (Point::from(Normal::Axis(d)) * p, d),
+        None => penetrations.min_vector(),
+    };
 
     // Only push in positive directions to prevent infinite cycles.
     if direction.is_negative() {
@@ -11,7 +154,7 @@ pub fn push_extents_apart(r1: Extent, r2: Extent) -> (Extent, Extent) {
     }
 }
 
-pub fn resolve_extent_overlaps(rooms: &mut [Extent]) {
+pub fn resolve_extent_overlaps(rooms: &mut [Extent], weights: &SeparationWeights) {
     let num_rooms = rooms.len();
     loop {
         // PERF: N^2 gets slow for >1000 rooms
@@ -26,7 +169,7 @@ pub fn resolve_extent_overlaps(rooms: &mut [Extent]) {
                 }
 
                 all_rooms_separated = false;
-                let (r1, r2) = push_extents_apart(r1, r2);
+                let (r1, r2) = push_extents_apart(r1, r2, weights);
                 debug_assert!(r1.intersection(&r2).is_empty());
                 rooms[i] = r1;
                 rooms[j] = r2;
@@ -38,3 +181,377 @@ pub fn resolve_extent_overlaps(rooms: &mut [Extent]) {
         }
     }
 }
+
+/// The slab of voxels just outside of `extent` on its positive or negative side along `axis`.
+fn side_slab(extent: &Extent, axis: usize, positive: bool) -> Extent {
+    let min = extent.get_minimum();
+    let side = if positive {
+        component(&extent.get_world_supremum(), axis)
+    } else {
+        component(min, axis) - 1
+    };
+
+    Extent::from_min_and_local_supremum(
+        with_component(min, axis, side),
+        with_component(extent.get_local_supremum(), axis, 1),
+    )
+}
+
+/// The smallest extent that contains all of `extents`, or `None` if there are none.
+pub fn bounding_extent(extents: &[Extent]) -> Option<Extent> {
+    let mut extents = extents.iter();
+    let first = *extents.next()?;
+
+    Some(extents.fold(first, |bounds, e| {
+        let (min, e_min) = (bounds.get_minimum(), e.get_minimum());
+        let (sup, e_sup) = (bounds.get_world_supremum(), e.get_world_supremum());
+        Extent::from_min_and_world_supremum(
+            [min.x.min(e_min.x), min.y.min(e_min.y), min.z.min(e_min.z)].into(),
+            [sup.x.max(e_sup.x), sup.y.max(e_sup.y), sup.z.max(e_sup.z)].into(),
+        )
+    }))
+}
+
+/// The cells of the grid of `shape` sized chunks, aligned to the origin, that intersect `bounds`,
+/// in order of Z, then Y, then X.
+pub fn chunks_covering(bounds: &Extent, shape: Point) -> Vec<Extent> {
+    let shape = Point::from([shape.x.max(1), shape.y.max(1), shape.z.max(1)]);
+    let (min, sup) = (bounds.get_minimum(), bounds.get_world_supremum());
+    let cells = |axis: usize| {
+        let size = component(&shape, axis);
+        let first = component(min, axis).div_euclid(size);
+        let last = (component(&sup, axis) - 1).div_euclid(size);
+
+        (first..=last).map(move |c| c * size)
+    };
+
+    let mut chunks = Vec::new();
+    if bounds.is_empty() {
+        return chunks;
+    }
+    for z in cells(2) {
+        for y in cells(1) {
+            for x in cells(0) {
+                chunks.push(Extent::from_min_and_local_supremum([x, y, z].into(), shape));
+            }
+        }
+    }
+
+    chunks
+}
+
+/// Disjoint extents that cover `extent` except where it intersects `hole`. There are at most 6,
+/// sliced off one axis at a time.
+pub fn subtract_extent(extent: &Extent, hole: &Extent) -> Vec<Extent> {
+    let overlap = extent.intersection(hole);
+    if overlap.is_empty() {
+        return vec![*extent];
+    }
+
+    let mut pieces = Vec::new();
+    let mut rest = *extent;
+    for axis in 0..3 {
+        let (rest_min, rest_sup) = (*rest.get_minimum(), rest.get_world_supremum());
+        let (lo, hi) = (
+            component(overlap.get_minimum(), axis),
+            component(&overlap.get_world_supremum(), axis),
+        );
+        let below =
+            Extent::from_min_and_world_supremum(rest_min, with_component(&rest_sup, axis, lo));
+        let above =
+            Extent::from_min_and_world_supremum(with_component(&rest_min, axis, hi), rest_sup);
+        pieces.extend([below, above].iter().filter(|p| !p.is_empty()));
+        rest = Extent::from_min_and_world_supremum(
+            with_component(&rest_min, axis, lo),
+            with_component(&rest_sup, axis, hi),
+        );
+    }
+
+    pieces
+}
+
+/// Disjoint extents that cover `bounds` except where it intersects any of `holes`.
+pub fn subtract_extents(bounds: &Extent, holes: &[Extent]) -> Vec<Extent> {
+    holes.iter().fold(vec![*bounds], |pieces, hole| {
+        pieces
+            .iter()
+            .flat_map(|p| subtract_extent(p, hole))
+            .collect()
+    })
+}
+
+/// The faces of the `thickness` thick wall on the inside of `extent`, each with the direction it
+/// faces out of the room. Faces stop short of the edges and corners, so the faces, edges, corners
+/// and interior together partition `extent`.
+pub fn wall_face_slabs(extent: &Extent, thickness: i32) -> Vec<(Direction, Extent)> {
+    wall_pieces(extent, thickness, 1)
+        .map(|(dirs, piece)| (dirs[0], piece))
+        .collect()
+}
+
+/// The 12 prisms where two faces of the wall meet, each with the directions of both faces.
+pub fn wall_edge_prisms(extent: &Extent, thickness: i32) -> Vec<([Direction; 2], Extent)> {
+    wall_pieces(extent, thickness, 2)
+        .map(|(dirs, piece)| ([dirs[0], dirs[1]], piece))
+        .collect()
+}
+
+/// The 8 blocks where three faces of the wall meet, each with the directions of all three faces.
+pub fn wall_corner_blocks(extent: &Extent, thickness: i32) -> Vec<([Direction; 3], Extent)> {
+    wall_pieces(extent, thickness, 3)
+        .map(|(dirs, piece)| ([dirs[0], dirs[1], dirs[2]], piece))
+        .collect()
+}
+
+/// Splits `extent` into 3 bands along each axis: `thickness` voxels on the negative side, the
+/// middle, and `thickness` voxels on the positive side. Yields the cells of the 3x3x3 grid that
+/// are on the outside of exactly `num_sides` axes, along with the directions of those sides. When
+/// `extent` is thinner than twice the wall, the positive band gets what's left over and some
+/// cells are empty.
+fn wall_pieces(
+    extent: &Extent,
+    thickness: i32,
+    num_sides: usize,
+) -> impl Iterator<Item = (Vec<Direction>, Extent)> {
+    let (min, sup) = (*extent.get_minimum(), *extent.get_local_supremum());
+    let bands = |axis: usize| {
+        let (lo, len) = (component(&min, axis), component(&sup, axis));
+        let neg = thickness.min(len).max(0);
+        let pos = thickness.min(len - neg).max(0);
+
+        [
+            (lo, neg),
+            (lo + neg, len - neg - pos),
+            (lo + len - pos, pos),
+        ]
+    };
+    let bands = [bands(0), bands(1), bands(2)];
+    let axis_directions = [
+        (Direction::NegX, Direction::PosX),
+        (Direction::NegY, Direction::PosY),
+        (Direction::NegZ, Direction::PosZ),
+    ];
+
+    (0..27).filter_map(move |cell| {
+        let choice = [cell % 3, (cell / 3) % 3, cell / 9];
+        let mut dirs = Vec::new();
+        let mut piece_min = min;
+        let mut piece_sup = sup;
+        for (axis, &band) in choice.iter().enumerate() {
+            let (band_min, band_len) = bands[axis][band];
+            piece_min = with_component(&piece_min, axis, band_min);
+            piece_sup = with_component(&piece_sup, axis, band_len);
+            match band {
+                0 => dirs.push(axis_directions[axis].0),
+                2 => dirs.push(axis_directions[axis].1),
+                _ => (),
+            }
+        }
+
+        if dirs.len() == num_sides {
+            Some((
+                dirs,
+                Extent::from_min_and_local_supremum(piece_min, piece_sup),
+            ))
+        } else {
+            None
+        }
+    })
+}
+
+/// Greedily grows each extent horizontally into the empty space around it, one voxel per side in
+/// each round, until none can grow. A side stops growing when it would come within `min_gap` of
+/// another extent, leave `bounds`, or make the extent longer than `max_dim`.
+pub fn expand_extents_into_gaps(
+    extents: &mut [Extent],
+    min_gap: i32,
+    bounds: Option<&Extent>,
+    max_dim: i32,
+) {
+    loop {
+        let mut any_grew = false;
+        for i in 0..extents.len() {
+            for (axis, positive) in [(0, false), (0, true), (2, false), (2, true)].iter() {
+                let e = extents[i];
+                if component(e.get_local_supremum(), *axis) >= max_dim {
+                    continue;
+                }
+
+                let slab = side_slab(&e, *axis, *positive);
+                if bounds.is_some_and(|b| !slab.is_subset(b)) {
+                    continue;
+                }
+                let clearance = slab.radial_grow(min_gap);
+                let blocked = extents
+                    .iter()
+                    .enumerate()
+                    .any(|(j, other)| j != i && !clearance.intersection(other).is_empty());
+                if blocked {
+                    continue;
+                }
+
+                let min = if *positive {
+                    *e.get_minimum()
+                } else {
+                    *slab.get_minimum()
+                };
+                let sup = with_component(
+                    e.get_local_supremum(),
+                    *axis,
+                    component(e.get_local_supremum(), *axis) + 1,
+                );
+                extents[i] = Extent::from_min_and_local_supremum(min, sup);
+                any_grew = true;
+            }
+        }
+
+        if !any_grew {
+            break;
+        }
+    }
+}
+
+/// Pulls extents toward their common centroid one voxel at a time along each axis, as long as they
+/// don't come to overlap another extent, until none can move. Undoes the sprawl left behind by
+/// `resolve_extent_overlaps`, which only pushes extents in positive directions.
+pub fn compact_extents_toward_centroid(extents: &mut [Extent]) {
+    if extents.is_empty() {
+        return;
+    }
+
+    let centers: Vec<_> = extents.iter().map(extent_center).collect();
+    let n = centers.len() as f32;
+    let centroid = [
+        centers.iter().map(|c| c[0]).sum::<f32>() / n,
+        centers.iter().map(|c| c[1]).sum::<f32>() / n,
+        centers.iter().map(|c| c[2]).sum::<f32>() / n,
+    ];
+
+    loop {
+        let mut any_moved = false;
+        for i in 0..extents.len() {
+            for (axis, c) in centroid.iter().enumerate() {
+                let offset = c - extent_center(&extents[i])[axis];
+                if offset.abs() < 1.0 {
+                    continue;
+                }
+
+                let step = with_component(&[0, 0, 0].into(), axis, offset.signum() as i32);
+                let moved = extents[i] + step;
+                let overlaps = extents
+                    .iter()
+                    .enumerate()
+                    .any(|(j, other)| j != i && !moved.intersection(other).is_empty());
+                if !overlaps {
+                    extents[i] = moved;
+                    any_moved = true;
+                }
+            }
+        }
+
+        if !any_moved {
+            break;
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn assert_pieces_tile_wall_shell(extent: Extent, thickness: i32) {
+        let faces = wall_face_slabs(&extent, thickness);
+        let edges = wall_edge_prisms(&extent, thickness);
+        let corners = wall_corner_blocks(&extent, thickness);
+        assert_eq!((faces.len(), edges.len(), corners.len()), (6, 12, 8));
+
+        let mut covered: HashMap<Point, usize> = HashMap::new();
+        let pieces = faces
+            .iter()
+            .map(|(_, f)| f)
+            .chain(edges.iter().map(|(_, e)| e))
+            .chain(corners.iter().map(|(_, c)| c));
+        for piece in pieces {
+            for p in piece {
+                *covered.entry(p).or_insert(0) += 1;
+            }
+        }
+
+        let (min, sup) = (extent.get_minimum(), extent.get_world_supremum());
+        let in_shell = |p: &Point| {
+            (0..3).any(|a| {
+                let c = component(p, a);
+                c - component(min, a) < thickness || component(&sup, a) - 1 - c < thickness
+            })
+        };
+        for p in &extent {
+            let expected = if in_shell(&p) { 1 } else { 0 };
+            assert_eq!(covered.get(&p).cloned().unwrap_or(0), expected, "{:?}", p);
+        }
+        assert!(covered.keys().all(|p| extent.contains_world(p)));
+    }
+
+    #[test]
+    fn test_wall_pieces_tile_the_wall_shell() {
+        let min = [3, -2, 5].into();
+        assert_pieces_tile_wall_shell(
+            Extent::from_min_and_local_supremum(min, [10, 8, 12].into()),
+            2,
+        );
+        // No interior along Z.
+        assert_pieces_tile_wall_shell(
+            Extent::from_min_and_local_supremum(min, [6, 9, 4].into()),
+            2,
+        );
+        // Thinner than the walls.
+        assert_pieces_tile_wall_shell(
+            Extent::from_min_and_local_supremum(min, [3, 3, 3].into()),
+            2,
+        );
+        assert_pieces_tile_wall_shell(
+            Extent::from_min_and_local_supremum(min, [1, 5, 5].into()),
+            2,
+        );
+    }
+
+    #[test]
+    fn test_wall_pieces_face_out_of_the_extent() {
+        let extent = Extent::from_min_and_local_supremum([0, 0, 0].into(), [10, 8, 12].into());
+
+        let faces = wall_face_slabs(&extent, 2);
+        let ceiling = faces.iter().find(|(d, _)| *d == Direction::PosY).unwrap().1;
+        assert_eq!(
+            ceiling,
+            Extent::from_min_and_local_supremum([2, 6, 2].into(), [6, 2, 8].into())
+        );
+
+        let edges = wall_edge_prisms(&extent, 2);
+        let (_, floor_edge) = edges
+            .iter()
+            .find(|(d, _)| d.contains(&Direction::NegX) && d.contains(&Direction::NegY))
+            .unwrap();
+        assert_eq!(
+            *floor_edge,
+            Extent::from_min_and_local_supremum([0, 0, 2].into(), [2, 2, 8].into())
+        );
+
+        let corners = wall_corner_blocks(&extent, 2);
+        let (_, top_corner) = corners
+            .iter()
+            .find(|(d, _)| *d == [Direction::PosX, Direction::PosY, Direction::PosZ])
+            .unwrap();
+        assert_eq!(
+            *top_corner,
+            Extent::from_min_and_local_supremum([8, 6, 10].into(), [2, 2, 2].into())
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    room::{Room, EMPTY_VOXEL, WALL_THICKNESS},
+    Voxel, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Balconies that look down into the room below through an opening in the floor.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GallerySpec {
+    /// The chance that a room stacked on top of a hall overlooks it.
+    pub probability: f32,
+    /// The opening must be at least this wide along X and Z.
+    pub min_opening_width: u32,
+    /// The floor left between the railing and the walls of the upper room.
+    pub walkway_width: u32,
+    pub railing_height: u32,
+}
+
+/// An opening through the floor of one room and the ceiling of the room below it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Gallery {
+    /// Spans the walls of both rooms.
+    pub opening: Extent,
+    /// The strips standing on the upper floor around the opening.
+    pub railings: Vec<Extent>,
+}
+
+/// Cuts an opening from `upper` down into `lower` where a part of `upper` sits directly on top of
+/// a part of `lower`, with a railing around it. Openings and railings stay clear of `doors`.
+/// Returns `None` if the chance fails or no opening is wide enough; if several pairs of parts are
+/// stacked, the widest opening is used.
+pub fn try_generate_gallery(
+    upper: &Room,
+    lower: &Room,
+    doors: &[Extent],
+    spec: &GallerySpec,
+    rng: &mut impl Rng,
+) -> Option<Gallery> {
+    if rng.gen::<f32>() >= spec.probability {
+        return None;
+    }
+
+    let area = |e: &Extent| {
+        let sup = e.get_local_supremum();
+        sup.x * sup.z
+    };
+
+    upper
+        .parts
+        .iter()
+        .flat_map(|a| {
+            lower
+                .parts
+                .iter()
+                .filter_map(move |b| opening_between(a, b, spec))
+        })
+        .filter(|opening| {
+            let grown = opening.radial_grow(1);
+            doors.iter().all(|d| grown.intersection(d).is_empty())
+        })
+        .fold(None, |best: Option<Extent>, candidate| match best {
+            Some(b) if area(&b) >= area(&candidate) => Some(b),
+            _ => Some(candidate),
+        })
+        .map(|opening| Gallery {
+            opening,
+            railings: railings_around(&opening, spec.railing_height as i32),
+        })
+}
+
+/// The opening between `upper` on top of `lower`, leaving room for the walkway and railing inside
+/// the interior of `upper`.
+fn opening_between(upper: &Extent, lower: &Extent, spec: &GallerySpec) -> Option<Extent> {
+    let floor_y = upper.get_minimum().y;
+    if floor_y != lower.get_world_supremum().y {
+        return None;
+    }
+
+    let (upper_interior, lower_interior) = (
+        upper.radial_grow(-WALL_THICKNESS),
+        lower.radial_grow(-WALL_THICKNESS),
+    );
+    if upper_interior.is_empty() || lower_interior.is_empty() {
+        return None;
+    }
+    let inset = spec.walkway_width as i32 + 1;
+    let (u_min, u_sup) = (
+        *upper_interior.get_minimum() + [inset, 0, inset].into(),
+        upper_interior.get_world_supremum() - [inset, 0, inset].into(),
+    );
+    let (l_min, l_sup) = (
+        lower_interior.get_minimum(),
+        lower_interior.get_world_supremum(),
+    );
+    let min = Point {
+        x: u_min.x.max(l_min.x),
+        y: floor_y - WALL_THICKNESS,
+        z: u_min.z.max(l_min.z),
+    };
+    let sup = Point {
+        x: u_sup.x.min(l_sup.x),
+        y: floor_y + WALL_THICKNESS,
+        z: u_sup.z.min(l_sup.z),
+    };
+    let min_width = spec.min_opening_width.max(1) as i32;
+    if sup.x - min.x < min_width || sup.z - min.z < min_width {
+        return None;
+    }
+
+    Some(Extent::from_min_and_world_supremum(min, sup))
+}
+
+/// A ring of strips one voxel thick around the top of `opening`, `height` voxels tall.
+fn railings_around(opening: &Extent, height: i32) -> Vec<Extent> {
+    if height <= 0 {
+        return Vec::new();
+    }
+
+    let (min, sup) = (opening.get_minimum(), opening.get_world_supremum());
+    let (y, top) = (sup.y, sup.y + height);
+    let strip =
+        |min: [i32; 3], sup: [i32; 3]| Extent::from_min_and_world_supremum(min.into(), sup.into());
+
+    vec![
+        strip([min.x - 1, y, min.z - 1], [sup.x + 1, top, min.z]),
+        strip([min.x - 1, y, sup.z], [sup.x + 1, top, sup.z + 1]),
+        strip([min.x - 1, y, min.z], [min.x, top, sup.z]),
+        strip([sup.x, y, min.z], [sup.x + 1, top, sup.z]),
+    ]
+}
+
+/// Empties each opening and writes its railings with `railing_voxel_type`.
+pub fn fill_map_with_galleries(
+    galleries: &[Gallery],
+    railing_voxel_type: u8,
+    encoder: &mut impl VoxelEncoder,
+) {
+    let railing = Voxel {
+        distance: -1.0,
+        voxel_type: railing_voxel_type,
+    };
+    for g in galleries.iter() {
+        for p in &g.opening {
+            encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            encoder.encode_flags(&p, VoxelFlags::NONE);
+        }
+        for r in g.railings.iter() {
+            for p in r {
+                encoder.encode_voxel(&p, &railing);
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::small_rng;
+
+    #[test]
+    fn test_opening_cuts_through_both_walls_inside_railing() {
+        let lower = Extent::from_min_and_local_supremum([0, 0, 0].into(), [30, 20, 30].into());
+        let upper = Extent::from_min_and_local_supremum([0, 20, 0].into(), [30, 20, 30].into());
+        let spec = GallerySpec {
+            probability: 1.0,
+            min_opening_width: 4,
+            walkway_width: 3,
+            railing_height: 2,
+        };
+        let gallery = try_generate_gallery(
+            &Room::from(upper),
+            &Room::from(lower),
+            &[],
+            &spec,
+            &mut small_rng([1; 4]),
+        )
+        .unwrap();
+
+        // Interiors span [5, 25) horizontally, less the walkway and railing.
+        assert_eq!(
+            gallery.opening,
+            Extent::from_min_and_world_supremum([9, 15, 9].into(), [21, 25, 21].into())
+        );
+        let railing_voxels: usize = gallery.railings.iter().map(|r| r.into_iter().count()).sum();
+        assert_eq!(railing_voxels, 2 * (4 * 12 + 4));
+        for r in gallery.railings.iter() {
+            assert!(r.intersection(&gallery.opening).is_empty());
+            assert!(r.is_subset(&upper.radial_grow(-WALL_THICKNESS)));
+        }
+
+        // Not stacked, or blocked by a door.
+        assert!(try_generate_gallery(
+            &Room::from(lower),
+            &Room::from(upper),
+            &[],
+            &spec,
+            &mut small_rng([1; 4])
+        )
+        .is_none());
+        let door = Extent::from_min_and_local_supremum([20, 19, 20].into(), [2, 2, 2].into());
+        assert!(try_generate_gallery(
+            &Room::from(upper),
+            &Room::from(lower),
+            &[door],
+            &spec,
+            &mut small_rng([1; 4])
+        )
+        .is_none());
+    }
+}
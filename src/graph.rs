@@ -1,11 +1,13 @@
 use petgraph::{
-    algo::tarjan_scc,
-    graph::NodeIndex,
+    algo::{min_spanning_tree, tarjan_scc},
+    data::FromElements,
+    graph::{EdgeIndex, NodeIndex},
     stable_graph::StableGraph,
-    visit::{depth_first_search, Control, DfsEvent, EdgeRef},
+    visit::{depth_first_search, Control, DfsEvent, EdgeRef, NodeIndexable},
     EdgeType, Undirected,
 };
-use std::collections::HashSet;
+use rand::{seq::SliceRandom, Rng};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 use std::iter::FromIterator;
 
 pub fn induced_subgraph<N: Clone, E: Clone, Ty: EdgeType, S: std::hash::BuildHasher>(
@@ -50,7 +52,7 @@ where
 
 /// Removes nodes with the fewest edges until the desired number of nodes is reached.
 /// `accept_fn` allows external constraints to be enforced, preventing certain nodes from being
-/// removed.
+/// removed. Gives up if no more nodes can be removed, leaving the graph larger than desired.
 pub fn prune_outer_nodes_to_reach_size<N: Clone, E: Clone>(
     graph: &mut StableGraph<N, E, Undirected>,
     accept_fn: impl Fn(&StableGraph<N, E, Undirected>) -> bool,
@@ -97,8 +99,498 @@ pub fn prune_outer_nodes_to_reach_size<N: Clone, E: Clone>(
 
         if !removed_nodes {
             max_edges_per_removed_node += 1;
+            // No node has enough edges to be considered anymore, so nothing else can be removed.
+            let max_degree = graph
+                .node_indices()
+                .map(|n| graph.edges(n).count())
+                .max()
+                .unwrap_or(0);
+            if max_edges_per_removed_node > max_degree {
+                return;
+            }
+        }
+    }
+}
+
+/// Returns the node with the most edges, preferring the lowest index on ties.
+pub fn node_with_max_degree<N, E>(graph: &StableGraph<N, E, Undirected>) -> Option<NodeIndex> {
+    graph
+        .node_indices()
+        .max_by_key(|n| (graph.edges(*n).count(), std::cmp::Reverse(*n)))
+}
+
+/// Returns the edges whose removal would disconnect their endpoints from each other, in index
+/// order. Uses Tarjan's low-link depth-first search, so parallel edges are never bridges.
+pub fn bridges<N, E>(graph: &StableGraph<N, E, Undirected>) -> Vec<EdgeIndex> {
+    let unvisited = usize::MAX;
+    let mut order = vec![unvisited; graph.node_bound()];
+    let mut low = vec![unvisited; graph.node_bound()];
+    let mut next_order = 0;
+    let mut found = Vec::new();
+    for root in graph.node_indices() {
+        if order[root.index()] != unvisited {
+            continue;
+        }
+        order[root.index()] = next_order;
+        low[root.index()] = next_order;
+        next_order += 1;
+        // Each node on the stack keeps the edge it was entered by and the edges left to follow.
+        let mut stack = vec![(root, EdgeIndex::end(), graph.edges(root))];
+        while let Some((n, entered_by, next_edge)) = stack
+            .last_mut()
+            .map(|(n, entered_by, edges)| (*n, *entered_by, edges.next()))
+        {
+            match next_edge {
+                Some(e) if e.id() == entered_by => (),
+                Some(e) => {
+                    let other = if e.source() == n {
+                        e.target()
+                    } else {
+                        e.source()
+                    };
+                    if order[other.index()] == unvisited {
+                        order[other.index()] = next_order;
+                        low[other.index()] = next_order;
+                        next_order += 1;
+                        stack.push((other, e.id(), graph.edges(other)));
+                    } else {
+                        low[n.index()] = low[n.index()].min(order[other.index()]);
+                    }
+                }
+                None => {
+                    stack.pop();
+                    if let Some((parent, _, _)) = stack.last() {
+                        low[parent.index()] = low[parent.index()].min(low[n.index()]);
+                        if low[n.index()] > order[parent.index()] {
+                            found.push(entered_by);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    found.sort_unstable();
+
+    found
+}
+
+/// Labels each node of `graph` with its component once `bridges` are removed, indexed by node
+/// index. Holes in the indices get `usize::MAX`.
+fn components_without_bridges<N, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    bridges: &[EdgeIndex],
+) -> Vec<usize> {
+    let bridges: HashSet<EdgeIndex> = bridges.iter().cloned().collect();
+    let mut component = vec![usize::MAX; graph.node_bound()];
+    let mut num_components = 0;
+    for start in graph.node_indices() {
+        if component[start.index()] != usize::MAX {
+            continue;
+        }
+        component[start.index()] = num_components;
+        let mut stack = vec![start];
+        while let Some(n) = stack.pop() {
+            for e in graph.edges(n).filter(|e| !bridges.contains(&e.id())) {
+                let other = if e.source() == n {
+                    e.target()
+                } else {
+                    e.source()
+                };
+                if component[other.index()] == usize::MAX {
+                    component[other.index()] = num_components;
+                    stack.push(other);
+                }
+            }
+        }
+        num_components += 1;
+    }
+
+    component
+}
+
+/// Returns the nodes whose removal would disconnect some of their neighbors from each other.
+pub fn articulation_points<N, E>(graph: &StableGraph<N, E, Undirected>) -> Vec<NodeIndex> {
+    graph
+        .node_indices()
+        .filter(|n| {
+            let mut neighbors = graph.neighbors(*n);
+            let first = match neighbors.next() {
+                Some(first) => first,
+                None => return false,
+            };
+            let reached = reachable_without_node(graph, first, *n);
+
+            neighbors.any(|other| !reached.contains(&other))
+        })
+        .collect()
+}
+
+/// Returns the number of shortest paths between other pairs of nodes that pass through each node,
+/// with paths split evenly among ties. Parallel edges count once. Uses Brandes' algorithm.
+pub fn betweenness_centrality<N, E>(
+    graph: &StableGraph<N, E, Undirected>,
+) -> HashMap<NodeIndex, f32> {
+    let mut centrality: HashMap<NodeIndex, f32> = graph.node_indices().map(|n| (n, 0.0)).collect();
+    for source in graph.node_indices() {
+        // Breadth-first search, counting the shortest paths to each node.
+        let mut visited = Vec::new();
+        let mut predecessors: HashMap<NodeIndex, Vec<NodeIndex>> = HashMap::new();
+        let mut num_paths: HashMap<NodeIndex, f32> = HashMap::new();
+        let mut depth: HashMap<NodeIndex, usize> = HashMap::new();
+        num_paths.insert(source, 1.0);
+        depth.insert(source, 0);
+        let mut queue = VecDeque::new();
+        queue.push_back(source);
+        while let Some(n) = queue.pop_front() {
+            visited.push(n);
+            let neighbors: HashSet<NodeIndex> = graph.neighbors(n).collect();
+            for other in neighbors.into_iter() {
+                if !depth.contains_key(&other) {
+                    depth.insert(other, depth[&n] + 1);
+                    queue.push_back(other);
+                }
+                if depth[&other] == depth[&n] + 1 {
+                    *num_paths.entry(other).or_insert(0.0) += num_paths[&n];
+                    predecessors.entry(other).or_default().push(n);
+                }
+            }
+        }
+
+        // Accumulate back from the farthest nodes.
+        let mut dependency: HashMap<NodeIndex, f32> = HashMap::new();
+        for n in visited.into_iter().rev() {
+            let d = dependency.get(&n).cloned().unwrap_or(0.0);
+            for p in predecessors.get(&n).into_iter().flatten() {
+                *dependency.entry(*p).or_insert(0.0) += num_paths[p] / num_paths[&n] * (1.0 + d);
+            }
+            if n != source {
+                *centrality.get_mut(&n).unwrap() += d;
+            }
+        }
+    }
+
+    // Each path was counted from both of its ends.
+    for c in centrality.values_mut() {
+        *c /= 2.0;
+    }
+
+    centrality
+}
+
+/// Returns the nodes that can be reached from `start` without passing through `node`, including
+/// `start`.
+pub fn reachable_without_node<N, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    start: NodeIndex,
+    node: NodeIndex,
+) -> HashSet<NodeIndex> {
+    let mut reached = HashSet::new();
+    reached.insert(start);
+    let mut stack = vec![start];
+    while let Some(n) = stack.pop() {
+        for other in graph.neighbors(n).filter(|other| *other != node) {
+            if reached.insert(other) {
+                stack.push(other);
+            }
+        }
+    }
+
+    reached
+}
+
+/// Chooses edges from `candidates` to add to `graph` so that none of its edges are bridges, as far
+/// as the candidates allow. A candidate can join nodes that already share an edge, which adds a
+/// parallel edge.
+///
+/// The bridges join the components that are already 2-edge-connected into a tree, and each leaf of
+/// that tree can only be covered by an edge out of it. So each round chooses candidates between
+/// two leaves first, then out of the leaves that are left, using each leaf once and preferring
+/// candidates that aren't parallel to an edge. Once no leaf can be covered, any candidate across a
+/// bridge is chosen. Rounds repeat on the new bridge tree.
+pub fn two_edge_connecting_edges<N, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    candidates: &[(NodeIndex, NodeIndex)],
+) -> Vec<(NodeIndex, NodeIndex)> {
+    let mut augmented = graph.map(|_, _| (), |_, _| ());
+    let mut remaining = candidates.to_vec();
+    let mut chosen = Vec::new();
+    loop {
+        let bridges = bridges(&augmented);
+        if bridges.is_empty() {
+            break;
+        }
+        let component = components_without_bridges(&augmented, &bridges);
+        let mut degree: HashMap<usize, usize> = HashMap::new();
+        for e in bridges.iter() {
+            let (a, b) = augmented.edge_endpoints(*e).expect("Edge must exist");
+            *degree.entry(component[a.index()]).or_insert(0) += 1;
+            *degree.entry(component[b.index()]).or_insert(0) += 1;
+        }
+        let leaf = |n: &NodeIndex| {
+            let c = component[n.index()];
+            if degree.get(&c) == Some(&1) {
+                Some(c)
+            } else {
+                None
+            }
+        };
+        let crosses =
+            |(x, y): &(NodeIndex, NodeIndex)| component[x.index()] != component[y.index()];
+
+        // A parallel edge only covers the one bridge beside it, so the others go first.
+        let mut order: Vec<usize> = (0..remaining.len()).collect();
+        order.sort_by_key(|i| augmented.contains_edge(remaining[*i].0, remaining[*i].1));
+
+        let mut covered = HashSet::new();
+        let mut take = vec![false; remaining.len()];
+        for &i in order.iter() {
+            let (x, y) = &remaining[i];
+            if let (Some(cx), Some(cy)) = (leaf(x), leaf(y)) {
+                if cx != cy && !covered.contains(&cx) && !covered.contains(&cy) {
+                    covered.insert(cx);
+                    covered.insert(cy);
+                    take[i] = true;
+                }
+            }
+        }
+        for &i in order.iter() {
+            let edge = &remaining[i];
+            if take[i] || !crosses(edge) {
+                continue;
+            }
+            let uncovered = leaf(&edge.0)
+                .filter(|c| !covered.contains(c))
+                .or_else(|| leaf(&edge.1).filter(|c| !covered.contains(c)));
+            if let Some(c) = uncovered {
+                covered.insert(c);
+                take[i] = true;
+            }
+        }
+        if !take.contains(&true) {
+            match remaining.iter().position(crosses) {
+                Some(i) => take[i] = true,
+                None => break,
+            }
+        }
+
+        let mut take = take.into_iter();
+        let (added, rest): (Vec<_>, Vec<_>) = remaining
+            .into_iter()
+            .partition(|_| take.next().expect("One flag per candidate"));
+        remaining = rest;
+        for (x, y) in added.into_iter() {
+            augmented.add_edge(x, y, ());
+            chosen.push((x, y));
+        }
+    }
+
+    chosen
+}
+
+/// Returns the nodes that can be reached from `start` without crossing `edge`, including `start`.
+pub fn reachable_without_edge<N, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    start: NodeIndex,
+    edge: EdgeIndex,
+) -> HashSet<NodeIndex> {
+    let mut reached = HashSet::new();
+    reached.insert(start);
+    let mut stack = vec![start];
+    while let Some(n) = stack.pop() {
+        for e in graph.edges(n).filter(|e| e.id() != edge) {
+            let other = if e.source() == n {
+                e.target()
+            } else {
+                e.source()
+            };
+            if reached.insert(other) {
+                stack.push(other);
+            }
+        }
+    }
+
+    reached
+}
+
+/// Returns the tree of shortest paths from `hub`, keeping only the nodes within `max_depth` edges of
+/// it. Node indices are preserved, so the result may have holes.
+pub fn hub_and_spoke_tree<N: Clone, E: Clone>(
+    graph: &StableGraph<N, E, Undirected>,
+    hub: NodeIndex,
+    max_depth: usize,
+) -> StableGraph<N, E, Undirected> {
+    let mut depths = HashMap::new();
+    let mut tree_edges = HashSet::new();
+    let mut queue = VecDeque::new();
+    depths.insert(hub, 0);
+    queue.push_back(hub);
+    while let Some(n) = queue.pop_front() {
+        let depth = depths[&n];
+        if depth >= max_depth {
+            continue;
+        }
+        for e in graph.edges(n) {
+            let other = if e.source() == n {
+                e.target()
+            } else {
+                e.source()
+            };
+            if let Entry::Vacant(v) = depths.entry(other) {
+                v.insert(depth + 1);
+                tree_edges.insert(e.id());
+                queue.push_back(other);
+            }
+        }
+    }
+
+    graph.filter_map(
+        |i, n| {
+            if depths.contains_key(&i) {
+                Some(n.clone())
+            } else {
+                None
+            }
+        },
+        |i, e| {
+            if tree_edges.contains(&i) {
+                Some(e.clone())
+            } else {
+                None
+            }
+        },
+    )
+}
+
+/// Grows up to `count` paths out from the nodes of `trunk`, each through a number of other nodes
+/// sampled from `min_len..=max_len`, or at least `min_len` where it runs out of nodes to grow into.
+/// No node is in more than one path or in `trunk`, and paths only step from `a` to `b` if
+/// `allowed(a, b)`. Each path starts with the node of `trunk` that it grows from.
+pub fn grow_branches<N, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    trunk: &[NodeIndex],
+    count: usize,
+    min_len: usize,
+    max_len: usize,
+    allowed: impl Fn(NodeIndex, NodeIndex) -> bool,
+    rng: &mut impl Rng,
+) -> Vec<Vec<NodeIndex>> {
+    let min_len = min_len.max(1);
+    let max_len = max_len.max(min_len);
+    let mut used: HashSet<NodeIndex> = trunk.iter().cloned().collect();
+    let mut branches = Vec::new();
+    while branches.len() < count {
+        let target = rng.gen_range(min_len, max_len + 1);
+        let mut starts: Vec<(NodeIndex, NodeIndex)> = trunk
+            .iter()
+            .flat_map(|t| graph.neighbors(*t).map(move |n| (*t, n)))
+            .filter(|(t, n)| !used.contains(n) && allowed(*t, *n))
+            .collect();
+        starts.shuffle(rng);
+
+        let branch = starts.into_iter().find_map(|(t, n)| {
+            let mut branch = vec![t, n];
+            while branch.len() <= target {
+                let last = *branch.last().unwrap();
+                let next: Vec<NodeIndex> = graph
+                    .neighbors(last)
+                    .filter(|m| !used.contains(m) && !branch.contains(m) && allowed(last, *m))
+                    .collect();
+                match next.choose(rng) {
+                    Some(m) => branch.push(*m),
+                    None => break,
+                }
+            }
+
+            if branch.len() > min_len {
+                Some(branch)
+            } else {
+                None
+            }
+        });
+        match branch {
+            Some(b) => {
+                used.extend(b.iter().cloned());
+                branches.push(b);
+            }
+            None => break,
         }
     }
+
+    branches
+}
+
+/// The spanning tree of `graph` with the least total `weight`, or the most if `maximum`. Node
+/// indices are not preserved.
+pub fn weighted_spanning_tree<N: Clone, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    weight: impl Fn(EdgeIndex) -> f32,
+    maximum: bool,
+) -> StableGraph<N, (), Undirected> {
+    let weighted = graph.map(
+        |_, n| n.clone(),
+        |e, _| if maximum { -weight(e) } else { weight(e) },
+    );
+    let tree: StableGraph<N, f32, Undirected> =
+        StableGraph::from_elements(min_spanning_tree(&weighted));
+
+    tree.map(|_, n| n.clone(), |_, _| ())
+}
+
+/// A spanning tree of `graph` chosen uniformly at random from all of them, with Wilson's algorithm.
+/// Each component of a disconnected graph gets its own tree. Node indices are not preserved.
+pub fn random_spanning_tree<N: Clone, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    rng: &mut impl Rng,
+) -> StableGraph<N, (), Undirected> {
+    let mut in_tree = HashSet::new();
+    let mut tree_edges = HashSet::new();
+    for start in graph.node_indices() {
+        if in_tree.contains(&start) {
+            continue;
+        }
+        let component = reachable_without_edge(graph, start, EdgeIndex::end());
+        if !component.iter().any(|n| in_tree.contains(n)) {
+            // The root of this component's tree.
+            in_tree.insert(start);
+            continue;
+        }
+
+        // Random walk until the tree is hit. Remembering only the last edge out of each node
+        // erases the loops.
+        let mut next_edge = HashMap::new();
+        let mut n = start;
+        while !in_tree.contains(&n) {
+            let edges: Vec<_> = graph.edges(n).collect();
+            let e = edges.choose(rng).expect("Component has other nodes");
+            next_edge.insert(n, e.id());
+            n = if e.source() == n {
+                e.target()
+            } else {
+                e.source()
+            };
+        }
+
+        let mut n = start;
+        while !in_tree.contains(&n) {
+            in_tree.insert(n);
+            let e = next_edge[&n];
+            tree_edges.insert(e);
+            let (a, b) = graph.edge_endpoints(e).expect("Edge must exist");
+            n = if a == n { b } else { a };
+        }
+    }
+
+    let mut tree = StableGraph::default();
+    let nodes: HashMap<NodeIndex, NodeIndex> = graph
+        .node_indices()
+        .map(|n| (n, tree.add_node(graph[n].clone())))
+        .collect();
+    for e in graph.edge_indices().filter(|e| tree_edges.contains(e)) {
+        let (a, b) = graph.edge_endpoints(e).expect("Edge must exist");
+        tree.add_edge(nodes[&a], nodes[&b], ());
+    }
+
+    tree
 }
 
 /// Assumes `graph` is a tree.
@@ -153,3 +645,340 @@ pub fn longest_path_in_tree<N, E>(graph: &StableGraph<N, E, Undirected>) -> Vec<
 
     longest_path_to_point_in_tree(graph, *path.first().expect("Must have at least one node"))
 }
+
+/// `longest_simple_path` stops searching for a longer path after this many steps.
+const LONGEST_PATH_SEARCH_STEPS: usize = 100_000;
+
+/// Returns a longest path in `graph` that doesn't visit any node twice, even if `graph` has cycles.
+/// Trees are left to `longest_path_in_tree`. Otherwise, a greedy walk from each node finds a long
+/// path, and an exhaustive search then looks for a longer one. The search gives up after
+/// `LONGEST_PATH_SEARCH_STEPS`, so the result is only guaranteed to be the longest for small graphs.
+pub fn longest_simple_path<N, E>(graph: &StableGraph<N, E, Undirected>) -> Vec<NodeIndex> {
+    let is_tree = match graph.node_indices().next() {
+        Some(first) => {
+            graph.edge_count() + 1 == graph.node_count()
+                && reachable_without_edge(graph, first, EdgeIndex::end()).len()
+                    == graph.node_count()
+        }
+        None => false,
+    };
+    if is_tree {
+        return longest_path_in_tree(graph);
+    }
+
+    let mut search = LongestPathSearch {
+        graph,
+        visited: vec![false; graph.node_bound()],
+        path: Vec::new(),
+        longest: Vec::new(),
+        steps: 0,
+    };
+    for start in graph.node_indices() {
+        let walk = search.greedy_walk(start);
+        if walk.len() > search.longest.len() {
+            search.longest = walk;
+        }
+    }
+    for start in graph.node_indices() {
+        if search.longest.len() == graph.node_count() || search.steps > LONGEST_PATH_SEARCH_STEPS {
+            break;
+        }
+        search.extend(start);
+    }
+
+    search.longest
+}
+
+struct LongestPathSearch<'a, N, E> {
+    graph: &'a StableGraph<N, E, Undirected>,
+    visited: Vec<bool>,
+    path: Vec<NodeIndex>,
+    longest: Vec<NodeIndex>,
+    steps: usize,
+}
+
+impl<'a, N, E> LongestPathSearch<'a, N, E> {
+    fn unvisited_neighbors(&self, n: NodeIndex) -> Vec<NodeIndex> {
+        let mut neighbors: Vec<NodeIndex> = self
+            .graph
+            .neighbors(n)
+            .filter(|m| !self.visited[m.index()])
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+
+        neighbors
+    }
+
+    /// Walks from `start` to the unvisited neighbor with the fewest ways onward, so dead ends are
+    /// visited before they're cut off.
+    fn greedy_walk(&mut self, start: NodeIndex) -> Vec<NodeIndex> {
+        let mut walk = vec![start];
+        self.visited[start.index()] = true;
+        let mut n = start;
+        loop {
+            let next = self
+                .unvisited_neighbors(n)
+                .into_iter()
+                .min_by_key(|m| self.unvisited_neighbors(*m).len());
+            match next {
+                Some(m) => {
+                    self.visited[m.index()] = true;
+                    walk.push(m);
+                    n = m;
+                }
+                None => break,
+            }
+        }
+        for n in walk.iter() {
+            self.visited[n.index()] = false;
+        }
+
+        walk
+    }
+
+    /// Tries every path that continues from `n`, keeping the longest.
+    fn extend(&mut self, n: NodeIndex) {
+        self.steps += 1;
+        if self.steps > LONGEST_PATH_SEARCH_STEPS {
+            return;
+        }
+
+        self.visited[n.index()] = true;
+        self.path.push(n);
+        if self.path.len() > self.longest.len() {
+            self.longest = self.path.clone();
+        }
+        for m in self.unvisited_neighbors(n) {
+            if self.longest.len() == self.graph.node_count() {
+                break;
+            }
+            self.extend(m);
+        }
+        self.path.pop();
+        self.visited[n.index()] = false;
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::small_rng;
+    use petgraph::visit::IntoEdgeReferences;
+
+    /// A `size` by `size` grid of nodes, weighted by their position, with both diagonals of each
+    /// square.
+    fn grid_with_diagonals(size: i32) -> StableGraph<(i32, i32), (), Undirected> {
+        let mut graph = StableGraph::default();
+        let mut nodes = HashMap::new();
+        for x in 0..size {
+            for y in 0..size {
+                nodes.insert((x, y), graph.add_node((x, y)));
+            }
+        }
+        for x in 0..size {
+            for y in 0..size {
+                for (dx, dy) in &[(1, 0), (0, 1), (1, 1), (1, -1)] {
+                    if let Some(other) = nodes.get(&(x + dx, y + dy)) {
+                        graph.add_edge(nodes[&(x, y)], *other, ());
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    #[test]
+    fn test_random_spanning_tree_spans_the_graph() {
+        let graph = grid_with_diagonals(3);
+        let edges: HashSet<_> = graph
+            .edge_references()
+            .map(|e| (graph[e.source()], graph[e.target()]))
+            .collect();
+        for seed in 0..20 {
+            let tree = random_spanning_tree(&graph, &mut small_rng([seed; 4]));
+
+            assert_eq!(tree.node_count(), graph.node_count());
+            assert_eq!(tree.edge_count(), graph.node_count() - 1);
+            let start = tree.node_indices().next().unwrap();
+            assert_eq!(
+                reachable_without_edge(&tree, start, EdgeIndex::end()).len(),
+                tree.node_count()
+            );
+            for e in tree.edge_references() {
+                let (a, b) = (tree[e.source()], tree[e.target()]);
+                assert!(edges.contains(&(a, b)) || edges.contains(&(b, a)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_grow_branches_off_the_trunk() {
+        let graph = grid_with_diagonals(6);
+        let trunk: Vec<NodeIndex> = graph.node_indices().filter(|n| graph[*n].1 == 0).collect();
+        for seed in 0..10 {
+            let branches = grow_branches(
+                &graph,
+                &trunk,
+                3,
+                2,
+                4,
+                |_, _| true,
+                &mut small_rng([seed; 4]),
+            );
+
+            assert_eq!(branches.len(), 3);
+            let max_depth = branches.iter().map(|b| b.len() - 1).max().unwrap();
+            assert!(max_depth <= 4);
+            let mut nodes: HashSet<NodeIndex> = trunk.iter().cloned().collect();
+            let mut tree = StableGraph::<(), (), Undirected>::default();
+            let tree_nodes: HashMap<NodeIndex, NodeIndex> = graph
+                .node_indices()
+                .map(|n| (n, tree.add_node(())))
+                .collect();
+            for path in std::iter::once(&trunk).chain(branches.iter()) {
+                for w in path.windows(2) {
+                    assert!(graph.find_edge(w[0], w[1]).is_some());
+                    tree.add_edge(tree_nodes[&w[0]], tree_nodes[&w[1]], ());
+                }
+            }
+            for branch in branches.iter() {
+                assert!(trunk.contains(&branch[0]));
+                assert!((2..=4).contains(&(branch.len() - 1)));
+                for n in branch[1..].iter() {
+                    assert!(nodes.insert(*n), "Branches must not share nodes");
+                }
+            }
+            tree.retain_nodes(|_, n| nodes.contains(&n));
+            assert_eq!(tree.edge_count() + 1, tree.node_count());
+            let start = tree.node_indices().next().unwrap();
+            assert_eq!(
+                reachable_without_edge(&tree, start, EdgeIndex::end()).len(),
+                tree.node_count()
+            );
+        }
+    }
+
+    fn cycle(size: usize) -> (StableGraph<(), (), Undirected>, Vec<NodeIndex>) {
+        let mut graph = StableGraph::default();
+        let nodes: Vec<NodeIndex> = (0..size).map(|_| graph.add_node(())).collect();
+        for i in 0..size {
+            graph.add_edge(nodes[i], nodes[(i + 1) % size], ());
+        }
+
+        (graph, nodes)
+    }
+
+    /// Every pair of nodes, as candidates.
+    fn all_pairs(nodes: &[NodeIndex]) -> Vec<(NodeIndex, NodeIndex)> {
+        nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| nodes[i + 1..].iter().map(move |b| (*a, *b)))
+            .collect()
+    }
+
+    fn with_edges(
+        graph: &StableGraph<(), (), Undirected>,
+        edges: &[(NodeIndex, NodeIndex)],
+    ) -> StableGraph<(), (), Undirected> {
+        let mut graph = graph.clone();
+        for (a, b) in edges {
+            graph.add_edge(*a, *b, ());
+        }
+
+        graph
+    }
+
+    #[test]
+    fn test_bridges_of_a_path() {
+        let (mut graph, nodes) = cycle(6);
+        let closing = graph.find_edge(nodes[5], nodes[0]).unwrap();
+        graph.remove_edge(closing);
+
+        let mut expected: Vec<EdgeIndex> = graph.edge_indices().collect();
+        expected.sort_unstable();
+        assert_eq!(bridges(&graph), expected);
+
+        let added = two_edge_connecting_edges(&graph, &all_pairs(&nodes));
+        assert_eq!(added.len(), 1);
+        assert!(bridges(&with_edges(&graph, &added)).is_empty());
+
+        // Nothing crosses the last edge.
+        let candidates: Vec<_> = all_pairs(&nodes[..5]);
+        let added = two_edge_connecting_edges(&graph, &candidates);
+        let left = bridges(&with_edges(&graph, &added));
+        assert_eq!(left, vec![graph.find_edge(nodes[4], nodes[5]).unwrap()]);
+    }
+
+    #[test]
+    fn test_bridges_of_a_cycle() {
+        let (graph, nodes) = cycle(6);
+
+        assert!(bridges(&graph).is_empty());
+        assert!(two_edge_connecting_edges(&graph, &all_pairs(&nodes)).is_empty());
+    }
+
+    #[test]
+    fn test_bridges_of_two_cycles_joined_by_a_bridge() {
+        let (mut graph, mut nodes) = cycle(4);
+        let others: Vec<NodeIndex> = (0..4).map(|_| graph.add_node(())).collect();
+        for i in 0..4 {
+            graph.add_edge(others[i], others[(i + 1) % 4], ());
+        }
+        let bridge = graph.add_edge(nodes[0], others[0], ());
+        nodes.extend(others.iter().cloned());
+
+        assert_eq!(bridges(&graph), vec![bridge]);
+
+        let added = two_edge_connecting_edges(&graph, &all_pairs(&nodes));
+        assert_eq!(added.len(), 1);
+        assert!(bridges(&with_edges(&graph, &added)).is_empty());
+
+        // A parallel edge is enough too.
+        let added = two_edge_connecting_edges(&graph, &[(nodes[0], others[0])]);
+        assert_eq!(added, vec![(nodes[0], others[0])]);
+        assert!(bridges(&with_edges(&graph, &added)).is_empty());
+    }
+
+    fn assert_simple_path<N, E>(graph: &StableGraph<N, E, Undirected>, path: &[NodeIndex]) {
+        assert_eq!(path.iter().collect::<HashSet<_>>().len(), path.len());
+        for w in path.windows(2) {
+            assert!(graph.find_edge(w[0], w[1]).is_some());
+        }
+    }
+
+    #[test]
+    fn test_longest_simple_path_through_a_cycle() {
+        // A 5-cycle with a tail on one of its nodes. Going around the cycle from the tail visits
+        // every node, while any spanning tree's longest path misses at least one.
+        let mut graph = StableGraph::<(), (), Undirected>::default();
+        let n: Vec<_> = (0..6).map(|_| graph.add_node(())).collect();
+        for (a, b) in &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 0), (0, 5)] {
+            graph.add_edge(n[*a], n[*b], ());
+        }
+        let path = longest_simple_path(&graph);
+        assert_eq!(path.len(), 6);
+        assert_simple_path(&graph, &path);
+
+        // A triangle with two tails on one corner and one on another. Only one of the two tails
+        // on the same corner can be on a simple path.
+        let mut graph = StableGraph::<(), (), Undirected>::default();
+        let n: Vec<_> = (0..6).map(|_| graph.add_node(())).collect();
+        for (a, b) in &[(0, 1), (1, 2), (2, 0), (0, 3), (0, 4), (1, 5)] {
+            graph.add_edge(n[*a], n[*b], ());
+        }
+        let path = longest_simple_path(&graph);
+        assert_eq!(path.len(), 5);
+        assert_simple_path(&graph, &path);
+        assert!(path.contains(&n[5]));
+    }
+}
@@ -0,0 +1,626 @@
+use ilattice3::Extent;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LayoutDoor {
+    /// Indices into `Layout::rooms`, smallest first.
+    pub rooms: (usize, usize),
+    pub extent: Extent,
+}
+
+impl LayoutDoor {
+    pub fn new(room1: usize, room2: usize, extent: Extent) -> Self {
+        LayoutDoor {
+            rooms: (room1.min(room2), room1.max(room2)),
+            extent,
+        }
+    }
+}
+
+/// The rooms and doors of a generated map, independent of the voxels that were written for them.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Layout {
+    pub rooms: Vec<Extent>,
+    pub doors: Vec<LayoutDoor>,
+}
+
+fn extent_key(e: &Extent) -> [i32; 6] {
+    let min = e.get_minimum();
+    let sup = e.get_local_supremum();
+
+    [min.x, min.y, min.z, sup.x, sup.y, sup.z]
+}
+
+/// Identifies a door by its own extent and the extents of the rooms it joins, so doors can be
+/// matched between layouts whose room indices differ.
+fn door_key(layout: &Layout, door: &LayoutDoor) -> ([i32; 6], [i32; 6], [i32; 6]) {
+    let r1 = extent_key(&layout.rooms[door.rooms.0]);
+    let r2 = extent_key(&layout.rooms[door.rooms.1]);
+
+    (extent_key(&door.extent), r1.min(r2), r1.max(r2))
+}
+
+impl Layout {
+    /// Returns an equivalent layout where rooms are sorted by position, door room indices are
+    /// remapped accordingly, and doors are sorted. Two layouts with the same geometry have equal
+    /// canonical forms regardless of the order in which they were generated.
+    pub fn canonicalize(&self) -> Layout {
+        let mut order: Vec<usize> = (0..self.rooms.len()).collect();
+        order.sort_by_key(|i| extent_key(&self.rooms[*i]));
+        let mut new_index = vec![0; self.rooms.len()];
+        for (new, old) in order.iter().enumerate() {
+            new_index[*old] = new;
+        }
+
+        let rooms = order.iter().map(|i| self.rooms[*i]).collect();
+        let mut doors: Vec<_> = self
+            .doors
+            .iter()
+            .map(|d| LayoutDoor::new(new_index[d.rooms.0], new_index[d.rooms.1], d.extent))
+            .collect();
+        doors.sort_by_key(|d| (d.rooms, extent_key(&d.extent)));
+
+        Layout { rooms, doors }
+    }
+
+    /// The first door between rooms `a` and `b`, in either order.
+    pub fn door_between(&self, a: usize, b: usize) -> Option<&LayoutDoor> {
+        self.doors_between(a, b).next()
+    }
+
+    /// Every door between rooms `a` and `b`, in either order.
+    pub fn doors_between(&self, a: usize, b: usize) -> impl Iterator<Item = &LayoutDoor> {
+        let rooms = (a.min(b), a.max(b));
+
+        self.doors.iter().filter(move |d| d.rooms == rooms)
+    }
+
+    /// A stable text serialization of the canonical layout, suitable for snapshot tests.
+    pub fn to_snapshot(&self) -> String {
+        ron::ser::to_string_pretty(&self.canonicalize(), ron::ser::PrettyConfig::new())
+            .expect("Failed to serialize layout")
+    }
+
+    pub fn from_snapshot(snapshot: &str) -> Result<Layout, ron::Error> {
+        ron::de::from_str(snapshot)
+    }
+
+    /// Returns what changed going from `self` to `other`.
+    pub fn diff(&self, other: &Layout) -> LayoutDiff {
+        let (old, new) = (self.canonicalize(), other.canonicalize());
+
+        let old_room_keys: Vec<_> = old.rooms.iter().map(extent_key).collect();
+        let new_room_keys: Vec<_> = new.rooms.iter().map(extent_key).collect();
+        let old_door_keys: Vec<_> = old.doors.iter().map(|d| door_key(&old, d)).collect();
+        let new_door_keys: Vec<_> = new.doors.iter().map(|d| door_key(&new, d)).collect();
+
+        LayoutDiff {
+            removed_rooms: old
+                .rooms
+                .iter()
+                .filter(|r| !new_room_keys.contains(&extent_key(r)))
+                .cloned()
+                .collect(),
+            added_rooms: new
+                .rooms
+                .iter()
+                .filter(|r| !old_room_keys.contains(&extent_key(r)))
+                .cloned()
+                .collect(),
+            removed_doors: old
+                .doors
+                .iter()
+                .filter(|d| !new_door_keys.contains(&door_key(&old, d)))
+                .map(|d| d.extent)
+                .collect(),
+            added_doors: new
+                .doors
+                .iter()
+                .filter(|d| !old_door_keys.contains(&door_key(&new, d)))
+                .map(|d| d.extent)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LayoutDiff {
+    pub removed_rooms: Vec<Extent>,
+    pub added_rooms: Vec<Extent>,
+    pub removed_doors: Vec<Extent>,
+    pub added_doors: Vec<Extent>,
+}
+
+impl LayoutDiff {
+    pub fn is_empty(&self) -> bool {
+        self.removed_rooms.is_empty()
+            && self.added_rooms.is_empty()
+            && self.removed_doors.is_empty()
+            && self.added_doors.is_empty()
+    }
+}
+
+fn write_extent(f: &mut fmt::Formatter, prefix: &str, kind: &str, e: &Extent) -> fmt::Result {
+    let min = e.get_minimum();
+    let sup = e.get_local_supremum();
+
+    writeln!(
+        f,
+        "{} {} at [{}, {}, {}] size [{}, {}, {}]",
+        prefix, kind, min.x, min.y, min.z, sup.x, sup.y, sup.z
+    )
+}
+
+impl fmt::Display for LayoutDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "layouts are identical");
+        }
+        for r in self.removed_rooms.iter() {
+            write_extent(f, "-", "room", r)?;
+        }
+        for r in self.added_rooms.iter() {
+            write_extent(f, "+", "room", r)?;
+        }
+        for d in self.removed_doors.iter() {
+            write_extent(f, "-", "door", d)?;
+        }
+        for d in self.added_doors.iter() {
+            write_extent(f, "+", "door", d)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Structural metrics of a layout, for comparing the effects of spec changes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayoutSummary {
+    pub num_rooms: usize,
+    pub num_doors: usize,
+    /// `degree_histogram[d]` is the number of rooms with `d` doors.
+    pub degree_histogram: Vec<usize>,
+    /// The most doors on the shortest path between any two connected rooms.
+    pub longest_path: usize,
+    /// The mean number of doors on the shortest path between two connected rooms.
+    pub mean_path_length: f32,
+    pub total_room_volume: i64,
+}
+
+/// Two layouts' summaries, along with the geometric differences between them.
+#[derive(Clone, Debug, Default)]
+pub struct LayoutComparison {
+    pub before: LayoutSummary,
+    pub after: LayoutSummary,
+    pub diff: LayoutDiff,
+}
+
+/// The rooms of a layout as the nodes of a graph, with an edge between each pair of rooms that
+/// share doors.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RoomGraph {
+    /// Indexed like `Layout::rooms`.
+    pub nodes: Vec<Extent>,
+    /// Sorted by `rooms`.
+    pub edges: Vec<RoomGraphEdge>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RoomGraphEdge {
+    /// Indices into `RoomGraph::nodes`, smallest first.
+    pub rooms: (usize, usize),
+    /// Indices into `Layout::doors`, in order.
+    pub doors: Vec<usize>,
+}
+
+impl RoomGraph {
+    pub fn neighbors(&self, room: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges.iter().filter_map(move |e| match e.rooms {
+            (a, b) if a == room => Some(b),
+            (a, b) if b == room => Some(a),
+            _ => None,
+        })
+    }
+
+    /// The graph in the Graphviz DOT language. Nodes are labeled with their room's extent, and
+    /// edges with their doors.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph rooms {\n");
+        for (i, e) in self.nodes.iter().enumerate() {
+            let min = e.get_minimum();
+            let sup = e.get_local_supremum();
+            dot.push_str(&format!(
+                "    {} [label=\"{}: [{}, {}, {}] size [{}, {}, {}]\"];\n",
+                i, i, min.x, min.y, min.z, sup.x, sup.y, sup.z
+            ));
+        }
+        for e in self.edges.iter() {
+            let doors: Vec<String> = e.doors.iter().map(|d| d.to_string()).collect();
+            dot.push_str(&format!(
+                "    {} -- {} [label=\"{}\"];\n",
+                e.rooms.0,
+                e.rooms.1,
+                doors.join(", ")
+            ));
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+impl Layout {
+    pub fn room_graph(&self) -> RoomGraph {
+        let mut edges: Vec<RoomGraphEdge> = Vec::new();
+        for (i, d) in self.doors.iter().enumerate() {
+            match edges.iter_mut().find(|e| e.rooms == d.rooms) {
+                Some(e) => e.doors.push(i),
+                None => edges.push(RoomGraphEdge {
+                    rooms: d.rooms,
+                    doors: vec![i],
+                }),
+            }
+        }
+        edges.sort_by_key(|e| e.rooms);
+
+        RoomGraph {
+            nodes: self.rooms.clone(),
+            edges,
+        }
+    }
+
+    /// For each room, the indices of the rooms it shares a door with.
+    fn adjacency(&self) -> Vec<Vec<usize>> {
+        let mut adjacency = vec![Vec::new(); self.rooms.len()];
+        for d in self.doors.iter() {
+            adjacency[d.rooms.0].push(d.rooms.1);
+            adjacency[d.rooms.1].push(d.rooms.0);
+        }
+
+        adjacency
+    }
+
+    pub fn summary(&self) -> LayoutSummary {
+        let adjacency = self.adjacency();
+
+        let mut degree_histogram = Vec::new();
+        for neighbors in adjacency.iter() {
+            if degree_histogram.len() <= neighbors.len() {
+                degree_histogram.resize(neighbors.len() + 1, 0);
+            }
+            degree_histogram[neighbors.len()] += 1;
+        }
+
+        // Breadth-first search from every room. Layouts are small enough for this to be cheap.
+        let mut longest_path = 0;
+        let (mut path_sum, mut num_paths) = (0, 0);
+        for start in 0..self.rooms.len() {
+            let mut distance = vec![None; self.rooms.len()];
+            distance[start] = Some(0);
+            let mut queue = VecDeque::new();
+            queue.push_back(start);
+            while let Some(room) = queue.pop_front() {
+                let d = distance[room].unwrap();
+                for n in adjacency[room].iter() {
+                    if distance[*n].is_none() {
+                        distance[*n] = Some(d + 1);
+                        queue.push_back(*n);
+                    }
+                }
+            }
+            for d in distance.iter().skip(start + 1).flatten() {
+                longest_path = longest_path.max(*d);
+                path_sum += *d;
+                num_paths += 1;
+            }
+        }
+
+        LayoutSummary {
+            num_rooms: self.rooms.len(),
+            num_doors: self.doors.len(),
+            degree_histogram,
+            longest_path,
+            mean_path_length: if num_paths == 0 {
+                0.0
+            } else {
+                path_sum as f32 / num_paths as f32
+            },
+            total_room_volume: self
+                .rooms
+                .iter()
+                .map(|r| {
+                    let sup = r.get_local_supremum();
+
+                    sup.x as i64 * sup.y as i64 * sup.z as i64
+                })
+                .sum(),
+        }
+    }
+
+    /// Compares the structure of `self` to `other`.
+    pub fn compare(&self, other: &Layout) -> LayoutComparison {
+        LayoutComparison {
+            before: self.summary(),
+            after: other.summary(),
+            diff: self.diff(other),
+        }
+    }
+
+    /// A seeded random walk through up to `num_steps` doors, as the rooms visited starting with
+    /// `start`. The walk only goes straight back to the room it just left from a dead end, so it
+    /// suits wandering NPCs and camera paths.
+    pub fn random_walk(&self, start: usize, num_steps: usize, rng: &mut impl Rng) -> Vec<usize> {
+        let adjacency = self.adjacency();
+        let mut walk = vec![start];
+        let mut previous = None;
+        for _ in 0..num_steps {
+            let room = *walk.last().unwrap();
+            let onward: Vec<usize> = adjacency[room]
+                .iter()
+                .cloned()
+                .filter(|n| Some(*n) != previous)
+                .collect();
+            let next = match onward.choose(rng).or_else(|| adjacency[room].first()) {
+                Some(n) => *n,
+                None => break,
+            };
+            previous = Some(room);
+            walk.push(next);
+        }
+
+        walk
+    }
+
+    /// A seeded tour from `start` through every door reachable from it, as the rooms visited
+    /// starting with `start`. The tour goes through a random untraveled door whenever it can, which
+    /// never backtracks. Otherwise, it takes the shortest route to the nearest room with an
+    /// untraveled door, so a bot following the tour sees the whole map without much retracing.
+    pub fn door_tour(&self, start: usize, rng: &mut impl Rng) -> Vec<usize> {
+        let mut doors_of = vec![Vec::new(); self.rooms.len()];
+        for (i, d) in self.doors.iter().enumerate() {
+            doors_of[d.rooms.0].push(i);
+            doors_of[d.rooms.1].push(i);
+        }
+        let through = |door: usize, room: usize| {
+            let (a, b) = self.doors[door].rooms;
+            if a == room {
+                b
+            } else {
+                a
+            }
+        };
+
+        let mut traveled = vec![false; self.doors.len()];
+        let mut tour = vec![start];
+        loop {
+            let room = *tour.last().unwrap();
+            let untraveled: Vec<usize> = doors_of[room]
+                .iter()
+                .cloned()
+                .filter(|d| !traveled[*d])
+                .collect();
+            if let Some(door) = untraveled.choose(rng) {
+                traveled[*door] = true;
+                tour.push(through(*door, room));
+                continue;
+            }
+
+            // Every room on the way has only traveled doors, or it would be the nearest.
+            let mut parent = vec![None; self.rooms.len()];
+            let mut queue = VecDeque::new();
+            parent[room] = Some(room);
+            queue.push_back(room);
+            let mut target = None;
+            while let Some(r) = queue.pop_front() {
+                if doors_of[r].iter().any(|d| !traveled[*d]) {
+                    target = Some(r);
+                    break;
+                }
+                for d in doors_of[r].iter() {
+                    let n = through(*d, r);
+                    if parent[n].is_none() {
+                        parent[n] = Some(r);
+                        queue.push_back(n);
+                    }
+                }
+            }
+
+            let mut r = match target {
+                Some(r) => r,
+                None => break,
+            };
+            let mut route = Vec::new();
+            while r != room {
+                route.push(r);
+                r = parent[r].unwrap();
+            }
+            tour.extend(route.into_iter().rev());
+        }
+
+        tour
+    }
+}
+
+impl fmt::Display for LayoutComparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (b, a) = (&self.before, &self.after);
+        let counts = [
+            ("rooms", b.num_rooms as i64, a.num_rooms as i64),
+            ("doors", b.num_doors as i64, a.num_doors as i64),
+            ("longest path", b.longest_path as i64, a.longest_path as i64),
+            ("room volume", b.total_room_volume, a.total_room_volume),
+        ];
+        for (name, before, after) in counts.iter() {
+            writeln!(
+                f,
+                "{}: {} -> {} ({:+})",
+                name,
+                before,
+                after,
+                after - before
+            )?;
+        }
+        writeln!(
+            f,
+            "mean path length: {:.2} -> {:.2} ({:+.2})",
+            b.mean_path_length,
+            a.mean_path_length,
+            a.mean_path_length - b.mean_path_length
+        )?;
+        writeln!(
+            f,
+            "degree histogram: {:?} -> {:?}",
+            b.degree_histogram, a.degree_histogram
+        )?;
+        write!(f, "{}", self.diff)
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+/// A layout of `num_rooms` 4 voxel cubes in a row along X, with a door for each pair in `doors`.
+/// Each door is its own cube past the end of the row.
+#[cfg(test)]
+pub(crate) fn row_layout(num_rooms: usize, doors: &[(usize, usize)]) -> Layout {
+    let cube = |x| Extent::from_min_and_local_supremum([x, 0, 0].into(), [4, 4, 4].into());
+    let doors_start = 4 * num_rooms as i32;
+
+    Layout {
+        rooms: (0..num_rooms as i32).map(|i| cube(4 * i)).collect(),
+        doors: doors
+            .iter()
+            .enumerate()
+            .map(|(i, (a, b))| LayoutDoor::new(*a, *b, cube(doors_start + 4 * i as i32)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::small_rng;
+
+    fn cube(x: i32) -> Extent {
+        Extent::from_min_and_local_supremum([x, 0, 0].into(), [4, 4, 4].into())
+    }
+
+    #[test]
+    fn test_canonical_layout_ignores_generation_order() {
+        let door = cube(100);
+        let layout1 = Layout {
+            rooms: vec![cube(0), cube(4), cube(8)],
+            doors: vec![LayoutDoor::new(0, 1, door)],
+        };
+        let layout2 = Layout {
+            rooms: vec![cube(8), cube(4), cube(0)],
+            doors: vec![LayoutDoor::new(2, 1, door)],
+        };
+
+        assert_eq!(layout1.to_snapshot(), layout2.to_snapshot());
+        assert!(layout1.diff(&layout2).is_empty());
+        assert_eq!(
+            Layout::from_snapshot(&layout1.to_snapshot()).unwrap(),
+            layout1.canonicalize()
+        );
+    }
+
+    #[test]
+    fn test_room_graph_merges_doors_between_the_same_rooms() {
+        let layout = Layout {
+            rooms: vec![cube(0), cube(4), cube(8)],
+            doors: vec![
+                LayoutDoor::new(2, 1, cube(100)),
+                LayoutDoor::new(0, 1, cube(104)),
+                LayoutDoor::new(1, 2, cube(108)),
+            ],
+        };
+
+        let graph = layout.room_graph();
+
+        assert_eq!(graph.nodes, layout.rooms);
+        let edges: Vec<((usize, usize), Vec<usize>)> = graph
+            .edges
+            .iter()
+            .map(|e| (e.rooms, e.doors.clone()))
+            .collect();
+        assert_eq!(edges, vec![((0, 1), vec![1]), ((1, 2), vec![0, 2])]);
+        assert_eq!(graph.neighbors(1).collect::<Vec<_>>(), vec![0, 2]);
+        assert!(graph.to_dot().contains("    1 -- 2 [label=\"0, 2\"];\n"));
+    }
+
+    #[test]
+    fn test_layout_diff_reports_changes() {
+        let door = cube(100);
+        let old = Layout {
+            rooms: vec![cube(0), cube(4)],
+            doors: vec![LayoutDoor::new(0, 1, door)],
+        };
+        let new = Layout {
+            rooms: vec![cube(0), cube(5)],
+            doors: vec![],
+        };
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.removed_rooms, vec![cube(4)]);
+        assert_eq!(diff.added_rooms, vec![cube(5)]);
+        assert_eq!(diff.removed_doors, vec![door]);
+        assert!(diff.added_doors.is_empty());
+    }
+
+    #[test]
+    fn test_door_tour_goes_through_every_door() {
+        // A loop of 4 rooms with a dead end hanging off of room 2.
+        let layout = Layout {
+            rooms: (0..5).map(|i| cube(4 * i)).collect(),
+            doors: vec![
+                LayoutDoor::new(0, 1, cube(100)),
+                LayoutDoor::new(1, 2, cube(104)),
+                LayoutDoor::new(2, 3, cube(108)),
+                LayoutDoor::new(3, 0, cube(112)),
+                LayoutDoor::new(2, 4, cube(116)),
+            ],
+        };
+        let has_door =
+            |a: usize, b: usize| layout.doors.iter().any(|d| d.rooms == (a.min(b), a.max(b)));
+
+        let tour = layout.door_tour(0, &mut small_rng([1, 2, 3, 4]));
+        assert_eq!(tour[0], 0);
+        assert!(tour.windows(2).all(|w| has_door(w[0], w[1])));
+        for d in layout.doors.iter() {
+            assert!(tour
+                .windows(2)
+                .any(|w| (w[0].min(w[1]), w[0].max(w[1])) == d.rooms));
+        }
+
+        let walk = layout.random_walk(0, 20, &mut small_rng([1, 2, 3, 4]));
+        assert_eq!(walk.len(), 21);
+        assert!(walk.windows(2).all(|w| has_door(w[0], w[1])));
+        // Only room 4 is a dead end.
+        assert!(walk.windows(3).all(|w| w[0] != w[2] || w[1] == 4));
+    }
+
+    #[test]
+    fn test_layout_summary_of_chain() {
+        let layout = Layout {
+            rooms: vec![cube(0), cube(4), cube(8)],
+            doors: vec![
+                LayoutDoor::new(0, 1, cube(100)),
+                LayoutDoor::new(1, 2, cube(104)),
+            ],
+        };
+
+        let summary = layout.summary();
+        assert_eq!(summary.degree_histogram, vec![0, 2, 1]);
+        assert_eq!(summary.longest_path, 2);
+        // Paths of 1, 1, and 2 doors.
+        assert!((summary.mean_path_length - 4.0 / 3.0).abs() < 1e-6);
+        assert_eq!(summary.total_room_volume, 3 * 64);
+    }
+}
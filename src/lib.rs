@@ -1,26 +1,325 @@
+pub mod atmosphere;
+pub mod audio;
+pub mod balcony;
+pub mod camera;
+pub mod channel;
+pub mod climbing;
+pub mod corridor;
+pub mod decoration;
+#[cfg(feature = "dev-tools")]
+pub mod dev_tools;
+pub mod difficulty;
+pub mod encoder;
 pub mod extent;
+pub mod gallery;
 pub mod graph;
+pub mod layout;
+pub mod lighting;
 pub mod map_types;
+pub mod mission;
+pub mod movement;
+pub mod noise;
+pub mod pass;
+pub mod prefab;
+pub mod prelude;
+pub mod progression;
 pub mod room;
 pub mod sampling;
+pub mod sdf;
+pub mod stairs;
+pub mod stats;
+pub mod symmetric_map;
+pub mod visibility;
+pub mod wfc;
 
-mod symmetric_map;
+mod occupancy;
 
 use ilattice3::Point;
 use serde::{Deserialize, Serialize};
+use std::ops::{BitOr, BitOrAssign};
 
 /// Implement this to allow the procedural generation algorithms to write into your voxel map.
 pub trait VoxelEncoder {
-    /// `data` is the voxel data to write into `point`.
+    /// `data` is the voxel data to write into `point` of the `VoxelLayer::Structure` layer.
     fn encode_voxel(&mut self, point: &Point, data: &Voxel);
+
+    /// Writes `data` into `point` of `layer`, for engines with layered voxel storage. Writes to
+    /// `VoxelLayer::Structure` must behave like `encode_voxel`. By default, every layer is merged
+    /// into the structure.
+    fn encode_layer_voxel(&mut self, _layer: VoxelLayer, point: &Point, data: &Voxel) {
+        self.encode_voxel(point, data);
+    }
+
+    /// `flags` describe the gameplay semantics of the voxel at `point`. Called after the voxel at
+    /// `point` has been encoded. Ignored by default.
+    fn encode_flags(&mut self, _point: &Point, _flags: VoxelFlags) {}
+}
+
+/// Reads back voxels that were already encoded, so late passes can check the geometry that earlier
+/// passes actually wrote rather than the extents they were given.
+pub trait VoxelSource {
+    /// The voxel last written into `point` of `layer`, if any.
+    fn get_voxel(&self, layer: VoxelLayer, point: &Point) -> Option<Voxel>;
+
+    /// The flags last encoded for the `VoxelLayer::Structure` voxel at `point`, if known, so passes
+    /// that rewrite a voxel can keep them. Unknown by default.
+    fn get_flags(&self, _point: &Point) -> Option<VoxelFlags> {
+        None
+    }
+}
+
+/// A layer of voxel storage that generation passes write to.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum VoxelLayer {
+    /// The walls and floors, and the empty space carved out of them.
+    Structure,
+    /// Props that stand in or hang into the empty space, like pillars, crates and stalactites.
+    Decoration,
+    Liquid,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SpawnArea {
     pub valid_spawn_points: Vec<Point>,
+    /// Which way characters should face when they spawn, if the map type has a suggestion.
+    #[serde(default)]
+    pub facing: Option<SpawnFacing>,
+}
+
+/// Horizontal unit vectors for spawned characters to face, so they aren't staring at a wall.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum SpawnFacing {
+    /// Every point faces the same way.
+    Area([f32; 3]),
+    /// One direction for each of the valid spawn points, in the same order.
+    PerPoint(Vec<[f32; 3]>),
+}
+
+impl SpawnArea {
+    /// The direction to face when spawning at `valid_spawn_points[i]`.
+    pub fn facing_at(&self, i: usize) -> Option<[f32; 3]> {
+        match self.facing.as_ref()? {
+            SpawnFacing::Area(f) => Some(*f),
+            SpawnFacing::PerPoint(fs) => fs.get(i).cloned(),
+        }
+    }
+
+    /// Makes each point face horizontally toward `target`. Points directly above or below
+    /// `target` face the same way as the area's centroid does, if it isn't also directly above.
+    pub fn facing_toward(mut self, target: &[f32; 3]) -> SpawnArea {
+        let toward = |p: [f32; 3]| {
+            let (dx, dz) = (target[0] - p[0], target[2] - p[2]);
+            let len = (dx * dx + dz * dz).sqrt();
+
+            if len > 0.0 {
+                Some([dx / len, 0.0, dz / len])
+            } else {
+                None
+            }
+        };
+        let center = |p: &Point| [p.x as f32 + 0.5, p.y as f32 + 0.5, p.z as f32 + 0.5];
+
+        let n = self.valid_spawn_points.len().max(1) as f32;
+        let centroid = self
+            .valid_spawn_points
+            .iter()
+            .map(center)
+            .fold([0.0; 3], |c, p| {
+                [c[0] + p[0] / n, c[1] + p[1] / n, c[2] + p[2] / n]
+            });
+        let fallback = if let Some(f) = toward(centroid) {
+            f
+        } else {
+            return self;
+        };
+
+        self.facing = Some(SpawnFacing::PerPoint(
+            self.valid_spawn_points
+                .iter()
+                .map(|p| toward(center(p)).unwrap_or(fallback))
+                .collect(),
+        ));
+
+        self
+    }
+}
+
+/// Requirements on where a spawn area may be, e.g. so a generated map lines up with a fixed
+/// entrance in the overworld.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpawnConstraint {
+    pub anchor: Point,
+    /// Maximum Euclidean distance of a spawn point from `anchor`.
+    pub max_distance: f32,
+    pub min_y: i32,
+}
+
+impl SpawnConstraint {
+    pub fn accepts_point(&self, p: &Point) -> bool {
+        let d = *p - self.anchor;
+        let dist_sq = (d.x as f32).powi(2) + (d.y as f32).powi(2) + (d.z as f32).powi(2);
+
+        p.y >= self.min_y && dist_sq <= self.max_distance * self.max_distance
+    }
+
+    /// Removes the points that violate the constraint. Returns `None` if no points remain.
+    pub fn restrict(&self, area: SpawnArea) -> Option<SpawnArea> {
+        let keep: Vec<bool> = area
+            .valid_spawn_points
+            .iter()
+            .map(|p| self.accepts_point(p))
+            .collect();
+        let valid_spawn_points: Vec<_> = area
+            .valid_spawn_points
+            .into_iter()
+            .zip(keep.iter())
+            .filter_map(|(p, k)| if *k { Some(p) } else { None })
+            .collect();
+        let facing = area.facing.map(|f| match f {
+            SpawnFacing::PerPoint(fs) => SpawnFacing::PerPoint(
+                fs.into_iter()
+                    .zip(keep.iter())
+                    .filter_map(|(f, k)| if *k { Some(f) } else { None })
+                    .collect(),
+            ),
+            area_facing => area_facing,
+        });
+
+        if valid_spawn_points.is_empty() {
+            None
+        } else {
+            Some(SpawnArea {
+                valid_spawn_points,
+                facing,
+            })
+        }
+    }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct Voxel {
     pub distance: f32,
     pub voxel_type: u8,
 }
+
+/// Semantic properties of a voxel, so engines don't need to infer them from `voxel_type`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct VoxelFlags(pub u8);
+
+impl VoxelFlags {
+    pub const NONE: VoxelFlags = VoxelFlags(0);
+    /// A solid voxel whose top face can be stood on.
+    pub const WALKABLE: VoxelFlags = VoxelFlags(1);
+    pub const CLIMBABLE: VoxelFlags = VoxelFlags(1 << 1);
+    pub const DESTRUCTIBLE: VoxelFlags = VoxelFlags(1 << 2);
+    pub const WATER: VoxelFlags = VoxelFlags(1 << 3);
+    pub const TRIGGER: VoxelFlags = VoxelFlags(1 << 4);
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: VoxelFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: VoxelFlags) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: VoxelFlags) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for VoxelFlags {
+    type Output = VoxelFlags;
+
+    fn bitor(self, other: VoxelFlags) -> VoxelFlags {
+        VoxelFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for VoxelFlags {
+    fn bitor_assign(&mut self, other: VoxelFlags) {
+        self.insert(other);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_facing_eq(actual: Option<[f32; 3]>, expected: [f32; 3]) {
+        let actual = actual.unwrap();
+        for i in 0..3 {
+            assert!((actual[i] - expected[i]).abs() < 1e-6, "{:?}", actual);
+        }
+    }
+
+    #[test]
+    fn test_facing_toward_target() {
+        let area = SpawnArea {
+            valid_spawn_points: vec![[0, 0, 0].into(), [4, 0, 6].into(), [10, 3, 0].into()],
+            facing: None,
+        }
+        .facing_toward(&[10.5, 0.0, 0.5]);
+
+        assert_facing_eq(area.facing_at(0), [1.0, 0.0, 0.0]);
+        let diagonal = 0.5f32.sqrt();
+        assert_facing_eq(area.facing_at(1), [diagonal, 0.0, -diagonal]);
+        // Directly above the target, so it faces the way the centroid does.
+        let (dx, dz): (f32, f32) = (10.5 - 15.5 / 3.0, 0.5 - 2.5);
+        let len = (dx * dx + dz * dz).sqrt();
+        assert_facing_eq(area.facing_at(2), [dx / len, 0.0, dz / len]);
+    }
+
+    #[test]
+    fn test_facing_toward_target_above_centroid_changes_nothing() {
+        let area = SpawnArea {
+            valid_spawn_points: vec![[0, 0, 0].into()],
+            facing: None,
+        }
+        .facing_toward(&[0.5, 10.0, 0.5]);
+
+        assert_eq!(area.facing, None);
+    }
+
+    #[test]
+    fn test_restrict_keeps_facing_of_kept_points() {
+        let constraint = SpawnConstraint {
+            anchor: [0, 0, 0].into(),
+            max_distance: 5.0,
+            min_y: 0,
+        };
+        let area = SpawnArea {
+            valid_spawn_points: vec![[0, 0, 0].into(), [9, 0, 0].into(), [0, 0, 3].into()],
+            facing: Some(SpawnFacing::PerPoint(vec![
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [-1.0, 0.0, 0.0],
+            ])),
+        };
+
+        let restricted = constraint.restrict(area).unwrap();
+
+        assert_eq!(
+            restricted.valid_spawn_points,
+            vec![[0, 0, 0].into(), [0, 0, 3].into()]
+        );
+        assert_eq!(
+            restricted.facing,
+            Some(SpawnFacing::PerPoint(vec![
+                [1.0, 0.0, 0.0],
+                [-1.0, 0.0, 0.0]
+            ]))
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{
+    extent::{component, with_component},
+    room::{Room, WALL_THICKNESS},
+    Voxel, VoxelEncoder, VoxelLayer,
+};
+
+use fnv::FnvHashSet;
+use ilattice3::{
+    normal::{Direction, Normal},
+    Extent, Point,
+};
+use serde::{Deserialize, Serialize};
+
+/// Torches and sconces along the walls of each room.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LightingSpec {
+    /// The distance between neighboring lights on the same wall.
+    pub spacing: u32,
+    /// How far above the floor the lights hang.
+    pub height: u32,
+    /// Lights stay at least this far from doors, windows and other obstacles.
+    pub clearance: u32,
+    /// If set, each light is written into the decoration layer with this voxel type, e.g. one that
+    /// the engine renders as emissive. Otherwise the lights are only metadata.
+    #[serde(default)]
+    pub voxel_type: Option<u8>,
+}
+
+/// Where a light entity should be spawned.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LightSource {
+    /// The empty voxel against the wall.
+    pub point: Point,
+    /// Away from the wall, into the room.
+    pub facing: Direction,
+}
+
+/// Places lights `spec.spacing` apart along the inside of every wall of `room`, centered on each
+/// wall, `spec.height` above the floor of the part the wall belongs to. Lights aren't placed within
+/// `spec.clearance` of `obstacles`, or where the part opens into another part of the room.
+pub fn lights_for_room(room: &Room, obstacles: &[Extent], spec: &LightingSpec) -> Vec<LightSource> {
+    let spacing = spec.spacing.max(1) as i32;
+    let grown_obstacles: Vec<Extent> = obstacles
+        .iter()
+        .map(|o| o.radial_grow(spec.clearance as i32))
+        .collect();
+
+    let mut seen = FnvHashSet::default();
+    let mut lights = Vec::new();
+    for part in room.parts.iter() {
+        let interior = part.radial_grow(-WALL_THICKNESS);
+        if interior.is_empty() {
+            continue;
+        }
+        let (min, sup) = (interior.get_minimum(), interior.get_world_supremum());
+        let y = min.y + spec.height as i32;
+        if y >= sup.y {
+            continue;
+        }
+
+        let walls = [
+            (0, component(min, 0), Direction::PosX),
+            (0, component(&sup, 0) - 1, Direction::NegX),
+            (2, component(min, 2), Direction::PosZ),
+            (2, component(&sup, 2) - 1, Direction::NegZ),
+        ];
+        for (axis, row, facing) in walls.iter() {
+            let along = 2 - axis;
+            let (along_min, along_sup) = (component(min, along), component(&sup, along));
+            let num_lights = (along_sup - along_min) / spacing;
+            if num_lights == 0 {
+                continue;
+            }
+            let first = along_min + (along_sup - along_min - 1 - (num_lights - 1) * spacing) / 2;
+            let behind = Point::from(Normal::Axis(*facing)) * -1;
+
+            for i in 0..num_lights {
+                let p = with_component(
+                    &with_component(&with_component(min, 1, y), *axis, *row),
+                    along,
+                    first + i * spacing,
+                );
+                let against_wall = !room.interior_contains(&(p + behind));
+                let blocked = grown_obstacles.iter().any(|o| o.contains_world(&p));
+                if against_wall && !blocked && seen.insert(p) {
+                    lights.push(LightSource {
+                        point: p,
+                        facing: *facing,
+                    });
+                }
+            }
+        }
+    }
+
+    lights
+}
+
+pub fn fill_map_with_lights(
+    lights: &[LightSource],
+    voxel_type: u8,
+    encoder: &mut impl VoxelEncoder,
+) {
+    let light = Voxel {
+        distance: -1.0,
+        voxel_type,
+    };
+    for l in lights.iter() {
+        encoder.encode_layer_voxel(VoxelLayer::Decoration, &l.point, &light);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lights_line_the_walls_away_from_doors() {
+        let extent = Extent::from_min_and_local_supremum([0, 0, 0].into(), [30, 20, 30].into());
+        let room = Room::from(extent);
+        let door = Extent::from_min_and_local_supremum([3, 5, 12].into(), [2, 6, 6].into());
+        let spec = LightingSpec {
+            spacing: 6,
+            height: 4,
+            clearance: 1,
+            voxel_type: None,
+        };
+
+        let lights = lights_for_room(&room, &[door], &spec);
+
+        // Three lights per 20 voxel wall, less the one next to the door.
+        assert_eq!(lights.len(), 4 * 3 - 1);
+        for l in lights.iter() {
+            assert_eq!(l.point.y, 9);
+            assert!(room.interior_contains(&l.point));
+            let behind = l.point - Point::from(Normal::Axis(l.facing));
+            assert!(!room.interior_contains(&behind));
+            assert!(!door.radial_grow(1).contains_world(&l.point));
+        }
+    }
+}
@@ -1 +1,12 @@
+pub mod bsp;
+pub mod castle;
+pub mod catacomb;
+pub mod cave;
+pub mod city;
 pub mod dungeon;
+pub mod islands;
+pub mod mine;
+pub mod sewer;
+pub mod terrain;
+pub mod tunnel;
+pub mod wfc;
@@ -0,0 +1,280 @@
+use crate::{
+    corridor::{try_route_corridor, Corridor, CorridorSpec},
+    extent::{component, with_component},
+    room::{
+        fill_map_with_doors, fill_map_with_rooms, spawn_in_room,
+        try_generate_door_big_enough_between_rooms,
+    },
+    sampling::{retry_with_derived_seeds, small_rng, SubspanSampler},
+    SpawnArea, VoxelEncoder,
+};
+
+use ilattice3::{Extent, Point};
+use rand::{prelude::*, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BspDungeonMeta {
+    pub spawn_area: SpawnArea,
+    pub rooms: Vec<Extent>,
+    pub doors: Vec<Extent>,
+    pub corridors: Vec<Corridor>,
+}
+
+/// Generates a dungeon by recursively splitting a bounding extent with axis-aligned planes. Every
+/// leaf of the partition becomes a room, so the rooms tile the bounds, and the two sides of every
+/// split are connected by a door across the split plane.
+///
+/// With `corridors`, each room is inset from the sides of its cell so there is a gap one corridor
+/// wide along every vertical split plane, and the two sides of a split are connected by a corridor
+/// through that gap instead. Rooms split by a horizontal plane still touch, so they get a door.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct BspDungeonSpec {
+    pub seed: [u32; 4],
+    /// The extent from the origin to this supremum is partitioned.
+    pub size: Point,
+    /// A node is only split if both halves are at least this long along the split axis.
+    pub min_room_dim: u32,
+    /// The split plane is placed at a fraction of the node's length sampled uniformly from this
+    /// range.
+    pub split_ratio: (f32, f32),
+    pub max_depth: usize,
+    pub min_door_dim: u32,
+    pub max_door_dim: u32,
+    #[serde(default)]
+    pub corridors: Option<CorridorSpec>,
+}
+
+struct Partition {
+    rooms: Vec<Extent>,
+    doors: Vec<Extent>,
+    corridors: Vec<Corridor>,
+}
+
+impl BspDungeonSpec {
+    /// Splits `node` into two extents, or returns `None` if it should be a leaf.
+    fn split(&self, node: &Extent, depth: usize, rng: &mut impl Rng) -> Option<(Extent, Extent)> {
+        if depth >= self.max_depth {
+            return None;
+        }
+
+        // Split the longest axis that has room for two rooms.
+        let min_dim = self.min_room_dim as i32;
+        let sup = node.get_local_supremum();
+        let axis = (0..3)
+            .filter(|a| component(sup, *a) >= 2 * min_dim)
+            .max_by_key(|a| component(sup, *a))?;
+
+        let len = component(sup, axis);
+        let (lo, hi) = self.split_ratio;
+        let ratio = rng.gen_range(lo.min(hi), hi.max(lo) + f32::EPSILON);
+        let split_len = ((len as f32 * ratio).round() as i32)
+            .max(min_dim)
+            .min(len - min_dim);
+
+        let min = node.get_minimum();
+        let left = Extent::from_min_and_local_supremum(*min, with_component(sup, axis, split_len));
+        let right = Extent::from_min_and_local_supremum(
+            with_component(min, axis, component(min, axis) + split_len),
+            with_component(sup, axis, len - split_len),
+        );
+
+        Some((left, right))
+    }
+
+    /// The room in the leaf `cell`. With corridors, it's inset along X and Z by half of a
+    /// corridor's width, or `None` if that leaves nothing.
+    fn room_in_cell(&self, cell: &Extent) -> Option<Extent> {
+        let corridors = match &self.corridors {
+            Some(c) => c,
+            None => return Some(*cell),
+        };
+
+        let inset = (corridors.outer_width() + 1) / 2;
+        let (min, sup) = (cell.get_minimum(), cell.get_local_supremum());
+        let (mut room_min, mut room_sup) = (*min, *sup);
+        for a in [0, 2].iter().cloned() {
+            let len = component(sup, a) - 2 * inset;
+            if len <= 0 {
+                return None;
+            }
+            room_min = with_component(&room_min, a, component(min, a) + inset);
+            room_sup = with_component(&room_sup, a, len);
+        }
+
+        Some(Extent::from_min_and_local_supremum(room_min, room_sup))
+    }
+
+    /// Returns the indices of the rooms in the subtree rooted at `node`, or `None` if some split
+    /// couldn't be connected.
+    fn partition(
+        &self,
+        node: Extent,
+        depth: usize,
+        door_dims: &SubspanSampler,
+        rng: &mut impl Rng,
+        partition: &mut Partition,
+    ) -> Option<Vec<usize>> {
+        let (left, right) = if let Some(halves) = self.split(&node, depth, rng) {
+            halves
+        } else {
+            partition.rooms.push(self.room_in_cell(&node)?);
+
+            return Some(vec![partition.rooms.len() - 1]);
+        };
+
+        let mut left_rooms = self.partition(left, depth + 1, door_dims, rng, partition)?;
+        let right_rooms = self.partition(right, depth + 1, door_dims, rng, partition)?;
+
+        // Connect the two halves with a door between a random pair of rooms that touch across
+        // the split plane, or else a corridor between a pair that doesn't.
+        let mut pairs: Vec<_> = left_rooms
+            .iter()
+            .flat_map(|l| right_rooms.iter().map(move |r| (*l, *r)))
+            .collect();
+        pairs.shuffle(rng);
+        let door = pairs.iter().find_map(|(l, r)| {
+            try_generate_door_big_enough_between_rooms(
+                door_dims,
+                &partition.rooms[*l],
+                &partition.rooms[*r],
+                rng,
+            )
+        });
+        if let Some(door) = door {
+            partition.doors.push(door);
+        } else {
+            let spec = self.corridors.as_ref()?;
+            // Corridors stay inside the node, so only the rooms and corridors in it can get in
+            // the way.
+            let obstacles: Vec<Extent> = partition
+                .rooms
+                .iter()
+                .chain(partition.corridors.iter().flat_map(|c| c.segments.iter()))
+                .cloned()
+                .collect();
+            let corridor = pairs.iter().find_map(|(l, r)| {
+                try_route_corridor(
+                    &partition.rooms[*l],
+                    &partition.rooms[*r],
+                    spec,
+                    door_dims,
+                    &obstacles,
+                    rng,
+                )
+            })?;
+            partition.corridors.push(corridor);
+        }
+
+        left_rooms.extend(right_rooms);
+
+        Some(left_rooms)
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<BspDungeonMeta> {
+        log::debug!("Generating BSP dungeon map");
+
+        let bounds = Extent::from_min_and_local_supremum([0, 0, 0].into(), self.size);
+        let door_dims = SubspanSampler::new(self.min_door_dim as i32, self.max_door_dim as i32);
+        let mut partition = Partition {
+            rooms: Vec::new(),
+            doors: Vec::new(),
+            corridors: Vec::new(),
+        };
+        self.partition(bounds, 0, &door_dims, rng, &mut partition)?;
+        log::debug!(
+            "Partitioned into {} rooms with {} doors and {} corridors",
+            partition.rooms.len(),
+            partition.doors.len(),
+            partition.corridors.len()
+        );
+
+        fill_map_with_rooms(&partition.rooms, encoder);
+        for c in partition.corridors.iter() {
+            fill_map_with_rooms(&c.segments, encoder);
+        }
+        fill_map_with_doors(&partition.doors, encoder);
+        for c in partition.corridors.iter() {
+            fill_map_with_doors(&c.doors, encoder);
+        }
+
+        let spawn_room = partition.rooms.choose(rng)?;
+
+        Some(BspDungeonMeta {
+            spawn_area: spawn_in_room(spawn_room),
+            rooms: partition.rooms,
+            doors: partition.doors,
+            corridors: partition.corridors,
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> BspDungeonMeta {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |_, seed| {
+            self.try_generate(&mut small_rng(seed), encoder)
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "Failed to generate BSP dungeon after {} tries",
+                MAX_GENERATE_TRIES
+            )
+        })
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::NullEncoder;
+
+    #[test]
+    fn test_corridors_connect_inset_rooms() {
+        let spec = BspDungeonSpec {
+            seed: [1, 2, 3, 4],
+            size: [192, 24, 192].into(),
+            min_room_dim: 36,
+            split_ratio: (0.3, 0.7),
+            max_depth: 4,
+            min_door_dim: 3,
+            max_door_dim: 5,
+            corridors: Some(CorridorSpec {
+                width: 3,
+                height: 4,
+                max_length: 40,
+            }),
+        };
+        let meta = spec.generate(&mut small_rng(spec.seed), &mut NullEncoder);
+
+        // Every split is connected once, and with no horizontal splits, always by a corridor.
+        assert!(meta.doors.is_empty());
+        assert_eq!(meta.corridors.len() + 1, meta.rooms.len());
+
+        let segments: Vec<Extent> = meta
+            .corridors
+            .iter()
+            .flat_map(|c| c.segments.iter().cloned())
+            .collect();
+        for (i, s) in segments.iter().enumerate() {
+            for r in meta.rooms.iter() {
+                assert!(s.intersection(r).is_empty());
+            }
+            for other in segments[i + 1..].iter() {
+                assert!(s.intersection(other).is_empty());
+            }
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use super::city::BuildingMeta;
+use crate::{
+    room::{
+        fill_map_with_doors, fill_map_with_rooms, try_select_door_between_rooms, DoorScoringPolicy,
+        DoorSelectionSpec, EMPTY_VOXEL, FLOOR_VOXEL,
+    },
+    sampling::SubspanSampler,
+    SpawnArea, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CastleMeta {
+    pub spawn_area: SpawnArea,
+    /// The open space enclosed by the curtain wall.
+    pub courtyard: Extent,
+    /// The wall segments between the towers. Their tops are walkable.
+    pub curtain_walls: Vec<Extent>,
+    /// One room at each corner of the curtain wall.
+    pub towers: Vec<Extent>,
+    /// The doors from the towers into the courtyard.
+    pub tower_doors: Vec<Extent>,
+    /// The opening in the front (-Z) curtain wall.
+    pub gate: Extent,
+    pub keep: BuildingMeta,
+}
+
+/// Generates a square curtain wall with a tower at each corner and a gate in the front (-Z) wall.
+/// The courtyard inside holds a keep, which is a grid of rooms on each floor, connected with the
+/// existing room API like the buildings of a city.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct CastleMapSpec {
+    pub seed: [u32; 4],
+    /// The horizontal length of the square inside of the curtain wall.
+    pub courtyard_size: u32,
+    pub ground_thickness: u32,
+    pub curtain_height: u32,
+    pub curtain_thickness: u32,
+    /// Towers are square in the XZ plane.
+    pub tower_size: u32,
+    pub tower_height: u32,
+    pub gate_width: u32,
+    pub gate_height: u32,
+    pub keep_rooms_x: u32,
+    pub keep_rooms_z: u32,
+    /// Keep rooms are square in the XZ plane.
+    pub keep_room_size: u32,
+    pub keep_floors: u32,
+    pub keep_floor_height: u32,
+    pub min_door_dim: u32,
+    pub max_door_dim: u32,
+}
+
+impl CastleMapSpec {
+    /// The horizontal length of the whole castle, including the curtain wall.
+    fn outer_size(&self) -> i32 {
+        (self.courtyard_size + 2 * self.curtain_thickness) as i32
+    }
+
+    pub fn size(&self) -> Point {
+        let height = self
+            .curtain_height
+            .max(self.tower_height)
+            .max(self.keep_floors * self.keep_floor_height);
+
+        [
+            self.outer_size(),
+            (self.ground_thickness + height) as i32,
+            self.outer_size(),
+        ]
+        .into()
+    }
+
+    fn towers(&self) -> Vec<Extent> {
+        let far = self.outer_size() - self.tower_size as i32;
+        let sup: Point = [
+            self.tower_size as i32,
+            self.tower_height as i32,
+            self.tower_size as i32,
+        ]
+        .into();
+
+        [(0, 0), (far, 0), (0, far), (far, far)]
+            .iter()
+            .map(|(x, z)| {
+                Extent::from_min_and_local_supremum(
+                    [*x, self.ground_thickness as i32, *z].into(),
+                    sup,
+                )
+            })
+            .collect()
+    }
+
+    /// The four wall segments between the towers, starting with the front.
+    fn curtain_walls(&self) -> Vec<Extent> {
+        let (s, t) = (self.outer_size(), self.curtain_thickness as i32);
+        let ts = self.tower_size as i32;
+        let (y, h) = (self.ground_thickness as i32, self.curtain_height as i32);
+        let between = s - 2 * ts;
+
+        vec![
+            Extent::from_min_and_local_supremum([ts, y, 0].into(), [between, h, t].into()),
+            Extent::from_min_and_local_supremum([ts, y, s - t].into(), [between, h, t].into()),
+            Extent::from_min_and_local_supremum([0, y, ts].into(), [t, h, between].into()),
+            Extent::from_min_and_local_supremum([s - t, y, ts].into(), [t, h, between].into()),
+        ]
+    }
+
+    fn gate(&self) -> Extent {
+        let width = self.gate_width as i32;
+
+        Extent::from_min_and_local_supremum(
+            [
+                (self.outer_size() - width) / 2,
+                self.ground_thickness as i32,
+                0,
+            ]
+            .into(),
+            [
+                width,
+                self.gate_height as i32,
+                self.curtain_thickness as i32,
+            ]
+            .into(),
+        )
+    }
+
+    /// The keep's rooms on each floor, centered in the courtyard, from the ground floor up. Each
+    /// floor lists its rooms with X varying fastest.
+    fn keep_rooms(&self) -> Vec<Vec<Extent>> {
+        let room = self.keep_room_size as i32;
+        let (nx, nz) = (self.keep_rooms_x as i32, self.keep_rooms_z as i32);
+        let x0 = (self.outer_size() - nx * room) / 2;
+        let z0 = (self.outer_size() - nz * room) / 2;
+        let floor_height = self.keep_floor_height as i32;
+
+        (0..self.keep_floors as i32)
+            .map(|f| {
+                let y = self.ground_thickness as i32 + f * floor_height;
+                (0..nz)
+                    .flat_map(|j| {
+                        (0..nx).map(move |i| {
+                            Extent::from_min_and_local_supremum(
+                                [x0 + i * room, y, z0 + j * room].into(),
+                                [room, floor_height, room].into(),
+                            )
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn try_generate_keep(
+        &self,
+        door_dims: &SubspanSampler,
+        rng: &mut impl Rng,
+    ) -> Option<BuildingMeta> {
+        let floors = self.keep_rooms();
+        let nx = self.keep_rooms_x as usize;
+
+        let wall_doors = DoorSelectionSpec {
+            num_candidates: 4,
+            policy: DoorScoringPolicy::ClosestToFloor,
+            ..Default::default()
+        };
+        let hatches = DoorSelectionSpec::default();
+
+        let mut doors = Vec::new();
+        for (f, floor) in floors.iter().enumerate() {
+            // Every room opens onto its +X neighbor, and the first column is connected along Z,
+            // which is enough to connect the whole floor.
+            for (i, room) in floor.iter().enumerate() {
+                let mut neighbors = Vec::new();
+                if (i + 1) % nx != 0 {
+                    neighbors.push(i + 1);
+                }
+                if i % nx == 0 && i + nx < floor.len() {
+                    neighbors.push(i + nx);
+                }
+                for n in neighbors.into_iter() {
+                    let door = try_select_door_between_rooms(
+                        door_dims,
+                        &wall_doors,
+                        &doors,
+                        room,
+                        &floor[n],
+                        rng,
+                    )?;
+                    doors.push(door);
+                }
+            }
+
+            if let Some(above) = floors.get(f + 1) {
+                let door = try_select_door_between_rooms(
+                    door_dims, &hatches, &doors, &floor[0], &above[0], rng,
+                )?;
+                doors.push(door);
+            }
+        }
+
+        // The entrance opens from the first ground floor room onto the courtyard in front of it.
+        let entrance_room = floors.first()?.first()?;
+        let courtyard_front = Extent::from_min_and_local_supremum(
+            *entrance_room.get_minimum() - [0, 0, 1].into(),
+            [
+                entrance_room.get_local_supremum().x,
+                entrance_room.get_local_supremum().y,
+                1,
+            ]
+            .into(),
+        );
+        let entrance = try_select_door_between_rooms(
+            door_dims,
+            &wall_doors,
+            &doors,
+            entrance_room,
+            &courtyard_front,
+            rng,
+        )?;
+        doors.push(entrance);
+
+        let first_floor = floors.first()?;
+        let footprint = Extent::from_min_and_world_supremum(
+            *first_floor.first()?.get_minimum(),
+            *first_floor.last()?.get_minimum()
+                + [
+                    self.keep_room_size as i32,
+                    (self.keep_floors * self.keep_floor_height) as i32,
+                    self.keep_room_size as i32,
+                ]
+                .into(),
+        );
+
+        Some(BuildingMeta {
+            footprint,
+            rooms: floors.into_iter().flatten().collect(),
+            doors,
+        })
+    }
+
+    /// The slice of courtyard that each tower opens onto, along the tower's face that points
+    /// into the castle along Z.
+    fn tower_fronts(&self, towers: &[Extent]) -> Vec<Extent> {
+        let (ts, t) = (self.tower_size as i32, self.curtain_thickness as i32);
+
+        towers
+            .iter()
+            .map(|tower| {
+                let min = tower.get_minimum();
+                // Stay out of the curtain wall on the X side.
+                let x_min = if min.x == 0 { t } else { min.x };
+                let z = if min.z == 0 { ts } else { min.z - 1 };
+
+                Extent::from_min_and_local_supremum(
+                    [x_min, min.y, z].into(),
+                    [ts - t, self.tower_height as i32, 1].into(),
+                )
+            })
+            .collect()
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<CastleMeta> {
+        log::debug!("Generating castle map");
+
+        let (ts, t) = (self.tower_size as i32, self.curtain_thickness as i32);
+        let no_keep = self.keep_floors == 0 || self.keep_rooms_x == 0 || self.keep_rooms_z == 0;
+        if ts <= t || 2 * ts >= self.outer_size() || no_keep {
+            return None;
+        }
+
+        let size = self.size();
+        let towers = self.towers();
+        let curtain_walls = self.curtain_walls();
+        let gate = self.gate();
+        let courtyard = Extent::from_min_and_local_supremum(
+            [t, self.ground_thickness as i32, t].into(),
+            [
+                self.courtyard_size as i32,
+                self.curtain_height as i32,
+                self.courtyard_size as i32,
+            ]
+            .into(),
+        );
+        if !gate.is_subset(&curtain_walls[0]) {
+            log::debug!("Gate doesn't fit between the front towers");
+            return None;
+        }
+
+        let door_dims = SubspanSampler::new(self.min_door_dim as i32, self.max_door_dim as i32);
+        let keep = self.try_generate_keep(&door_dims, rng)?;
+        // Leave room to walk around the keep.
+        let keep_clearance = keep.footprint.radial_grow(1);
+        if towers
+            .iter()
+            .chain(curtain_walls.iter())
+            .any(|e| !e.intersection(&keep_clearance).is_empty())
+        {
+            log::debug!("Keep doesn't fit in the courtyard");
+            return None;
+        }
+
+        let tower_selection = DoorSelectionSpec {
+            num_candidates: 4,
+            policy: DoorScoringPolicy::ClosestToFloor,
+            ..Default::default()
+        };
+        let mut tower_doors = Vec::new();
+        for (tower, front) in towers.iter().zip(self.tower_fronts(&towers).iter()) {
+            let door = try_select_door_between_rooms(
+                &door_dims,
+                &tower_selection,
+                &tower_doors,
+                tower,
+                front,
+                rng,
+            )?;
+            tower_doors.push(door);
+        }
+
+        let ground = Extent::from_min_and_local_supremum(
+            [0, 0, 0].into(),
+            [size.x, self.ground_thickness as i32, size.z].into(),
+        );
+        let surface_y = self.ground_thickness as i32 - 1;
+        for p in &ground {
+            encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            if p.y == surface_y {
+                encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+            }
+        }
+        for w in curtain_walls.iter() {
+            let top_y = w.get_world_supremum().y - 1;
+            for p in w {
+                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+                if p.y == top_y {
+                    encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+                }
+            }
+        }
+        for p in &gate {
+            encoder.encode_voxel(&p, &EMPTY_VOXEL);
+        }
+        fill_map_with_rooms(&towers, encoder);
+        fill_map_with_doors(&tower_doors, encoder);
+        fill_map_with_rooms(&keep.rooms, encoder);
+        fill_map_with_doors(&keep.doors, encoder);
+
+        // Spawn in the courtyard, just inside the gate.
+        let gate_min = gate.get_minimum();
+        let spawn_area = SpawnArea {
+            valid_spawn_points: (gate_min.x..gate_min.x + gate.get_local_supremum().x)
+                .map(|x| [x, self.ground_thickness as i32, t].into())
+                .collect(),
+            facing: None,
+        };
+
+        Some(CastleMeta {
+            spawn_area,
+            courtyard,
+            curtain_walls,
+            towers,
+            tower_doors,
+            gate,
+            keep,
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> CastleMeta {
+        for _ in 0..MAX_GENERATE_TRIES {
+            if let Some(meta) = self.try_generate(rng, encoder) {
+                return meta;
+            }
+        }
+
+        panic!(
+            "Failed to generate castle after {} tries",
+            MAX_GENERATE_TRIES
+        );
+    }
+}
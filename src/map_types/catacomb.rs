@@ -0,0 +1,220 @@
+use crate::{
+    occupancy::OccupancyGrid,
+    room::{EMPTY_VOXEL, FLOOR_VOXEL},
+    sampling::{retry_with_derived_seeds, small_rng},
+    SpawnArea, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CatacombMeta {
+    pub spawn_area: SpawnArea,
+    /// Useful places for tombs or loot.
+    pub alcoves: Vec<Extent>,
+    pub chambers: Vec<Extent>,
+}
+
+/// Generates a crypt by tiling square cells across the map. Corridors run through the center of
+/// every cell along X and Z, so the corridors form a connected grid. Alcoves are carved into the
+/// corridor walls, and occasionally a whole cell is hollowed out into a chamber.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct CatacombMapSpec {
+    pub seed: [u32; 4],
+    /// The map occupies the extent from the origin to this supremum.
+    pub size: Point,
+    /// The length of a cell along X and Z.
+    pub cell_size: i32,
+    pub corridor_width: i32,
+    pub corridor_height: i32,
+    pub alcove_width: i32,
+    pub alcove_depth: i32,
+    pub alcove_height: i32,
+    /// The probability that each alcove slot is carved. Every cell has 4 slots, one on each side of
+    /// each of its corridors.
+    pub alcove_density: f32,
+    /// The probability that a cell is hollowed out into a chamber.
+    pub chamber_probability: f32,
+    pub chamber_height: i32,
+}
+
+impl CatacombMapSpec {
+    fn extent(&self) -> Extent {
+        Extent::from_min_and_local_supremum([0, 0, 0].into(), self.size)
+    }
+
+    /// Makes every cell in `extent` empty, except for the outer shell of the map.
+    fn carve(&self, grid: &mut OccupancyGrid, extent: &Extent) {
+        for p in &extent.intersection(&self.extent().radial_grow(-1)) {
+            grid.set_solid(&p, false);
+        }
+    }
+
+    fn num_cells(&self) -> (i32, i32) {
+        let cell = self.cell_size.max(1);
+
+        ((self.size.x - 2) / cell, (self.size.z - 2) / cell)
+    }
+
+    /// Carves the corridors, alcoves, and maybe a chamber for the cell at `(i, j)`.
+    fn carve_cell(
+        &self,
+        i: i32,
+        j: i32,
+        grid: &mut OccupancyGrid,
+        rng: &mut impl Rng,
+        meta: &mut CatacombMeta,
+    ) {
+        let cell = self.cell_size;
+        let floor_y = 1;
+        let origin: Point = [1 + i * cell, floor_y, 1 + j * cell].into();
+        // The corridors' minimum coordinates across their width.
+        let corridor_x = origin.x + (cell - self.corridor_width) / 2;
+        let corridor_z = origin.z + (cell - self.corridor_width) / 2;
+
+        let along_x = Extent::from_min_and_local_supremum(
+            [origin.x, floor_y, corridor_z].into(),
+            [cell, self.corridor_height, self.corridor_width].into(),
+        );
+        let along_z = Extent::from_min_and_local_supremum(
+            [corridor_x, floor_y, origin.z].into(),
+            [self.corridor_width, self.corridor_height, cell].into(),
+        );
+        self.carve(grid, &along_x);
+        self.carve(grid, &along_z);
+
+        // Slots sit a quarter of the way along each corridor so they don't run into the crossing.
+        let slot_offset = cell / 4 - self.alcove_width / 2;
+        let slots = [
+            // -Z and +Z sides of the X corridor.
+            Extent::from_min_and_local_supremum(
+                [
+                    origin.x + slot_offset,
+                    floor_y,
+                    corridor_z - self.alcove_depth,
+                ]
+                .into(),
+                [self.alcove_width, self.alcove_height, self.alcove_depth].into(),
+            ),
+            Extent::from_min_and_local_supremum(
+                [
+                    origin.x + slot_offset,
+                    floor_y,
+                    corridor_z + self.corridor_width,
+                ]
+                .into(),
+                [self.alcove_width, self.alcove_height, self.alcove_depth].into(),
+            ),
+            // -X and +X sides of the Z corridor.
+            Extent::from_min_and_local_supremum(
+                [
+                    corridor_x - self.alcove_depth,
+                    floor_y,
+                    origin.z + slot_offset,
+                ]
+                .into(),
+                [self.alcove_depth, self.alcove_height, self.alcove_width].into(),
+            ),
+            Extent::from_min_and_local_supremum(
+                [
+                    corridor_x + self.corridor_width,
+                    floor_y,
+                    origin.z + slot_offset,
+                ]
+                .into(),
+                [self.alcove_depth, self.alcove_height, self.alcove_width].into(),
+            ),
+        ];
+        for slot in slots.iter() {
+            if rng.gen::<f32>() < self.alcove_density {
+                self.carve(grid, slot);
+                meta.alcoves.push(*slot);
+            }
+        }
+
+        if rng.gen::<f32>() < self.chamber_probability {
+            // Leave a 1 voxel border so neighboring chambers don't merge into one big space.
+            let chamber = Extent::from_min_and_local_supremum(
+                origin + [1, 0, 1].into(),
+                [cell - 2, self.chamber_height, cell - 2].into(),
+            );
+            self.carve(grid, &chamber);
+            meta.chambers.push(chamber);
+        }
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<CatacombMeta> {
+        log::debug!("Generating catacomb map");
+
+        let (cells_x, cells_z) = self.num_cells();
+        if cells_x < 1 || cells_z < 1 {
+            return None;
+        }
+
+        let mut grid = OccupancyGrid::new(self.extent(), true);
+        let mut meta = CatacombMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points: Vec::new(),
+                facing: None,
+            },
+            alcoves: Vec::new(),
+            chambers: Vec::new(),
+        };
+        for j in 0..cells_z {
+            for i in 0..cells_x {
+                self.carve_cell(i, j, &mut grid, rng, &mut meta);
+            }
+        }
+        log::debug!(
+            "Carved {} alcoves and {} chambers",
+            meta.alcoves.len(),
+            meta.chambers.len()
+        );
+
+        let open_points = grid.keep_largest_empty_component();
+        let valid_spawn_points: Vec<_> = grid.floor_points(&open_points).collect();
+        if valid_spawn_points.is_empty() {
+            return None;
+        }
+
+        for p in &self.extent() {
+            if grid.is_solid(&p) {
+                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            } else {
+                encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            }
+        }
+        for p in valid_spawn_points.iter() {
+            encoder.encode_flags(&(*p - [0, 1, 0].into()), VoxelFlags::WALKABLE);
+        }
+
+        meta.spawn_area = SpawnArea {
+            valid_spawn_points,
+            facing: None,
+        };
+
+        Some(meta)
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> CatacombMeta {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |_, seed| {
+            self.try_generate(&mut small_rng(seed), encoder)
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "Failed to generate catacomb map after {} tries",
+                MAX_GENERATE_TRIES
+            )
+        })
+    }
+}
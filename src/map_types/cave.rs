@@ -0,0 +1,121 @@
+use crate::{
+    occupancy::OccupancyGrid,
+    room::{EMPTY_VOXEL, FLOOR_VOXEL},
+    sampling::{retry_with_derived_seeds, small_rng},
+    SpawnArea, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CaveMeta {
+    pub spawn_area: SpawnArea,
+}
+
+/// Generates organic caves by smoothing random noise with a 3D cellular automaton.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct CaveMapSpec {
+    pub seed: [u32; 4],
+    /// The cave occupies the extent from the origin to this supremum.
+    pub size: Point,
+    /// The probability that a cell starts out solid.
+    pub fill_ratio: f32,
+    pub smoothing_iterations: usize,
+    /// During smoothing, a cell becomes solid iff at least this many of its 26 neighbors are
+    /// solid.
+    pub solid_neighbor_threshold: usize,
+    /// Attempts where the connected open space is a smaller fraction of the volume than this are
+    /// rejected.
+    pub min_open_ratio: f32,
+}
+
+impl CaveMapSpec {
+    fn extent(&self) -> Extent {
+        Extent::from_min_and_local_supremum([0, 0, 0].into(), self.size)
+    }
+
+    fn random_fill(&self, rng: &mut impl Rng) -> OccupancyGrid {
+        let extent = self.extent();
+        let boundary = extent.radial_grow(-1);
+        let mut grid = OccupancyGrid::new(extent, true);
+        for p in &extent {
+            // Keep the outer shell solid so the cave is closed.
+            if boundary.contains_world(&p) {
+                grid.set_solid(&p, rng.gen::<f32>() < self.fill_ratio);
+            }
+        }
+
+        grid
+    }
+
+    fn smooth(&self, grid: &OccupancyGrid) -> OccupancyGrid {
+        let mut smoothed = grid.clone();
+        let boundary = grid.extent().radial_grow(-1);
+        for p in &boundary {
+            smoothed.set_solid(
+                &p,
+                grid.count_solid_neighbors(&p) >= self.solid_neighbor_threshold,
+            );
+        }
+
+        smoothed
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<CaveMeta> {
+        log::debug!("Generating cave map");
+
+        let mut grid = self.random_fill(rng);
+        for _ in 0..self.smoothing_iterations {
+            grid = self.smooth(&grid);
+        }
+
+        // Guarantee connectivity by filling in any pockets that can't be reached from the main
+        // cave.
+        let open_points = grid.keep_largest_empty_component();
+        let open_ratio = open_points.len() as f32 / grid.volume() as f32;
+        log::debug!("Open ratio = {}", open_ratio);
+        if open_points.is_empty() || open_ratio < self.min_open_ratio {
+            return None;
+        }
+
+        let valid_spawn_points: Vec<_> = grid.floor_points(&open_points).collect();
+        if valid_spawn_points.is_empty() {
+            return None;
+        }
+
+        for p in &self.extent() {
+            if grid.is_solid(&p) {
+                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            } else {
+                encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            }
+        }
+        for p in valid_spawn_points.iter() {
+            encoder.encode_flags(&(*p - [0, 1, 0].into()), VoxelFlags::WALKABLE);
+        }
+
+        Some(CaveMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points,
+                facing: None,
+            },
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> CaveMeta {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |_, seed| {
+            self.try_generate(&mut small_rng(seed), encoder)
+        })
+        .unwrap_or_else(|| panic!("Failed to generate cave after {} tries", MAX_GENERATE_TRIES))
+    }
+}
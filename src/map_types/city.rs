@@ -0,0 +1,298 @@
+use crate::{
+    room::{
+        fill_map_with_doors, fill_map_with_rooms, try_select_door_between_rooms, DoorScoringPolicy,
+        DoorSelectionSpec, FLOOR_VOXEL,
+    },
+    sampling::SubspanSampler,
+    SpawnArea, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BuildingMeta {
+    pub footprint: Extent,
+    /// Every room of every floor, from the ground floor up.
+    pub rooms: Vec<Extent>,
+    /// Includes the entrance from the street.
+    pub doors: Vec<Extent>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CityMeta {
+    pub spawn_area: SpawnArea,
+    /// Strips of ground between the blocks. Strips overlap at intersections.
+    pub streets: Vec<Extent>,
+    pub buildings: Vec<BuildingMeta>,
+}
+
+/// Generates a grid of city blocks separated by streets. Each block holds one building, which is a
+/// stack of floors that are each divided into rooms and connected with the existing room API.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct CityMapSpec {
+    pub seed: [u32; 4],
+    pub num_blocks_x: u32,
+    pub num_blocks_z: u32,
+    /// Blocks are square in the XZ plane.
+    pub block_size: u32,
+    pub street_width: u32,
+    pub ground_thickness: u32,
+    /// The minimum distance between a building and the edge of its block.
+    pub setback: u32,
+    pub min_footprint_dim: u32,
+    pub min_floors: u32,
+    pub max_floors: u32,
+    pub floor_height: u32,
+    pub rooms_per_floor: u32,
+    pub min_door_dim: u32,
+    pub max_door_dim: u32,
+}
+
+impl CityMapSpec {
+    fn block_stride(&self) -> i32 {
+        (self.block_size + self.street_width) as i32
+    }
+
+    pub fn size(&self) -> Point {
+        let height = self.ground_thickness + self.max_floors * self.floor_height;
+
+        [
+            self.num_blocks_x as i32 * self.block_stride() + self.street_width as i32,
+            height as i32,
+            self.num_blocks_z as i32 * self.block_stride() + self.street_width as i32,
+        ]
+        .into()
+    }
+
+    fn block(&self, i: u32, j: u32) -> Extent {
+        let stride = self.block_stride();
+        let street = self.street_width as i32;
+        let size = self.block_size as i32;
+
+        Extent::from_min_and_local_supremum(
+            [
+                street + i as i32 * stride,
+                self.ground_thickness as i32,
+                street + j as i32 * stride,
+            ]
+            .into(),
+            [size, (self.max_floors * self.floor_height) as i32, size].into(),
+        )
+    }
+
+    fn streets(&self) -> Vec<Extent> {
+        let size = self.size();
+        let stride = self.block_stride();
+        let street = self.street_width as i32;
+        let ground = self.ground_thickness as i32;
+
+        let along_z = (0..=self.num_blocks_x).map(|i| {
+            Extent::from_min_and_local_supremum(
+                [i as i32 * stride, 0, 0].into(),
+                [street, ground, size.z].into(),
+            )
+        });
+        let along_x = (0..=self.num_blocks_z).map(|j| {
+            Extent::from_min_and_local_supremum(
+                [0, 0, j as i32 * stride].into(),
+                [size.x, ground, street].into(),
+            )
+        });
+
+        along_z.chain(along_x).collect()
+    }
+
+    /// Samples a footprint inside `block`, keeping the setback on every side except the front
+    /// (-Z) side, which faces the street so the entrance opens onto it.
+    fn sample_footprint(&self, block: &Extent, rng: &mut impl Rng) -> Option<Extent> {
+        let setback = self.setback as i32;
+        let max_dim = self.block_size as i32 - 2 * setback;
+        if max_dim < self.min_footprint_dim as i32 {
+            return None;
+        }
+
+        let dims = SubspanSampler::new(self.min_footprint_dim as i32, max_dim);
+        let min = block.get_minimum();
+        let (x_min, x_len) = dims.sample(rng, min.x + setback, max_dim);
+        let (_, z_len) = dims.sample(rng, min.z + setback, max_dim);
+
+        let num_floors = rng.gen_range(self.min_floors, self.max_floors + 1);
+
+        Some(Extent::from_min_and_local_supremum(
+            [x_min, min.y, min.z].into(),
+            [x_len, (num_floors * self.floor_height) as i32, z_len].into(),
+        ))
+    }
+
+    /// Divides each floor of `footprint` into rooms along its longer horizontal axis.
+    fn building_rooms(&self, footprint: &Extent) -> Vec<Vec<Extent>> {
+        let min = footprint.get_minimum();
+        let sup = footprint.get_local_supremum();
+        let floor_height = self.floor_height as i32;
+        let num_floors = sup.y / floor_height;
+        let n = self.rooms_per_floor.max(1) as i32;
+
+        let split_x = sup.x >= sup.z;
+        let len = if split_x { sup.x } else { sup.z };
+        let room_len = len / n;
+
+        (0..num_floors)
+            .map(|f| {
+                (0..n)
+                    .map(|r| {
+                        // The last room takes the remainder.
+                        let this_len = if r == n - 1 {
+                            len - r * room_len
+                        } else {
+                            room_len
+                        };
+                        let y = min.y + f * floor_height;
+                        if split_x {
+                            Extent::from_min_and_local_supremum(
+                                [min.x + r * room_len, y, min.z].into(),
+                                [this_len, floor_height, sup.z].into(),
+                            )
+                        } else {
+                            Extent::from_min_and_local_supremum(
+                                [min.x, y, min.z + r * room_len].into(),
+                                [sup.x, floor_height, this_len].into(),
+                            )
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn try_generate_building(
+        &self,
+        block: &Extent,
+        door_dims: &SubspanSampler,
+        rng: &mut impl Rng,
+    ) -> Option<BuildingMeta> {
+        let footprint = self.sample_footprint(block, rng)?;
+        let floors = self.building_rooms(&footprint);
+
+        let wall_doors = DoorSelectionSpec {
+            num_candidates: 4,
+            policy: DoorScoringPolicy::ClosestToFloor,
+            ..Default::default()
+        };
+        let hatches = DoorSelectionSpec::default();
+
+        let mut doors = Vec::new();
+        for (f, floor) in floors.iter().enumerate() {
+            for pair in floor.windows(2) {
+                let door = try_select_door_between_rooms(
+                    door_dims,
+                    &wall_doors,
+                    &doors,
+                    &pair[0],
+                    &pair[1],
+                    rng,
+                )?;
+                doors.push(door);
+            }
+
+            // Connect each floor to the one above with a hatch through the ceiling.
+            if let Some(above) = floors.get(f + 1) {
+                let door = try_select_door_between_rooms(
+                    door_dims, &hatches, &doors, &floor[0], &above[0], rng,
+                )?;
+                doors.push(door);
+            }
+        }
+
+        // The entrance connects the first ground floor room to a thin slice of street in front of
+        // it.
+        let entrance_room = floors.first()?.first()?;
+        let room_min = entrance_room.get_minimum();
+        let room_sup = entrance_room.get_local_supremum();
+        let street_front = Extent::from_min_and_local_supremum(
+            *room_min - [0, 0, 1].into(),
+            [room_sup.x, room_sup.y, 1].into(),
+        );
+        let entrance = try_select_door_between_rooms(
+            door_dims,
+            &wall_doors,
+            &doors,
+            entrance_room,
+            &street_front,
+            rng,
+        )?;
+        doors.push(entrance);
+
+        Some(BuildingMeta {
+            footprint,
+            rooms: floors.into_iter().flatten().collect(),
+            doors,
+        })
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<CityMeta> {
+        log::debug!("Generating city map");
+
+        let door_dims = SubspanSampler::new(self.min_door_dim as i32, self.max_door_dim as i32);
+        let mut buildings = Vec::new();
+        for j in 0..self.num_blocks_z {
+            for i in 0..self.num_blocks_x {
+                let block = self.block(i, j);
+                buildings.push(self.try_generate_building(&block, &door_dims, rng)?);
+            }
+        }
+        log::debug!("Generated {} buildings", buildings.len());
+
+        let streets = self.streets();
+        let ground = Extent::from_min_and_local_supremum(
+            [0, 0, 0].into(),
+            [self.size().x, self.ground_thickness as i32, self.size().z].into(),
+        );
+        let surface_y = self.ground_thickness as i32 - 1;
+        for p in &ground {
+            encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            if p.y == surface_y {
+                encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+            }
+        }
+        for b in buildings.iter() {
+            fill_map_with_rooms(&b.rooms, encoder);
+            fill_map_with_doors(&b.doors, encoder);
+        }
+
+        // Spawn on the street in front of the first building.
+        let first = &buildings.first()?.footprint;
+        let first_min = first.get_minimum();
+        let spawn_area = SpawnArea {
+            valid_spawn_points: (first_min.x..first_min.x + first.get_local_supremum().x)
+                .map(|x| [x, self.ground_thickness as i32, first_min.z - 1].into())
+                .collect(),
+            facing: None,
+        };
+
+        Some(CityMeta {
+            spawn_area,
+            streets,
+            buildings,
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> CityMeta {
+        for _ in 0..MAX_GENERATE_TRIES {
+            if let Some(meta) = self.try_generate(rng, encoder) {
+                return meta;
+            }
+        }
+
+        panic!("Failed to generate city after {} tries", MAX_GENERATE_TRIES);
+    }
+}
@@ -1,60 +1,974 @@
 use crate::{
-    extent::resolve_extent_overlaps,
-    graph::{largest_connected_subgraph, longest_path_in_tree, prune_outer_nodes_to_reach_size},
+    atmosphere::{AtmosphereSpec, AtmosphereVolume},
+    audio::{reverb_zones, ReverbZone},
+    balcony::{fill_map_with_balconies, try_generate_balcony, Balcony, BalconySpec},
+    camera::{CameraPose, OVERVIEW_VERTICAL_FOV},
+    climbing::{
+        climbable_strips, fill_map_with_climbables, fill_map_with_ladders, shaft_ladders,
+        ClimbingSpec, Ladder,
+    },
+    corridor::{
+        generate_connection_graph, generate_corridor_stubs, try_route_corridor, Corridor,
+        CorridorSpec, CorridorStub, CorridorStubSpec,
+    },
+    decoration::{
+        ceiling_features_for_room, fill_map_with_floor_features, fill_map_with_stalactites,
+        fill_map_with_support_columns, floor_features_for_room, support_columns_for_room,
+        CeilingDecorationSpec, CeilingFeature, CeilingFeatureKind, FloorFeature,
+        InteriorDecorationSpec, SupportColumnSpec,
+    },
+    difficulty::{branches, room_difficulties, Branch, BranchRewardSpec, DifficultySpec},
+    encoder::{BufferedEncoder, ClipEncoder, NullEncoder, ShellEncoder},
+    extent::{
+        bounding_extent, chunks_covering, compact_extents_toward_centroid, distance_squared,
+        expand_extents_into_gaps, extent_center, filter_close_extents, resolve_extent_overlaps,
+        with_component, SeparationWeights,
+    },
+    gallery::{fill_map_with_galleries, try_generate_gallery, Gallery, GallerySpec},
+    graph::{
+        articulation_points, betweenness_centrality, bridges, grow_branches, hub_and_spoke_tree,
+        largest_connected_subgraph, longest_simple_path, node_with_max_degree,
+        prune_outer_nodes_to_reach_size, random_spanning_tree, reachable_without_edge,
+        two_edge_connecting_edges, weighted_spanning_tree,
+    },
+    layout::{Layout, LayoutComparison, LayoutSummary, RoomGraph},
+    lighting::{fill_map_with_lights, lights_for_room, LightSource, LightingSpec},
+    mission::{map_mission, MissionGraph},
+    movement::{untraversable_doors, MovementSpec, TraversalProblem},
+    progression::{place_locks_and_keys, Lock, ProgressionSpec},
     room::{
-        collect_doors_from_room_graph, collect_rooms_from_room_graph, fill_map_with_doors,
-        fill_map_with_rooms, generate_door_graph, spawn_in_room,
+        backfill_map, classify_room, collect_doors_from_room_graph, collect_rooms_from_room_graph,
+        door_direction, door_sill_steps, fill_map_with_door_frames, fill_map_with_doors,
+        fill_map_with_rooms, fill_map_with_secret_doors, fill_map_with_steps,
+        fill_map_with_styled_doors, fill_map_with_typed_rooms, generate_composite_door_graph,
+        layout_from_room_graph, recarve_blocked_doors, select_door_between_composite_rooms,
+        spawn_in_composite_room, trim_door_jambs, try_generate_window_between_rooms, BackfillSpec,
+        DoorFrameSpec, DoorSelectionSpec, DoorStyle, Room, RoomOutline, RoomShape, RoomTheme,
+        SurfaceVoxelTypes, WindowSpec, WALL_THICKNESS,
+    },
+    sampling::{
+        derive_seed, named_seed, retry_with_derived_seeds, sample_extents, small_rng, CountingRng,
+        LatticeNormalDistSpec, LatticeUniformDistSpec, PoissonDiskDistSpec, SubspanSampler,
+        Variant,
     },
-    sampling::{sample_extents, LatticeNormalDistSpec, LatticeUniformDistSpec},
+    stairs::{fill_map_with_staircases, staircases, StairSpec, Staircase},
+    stats::GenerationStats,
     symmetric_map::SymmetricMap,
-    SpawnArea, VoxelEncoder,
+    visibility::{hidden_objective_point, ObjectivePoint},
+    SpawnArea, SpawnConstraint, VoxelEncoder,
 };
 
-use fnv::FnvHashSet;
-use ilattice3::Extent;
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
+use ilattice3::{normal::Direction, Extent, Point};
 use petgraph::{
-    algo::min_spanning_tree,
+    algo::{dijkstra, min_spanning_tree},
     data::FromElements,
     dot::{Config, Dot},
+    graph::{EdgeIndex, NodeIndex},
     stable_graph::StableGraph,
-    visit::IntoNodeReferences,
+    unionfind::UnionFind,
+    visit::{EdgeRef, IntoEdgeReferences, IntoNodeReferences, NodeIndexable},
     Undirected,
 };
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::iter::FromIterator;
+use std::{fmt, hash::Hasher, iter::FromIterator};
 
 pub const MAX_GENERATE_TRIES: usize = 200;
 
+/// How many times a door is sampled for a wall that already has one, before giving up on it.
+const SECOND_DOOR_TRIES: usize = 8;
+
+/// Identifies a room across regenerations from the same spec, so per-room game state like cleared
+/// rooms and opened chests can be saved against it. Derived from the map seed and the order in
+/// which the room was placed, rather than its index in `DungeonMeta::rooms`, which depends on
+/// which other rooms were pruned.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct RoomId(pub u64);
+
+impl RoomId {
+    /// `placement` is the index of the room among the candidates, in the order they were sampled
+    /// or given in `DungeonMapSpec::fixed_rooms`.
+    pub fn new(map_seed: [u32; 4], placement: usize) -> Self {
+        let mut hasher = FnvHasher::default();
+        for s in map_seed.iter() {
+            hasher.write_u32(*s);
+        }
+        hasher.write(b"room");
+        hasher.write_u64(placement as u64);
+
+        RoomId(hasher.finish())
+    }
+}
+
+impl fmt::Display for RoomId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "room-{:016x}", self.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RoomMeta {
+    pub id: RoomId,
+    /// The bounding extent of `parts`.
+    pub extent: Extent,
+    pub outline: RoomOutline,
+    /// The extents whose union is the room. Just `extent` unless the room has a composite outline.
+    pub parts: Vec<Extent>,
+    /// Derived from the map seed and the room's ID, so systems like loot tables can be
+    /// deterministic per room without sharing the generator's RNG.
+    pub seed: [u32; 4],
+    /// Like `seed`, but derived from the style seed, for choices about how the room looks.
+    pub style_seed: [u32; 4],
+    pub theme: RoomTheme,
+    pub shape: RoomShape,
+    /// The number of doors between this room and the entrance.
+    pub doors_from_entrance: usize,
+    /// The straight-line distance from the center of the entrance to the center of this room.
+    pub distance_from_entrance: f32,
+    /// Set if the spec has `difficulty`.
+    pub difficulty: Option<f32>,
+    /// Set for the terminal room of each branch if the spec has `branch_rewards`.
+    pub reward_tier: Option<usize>,
+    /// Safe rooms get no hazards.
+    pub safe: bool,
+    /// The doors of secret rooms are closed with `SECRET_WALL_VOXEL`.
+    pub secret: bool,
+    /// The number of floor voxels where an enemy could stand: the floor of the interior, minus
+    /// floor features, support columns and the spec's `spawn_safety_radius` around the spawn
+    /// points. Zero for safe rooms.
+    pub encounter_capacity: usize,
+    /// Set if the spec has an `atmosphere`.
+    pub atmosphere: Option<AtmosphereVolume>,
+    /// Set if the spec has `balconies` and the room is tall enough.
+    pub balcony: Option<Balcony>,
+    /// The layers of solid voxels that can be walked on, one voxel thick: the floor of each part,
+    /// then the top of the balcony.
+    pub walkable_surfaces: Vec<Extent>,
+}
+
+impl RoomMeta {
+    fn outlined(&self) -> Room {
+        Room {
+            outline: self.outline,
+            parts: self.parts.clone(),
+        }
+    }
+}
+
+/// Counts the floor columns of the interior of `room` that aren't covered by a floor feature or a
+/// support column, and aren't within `spawn_safety_radius` of a spawn point.
+fn encounter_capacity(
+    room: &Room,
+    floor_features: &[FloorFeature],
+    support_columns: &[Extent],
+    spawn_area: &SpawnArea,
+    spawn_safety_radius: f32,
+) -> usize {
+    let bounds = room.bounding_extent();
+    let mut columns = FnvHashSet::default();
+    for part in room.parts.iter() {
+        let interior = part.radial_grow(-WALL_THICKNESS);
+        let (min, sup) = (interior.get_minimum(), interior.get_world_supremum());
+        for x in min.x..sup.x {
+            for z in min.z..sup.z {
+                columns.insert((x, z));
+            }
+        }
+    }
+
+    let blocked = floor_features
+        .iter()
+        .flat_map(|f| &f.extent)
+        .chain(support_columns.iter().flatten())
+        .filter(|p| bounds.contains_world(p));
+    for p in blocked {
+        columns.remove(&(p.x, p.z));
+    }
+
+    let radius = spawn_safety_radius.max(0.0);
+    let reach = radius.floor() as i32;
+    let (min_y, sup_y) = (bounds.get_minimum().y, bounds.get_world_supremum().y);
+    for p in spawn_area.valid_spawn_points.iter() {
+        if p.y < min_y || p.y >= sup_y {
+            continue;
+        }
+        for dx in -reach..=reach {
+            for dz in -reach..=reach {
+                if ((dx * dx + dz * dz) as f32) <= radius * radius {
+                    columns.remove(&(p.x + dx, p.z + dz));
+                }
+            }
+        }
+    }
+
+    columns.len()
+}
+
+/// A door of the final map.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DoorRecord {
+    pub extent: Extent,
+    /// The way through the door when going from `rooms.0` to `rooms.1`. A corridor's door is in
+    /// the wall of only one of the rooms.
+    pub direction: Direction,
+    /// Indices into `DungeonMeta::rooms`, smallest first.
+    pub rooms: (usize, usize),
+    /// The door is closed with `SECRET_WALL_VOXEL`.
+    pub secret: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DungeonMeta {
     pub spawn_area: SpawnArea,
+    /// One entry per room in the final map.
+    pub rooms: Vec<RoomMeta>,
+    /// The number of rooms in the final map. Can exceed the requested number when pruning is
+    /// skipped or can't remove enough rooms without breaking the main path.
+    pub num_rooms: usize,
+    pub layout: Layout,
+    /// The graph of `layout`, for game AI and debugging tools.
+    pub room_graph: RoomGraph,
+    /// One for each door in `layout`, in the same order.
+    pub doors: Vec<DoorRecord>,
+    /// Indices into `doors` for each pair of rooms with doors between them.
+    pub doors_by_rooms: SymmetricMap<Vec<usize>, RoomId>,
+    /// Room and door indices are into `layout`. Every lock can be opened before the objective is
+    /// reached.
+    pub locks: Vec<Lock>,
+    /// Room indices are into `layout`. Empty unless the spec has `branch_rewards`.
+    pub branches: Vec<Branch>,
+    /// The corridors between rooms that don't share a wall. The layout records each corridor as a
+    /// door between its rooms, using the door into the first room.
+    pub corridors: Vec<Corridor>,
+    /// Room indices are into `layout`. Stubs lead nowhere, so they aren't in `layout`, and
+    /// minimaps can leave them out.
+    pub corridor_stubs: Vec<CorridorStub>,
+    /// Wall strips and ladders that were made climbable to reach raised doors.
+    pub climbable_strips: Vec<Extent>,
+    /// Room and door indices are into `layout`. Doors in floors with a staircase don't get ladders.
+    pub staircases: Vec<Staircase>,
+    /// Door indices are into `layout`. Doors with a staircase don't get ladders.
+    pub ladders: Vec<Ladder>,
+    /// Indices into `rooms` of each region that the rest of the map still only reaches through a
+    /// single door, because no second door fits. Empty unless `avoid_bottlenecks` is set.
+    pub bottleneck_regions: Vec<Vec<usize>>,
+    /// Indices into `rooms` of the rooms that every way between some other rooms goes through, so
+    /// removing one would split the map. Good places for gates and mini-bosses.
+    pub chokepoints: Vec<usize>,
+    /// Pairs of indices into `rooms` that share a door even though `never_connect` forbids it,
+    /// because nothing else connects them. The entrance is where it ended up, so moving the
+    /// entrance to satisfy `spawn_constraint` can also break the constraints.
+    pub never_connect_violations: Vec<(usize, usize)>,
+    /// The index into `rooms` of the room of each node of the spec's `mission`, in the same
+    /// order. Empty without a mission.
+    pub mission_rooms: Vec<usize>,
+    /// Indices into `rooms` of the most central rooms, most central first. Empty unless the spec
+    /// has `hubs`.
+    pub hubs: Vec<usize>,
+    pub ceiling_features: Vec<CeilingFeature>,
+    pub floor_features: Vec<FloorFeature>,
+    pub support_columns: Vec<Extent>,
+    /// At most one between each pair of rooms connected through a shared wall.
+    pub windows: Vec<Extent>,
+    /// Openings from rooms down into the halls below them.
+    pub galleries: Vec<Gallery>,
+    /// Where to spawn the torches and sconces on the walls.
+    pub lights: Vec<LightSource>,
+    pub reverb_zones: Vec<ReverbZone>,
+    pub door_style: DoorStyle,
+    /// Index into `rooms` of the room with the spawn area, at one end of the main path.
+    pub entrance_room: usize,
+    /// Index into `rooms` of the room at the other end of the main path.
+    pub objective_room: usize,
+    /// In `objective_room`, out of sight of its doors if possible.
+    pub objective: Option<ObjectivePoint>,
+    /// Frames the rooms for a camera with `OVERVIEW_VERTICAL_FOV`.
+    pub overview_camera: CameraPose,
+    /// The bounding extent of every voxel the generator wrote, backfill included, even when only
+    /// part of the map was written. `None` if nothing was written.
+    pub bounds: Option<Extent>,
+    pub stats: GenerationStats,
+}
+
+impl DungeonMeta {
+    pub fn room_with_id(&self, id: RoomId) -> Option<&RoomMeta> {
+        self.rooms.iter().find(|r| r.id == id)
+    }
+
+    /// The first door between the rooms with IDs `a` and `b`, in either order.
+    pub fn door_between(&self, a: RoomId, b: RoomId) -> Option<&DoorRecord> {
+        self.doors_by_rooms
+            .try_get(a, b)
+            .and_then(|doors| doors.first())
+            .map(|d| &self.doors[*d])
+    }
+}
+
+/// The outcome of `DungeonMapSpec::generate_best_of`.
+#[derive(Debug)]
+pub struct BestOfDungeons {
+    pub meta: DungeonMeta,
+    /// The seed that generated `meta`.
+    pub seed: [u32; 4],
+    /// Each candidate seed with the score of its layout, in the order the seeds were derived.
+    pub scores: Vec<([u32; 4], f32)>,
+}
+
+/// How the rooms are connected once the door graph is known.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub enum RoomGraphLayout {
+    /// Keep every door between the chosen rooms.
+    #[default]
+    SpanningTree,
+    /// Pick the room with the most doors as a hub, and only keep the doors on the shortest routes
+    /// from the hub to rooms within `max_spoke_length` doors of it. The main path runs out along
+    /// one spoke and back in along another, so it can't be longer than `2 * max_spoke_length + 1`
+    /// rooms.
+    HubAndSpoke { max_spoke_length: usize },
+}
+
+/// What a room is for, as far as `DungeonMapSpec::never_connect` is concerned. The entrance and
+/// the objective are also on the main path.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RoomRole {
+    Entrance,
+    Objective,
+    MainPath,
+    OffMainPath,
+}
+
+impl RoomRole {
+    /// Whether the room with index `room` has this role, where `main_path` runs from the
+    /// objective to the entrance.
+    pub fn fits(&self, main_path: &[usize], room: usize) -> bool {
+        match self {
+            RoomRole::Entrance => main_path.last() == Some(&room),
+            RoomRole::Objective => main_path.first() == Some(&room),
+            RoomRole::MainPath => main_path.contains(&room),
+            RoomRole::OffMainPath => !main_path.contains(&room),
+        }
+    }
+}
+
+/// Shapes the rooms off the main path into dead-end branches.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeadEndSpec {
+    /// The number of branches off the main path. Attempts that can't fit this many fail.
+    pub count: usize,
+    /// The number of rooms in each branch is sampled from `min_length..=max_length`. A branch
+    /// that runs out of rooms to grow into is kept if it has at least `min_length`.
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+/// How central a room is in the final room graph.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Centrality {
+    /// The number of rooms that share a door with the room.
+    Degree,
+    /// The number of shortest paths between other rooms that pass through the room, so the player
+    /// passes through it often.
+    Betweenness,
+}
+
+/// Picks out the rooms the player is likely to pass through most, e.g. for shops and safe rooms.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HubSpec {
+    pub centrality: Centrality,
+    /// The number of hubs to report. Fewer are reported if the map has fewer rooms.
+    pub count: usize,
+}
+
+impl HubSpec {
+    /// The `count` nodes of `graph` with the highest centrality, most central first. Ties go to the
+    /// lowest index.
+    pub fn hubs<N, E>(&self, graph: &StableGraph<N, E, Undirected>) -> Vec<NodeIndex> {
+        let mut centrality: Vec<(NodeIndex, f32)> = match self.centrality {
+            Centrality::Degree => graph
+                .node_indices()
+                .map(|n| {
+                    let neighbors: FnvHashSet<NodeIndex> = graph.neighbors(n).collect();
+
+                    (n, neighbors.len() as f32)
+                })
+                .collect(),
+            Centrality::Betweenness => {
+                let betweenness = betweenness_centrality(graph);
+
+                graph.node_indices().map(|n| (n, betweenness[&n])).collect()
+            }
+        };
+        centrality.sort_by(|(n1, c1), (n2, c2)| c2.partial_cmp(c1).unwrap().then(n1.cmp(n2)));
+
+        centrality
+            .into_iter()
+            .take(self.count)
+            .map(|(n, _)| n)
+            .collect()
+    }
+}
+
+/// How the doors between rooms are weighed for the spanning tree that the main path is chosen
+/// from.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SpanningTreeWeight {
+    /// Every door weighs the same, so the tree follows the order the rooms were sampled in.
+    #[default]
+    Unweighted,
+    /// The distance between the centers of the rooms.
+    CenterDistance,
+    /// One over the size of the doors between the rooms, so rooms with wide openings between them
+    /// are close.
+    InverseDoorArea,
+}
+
+impl SpanningTreeWeight {
+    pub fn weight(&self, r1: &Extent, r2: &Extent, doors: &[Extent]) -> f32 {
+        match self {
+            SpanningTreeWeight::Unweighted => 0.0,
+            SpanningTreeWeight::CenterDistance => {
+                distance_squared(&extent_center(r1), &extent_center(r2)).sqrt()
+            }
+            // Doors are two voxels thick, so their volume grows with the area of the opening.
+            SpanningTreeWeight::InverseDoorArea => {
+                1.0 / doors.iter().map(|d| d.volume()).sum::<usize>().max(1) as f32
+            }
+        }
+    }
 }
 
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct RoomGraphSpec {
     pub num_rooms: usize,
     pub entrance_to_objective_path_length: usize,
+    #[serde(default)]
+    pub layout: RoomGraphLayout,
+    /// Keep every connected room instead of pruning down to `num_rooms`, which becomes a minimum.
+    #[serde(default)]
+    pub keep_all_connected: bool,
+    /// If set, the rooms are connected by the tree that `layout` and pruning leave, plus this
+    /// fraction of the other doors between them, chosen at random, so there are alternate routes.
+    /// This can bring back doors that `layout` dropped. With `RoomGraphLayout::SpanningTree`, the
+    /// tree is the chosen spanning tree. The main path is then chosen from all of the doors, since
+    /// the loops can make it longer than any path in the tree. If `None`, `layout` decides which
+    /// doors are kept.
+    #[serde(default)]
+    pub loop_ratio: Option<f32>,
+    /// Make the two rooms farthest apart in the spanning tree the entrance and the objective, with
+    /// the whole path between them as the main path. `entrance_to_objective_path_length` becomes
+    /// a minimum. Otherwise, the main path is cut from one end of that path at the desired length.
+    #[serde(default)]
+    pub farthest_apart: bool,
+    #[serde(default)]
+    pub spanning_tree_weight: SpanningTreeWeight,
+    /// Choose the main path from the heaviest spanning tree instead of the lightest. With
+    /// `SpanningTreeWeight::CenterDistance`, the lightest tree keeps the main path compact and the
+    /// heaviest makes it sprawl.
+    #[serde(default)]
+    pub maximum_spanning_tree: bool,
+    /// Choose the main path from a spanning tree picked uniformly at random instead, so rooms in
+    /// the same places can still be laid out differently from seed to seed. Overrides
+    /// `spanning_tree_weight` and `maximum_spanning_tree`.
+    #[serde(default)]
+    pub random_spanning_tree: bool,
+    /// If set, pruning keeps the main path and these branches off of it, with no other doors
+    /// between them, instead of removing outer rooms until `num_rooms` is reached. `num_rooms`
+    /// then only decides how many rooms there are to grow the branches into. `loop_ratio` can
+    /// still add doors afterward. Ignored with `keep_all_connected`.
+    #[serde(default)]
+    pub dead_ends: Option<DeadEndSpec>,
+    /// If set, the number of rooms is this many per voxel of `RoomDistributionSpec::bounds`
+    /// instead of `num_rooms`, so one spec fits regions of any size. There are always enough for
+    /// the main path.
+    #[serde(default)]
+    pub density: Option<f32>,
 }
 
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct RoomDistributionSpec {
     pub location: LatticeUniformDistSpec,
     pub size: LatticeNormalDistSpec,
+    /// Candidates whose centers are closer than this to an already accepted candidate are
+    /// dropped, since near-duplicates only add overlap resolution work.
+    #[serde(default)]
+    pub min_center_separation: f32,
+    /// If set, the centers of the rooms are sampled this far apart, within the `location` ranges,
+    /// instead of the minimums being sampled independently. Rooms that start out spread apart
+    /// leave much less for overlap resolution to do.
+    #[serde(default)]
+    pub poisson_disk: Option<PoissonDiskDistSpec>,
+}
+
+impl RoomDistributionSpec {
+    /// Where rooms of the mean size go, from the lowest `location` to the highest plus the size.
+    pub fn bounds(&self) -> Extent {
+        let (location, size) = (&self.location, &self.size);
+        let min = [location.x.0, location.y.0, location.z.0];
+        let shape = [
+            location.x.1 - location.x.0 + size.x.mean.round() as i32,
+            location.y.1 - location.y.0 + size.y.mean.round() as i32,
+            location.z.1 - location.z.0 + size.z.mean.round() as i32,
+        ];
+
+        Extent::from_min_and_local_supremum(min.into(), shape.into())
+    }
+}
+
+/// Controls how many room candidates are sampled.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CandidateBudgetSpec {
+    /// The initial number of candidates is this factor times the desired number of rooms.
+    pub initial_factor: usize,
+    /// If the candidates can't be connected into enough rooms, batches of this many more are
+    /// sampled until there are enough or `max_candidates` is reached. Zero disables adaptive
+    /// sampling.
+    pub batch_size: usize,
+    pub max_candidates: usize,
+}
+
+impl Default for CandidateBudgetSpec {
+    fn default() -> Self {
+        CandidateBudgetSpec {
+            initial_factor: 10,
+            batch_size: 0,
+            max_candidates: 0,
+        }
+    }
+}
+
+impl CandidateBudgetSpec {
+    fn next_batch_size(&self, num_sampled: usize) -> usize {
+        self.batch_size
+            .min(self.max_candidates.saturating_sub(num_sampled))
+    }
+}
+
+/// Samples more room candidates than are needed and keeps the best, spatially diverse ones, instead
+/// of the first ones sampled.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CandidateScoringSpec {
+    /// The number of candidates sampled for each one that's kept.
+    pub oversampling: usize,
+    /// Rewards candidates that are far from the ones already kept, up to `max_room_dim` apart.
+    pub separation_weight: f32,
+    /// Rewards candidates by the fraction of their volume inside `bounds`.
+    pub bounds_weight: f32,
+    #[serde(default)]
+    pub bounds: Option<Extent>,
+    /// Rewards candidates by the density map at their centers.
+    pub density_weight: f32,
+    #[serde(default)]
+    pub density_peaks: Vec<DensityPeak>,
+}
+
+/// A place where the density map wants rooms. The density falls off linearly from 1 at `center` to
+/// 0 at `radius`, and the map is the highest density of any peak.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DensityPeak {
+    pub center: Point,
+    pub radius: f32,
+}
+
+impl CandidateScoringSpec {
+    /// The part of the score that doesn't depend on the other candidates.
+    fn intrinsic_score(&self, candidate: &Extent) -> f32 {
+        let volume = |e: &Extent| {
+            let s = e.get_local_supremum();
+            (s.x.max(0) * s.y.max(0) * s.z.max(0)) as f32
+        };
+        let fit = match &self.bounds {
+            Some(b) => volume(&candidate.intersection(b)) / volume(candidate).max(1.0),
+            None => 1.0,
+        };
+        let center = extent_center(candidate);
+        let density = self
+            .density_peaks
+            .iter()
+            .map(|peak| {
+                let c = [
+                    peak.center.x as f32,
+                    peak.center.y as f32,
+                    peak.center.z as f32,
+                ];
+                let d = distance_squared(&center, &c).sqrt();
+
+                (1.0 - d / peak.radius.max(f32::EPSILON)).max(0.0)
+            })
+            .fold(0.0, f32::max);
+
+        self.bounds_weight * fit + self.density_weight * density
+    }
+
+    /// Greedily picks `num` candidates from `pool`, each time taking the one whose intrinsic score
+    /// plus separation from `existing` and the candidates picked so far is highest. Ties go to the
+    /// earliest sampled.
+    fn select(
+        &self,
+        pool: Vec<Extent>,
+        existing: &[Extent],
+        num: usize,
+        separation_scale: f32,
+    ) -> Vec<Extent> {
+        let scores: Vec<f32> = pool.iter().map(|c| self.intrinsic_score(c)).collect();
+        let centers: Vec<[f32; 3]> = pool.iter().map(extent_center).collect();
+        let separation = |a: &[f32; 3], b: &[f32; 3]| {
+            (distance_squared(a, b).sqrt() / separation_scale.max(1.0)).min(1.0)
+        };
+        let mut nearest: Vec<f32> = centers
+            .iter()
+            .map(|c| {
+                existing
+                    .iter()
+                    .map(|e| separation(c, &extent_center(e)))
+                    .fold(1.0, f32::min)
+            })
+            .collect();
+
+        let mut picked = vec![false; pool.len()];
+        let mut selected = Vec::with_capacity(num.min(pool.len()));
+        while selected.len() < num {
+            let total = |i: usize| scores[i] + self.separation_weight * nearest[i];
+            let best =
+                (0..pool.len())
+                    .filter(|i| !picked[*i])
+                    .fold(None, |best: Option<usize>, i| match best {
+                        Some(b) if total(b) >= total(i) => Some(b),
+                        _ => Some(i),
+                    });
+            let i = match best {
+                Some(i) => i,
+                None => break,
+            };
+            picked[i] = true;
+            selected.push(pool[i]);
+            for (n, c) in nearest.iter_mut().zip(centers.iter()) {
+                *n = n.min(separation(c, &centers[i]));
+            }
+        }
+
+        selected
+    }
+}
+
+/// Grows rooms after overlap resolution for denser plans. Rooms never grow past `max_room_dim`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RoomExpansionSpec {
+    /// Rooms stop growing this far from other rooms. With zero, rooms grow until they share walls.
+    pub min_gap: u32,
+    /// If set, rooms don't grow outside of these bounds.
+    pub bounds: Option<Extent>,
+}
+
+/// Gives some rooms L, T or cross shaped outlines that fit inside the sampled extents.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CompositeRoomSpec {
+    /// The probability that a room gets a composite outline rather than staying a box.
+    pub probability: f32,
+    /// The minimum width of an outline's arms. Arms are always wide enough to have an interior
+    /// and narrow enough to leave a notch, so rooms that can't satisfy both stay boxes.
+    pub min_arm_width: u32,
+}
+
+impl CompositeRoomSpec {
+    fn sample_room(&self, extent: &Extent, rng: &mut impl Rng) -> Room {
+        if rng.gen::<f32>() >= self.probability {
+            return Room::from(*extent);
+        }
+
+        let outline = *[RoomOutline::L, RoomOutline::T, RoomOutline::Cross]
+            .choose(rng)
+            .unwrap();
+        let sup = extent.get_local_supremum();
+        let min_width = (self.min_arm_width as i32).max(2 * WALL_THICKNESS + 1);
+        let max_width = sup.x.min(sup.z) - 1;
+        if min_width > max_width {
+            return Room::from(*extent);
+        }
+
+        Room::with_outline(
+            extent,
+            outline,
+            rng.gen_range(min_width, max_width + 1),
+            rng,
+        )
+    }
+}
+
+/// What the phases of generation pass along to each other. `DungeonMapSpec::try_generate` runs
+/// every phase in turn, but a custom pipeline can run them itself and replace any of them, e.g.
+/// start from `GenerationState::new` with the rooms of its own layout algorithm. Room IDs are
+/// indices into `room_candidates`.
+#[derive(Clone)]
+pub struct GenerationState {
+    /// The bounding extents of the rooms, including walls.
+    pub room_candidates: Vec<Extent>,
+    /// The outline of each room in `room_candidates`.
+    pub outlined_rooms: Vec<Room>,
+    pub doors: SymmetricMap<Vec<Extent>>,
+    pub corridors: SymmetricMap<Corridor>,
+    /// The rooms and the doors between them that make it into the map. Node weights are room IDs.
+    pub room_graph: StableGraph<usize, (), Undirected>,
+    /// Every door between the connected rooms, before any were removed to shape the map.
+    pub connected_graph: StableGraph<usize, (), Undirected>,
+    /// The spanning tree of `room_graph` that `main_path` was chosen from. Node weights are room
+    /// IDs.
+    pub spanning_tree: StableGraph<usize, (), Undirected>,
+    /// Room IDs from the objective to the entrance.
+    pub main_path: Vec<usize>,
+    /// Like `DungeonMeta::bottleneck_regions`.
+    pub bottleneck_regions: Vec<Vec<usize>>,
+    pub stats: GenerationStats,
+    injection: Option<SmallRng>,
+}
+
+impl GenerationState {
+    pub fn new(room_candidates: Vec<Extent>) -> Self {
+        GenerationState {
+            room_candidates,
+            outlined_rooms: Vec::new(),
+            doors: SymmetricMap::new(),
+            corridors: SymmetricMap::new(),
+            room_graph: StableGraph::default(),
+            connected_graph: StableGraph::default(),
+            spanning_tree: StableGraph::default(),
+            main_path: Vec::new(),
+            bottleneck_regions: Vec::new(),
+            stats: GenerationStats::default(),
+            injection: None,
+        }
+    }
+}
+
+/// Makes phases of generation fail on purpose, so integrations can exercise their retry and error
+/// handling against the failures the generator really has. Failures are drawn from a stream seeded
+/// by the attempt's RNG, so they're reproducible.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FailureInjectionSpec {
+    /// The chance that each sampled room candidate is rejected.
+    pub candidate_rejection: f32,
+    /// The chance that each door between a pair of rooms fails to generate.
+    pub door_failure: f32,
+    /// The chance that an attempt fails after every phase has succeeded, before anything is
+    /// written.
+    pub attempt_failure: f32,
+}
+
+impl FailureInjectionSpec {
+    fn reject_candidates(&self, candidates: &mut Vec<Extent>, rng: &mut impl Rng) {
+        let before = candidates.len();
+        candidates.retain(|_| rng.gen::<f32>() >= self.candidate_rejection);
+        log::debug!(
+            "Injected {} candidate rejections",
+            before - candidates.len()
+        );
+    }
+
+    fn fail_doors(&self, room_graph: &mut StableGraph<usize, (), Undirected>, rng: &mut impl Rng) {
+        let failed: Vec<_> = room_graph
+            .edge_indices()
+            .filter(|_| rng.gen::<f32>() < self.door_failure)
+            .collect();
+        log::debug!("Injected {} door failures", failed.len());
+        for e in failed.into_iter() {
+            room_graph.remove_edge(e);
+        }
+    }
 }
 
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct DungeonMapSpec {
     pub seed: [u32; 4],
+    /// Seeds the choices about how the map looks, like the door style, room themes and
+    /// decorations, instead of `seed`. A campaign can share one style seed between maps with
+    /// different layouts to keep them looking alike.
+    #[serde(default)]
+    pub style_seed: Option<[u32; 4]>,
     pub room_graph: RoomGraphSpec,
     pub room_dist: RoomDistributionSpec,
+    #[serde(default)]
+    pub candidate_budget: CandidateBudgetSpec,
+    /// If set, candidates are chosen from a larger pool by score.
+    #[serde(default)]
+    pub candidate_scoring: Option<CandidateScoringSpec>,
     pub min_room_dim: u32,
     pub max_room_dim: u32,
     pub min_door_dim: u32,
     pub max_door_dim: u32,
+    #[serde(default)]
+    pub door_selection: DoorSelectionSpec,
+    /// The voxel types of room floors, walls and ceilings. Corridors are always `FLOOR_VOXEL`.
+    #[serde(default)]
+    pub surface_voxel_types: SurfaceVoxelTypes,
+    /// Resolved once per map from the seed, so every door in a map has the same style.
+    #[serde(default)]
+    pub door_style: Variant<DoorStyle>,
+    /// If set, the openings of doors that aren't secret are framed with trim.
+    #[serde(default)]
+    pub door_frames: Option<DoorFrameSpec>,
+    /// After every other pass, empty anything that ended up in the opening of a door that isn't
+    /// secret. Reading back the written voxels takes memory for every voxel of the map.
+    #[serde(default)]
+    pub recarve_doors: bool,
+    /// After every other pass, the walls around the openings of doors that aren't secret are
+    /// pulled toward the surface, so smooth meshing of the distance field makes clean jambs. Like
+    /// `recarve_doors`, this reads back the written voxels.
+    #[serde(default)]
+    pub trim_door_jambs: bool,
+    /// If set, the spawn area must satisfy this constraint or the attempt is rejected.
+    #[serde(default)]
+    pub spawn_constraint: Option<SpawnConstraint>,
+    /// If set, walls below doors that are too high to jump to are made climbable.
+    #[serde(default)]
+    pub climbing: Option<ClimbingSpec>,
+    /// If set, doors in floors get a staircase up from the room below where there's space for
+    /// one. Handrails use the wall type of `surface_voxel_types`.
+    #[serde(default)]
+    pub stairs: Option<StairSpec>,
+    /// Doors in floors without a staircase get a ladder of `LADDER_VOXEL` up one wall of the
+    /// shaft.
+    #[serde(default)]
+    pub ladders: bool,
+    /// The probability that a room is cave-themed rather than a hall.
+    #[serde(default)]
+    pub cave_room_ratio: f32,
+    #[serde(default)]
+    pub ceiling_decoration: Option<CeilingDecorationSpec>,
+    /// If set, large rooms get columns of the wall type of `surface_voxel_types`.
+    #[serde(default)]
+    pub support_columns: Option<SupportColumnSpec>,
+    /// If set, lights are placed along the walls of every room.
+    #[serde(default)]
+    pub lighting: Option<LightingSpec>,
+    /// If set, pillars, rubble and crates are scattered over the floors.
+    #[serde(default)]
+    pub interior_decoration: Option<InteriorDecorationSpec>,
+    /// If set, each room's metadata describes the fog and gas inside of it.
+    #[serde(default)]
+    pub atmosphere: Option<AtmosphereSpec>,
+    /// If set, the space between rooms and corridors is filled with solid voxels instead of being
+    /// left untouched.
+    #[serde(default)]
+    pub backfill: Option<BackfillSpec>,
+    /// If set, doors along the main path are locked, with their keys in side rooms.
+    #[serde(default)]
+    pub progression: Option<ProgressionSpec>,
+    /// If set, each room gets a difficulty score that rises from the entrance to the objective
+    /// and out along the branches.
+    #[serde(default)]
+    pub difficulty: Option<DifficultySpec>,
+    /// If set, the branches off the main path are recorded, and the room at the end of each gets
+    /// a reward tier that grows with the length of the detour.
+    #[serde(default)]
+    pub branch_rewards: Option<BranchRewardSpec>,
+    /// The probability that a dead-end room off the main path is secret, with its doors closed by
+    /// `SECRET_WALL_VOXEL`.
+    #[serde(default)]
+    pub secret_room_ratio: f32,
+    /// If set, tall rooms without composite outlines get a balcony.
+    #[serde(default)]
+    pub balconies: Option<BalconySpec>,
+    /// If set, windows may be cut above the floor in the walls between connected rooms.
+    #[serde(default)]
+    pub windows: Option<WindowSpec>,
+    /// If set, rooms stacked on top of halls may overlook them through railed openings in the
+    /// floor.
+    #[serde(default)]
+    pub galleries: Option<GallerySpec>,
+    /// If set, rooms that don't share a wall may be connected by corridors.
+    #[serde(default)]
+    pub corridors: Option<CorridorSpec>,
+    /// If set, short sealed corridors lead off some of the walls without doors.
+    #[serde(default)]
+    pub corridor_stubs: Option<CorridorStubSpec>,
+    /// Every region of rooms that the rest of the map only reaches through a single door gets a
+    /// second door where the rooms allow it, so no region has a single chokepoint. This can bring
+    /// back doors that `RoomGraphLayout::HubAndSpoke` or pruning dropped.
+    #[serde(default)]
+    pub avoid_bottlenecks: bool,
+    /// If set, each step of the mission is put in its own room, so that a step's room can only be
+    /// reached through the rooms of the steps before it. Attempts where the rooms can't be
+    /// arranged that way fail, so check the mission with `MissionGraph::order` first.
+    #[serde(default)]
+    pub mission: Option<MissionGraph>,
+    /// Rooms with these pairs of roles don't share doors. Roles are only known once the main path
+    /// is chosen, so the doors that break these are dropped then, and the passes that add doors
+    /// afterward skip them. Doors that are the only way to connect the rooms, or that join rooms
+    /// along the main path, are kept and reported in `DungeonMeta::never_connect_violations`.
+    #[serde(default)]
+    pub never_connect: Vec<(RoomRole, RoomRole)>,
+    /// If set, the most central rooms of the final map are reported in `DungeonMeta::hubs`.
+    #[serde(default)]
+    pub hubs: Option<HubSpec>,
+    /// Requires that no single door can be removed to split the map in two. Doors of the
+    /// connected candidates that were dropped are brought back, and second doors are added
+    /// between rooms that share a wall, where needed. Attempts where that isn't enough fail.
+    #[serde(default)]
+    pub two_edge_connected: bool,
+    /// After pruning, the clusters of rooms that share walls are pulled toward each other and the
+    /// corridors between them are routed again, so the map's bounding box is smaller. Only has an
+    /// effect with `corridors`, since rooms that share a wall can't move apart.
+    #[serde(default)]
+    pub shrink_to_fit: bool,
+    /// Rooms within this many doors of the entrance are safe. If `None`, no rooms are safe.
+    #[serde(default)]
+    pub safe_zone_radius: Option<usize>,
+    /// Floor voxels within this horizontal distance of a spawn point don't count toward a room's
+    /// `encounter_capacity`, so enemies aren't budgeted right next to the player. At 0, only the
+    /// spawn points themselves are left out.
+    #[serde(default)]
+    pub spawn_safety_radius: f32,
+    /// If set, sill steps and climbable strips respect the character's limits, and dungeons with
+    /// doors the character can't get through are rejected.
+    #[serde(default)]
+    pub movement: Option<MovementSpec>,
+    /// If set, these are the room candidates instead of sampled ones, e.g. the rooms of a previous
+    /// `DungeonMeta::layout`. They're used as-is without overlap resolution, so only the
+    /// connections and everything that depends on them are regenerated.
+    #[serde(default)]
+    pub fixed_rooms: Option<Vec<Extent>>,
+    /// Controls whether overlapping rooms are pushed apart horizontally, vertically or both.
+    #[serde(default)]
+    pub separation_weights: SeparationWeights,
+    /// Pull rooms back toward the center of the layout after overlap resolution, so the layout
+    /// doesn't sprawl.
+    #[serde(default)]
+    pub compact_rooms: bool,
+    /// Only write the voxels at the boundary between solid and air, for engines that reconstruct
+    /// solid interiors themselves.
+    #[serde(default)]
+    pub shell_only: bool,
+    /// If set, rooms grow into the gaps left by overlap resolution.
+    #[serde(default)]
+    pub room_expansion: Option<RoomExpansionSpec>,
+    /// If set, some rooms aren't boxes. Ignored with `corridors`, which are routed between whole
+    /// extents.
+    #[serde(default)]
+    pub composite_rooms: Option<CompositeRoomSpec>,
+    /// Only for testing how callers handle failed attempts.
+    #[serde(default)]
+    pub failure_injection: Option<FailureInjectionSpec>,
 }
 
 impl DungeonMapSpec {
+    /// `style_seed` if set, otherwise `seed`.
+    pub fn style_seed(&self) -> [u32; 4] {
+        self.style_seed.unwrap_or(self.seed)
+    }
+
+    /// `room_graph.num_rooms`, or the number that `room_graph.density` gives.
+    pub fn num_rooms(&self) -> usize {
+        match self.room_graph.density {
+            Some(density) => {
+                let volume = self.room_dist.bounds().volume() as f32;
+
+                ((density * volume).round() as usize)
+                    .max(self.room_graph.entrance_to_objective_path_length)
+            }
+            None => self.room_graph.num_rooms,
+        }
+    }
+
     fn valid_room_size(&self, room: &Extent) -> bool {
         let dims = room.get_local_supremum();
 
@@ -62,14 +976,56 @@ impl DungeonMapSpec {
             && *dims <= [self.max_room_dim as i32; 3].into()
     }
 
-    fn generate_room_candidates(&self, rng: &mut impl Rng) -> Vec<Extent> {
-        sample_extents(
-            10 * self.room_graph.num_rooms,
-            |r: &Extent| self.valid_room_size(r),
-            self.room_dist.location.make(),
-            self.room_dist.size.make(),
-            rng,
-        )
+    /// With `candidate_scoring`, the candidates are also scored for their separation from
+    /// `existing` ones.
+    fn generate_room_candidates(
+        &self,
+        num_candidates: usize,
+        existing: &[Extent],
+        rng: &mut impl Rng,
+    ) -> Vec<Extent> {
+        let oversampling = self
+            .candidate_scoring
+            .as_ref()
+            .map_or(1, |s| s.oversampling.max(1));
+        let sampled = match &self.room_dist.poisson_disk {
+            Some(spec) => {
+                let existing_centers: Vec<Point> = existing
+                    .iter()
+                    .map(|e| *e.get_minimum() + *e.get_local_supremum() / 2)
+                    .collect();
+                let centers = spec.sample(
+                    &self.room_dist.location,
+                    num_candidates * oversampling,
+                    &existing_centers,
+                    rng,
+                );
+                let size_dist = self.room_dist.size.make();
+
+                centers
+                    .into_iter()
+                    .map(|center| loop {
+                        let size = size_dist.sample(rng);
+                        let extent = Extent::from_min_and_local_supremum(center - size / 2, size);
+                        if self.valid_room_size(&extent) {
+                            break extent;
+                        }
+                    })
+                    .collect()
+            }
+            None => sample_extents(
+                num_candidates * oversampling,
+                |r: &Extent| self.valid_room_size(r),
+                self.room_dist.location.make(),
+                self.room_dist.size.make(),
+                rng,
+            ),
+        };
+
+        match &self.candidate_scoring {
+            Some(spec) => spec.select(sampled, existing, num_candidates, self.max_room_dim as f32),
+            None => sampled,
+        }
     }
 
     /// Returns true iff we were able to remove exactly enough rooms to hit the desired room count.
@@ -97,7 +1053,7 @@ impl DungeonMapSpec {
             true
         };
 
-        prune_outer_nodes_to_reach_size(room_graph, accept_fn, self.room_graph.num_rooms);
+        prune_outer_nodes_to_reach_size(room_graph, accept_fn, self.num_rooms());
         log::debug!(
             "{} rooms after pruning outer nodes",
             room_graph.node_count()
@@ -107,91 +1063,2074 @@ impl DungeonMapSpec {
             Dot::with_config(&room_graph.clone(), &[Config::EdgeNoLabel])
         );
 
-        room_graph.node_count() >= self.room_graph.num_rooms
+        room_graph.node_count() >= self.num_rooms()
     }
 
-    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
-    /// encoder untouched on failure.
-    pub fn try_generate(
+    /// Whether `never_connect` forbids a door between rooms `a` and `b`.
+    fn never_connects(&self, main_path: &[usize], a: usize, b: usize) -> bool {
+        self.never_connect.iter().any(|(x, y)| {
+            (x.fits(main_path, a) && y.fits(main_path, b))
+                || (x.fits(main_path, b) && y.fits(main_path, a))
+        })
+    }
+
+    /// Removes the doors of `room_graph` that `never_connect` forbids, unless they join rooms
+    /// along `main_path` or the rooms aren't connected without them.
+    fn drop_forbidden_doors(
         &self,
+        main_path: &[usize],
+        room_graph: &mut StableGraph<usize, (), Undirected>,
+    ) {
+        let forbidden: Vec<EdgeIndex> = room_graph
+            .edge_references()
+            .filter(|e| {
+                let (a, b) = (room_graph[e.source()], room_graph[e.target()]);
+                self.never_connects(main_path, a, b) && !along_main_path(main_path, a, b)
+            })
+            .map(|e| e.id())
+            .collect();
+        for e in forbidden.into_iter() {
+            let (a, b) = room_graph.edge_endpoints(e).expect("Edge must exist");
+            if reachable_without_edge(room_graph, a, e).contains(&b) {
+                log::debug!(
+                    "Dropped the forbidden door {:?}",
+                    (room_graph[a], room_graph[b])
+                );
+                room_graph.remove_edge(e);
+            }
+        }
+    }
+
+    /// Removes every room and door of `room_graph` that isn't on `main_path` or one of the dead-end
+    /// branches grown off of it. Returns false if fewer than `spec.count` branches fit.
+    fn keep_dead_ends(
+        &self,
+        spec: &DeadEndSpec,
+        main_path: &[usize],
+        room_graph: &mut StableGraph<usize, (), Undirected>,
         rng: &mut impl Rng,
-        encoder: &mut impl VoxelEncoder,
-    ) -> Option<DungeonMeta> {
-        log::debug!("Generating dungeon map");
+    ) -> bool {
+        let node_of: FnvHashMap<usize, NodeIndex> = room_graph
+            .node_indices()
+            .map(|n| (room_graph[n], n))
+            .collect();
+        let trunk: Vec<NodeIndex> = main_path.iter().map(|r| node_of[r]).collect();
+        let branches = grow_branches(
+            room_graph,
+            &trunk,
+            spec.count,
+            spec.min_length,
+            spec.max_length,
+            |a, b| !self.never_connects(main_path, room_graph[a], room_graph[b]),
+            rng,
+        );
+        log::debug!(
+            "Dead ends = {:?}",
+            branches
+                .iter()
+                .map(|b| b.iter().map(|n| room_graph[*n]).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        );
 
-        let mut room_candidates = self.generate_room_candidates(rng);
-        log::debug!("Generated {} room candidates", room_candidates.len());
+        let kept: FnvHashSet<NodeIndex> = trunk
+            .iter()
+            .chain(branches.iter().flatten())
+            .cloned()
+            .collect();
+        room_graph.retain_nodes(|_, n| kept.contains(&n));
+        room_graph.clear_edges();
+        for path in std::iter::once(&trunk).chain(branches.iter()) {
+            for w in path.windows(2) {
+                room_graph.add_edge(w[0], w[1], ());
+            }
+        }
 
-        resolve_extent_overlaps(&mut room_candidates);
-        log::debug!("Done resolving room overlaps");
+        branches.len() == spec.count
+    }
+
+    /// Adds `loop_ratio` of the edges of `connected_graph` between the rooms of `room_graph` that
+    /// it doesn't have yet, chosen with `rng`. If `room_graph` still has every door, it's first cut
+    /// down to a spanning tree with the edges of `main_path` and as many of `spanning_tree` as
+    /// possible, so the loops are the only alternate routes.
+    fn keep_loops(
+        &self,
+        loop_ratio: f32,
+        connected_graph: &StableGraph<usize, (), Undirected>,
+        spanning_tree: &StableGraph<usize, (), Undirected>,
+        main_path: &[usize],
+        room_graph: &mut StableGraph<usize, (), Undirected>,
+        rng: &mut impl Rng,
+    ) {
+        if room_graph.edge_count() >= room_graph.node_count() {
+            let mut in_tree = SymmetricMap::new();
+            for e in spanning_tree.edge_references() {
+                in_tree.insert(spanning_tree[e.source()], spanning_tree[e.target()], ());
+            }
+            let mut edges: Vec<(NodeIndex, NodeIndex)> = room_graph
+                .edge_references()
+                .map(|e| (e.source(), e.target()))
+                .collect();
+            // Main path edges go first, so the tree contains them, then the edges of the spanning
+            // tree, and forbidden edges go last, so the tree only contains those it can't do
+            // without. The sort is stable.
+            edges.sort_by_key(|(a, b)| {
+                let (a, b) = (room_graph[*a], room_graph[*b]);
+                (
+                    !along_main_path(main_path, a, b),
+                    in_tree.try_get(a, b).is_none(),
+                    self.never_connects(main_path, a, b),
+                )
+            });
+            let mut components = UnionFind::new(room_graph.node_bound());
+            let tree: Vec<_> = edges
+                .into_iter()
+                .filter(|(a, b)| components.union(a.index(), b.index()))
+                .collect();
+            room_graph.clear_edges();
+            for (a, b) in tree {
+                room_graph.add_edge(a, b, ());
+            }
+        }
+
+        let kept: FnvHashMap<usize, NodeIndex> = room_graph
+            .node_indices()
+            .map(|n| (room_graph[n], n))
+            .collect();
+        let mut others: Vec<(NodeIndex, NodeIndex)> = connected_graph
+            .edge_references()
+            .filter_map(|e| {
+                let (a, b) = (connected_graph[e.source()], connected_graph[e.target()]);
+                Some((*kept.get(&a)?, *kept.get(&b)?))
+            })
+            .filter(|(a, b)| {
+                room_graph.find_edge(*a, *b).is_none()
+                    && !self.never_connects(main_path, room_graph[*a], room_graph[*b])
+            })
+            .collect();
+        others.shuffle(rng);
+        let num_loops = (loop_ratio.clamp(0.0, 1.0) * others.len() as f32).round() as usize;
+        log::debug!("Keeping {} of {} loop doors", num_loops, others.len());
+
+        for (a, b) in others.into_iter().take(num_loops) {
+            room_graph.add_edge(a, b, ());
+        }
+    }
 
-        let mut doors = SymmetricMap::new();
-        let mut room_graph = generate_door_graph(
-            &room_candidates,
-            self.min_door_dim,
-            self.max_door_dim,
+    /// Adds doors with `make_two_edge_connected`, then returns the rooms of each region of
+    /// `room_graph` that the other rooms still only reach through a single door, as indices into
+    /// the final rooms. The region is the smaller side of that door.
+    #[allow(clippy::too_many_arguments)]
+    fn add_doors_to_bottlenecks(
+        &self,
+        main_path: &[usize],
+        connected_graph: &StableGraph<usize, (), Undirected>,
+        outlined_rooms: &[Room],
+        corridors: &SymmetricMap<Corridor>,
+        doors: &mut SymmetricMap<Vec<Extent>>,
+        room_graph: &mut StableGraph<usize, (), Undirected>,
+        rng: &mut impl Rng,
+    ) -> Vec<Vec<usize>> {
+        self.make_two_edge_connected(
+            main_path,
+            connected_graph,
+            outlined_rooms,
+            corridors,
+            doors,
+            room_graph,
             rng,
-            &mut doors,
         );
 
-        // Prune disconnected rooms.
-        if let Some(subgraph) = largest_connected_subgraph(&room_graph) {
-            if subgraph.node_count() < self.room_graph.num_rooms {
-                return None;
-            } else {
-                room_graph = subgraph;
+        let graph = door_graph(room_graph, doors);
+        let unmet: Vec<FnvHashSet<usize>> = bridges(&graph)
+            .into_iter()
+            .map(|e| {
+                let (a, _) = graph.edge_endpoints(e).expect("Edge must exist");
+                let side = reachable_without_edge(&graph, a, e);
+                let region: FnvHashSet<usize> = graph
+                    .node_indices()
+                    .filter(|n| side.contains(n) == (2 * side.len() <= graph.node_count()))
+                    .map(|n| graph[n])
+                    .collect();
+                log::debug!("Region {:?} is a bottleneck", region);
+
+                region
+            })
+            .collect();
+
+        let final_index: FnvHashMap<usize, usize> = room_graph
+            .node_indices()
+            .enumerate()
+            .map(|(i, n)| (room_graph[n], i))
+            .collect();
+
+        unmet
+            .iter()
+            .map(|region| {
+                let mut rooms: Vec<usize> = region.iter().map(|r| final_index[r]).collect();
+                rooms.sort_unstable();
+                rooms
+            })
+            .collect()
+    }
+
+    /// Adds doors until no single door of `room_graph` is a bridge, choosing among the doors of
+    /// `connected_graph` that were dropped and second doors between rooms that share a wall.
+    /// Returns false if that isn't possible.
+    #[allow(clippy::too_many_arguments)]
+    fn make_two_edge_connected(
+        &self,
+        main_path: &[usize],
+        connected_graph: &StableGraph<usize, (), Undirected>,
+        outlined_rooms: &[Room],
+        corridors: &SymmetricMap<Corridor>,
+        doors: &mut SymmetricMap<Vec<Extent>>,
+        room_graph: &mut StableGraph<usize, (), Undirected>,
+        rng: &mut impl Rng,
+    ) -> bool {
+        let door_dims = SubspanSampler::new(self.min_door_dim as i32, self.max_door_dim as i32);
+        let mut no_second_door = FnvHashSet::default();
+        loop {
+            let kept: FnvHashMap<usize, NodeIndex> = room_graph
+                .node_indices()
+                .map(|n| (room_graph[n], n))
+                .collect();
+            let dropped = connected_graph
+                .edge_references()
+                .filter_map(|e| {
+                    let x = *kept.get(&connected_graph[e.source()])?;
+                    let y = *kept.get(&connected_graph[e.target()])?;
+
+                    Some((x, y))
+                })
+                .filter(|(x, y)| !room_graph.contains_edge(*x, *y));
+            // A corridor only has room for one door at each end.
+            let second_doors = room_graph
+                .edge_references()
+                .map(|e| (e.source(), e.target()))
+                .filter(|(x, y)| {
+                    let (r1, r2) = (room_graph[*x], room_graph[*y]);
+                    corridors.try_get(r1, r2).is_none()
+                        && !no_second_door.contains(&(r1.min(r2), r1.max(r2)))
+                });
+            let candidates: Vec<(NodeIndex, NodeIndex)> = dropped
+                .chain(second_doors)
+                .filter(|(x, y)| !self.never_connects(main_path, room_graph[*x], room_graph[*y]))
+                .collect();
+
+            let chosen = two_edge_connecting_edges(&door_graph(room_graph, doors), &candidates);
+            let mut all_added = true;
+            for (x, y) in chosen.into_iter() {
+                let (r1, r2) = (room_graph[x], room_graph[y]);
+                if !room_graph.contains_edge(x, y) {
+                    log::debug!("Restored the door between rooms {} and {}", r1, r2);
+                    room_graph.add_edge(x, y, ());
+                    continue;
+                }
+
+                let other_doors = collect_doors_from_room_graph(doors, room_graph);
+                let mut pair_doors = doors.get(r1, r2).clone();
+                let door = (0..SECOND_DOOR_TRIES).find_map(|_| {
+                    select_door_between_composite_rooms(
+                        &door_dims,
+                        &self.door_selection,
+                        &other_doors,
+                        &pair_doors,
+                        &outlined_rooms[r1],
+                        &outlined_rooms[r2],
+                        rng,
+                    )
+                });
+                match door {
+                    Some(door) => {
+                        log::debug!("Added another door between rooms {} and {}", r1, r2);
+                        pair_doors.push(door);
+                        doors.insert(r1, r2, pair_doors);
+                    }
+                    None => {
+                        // Choose again without this pair.
+                        no_second_door.insert((r1.min(r2), r1.max(r2)));
+                        all_added = false;
+                        break;
+                    }
+                }
+            }
+            if all_added {
+                break;
             }
         }
-        log::debug!("{} connected rooms", room_graph.node_count());
 
-        let mst = StableGraph::from_elements(min_spanning_tree(&room_graph));
-        log::debug!(
-            "MST before pruning = {:?}",
-            Dot::with_config(&mst, &[Config::EdgeNoLabel])
-        );
+        bridges(&door_graph(room_graph, doors)).is_empty()
+    }
+
+    /// Moves each cluster of rooms in `room_graph` that are connected through shared walls toward
+    /// the centroid of all the rooms, one voxel at a time along X and Z, while it stays a corridor's
+    /// width away from the other clusters. Then routes each corridor between clusters again. Nothing
+    /// changes unless every corridor can be routed; returns whether the rooms moved.
+    #[allow(clippy::too_many_arguments)]
+    fn shrink_to_fit(
+        &self,
+        corridor_spec: &CorridorSpec,
+        room_graph: &StableGraph<usize, (), Undirected>,
+        room_candidates: &mut [Extent],
+        outlined_rooms: &mut [Room],
+        doors: &mut SymmetricMap<Vec<Extent>>,
+        corridors: &mut SymmetricMap<Corridor>,
+        rng: &mut impl Rng,
+    ) -> bool {
+        let mut components = UnionFind::new(room_graph.node_bound());
+        for e in room_graph.edge_references() {
+            if corridors
+                .try_get(room_graph[e.source()], room_graph[e.target()])
+                .is_none()
+            {
+                components.union(e.source().index(), e.target().index());
+            }
+        }
+        let mut cluster_of: FnvHashMap<usize, usize> = FnvHashMap::default();
+        let mut clusters: Vec<Vec<usize>> = Vec::new();
+        let mut cluster_of_root: FnvHashMap<usize, usize> = FnvHashMap::default();
+        for n in room_graph.node_indices() {
+            let c = *cluster_of_root
+                .entry(components.find(n.index()))
+                .or_insert_with(|| {
+                    clusters.push(Vec::new());
+                    clusters.len() - 1
+                });
+            clusters[c].push(room_graph[n]);
+            cluster_of.insert(room_graph[n], c);
+        }
+        if clusters.len() < 2 {
+            return false;
+        }
 
-        let main_path = choose_main_path(self.room_graph.entrance_to_objective_path_length, &mst)?;
-        log::debug!("Main path = {:?}", main_path);
+        let mut offsets = vec![Point::from([0, 0, 0]); clusters.len()];
+        let placed = |c: usize, offsets: &[Point]| -> Vec<Extent> {
+            clusters[c]
+                .iter()
+                .map(|r| room_candidates[*r] + offsets[c])
+                .collect()
+        };
+        let all_rooms: Vec<Extent> = clusters
+            .iter()
+            .flatten()
+            .map(|r| room_candidates[*r])
+            .collect();
+        let centroid = extent_center(&bounding_extent(&all_rooms).expect("Must have rooms"));
+        let margin = corridor_spec.outer_width();
+        loop {
+            let mut any_moved = false;
+            for c in 0..clusters.len() {
+                for axis in [0, 2].iter() {
+                    let rooms = placed(c, &offsets);
+                    let center = extent_center(&bounding_extent(&rooms).expect("Must have rooms"));
+                    let offset = centroid[*axis] - center[*axis];
+                    if offset.abs() < 1.0 {
+                        continue;
+                    }
 
-        // Make sure we keep at least the main path nodes.
-        self.prune_rooms_to_desired_size(&main_path, &mut room_graph);
+                    let step = with_component(&[0, 0, 0].into(), *axis, offset.signum() as i32);
+                    let collides = (0..clusters.len()).filter(|o| *o != c).any(|o| {
+                        placed(o, &offsets).iter().any(|other| {
+                            let grown = other.radial_grow(margin);
+                            rooms
+                                .iter()
+                                .any(|r| !(*r + step).intersection(&grown).is_empty())
+                        })
+                    });
+                    if !collides {
+                        offsets[c] = offsets[c] + step;
+                        any_moved = true;
+                    }
+                }
+            }
 
-        let chosen_rooms = collect_rooms_from_room_graph(&room_candidates, &room_graph);
-        let chosen_doors = collect_doors_from_room_graph(&doors, &room_graph);
+            if !any_moved {
+                break;
+            }
+        }
+        if offsets.iter().all(|o| *o == Point::from([0, 0, 0])) {
+            return false;
+        }
 
-        fill_map_with_rooms(&chosen_rooms, encoder);
-        fill_map_with_doors(&chosen_doors, encoder);
+        let moved = |r: usize| room_candidates[r] + offsets[cluster_of[&r]];
+        let door_dims = SubspanSampler::new(self.min_door_dim as i32, self.max_door_dim as i32);
+        let mut obstacles: Vec<Extent> = clusters.iter().flatten().map(|r| moved(*r)).collect();
+        let mut rerouted = Vec::new();
+        for e in room_graph.edge_references() {
+            let (a, b) = (room_graph[e.source()], room_graph[e.target()]);
+            if cluster_of[&a] == cluster_of[&b] {
+                continue;
+            }
+            // Routed from the lower index, like `generate_connection_graph`.
+            let (a, b) = (a.min(b), a.max(b));
+            match try_route_corridor(
+                &moved(a),
+                &moved(b),
+                corridor_spec,
+                &door_dims,
+                &obstacles,
+                rng,
+            ) {
+                Some(corridor) => {
+                    obstacles.extend(corridor.segments.iter().cloned());
+                    rerouted.push((a, b, corridor));
+                }
+                None => {
+                    log::debug!("Can't shrink to fit, no corridor from {} to {}", a, b);
+                    return false;
+                }
+            }
+        }
 
-        let spawn_area = spawn_in_room(&room_candidates[*main_path.last().unwrap()]);
-        log::debug!("Spawn area = {:?}", spawn_area);
+        for e in room_graph.edge_references() {
+            let (a, b) = (room_graph[e.source()], room_graph[e.target()]);
+            if cluster_of[&a] == cluster_of[&b] {
+                let offset = offsets[cluster_of[&a]];
+                let moved_doors = doors.get(a, b).iter().map(|d| *d + offset).collect();
+                doors.insert(a, b, moved_doors);
+            }
+        }
+        for (a, b, corridor) in rerouted.into_iter() {
+            doors.insert(a, b, vec![corridor.doors[0]]);
+            corridors.insert(a, b, corridor);
+        }
+        for (c, rooms) in clusters.iter().enumerate() {
+            for r in rooms.iter() {
+                room_candidates[*r] = room_candidates[*r] + offsets[c];
+                for part in outlined_rooms[*r].parts.iter_mut() {
+                    *part = *part + offsets[c];
+                }
+            }
+        }
+        log::debug!("Shrunk {} clusters to fit", clusters.len());
 
-        Some(DungeonMeta { spawn_area })
+        true
     }
 
-    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> DungeonMeta {
-        for _ in 0..MAX_GENERATE_TRIES {
-            if let Some(meta) = self.try_generate(rng, encoder) {
-                return meta;
-            }
+    /// The entrance is the last room of the main path. If that room can't satisfy the spawn
+    /// constraint, the other end of the main path is tried, and `main_path` is reversed so the
+    /// entrance remains last.
+    fn choose_spawn_area(&self, rooms: &[Room], main_path: &mut [usize]) -> Option<SpawnArea> {
+        let constraint = if let Some(c) = self.spawn_constraint.as_ref() {
+            c
+        } else {
+            return Some(spawn_in_composite_room(&rooms[*main_path.last()?]));
+        };
+
+        if let Some(area) = constraint.restrict(spawn_in_composite_room(&rooms[*main_path.last()?]))
+        {
+            return Some(area);
         }
+        let area = constraint.restrict(spawn_in_composite_room(&rooms[*main_path.first()?]))?;
+        log::debug!("Re-selected the entrance room to satisfy the spawn constraint");
+        main_path.reverse();
 
-        panic!(
-            "Failed to generate dungeon after {} tries",
-            MAX_GENERATE_TRIES
-        );
+        Some(area)
     }
-}
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure, since all writes are staged until the attempt succeeds. Runs
+    /// each phase of generation in turn, starting with `sample_candidates`; see `GenerationState`.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<DungeonMeta> {
+        self.try_generate_clipped(rng, None, encoder)
+    }
+
+    /// Like `try_generate`, but only the voxels inside of `clip` are staged and written.
+    fn try_generate_clipped(
+        &self,
+        rng: &mut impl Rng,
+        clip: Option<Extent>,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<DungeonMeta> {
+        if !self.shell_only {
+            return self.try_generate_all_voxels(rng, clip, encoder);
+        }
+
+        let meta = match clip {
+            Some(clip) => {
+                // The shell at the edge of `clip` depends on the voxels just outside of it.
+                let mut clipped = ClipEncoder::new(encoder, clip);
+                let mut shell = ShellEncoder::new(&mut clipped);
+                let meta =
+                    self.try_generate_all_voxels(rng, Some(clip.radial_grow(1)), &mut shell)?;
+                shell.finish();
+
+                meta
+            }
+            None => {
+                let mut shell = ShellEncoder::new(encoder);
+                let meta = self.try_generate_all_voxels(rng, None, &mut shell)?;
+                shell.finish();
+
+                meta
+            }
+        };
+
+        Some(meta)
+    }
+
+    fn try_generate_all_voxels(
+        &self,
+        rng: &mut impl Rng,
+        clip: Option<Extent>,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<DungeonMeta> {
+        log::debug!("Generating dungeon map");
+
+        let mut state = self.sample_candidates(rng);
+        loop {
+            self.resolve_overlaps(&mut state);
+            self.build_door_graph(&mut state, rng);
+            if state.room_graph.node_count() >= self.num_rooms() {
+                break;
+            }
+            // Not enough of the candidates are connectable, so sample more if the budget allows.
+            if !self.sample_more_candidates(&mut state, rng) {
+                return None;
+            }
+        }
+        log::debug!("{} connected rooms", state.room_graph.node_count());
+
+        if !self.extract_skeleton(&mut state, rng) || !self.prune(&mut state, rng) {
+            return None;
+        }
+
+        self.fill_clipped(state, clip, rng, encoder)
+    }
+
+    /// The first phase of generation: samples the room candidates, or takes `fixed_rooms`.
+    pub fn sample_candidates(&self, rng: &mut impl Rng) -> GenerationState {
+        // Seeded before counting, so the stats of the other phases are unchanged.
+        let injection = self
+            .failure_injection
+            .as_ref()
+            .map(|_| small_rng(rng.gen()));
+        let mut rng = CountingRng::new(rng);
+
+        let room_candidates = match &self.fixed_rooms {
+            Some(rooms) => rooms.clone(),
+            None => filter_close_extents(
+                self.generate_room_candidates(
+                    self.candidate_budget.initial_factor * self.num_rooms(),
+                    &[],
+                    &mut rng,
+                ),
+                self.room_dist.min_center_separation,
+            ),
+        };
+        let mut state = GenerationState::new(room_candidates);
+        state.injection = injection;
+        if let (Some(spec), Some(injection_rng)) = (&self.failure_injection, &mut state.injection) {
+            spec.reject_candidates(&mut state.room_candidates, injection_rng);
+        }
+        state
+            .stats
+            .record_rng_draws("sample_candidates", rng.take_words_drawn());
+        log::debug!("Generated {} room candidates", state.room_candidates.len());
+
+        state
+    }
+
+    /// Pushes the room candidates apart until none overlap, then compacts and expands them as the
+    /// spec asks. `fixed_rooms` are left as they are.
+    pub fn resolve_overlaps(&self, state: &mut GenerationState) {
+        if self.fixed_rooms.is_some() {
+            return;
+        }
+
+        resolve_extent_overlaps(&mut state.room_candidates, &self.separation_weights);
+        log::debug!("Done resolving room overlaps");
+        if self.compact_rooms {
+            compact_extents_toward_centroid(&mut state.room_candidates);
+            log::debug!("Done compacting rooms");
+        }
+        if let Some(expansion) = &self.room_expansion {
+            expand_extents_into_gaps(
+                &mut state.room_candidates,
+                expansion.min_gap as i32,
+                expansion.bounds.as_ref(),
+                self.max_room_dim as i32,
+            );
+            log::debug!("Done expanding rooms");
+        }
+    }
+
+    /// Outlines the room candidates and connects them with doors, or corridors if the spec has
+    /// them. Only the largest connected group of rooms is kept in `room_graph`, and its doors are
+    /// saved in `connected_graph`.
+    pub fn build_door_graph(&self, state: &mut GenerationState, rng: &mut impl Rng) {
+        let mut rng = CountingRng::new(rng);
+
+        state.outlined_rooms = match (&self.composite_rooms, &self.corridors) {
+            (Some(spec), None) => state
+                .room_candidates
+                .iter()
+                .map(|r| spec.sample_room(r, &mut rng))
+                .collect(),
+            _ => state
+                .room_candidates
+                .iter()
+                .map(|r| Room::from(*r))
+                .collect(),
+        };
+        state
+            .stats
+            .record_rng_draws("room_outlines", rng.take_words_drawn());
+
+        state.doors = SymmetricMap::new();
+        state.corridors = SymmetricMap::new();
+        state.room_graph = if let Some(corridor_spec) = self.corridors.as_ref() {
+            generate_connection_graph(
+                &state.room_candidates,
+                self.min_door_dim,
+                self.max_door_dim,
+                &self.door_selection,
+                corridor_spec,
+                &mut rng,
+                &mut state.doors,
+                &mut state.corridors,
+            )
+        } else {
+            generate_composite_door_graph(
+                &state.outlined_rooms,
+                self.min_door_dim,
+                self.max_door_dim,
+                &self.door_selection,
+                &mut rng,
+                &mut state.doors,
+            )
+        };
+        state
+            .stats
+            .record_rng_draws("door_graph", rng.take_words_drawn());
+        if let (Some(spec), Some(injection_rng)) = (&self.failure_injection, &mut state.injection) {
+            spec.fail_doors(&mut state.room_graph, injection_rng);
+        }
+
+        // Prune disconnected rooms.
+        if let Some(subgraph) = largest_connected_subgraph(&state.room_graph) {
+            state.room_graph = subgraph;
+        }
+        state.connected_graph = state.room_graph.clone();
+    }
+
+    /// Samples another batch of room candidates, for when too few of them could be connected.
+    /// Returns false if the candidate budget is spent, or the rooms are `fixed_rooms`.
+    pub fn sample_more_candidates(&self, state: &mut GenerationState, rng: &mut impl Rng) -> bool {
+        let batch_size = self
+            .candidate_budget
+            .next_batch_size(state.room_candidates.len());
+        if batch_size == 0 || self.fixed_rooms.is_some() {
+            return false;
+        }
+        log::debug!(
+            "Only {} connected rooms, sampling {} more candidates",
+            state.room_graph.node_count(),
+            batch_size
+        );
+
+        let mut rng = CountingRng::new(rng);
+        let mut batch = self.generate_room_candidates(batch_size, &state.room_candidates, &mut rng);
+        state
+            .stats
+            .record_rng_draws("sample_candidates", rng.take_words_drawn());
+        if let (Some(spec), Some(injection_rng)) = (&self.failure_injection, &mut state.injection) {
+            spec.reject_candidates(&mut batch, injection_rng);
+        }
+        state.room_candidates.extend(batch);
+        state.room_candidates = filter_close_extents(
+            std::mem::take(&mut state.room_candidates),
+            self.room_dist.min_center_separation,
+        );
+
+        true
+    }
+
+    /// Chooses the main path from a spanning tree of `room_graph`, or from all of `room_graph` with
+    /// `loop_ratio`, after cutting the graph down to a hub and its spokes if the layout asks for
+    /// it. Returns false if the attempt failed, e.g.
+    /// because `room_graph` has fewer than `num_rooms` rooms.
+    pub fn extract_skeleton(&self, state: &mut GenerationState, rng: &mut impl Rng) -> bool {
+        if state.room_graph.node_count() < self.num_rooms() {
+            return false;
+        }
+        if let RoomGraphLayout::HubAndSpoke { max_spoke_length } = self.room_graph.layout {
+            let hub = match node_with_max_degree(&state.room_graph) {
+                Some(hub) => hub,
+                None => return false,
+            };
+            state.room_graph = hub_and_spoke_tree(&state.room_graph, hub, max_spoke_length);
+            log::debug!(
+                "Hub room {} reaches {} rooms",
+                state.room_graph[hub],
+                state.room_graph.node_count()
+            );
+            if state.room_graph.node_count() < self.num_rooms() {
+                return false;
+            }
+        }
+
+        let room_graph = &state.room_graph;
+        let mst = if self.room_graph.random_spanning_tree {
+            let mut rng = CountingRng::new(rng);
+            let tree = random_spanning_tree(room_graph, &mut rng);
+            state
+                .stats
+                .record_rng_draws("spanning_tree", rng.take_words_drawn());
+            tree
+        } else {
+            match self.room_graph.spanning_tree_weight {
+                SpanningTreeWeight::Unweighted => {
+                    StableGraph::from_elements(min_spanning_tree(room_graph))
+                }
+                weight => weighted_spanning_tree(
+                    room_graph,
+                    |e| {
+                        let (a, b) = room_graph.edge_endpoints(e).expect("Edge must exist");
+                        let (i, j) = (room_graph[a], room_graph[b]);
+                        weight.weight(
+                            &state.room_candidates[i],
+                            &state.room_candidates[j],
+                            state.doors.try_get(i, j).map_or(&[], |d| d.as_slice()),
+                        )
+                    },
+                    self.room_graph.maximum_spanning_tree,
+                ),
+            }
+        };
+        log::debug!(
+            "MST before pruning = {:?}",
+            Dot::with_config(&mst, &[Config::EdgeNoLabel])
+        );
+
+        let main_path_graph = if self.room_graph.loop_ratio.is_some() {
+            room_graph
+        } else {
+            &mst
+        };
+        state.main_path = match choose_main_path(
+            self.room_graph.entrance_to_objective_path_length,
+            self.room_graph.farthest_apart,
+            main_path_graph,
+        ) {
+            Some(path) => path,
+            None => return false,
+        };
+        log::debug!("Main path = {:?}", state.main_path);
+        state.spanning_tree = mst;
+
+        true
+    }
+
+    /// Removes rooms and doors from `room_graph` until it has the shape the spec asks for, then
+    /// adds back the doors that loops, bottlenecks and two-edge connectivity need. Returns false if
+    /// the attempt failed.
+    pub fn prune(&self, state: &mut GenerationState, rng: &mut impl Rng) -> bool {
+        if !self.room_graph.keep_all_connected {
+            if let Some(spec) = &self.room_graph.dead_ends {
+                let mut rng = CountingRng::new(&mut *rng);
+                let kept =
+                    self.keep_dead_ends(spec, &state.main_path, &mut state.room_graph, &mut rng);
+                state
+                    .stats
+                    .record_rng_draws("dead_ends", rng.take_words_drawn());
+                if !kept {
+                    log::debug!("Not enough rooms for {} dead ends", spec.count);
+                    return false;
+                }
+            } else {
+                // Make sure we keep at least the main path nodes.
+                self.prune_rooms_to_desired_size(&state.main_path, &mut state.room_graph);
+            }
+        }
+
+        self.drop_forbidden_doors(&state.main_path, &mut state.room_graph);
+
+        if let Some(loop_ratio) = self.room_graph.loop_ratio {
+            let mut rng = CountingRng::new(&mut *rng);
+            self.keep_loops(
+                loop_ratio,
+                &state.connected_graph,
+                &state.spanning_tree,
+                &state.main_path,
+                &mut state.room_graph,
+                &mut rng,
+            );
+            state
+                .stats
+                .record_rng_draws("loops", rng.take_words_drawn());
+        }
+
+        if self.avoid_bottlenecks {
+            let mut rng = CountingRng::new(&mut *rng);
+            state.bottleneck_regions = self.add_doors_to_bottlenecks(
+                &state.main_path,
+                &state.connected_graph,
+                &state.outlined_rooms,
+                &state.corridors,
+                &mut state.doors,
+                &mut state.room_graph,
+                &mut rng,
+            );
+            state
+                .stats
+                .record_rng_draws("bottlenecks", rng.take_words_drawn());
+        }
+
+        if self.two_edge_connected {
+            let mut rng = CountingRng::new(&mut *rng);
+            let connected = self.make_two_edge_connected(
+                &state.main_path,
+                &state.connected_graph,
+                &state.outlined_rooms,
+                &state.corridors,
+                &mut state.doors,
+                &mut state.room_graph,
+                &mut rng,
+            );
+            state
+                .stats
+                .record_rng_draws("two_edge_connected", rng.take_words_drawn());
+            if !connected {
+                log::debug!("Some door is still the only way between two parts of the map");
+                return false;
+            }
+        }
+
+        if let (true, Some(corridor_spec)) = (self.shrink_to_fit, &self.corridors) {
+            let mut rng = CountingRng::new(rng);
+            self.shrink_to_fit(
+                corridor_spec,
+                &state.room_graph,
+                &mut state.room_candidates,
+                &mut state.outlined_rooms,
+                &mut state.doors,
+                &mut state.corridors,
+                &mut rng,
+            );
+            state
+                .stats
+                .record_rng_draws("shrink_to_fit", rng.take_words_drawn());
+        }
+
+        true
+    }
+
+    /// The last phase of generation: writes the rooms of `state` to `encoder` with everything in
+    /// and around them, and describes the result. Returns `None` if the attempt failed.
+    pub fn fill(
+        &self,
+        state: GenerationState,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<DungeonMeta> {
+        self.fill_clipped(state, None, rng, encoder)
+    }
+
+    fn fill_clipped(
+        &self,
+        state: GenerationState,
+        clip: Option<Extent>,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<DungeonMeta> {
+        let GenerationState {
+            room_candidates,
+            outlined_rooms,
+            doors,
+            corridors,
+            room_graph,
+            mut main_path,
+            bottleneck_regions,
+            mut stats,
+            injection,
+            ..
+        } = state;
+        // The passes below each get their own stream, so turning one on or off doesn't change what
+        // the others place.
+        let mut rng = CountingRng::new(rng);
+        let fill_seed: [u32; 4] = rng.gen();
+        stats.record_rng_draws("fill", rng.take_words_drawn());
+        let mut injection = self.failure_injection.as_ref().zip(injection);
+
+        let layout = layout_from_room_graph(&room_candidates, &doors, &room_graph);
+        let chosen_rooms = collect_rooms_from_room_graph(&room_candidates, &room_graph);
+        let chosen_doors = collect_doors_from_room_graph(&doors, &room_graph);
+        let chosen_corridors: Vec<Corridor> = room_graph
+            .edge_references()
+            .filter_map(|e| corridors.try_get(room_graph[e.source()], room_graph[e.target()]))
+            .cloned()
+            .collect();
+
+        let chosen_outlines: Vec<Room> = room_graph
+            .node_indices()
+            .map(|n| outlined_rooms[room_graph[n]].clone())
+            .collect();
+
+        let secret_rooms: FnvHashSet<usize> = room_graph
+            .node_indices()
+            .filter(|n| room_graph.neighbors(*n).count() == 1)
+            .map(|n| room_graph[n])
+            .filter(|id| !main_path.contains(id))
+            .filter(|id| {
+                let seed = named_seed(derive_seed(self.seed, *id as u64), "secret_rooms");
+                small_rng(seed).gen::<f32>() < self.secret_room_ratio
+            })
+            .collect();
+        let is_secret_door = |door: &Extent| {
+            secret_rooms.iter().any(|id| {
+                outlined_rooms[*id]
+                    .parts
+                    .iter()
+                    .any(|p| !p.intersection(door).is_empty())
+            })
+        };
+        let secret_doors: Vec<Extent> = chosen_doors
+            .iter()
+            .chain(chosen_corridors.iter().flat_map(|c| c.doors.iter()))
+            .filter(|d| is_secret_door(d))
+            .cloned()
+            .collect();
+
+        let corridor_stubs: Vec<CorridorStub> = match &self.corridor_stubs {
+            Some(spec) => {
+                let door_dims =
+                    SubspanSampler::new(self.min_door_dim as i32, self.max_door_dim as i32);
+                let all_doors: Vec<Extent> = chosen_doors
+                    .iter()
+                    .chain(chosen_corridors.iter().flat_map(|c| c.doors.iter()))
+                    .cloned()
+                    .collect();
+                let mut obstacles: Vec<Extent> = chosen_rooms
+                    .iter()
+                    .chain(chosen_corridors.iter().flat_map(|c| c.segments.iter()))
+                    .cloned()
+                    .collect();
+
+                room_graph
+                    .node_indices()
+                    .enumerate()
+                    .flat_map(|(i, n)| {
+                        let id = room_graph[n];
+                        let seed =
+                            named_seed(derive_seed(self.style_seed(), id as u64), "corridor_stubs");
+                        generate_corridor_stubs(
+                            i,
+                            &outlined_rooms[id],
+                            &all_doors,
+                            spec,
+                            &door_dims,
+                            &mut obstacles,
+                            &mut small_rng(seed),
+                        )
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        let staged = if self.recarve_doors || self.trim_door_jambs {
+            BufferedEncoder::with_read_back(encoder)
+        } else {
+            BufferedEncoder::new(encoder)
+        };
+        let mut staged = match clip {
+            Some(clip) => staged.clip_to(clip),
+            None => staged,
+        };
+        fill_map_with_typed_rooms(&chosen_outlines, &self.surface_voxel_types, &mut staged);
+        for c in chosen_corridors.iter() {
+            fill_map_with_rooms(&c.segments, &mut staged);
+        }
+        for s in corridor_stubs.iter() {
+            fill_map_with_rooms(&[s.segment], &mut staged);
+        }
+        if let Some(backfill) = &self.backfill {
+            let occupied: Vec<Extent> = chosen_outlines
+                .iter()
+                .flat_map(|r| r.parts.iter())
+                .chain(chosen_corridors.iter().flat_map(|c| c.segments.iter()))
+                .chain(corridor_stubs.iter().map(|s| &s.segment))
+                .cloned()
+                .collect();
+            if let Some(bounds) = backfill.bounds.or_else(|| bounding_extent(&occupied)) {
+                backfill_map(&bounds, &occupied, backfill.voxel_type, &mut staged);
+            }
+        }
+        let door_style = self.door_style.resolve(self.style_seed(), "door_style");
+        let open_doors: Vec<Extent> = chosen_doors
+            .iter()
+            .chain(chosen_corridors.iter().flat_map(|c| c.doors.iter()))
+            .chain(corridor_stubs.iter().map(|s| &s.door))
+            .filter(|d| !is_secret_door(d))
+            .cloned()
+            .collect();
+        if let Some(spec) = &self.door_frames {
+            fill_map_with_door_frames(&open_doors, door_style, spec, &mut staged);
+        }
+        fill_map_with_styled_doors(&chosen_doors, door_style, &mut staged);
+        for c in chosen_corridors.iter() {
+            fill_map_with_styled_doors(&c.doors, door_style, &mut staged);
+        }
+        for s in corridor_stubs.iter() {
+            fill_map_with_styled_doors(&[s.door], door_style, &mut staged);
+        }
+        fill_map_with_secret_doors(&secret_doors, &mut staged);
+        let steps = door_sill_steps(
+            &layout,
+            self.door_selection.sill_height,
+            self.movement.as_ref(),
+        );
+        fill_map_with_steps(&steps, &mut staged);
+        let staircases: Vec<Staircase> = match &self.stairs {
+            Some(spec) => staircases(&layout, spec, self.movement.as_ref())
+                .into_iter()
+                .filter(|s| !is_secret_door(&layout.doors[s.door].extent))
+                .filter(|s| {
+                    // The layout only knows bounding extents, so check the outline too.
+                    let room = &chosen_outlines[s.room];
+                    s.steps.iter().all(|e| {
+                        let floor_y = e.get_minimum().y;
+                        e.into_iter()
+                            .filter(|p| p.y == floor_y)
+                            .all(|p| room.interior_contains(&p))
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        fill_map_with_staircases(&staircases, self.surface_voxel_types.wall, &mut staged);
+        let ladders: Vec<Ladder> = if self.ladders {
+            shaft_ladders(&layout)
+                .into_iter()
+                .filter(|l| staircases.iter().all(|s| s.door != l.door))
+                .filter(|l| !is_secret_door(&layout.doors[l.door].extent))
+                .filter(|l| {
+                    let floor_y = l.extent.get_minimum().y;
+                    (&l.extent)
+                        .into_iter()
+                        .filter(|p| p.y == floor_y)
+                        .all(|p| chosen_outlines.iter().any(|r| r.interior_contains(&p)))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        fill_map_with_ladders(&ladders, &mut staged);
+        // Doors that were given a way up already can be reached from the room below.
+        let reached_door = |i: usize| {
+            staircases.iter().any(|s| s.door == i) || ladders.iter().any(|l| l.door == i)
+        };
+        let climbable_strips = match &self.climbing {
+            Some(spec) => {
+                let mut ladder_layout = layout.clone();
+                ladder_layout.doors = (0..layout.doors.len())
+                    .filter(|i| !reached_door(*i))
+                    .map(|i| layout.doors[i].clone())
+                    .collect();
+
+                climbable_strips(&ladder_layout, spec, self.movement.as_ref())
+            }
+            None => Vec::new(),
+        };
+        fill_map_with_climbables(&climbable_strips, &mut staged);
+        if let Some(movement) = &self.movement {
+            let mut problems = untraversable_doors(&layout, &steps, &climbable_strips, movement);
+            // Staircases only rise as far as the character can step, and ladders go all the way
+            // up.
+            problems.retain(|p| match p {
+                TraversalProblem::UnreachableDoor { door, .. } => !reached_door(*door),
+                _ => true,
+            });
+            if !problems.is_empty() {
+                log::debug!("Rejecting untraversable layout: {:?}", problems);
+                return None;
+            }
+        }
+
+        let spawn_area = self.choose_spawn_area(&outlined_rooms, &mut main_path)?;
+        // The entrance is the last room on the main path.
+        let entrance_id = *main_path.last()?;
+        // Face the door that leads onward along the main path. A corridor's door into the
+        // entrance can be at either end of it, and the layout only records the door into the
+        // first room.
+        let into_entrance = |door: &&Extent| {
+            outlined_rooms[entrance_id]
+                .parts
+                .iter()
+                .any(|p| !p.intersection(door).is_empty())
+        };
+        let main_door = main_path.iter().rev().nth(1).and_then(|next| {
+            match corridors.try_get(entrance_id, *next) {
+                Some(corridor) => corridor.doors.iter().find(into_entrance),
+                None => doors
+                    .try_get(entrance_id, *next)?
+                    .iter()
+                    .find(into_entrance),
+            }
+        });
+        let spawn_area = match main_door {
+            Some(door) => spawn_area.facing_toward(&extent_center(door)),
+            None => spawn_area,
+        };
+        log::debug!("Spawn area = {:?}", spawn_area);
+
+        let entrance_node = room_graph
+            .node_indices()
+            .find(|n| room_graph[*n] == entrance_id)?;
+        let doors_from_entrance = dijkstra(&room_graph, entrance_node, None, |_| 1);
+        let entrance_room = room_graph.node_indices().position(|n| n == entrance_node)?;
+        let entrance_center = extent_center(&room_candidates[entrance_id]);
+        let mission_rooms: Vec<usize> = match &self.mission {
+            Some(mission) => {
+                let objective_node = room_graph
+                    .node_indices()
+                    .find(|n| Some(&room_graph[*n]) == main_path.first())?;
+                let mut rng = small_rng(named_seed(fill_seed, "mission"));
+                match map_mission(
+                    &room_graph,
+                    entrance_node,
+                    objective_node,
+                    mission,
+                    &mut rng,
+                ) {
+                    Ok(nodes) => nodes
+                        .into_iter()
+                        .filter_map(|m| room_graph.node_indices().position(|n| n == m))
+                        .collect(),
+                    Err(e) => {
+                        log::debug!("The mission doesn't fit: {:?}", e);
+                        return None;
+                    }
+                }
+            }
+            None => Vec::new(),
+        };
+
+        let mut rooms: Vec<_> = room_graph
+            .node_indices()
+            .map(|n| {
+                let room_id = room_graph[n];
+                let seed = derive_seed(self.seed, room_id as u64);
+                let style_seed = derive_seed(self.style_seed(), room_id as u64);
+                let theme = if small_rng(style_seed).gen::<f32>() < self.cave_room_ratio {
+                    RoomTheme::Cave
+                } else {
+                    RoomTheme::Hall
+                };
+
+                let extent = room_candidates[room_id];
+                let outlined = &outlined_rooms[room_id];
+                let safe = self
+                    .safe_zone_radius
+                    .is_some_and(|r| doors_from_entrance[&n] <= r);
+
+                RoomMeta {
+                    id: RoomId::new(self.seed, room_id),
+                    extent,
+                    outline: outlined.outline,
+                    parts: outlined.parts.clone(),
+                    seed,
+                    style_seed,
+                    theme,
+                    shape: classify_room(&extent, room_graph.neighbors(n).count()),
+                    doors_from_entrance: doors_from_entrance[&n],
+                    distance_from_entrance: distance_squared(
+                        &extent_center(&extent),
+                        &entrance_center,
+                    )
+                    .sqrt(),
+                    // Known once the layout is built.
+                    difficulty: None,
+                    reward_tier: None,
+                    safe,
+                    secret: secret_rooms.contains(&room_id),
+                    // Known once the floor features are placed.
+                    encounter_capacity: 0,
+                    atmosphere: self
+                        .atmosphere
+                        .as_ref()
+                        .map(|a| a.volume_for_room(&extent, theme, doors_from_entrance[&n], safe)),
+                    // Known once the obstacles are placed.
+                    balcony: None,
+                    walkable_surfaces: outlined
+                        .parts
+                        .iter()
+                        .map(|p| {
+                            let interior = p.radial_grow(-WALL_THICKNESS);
+                            let min = interior.get_minimum();
+                            let sup = interior.get_local_supremum();
+
+                            Extent::from_min_and_local_supremum(
+                                *min - [0, 1, 0].into(),
+                                [sup.x, 1, sup.z].into(),
+                            )
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let galleries: Vec<Gallery> = match &self.galleries {
+            Some(spec) => {
+                let mut rng = small_rng(named_seed(fill_seed, "galleries"));
+                // Openings keep clear of everything that leads through floors.
+                let all_doors: Vec<Extent> = chosen_doors
+                    .iter()
+                    .chain(chosen_corridors.iter().flat_map(|c| c.doors.iter()))
+                    .chain(
+                        staircases
+                            .iter()
+                            .flat_map(|s| s.steps.iter().chain(s.handrails.iter())),
+                    )
+                    .chain(ladders.iter().map(|l| &l.extent))
+                    .cloned()
+                    .collect();
+                let mut galleries = Vec::new();
+                for upper in rooms.iter().filter(|r| !r.secret) {
+                    // Only halls are grand enough to be overlooked.
+                    for lower in rooms
+                        .iter()
+                        .filter(|r| r.theme == RoomTheme::Hall && !r.secret)
+                    {
+                        let gallery = try_generate_gallery(
+                            &upper.outlined(),
+                            &lower.outlined(),
+                            &all_doors,
+                            spec,
+                            &mut rng,
+                        );
+                        // Nobody should spawn over the edge.
+                        galleries.extend(gallery.filter(|g| {
+                            let grown = g.opening.radial_grow(1);
+                            !spawn_area
+                                .valid_spawn_points
+                                .iter()
+                                .any(|p| grown.contains_world(p))
+                        }));
+                    }
+                }
+
+                galleries
+            }
+            None => Vec::new(),
+        };
+        // Decorations treat gallery openings, railings, staircases and ladders like doors, so they
+        // don't block them.
+        let mut obstacles: Vec<Extent> = chosen_doors
+            .iter()
+            .chain(
+                galleries
+                    .iter()
+                    .flat_map(|g| std::iter::once(&g.opening).chain(g.railings.iter())),
+            )
+            .chain(
+                staircases
+                    .iter()
+                    .flat_map(|s| s.steps.iter().chain(s.handrails.iter())),
+            )
+            .chain(ladders.iter().map(|l| &l.extent))
+            .chain(corridor_stubs.iter().map(|s| &s.door))
+            .cloned()
+            .collect();
+
+        if let Some(spec) = &self.balconies {
+            let blockers: Vec<Extent> = obstacles
+                .iter()
+                .chain(chosen_corridors.iter().flat_map(|c| c.doors.iter()))
+                .chain(steps.iter())
+                .chain(climbable_strips.iter())
+                .cloned()
+                .collect();
+            for r in rooms.iter_mut().filter(|r| r.outline == RoomOutline::Box) {
+                let mut room_rng = small_rng(named_seed(r.seed, "balconies"));
+                r.balcony = try_generate_balcony(
+                    &r.extent,
+                    &blockers,
+                    &spawn_area.valid_spawn_points,
+                    spec,
+                    &mut room_rng,
+                );
+                if let Some(b) = &r.balcony {
+                    r.walkable_surfaces.push(b.walkable_surface());
+                    obstacles.push(b.platform);
+                    obstacles.extend(b.steps.iter().chain(b.railings.iter()));
+                }
+            }
+            let balconies: Vec<Balcony> = rooms.iter().filter_map(|r| r.balcony.clone()).collect();
+            fill_map_with_balconies(&balconies, self.surface_voxel_types.wall, &mut staged);
+        }
+
+        let support_columns: Vec<Extent> = match &self.support_columns {
+            Some(spec) => rooms
+                .iter()
+                .flat_map(|r| {
+                    let mut columns =
+                        support_columns_for_room(&r.extent, &obstacles, &spawn_area, spec);
+                    if r.outline != RoomOutline::Box {
+                        let room = r.outlined();
+                        columns.retain(|c| c.into_iter().all(|p| room.interior_contains(&p)));
+                    }
+
+                    columns
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        fill_map_with_support_columns(&support_columns, self.surface_voxel_types.wall, &mut staged);
+        obstacles.extend(support_columns.iter().cloned());
+
+        let ceiling_features = match &self.ceiling_decoration {
+            Some(spec) => rooms
+                .iter()
+                .flat_map(|r| {
+                    // Decorations use their own stream so they don't perturb the theme choice.
+                    let mut room_rng = small_rng(named_seed(r.style_seed, "ceiling"));
+                    let mut features = ceiling_features_for_room(
+                        &r.extent,
+                        r.theme,
+                        &obstacles,
+                        &spawn_area,
+                        spec,
+                        &mut room_rng,
+                    );
+                    if r.safe {
+                        features.retain(|f| f.kind != CeilingFeatureKind::Stalactite);
+                    }
+                    if r.outline != RoomOutline::Box {
+                        // Nothing can hang over a notch.
+                        let room = r.outlined();
+                        features.retain(|f| room.interior_contains(&(f.anchor - [0, 1, 0].into())));
+                    }
+
+                    features
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        fill_map_with_stalactites(&ceiling_features, &mut staged);
+
+        let floor_features = match &self.interior_decoration {
+            Some(spec) => rooms
+                .iter()
+                .flat_map(|r| {
+                    let mut room_rng = small_rng(named_seed(r.style_seed, "floor"));
+                    let mut features = floor_features_for_room(
+                        &r.extent,
+                        &obstacles,
+                        &spawn_area,
+                        spec,
+                        &mut room_rng,
+                    );
+                    if r.outline != RoomOutline::Box {
+                        let room = r.outlined();
+                        features.retain(|f| {
+                            (&f.extent).into_iter().all(|p| room.interior_contains(&p))
+                        });
+                    }
+
+                    features
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        fill_map_with_floor_features(&floor_features, &mut staged);
+        for r in rooms.iter_mut().filter(|r| !r.safe) {
+            r.encounter_capacity = encounter_capacity(
+                &r.outlined(),
+                &floor_features,
+                &support_columns,
+                &spawn_area,
+                self.spawn_safety_radius,
+            );
+        }
+
+        let windows: Vec<Extent> = match &self.windows {
+            Some(spec) => room_graph
+                .node_indices()
+                .zip(rooms.iter())
+                .flat_map(|(n, r)| {
+                    // Each room cuts the windows to its neighbors with higher IDs. Windows would
+                    // give secret rooms away.
+                    let i = room_graph[n];
+                    let mut neighbors: Vec<usize> = room_graph
+                        .neighbors(n)
+                        .map(|m| room_graph[m])
+                        .filter(|j| *j > i && !r.secret && !secret_rooms.contains(j))
+                        .collect();
+                    neighbors.sort_unstable();
+                    let mut room_rng = small_rng(named_seed(r.style_seed, "windows"));
+
+                    neighbors
+                        .into_iter()
+                        .filter_map(|j| {
+                            try_generate_window_between_rooms(
+                                &outlined_rooms[i],
+                                &outlined_rooms[j],
+                                doors.try_get(i, j)?,
+                                spec,
+                                &mut room_rng,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        fill_map_with_doors(&windows, &mut staged);
+        fill_map_with_galleries(&galleries, self.surface_voxel_types.wall, &mut staged);
+
+        let lights: Vec<LightSource> = match &self.lighting {
+            Some(spec) => {
+                let blockers: Vec<Extent> = obstacles
+                    .iter()
+                    .chain(chosen_corridors.iter().flat_map(|c| c.doors.iter()))
+                    .chain(windows.iter())
+                    .chain(steps.iter())
+                    .chain(climbable_strips.iter())
+                    .chain(floor_features.iter().map(|f| &f.extent))
+                    .cloned()
+                    .collect();
+
+                rooms
+                    .iter()
+                    .flat_map(|r| lights_for_room(&r.outlined(), &blockers, spec))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        if let Some(voxel_type) = self.lighting.as_ref().and_then(|l| l.voxel_type) {
+            fill_map_with_lights(&lights, voxel_type, &mut staged);
+        }
+
+        // The objective is the first room on the main path.
+        let objective_room = room_graph
+            .node_indices()
+            .position(|n| Some(&room_graph[n]) == main_path.first())?;
+        let objective = {
+            let room_doors: Vec<Extent> = layout
+                .doors
+                .iter()
+                .filter(|d| d.rooms.0 == objective_room || d.rooms.1 == objective_room)
+                .map(|d| d.extent)
+                .collect();
+            let blockers: Vec<Extent> = floor_features
+                .iter()
+                .map(|f| f.extent)
+                .chain(support_columns.iter().cloned())
+                .chain(rooms[objective_room].balcony.iter().flat_map(|b| {
+                    std::iter::once(b.platform)
+                        .chain(b.steps.iter().chain(b.railings.iter()).cloned())
+                }))
+                .chain(
+                    staircases
+                        .iter()
+                        .flat_map(|s| s.steps.iter().chain(s.handrails.iter()))
+                        .cloned(),
+                )
+                .chain(ladders.iter().map(|l| l.extent))
+                .chain(galleries.iter().flat_map(|g| g.railings.iter()).cloned())
+                .collect();
+
+            hidden_objective_point(&rooms[objective_room].outlined(), &room_doors, &blockers)
+        };
+        log::debug!("Objective = {:?}", objective);
+
+        let overview_camera = CameraPose::overview(&layout.rooms, OVERVIEW_VERTICAL_FOV)?;
+
+        // From the entrance to the objective, in layout indices.
+        let node_ids: Vec<usize> = room_graph.node_indices().map(|n| room_graph[n]).collect();
+        let path: Vec<usize> = main_path
+            .iter()
+            .rev()
+            .filter_map(|id| node_ids.iter().position(|n| n == id))
+            .collect();
+        let chokepoints: Vec<usize> = articulation_points(&room_graph)
+            .into_iter()
+            .filter_map(|a| room_graph.node_indices().position(|n| n == a))
+            .collect();
+        let hubs: Vec<usize> = match &self.hubs {
+            Some(spec) => spec
+                .hubs(&room_graph)
+                .into_iter()
+                .filter_map(|h| room_graph.node_indices().position(|n| n == h))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut never_connect_violations: Vec<(usize, usize)> = layout
+            .doors
+            .iter()
+            .map(|d| d.rooms)
+            .filter(|(a, b)| self.never_connects(&main_path, node_ids[*a], node_ids[*b]))
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        never_connect_violations.sort_unstable();
+        never_connect_violations.dedup();
+
+        if let Some(spec) = &self.difficulty {
+            let difficulties = room_difficulties(&layout, &path, spec);
+            for (room, difficulty) in rooms.iter_mut().zip(difficulties) {
+                room.difficulty = Some(difficulty);
+            }
+        }
+        let branches = match &self.branch_rewards {
+            Some(spec) => branches(&layout, &path, spec),
+            None => Vec::new(),
+        };
+        for b in branches.iter() {
+            rooms[b.terminal].reward_tier = Some(b.reward_tier);
+        }
+
+        let locks = match &self.progression {
+            Some(spec) => {
+                let mut rng = small_rng(named_seed(fill_seed, "progression"));
+
+                place_locks_and_keys(&layout, &path, spec, &mut rng)
+            }
+            None => Vec::new(),
+        };
+
+        let door_records = layout
+            .doors
+            .iter()
+            .map(|d| DoorRecord {
+                extent: d.extent,
+                direction: door_direction(&d.extent, &rooms[d.rooms.0].outlined())
+                    .or_else(|| {
+                        door_direction(&d.extent, &rooms[d.rooms.1].outlined()).map(|d| d.negate())
+                    })
+                    .expect("Doors are in the boundary of one of their rooms"),
+                rooms: d.rooms,
+                secret: is_secret_door(&d.extent),
+            })
+            .collect();
+        let mut doors_by_rooms = SymmetricMap::new();
+        for (i, d) in layout.doors.iter().enumerate() {
+            doors_by_rooms
+                .get_or_insert_with(rooms[d.rooms.0].id, rooms[d.rooms.1].id, Vec::new)
+                .push(i);
+        }
+
+        if self.recarve_doors {
+            let cleared = recarve_blocked_doors(&open_doors, door_style, &mut staged);
+            log::debug!("Cleared {} blocked door voxels", cleared);
+        }
+        if self.trim_door_jambs {
+            let trimmed = trim_door_jambs(&open_doors, door_style, &mut staged);
+            log::debug!("Trimmed {} door jamb voxels", trimmed);
+        }
+
+        if let Some((spec, injection_rng)) = &mut injection {
+            if injection_rng.gen::<f32>() < spec.attempt_failure {
+                log::debug!("Injected attempt failure");
+                return None;
+            }
+        }
+        let reverb_zones = reverb_zones(&chosen_rooms, &chosen_corridors, &chosen_doors);
+        let bounds = staged.bounds_of_writes();
+        staged.commit();
+
+        Some(DungeonMeta {
+            spawn_area,
+            num_rooms: rooms.len(),
+            rooms,
+            room_graph: layout.room_graph(),
+            layout,
+            doors: door_records,
+            doors_by_rooms,
+            locks,
+            branches,
+            corridors: chosen_corridors,
+            corridor_stubs,
+            climbable_strips,
+            staircases,
+            ladders,
+            bottleneck_regions,
+            chokepoints,
+            never_connect_violations,
+            mission_rooms,
+            hubs,
+            ceiling_features,
+            floor_features,
+            support_columns,
+            windows,
+            galleries,
+            lights,
+            reverb_zones,
+            door_style,
+            entrance_room,
+            objective_room,
+            objective,
+            overview_camera,
+            bounds,
+            stats,
+        })
+    }
+
+    /// Generates a dungeon from `self` and from `other`, each with an RNG seeded by its own spec,
+    /// and compares their layouts. To see what a seed change does, pass specs that only differ in
+    /// `seed`.
+    pub fn compare_layouts(&self, other: &DungeonMapSpec) -> LayoutComparison {
+        let layout = |spec: &DungeonMapSpec| {
+            spec.generate(&mut small_rng(spec.seed), &mut NullEncoder)
+                .layout
+        };
+
+        layout(self).compare(&layout(other))
+    }
+
+    /// Generates a dungeon from each of `n_seeds` seeds derived from `self.seed`, scores the
+    /// summary of each layout with `scorer`, and writes the dungeon with the highest score into
+    /// `encoder`. Ties go to the earliest seed. With `parallel`, each candidate is generated on its
+    /// own thread.
+    pub fn generate_best_of(
+        &self,
+        n_seeds: usize,
+        parallel: bool,
+        scorer: impl Fn(&LayoutSummary) -> f32 + Sync,
+        encoder: &mut impl VoxelEncoder,
+    ) -> BestOfDungeons {
+        let seeds: Vec<[u32; 4]> = (0..n_seeds.max(1))
+            .map(|i| derive_seed(self.seed, i as u64))
+            .collect();
+        let with_seed = |seed: [u32; 4]| DungeonMapSpec {
+            seed,
+            ..self.clone()
+        };
+        let score = |seed: &[u32; 4]| {
+            let meta = with_seed(*seed).generate(&mut small_rng(*seed), &mut NullEncoder);
+
+            scorer(&meta.layout.summary())
+        };
+
+        let scores: Vec<f32> = if parallel {
+            let score = &score;
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = seeds
+                    .iter()
+                    .map(|seed| scope.spawn(move || score(seed)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("Dungeon generation panicked"))
+                    .collect()
+            })
+        } else {
+            seeds.iter().map(score).collect()
+        };
+
+        let best =
+            (1..seeds.len()).fold(0, |best, i| if scores[i] > scores[best] { i } else { best });
+        let seed = seeds[best];
+        let meta = with_seed(seed).generate(&mut small_rng(seed), encoder);
+
+        BestOfDungeons {
+            meta,
+            seed,
+            scores: seeds.into_iter().zip(scores).collect(),
+        }
+    }
+
+    /// Retries until an attempt succeeds. Each attempt gets its own RNG, seeded from a seed drawn
+    /// once from `rng` and the index of the attempt, so an attempt doesn't depend on how much
+    /// earlier attempts drew, and can be reproduced on its own from `GenerationStats::attempt_seed`.
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> DungeonMeta {
+        self.generate_clipped(rng, None, encoder)
+    }
+
+    /// Like `generate`, but returns `None` instead of panicking if all `MAX_GENERATE_TRIES`
+    /// attempts fail, for specs that may not be satisfiable.
+    pub fn try_generate_with_retries(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<DungeonMeta> {
+        self.retry_clipped(rng, None, encoder)
+    }
+
+    /// Like `generate`, but for maps too big to hold in memory. The layout is generated first
+    /// without writing any voxels. Then, for each `chunk_shape` sized cell of a grid aligned to
+    /// the origin that intersects the map, in order of Z, then Y, then X, the successful attempt is
+    /// replayed with only the voxels inside that chunk staged and written into `encoder`. Nothing
+    /// more than one chunk of voxels is held at a time, at the cost of regenerating the layout
+    /// once per chunk.
+    pub fn generate_in_chunks(
+        &self,
+        rng: &mut impl Rng,
+        chunk_shape: Point,
+        encoder: &mut impl VoxelEncoder,
+    ) -> DungeonMeta {
+        let nothing = Extent::from_min_and_local_supremum([0, 0, 0].into(), [0, 0, 0].into());
+        let meta = self.generate_clipped(rng, Some(nothing), &mut NullEncoder);
+        let attempt_seed = meta
+            .stats
+            .attempt_seed
+            .expect("Successful attempts record their seed");
+
+        // The layout pass clipped everything away, but it still knows where it would have written.
+        let bounds = match meta.bounds {
+            Some(bounds) => bounds,
+            None => return meta,
+        };
+        for chunk in chunks_covering(&bounds, chunk_shape) {
+            self.try_generate_clipped(&mut small_rng(attempt_seed), Some(chunk), encoder)
+                .expect("Replaying a successful attempt must succeed");
+        }
+
+        meta
+    }
+
+    fn generate_clipped(
+        &self,
+        rng: &mut impl Rng,
+        clip: Option<Extent>,
+        encoder: &mut impl VoxelEncoder,
+    ) -> DungeonMeta {
+        self.retry_clipped(rng, clip, encoder).unwrap_or_else(|| {
+            panic!(
+                "Failed to generate dungeon after {} tries",
+                MAX_GENERATE_TRIES
+            )
+        })
+    }
+
+    fn retry_clipped(
+        &self,
+        rng: &mut impl Rng,
+        clip: Option<Extent>,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<DungeonMeta> {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |attempt, attempt_seed| {
+            let mut meta =
+                self.try_generate_clipped(&mut small_rng(attempt_seed), clip, encoder)?;
+            log::debug!("Attempt {} succeeded", attempt);
+            meta.stats.attempt = attempt;
+            meta.stats.attempt_seed = Some(attempt_seed);
+
+            Some(meta)
+        })
+    }
+}
+
+/// `room_graph` with one edge per door, so pairs of rooms with several doors aren't bridges.
+fn door_graph(
+    room_graph: &StableGraph<usize, (), Undirected>,
+    doors: &SymmetricMap<Vec<Extent>>,
+) -> StableGraph<usize, (), Undirected> {
+    let mut graph = room_graph.clone();
+    for e in room_graph.edge_references() {
+        let (a, b) = (e.source(), e.target());
+        let num_doors = doors.get(room_graph[a], room_graph[b]).len();
+        for _ in 1..num_doors {
+            graph.add_edge(a, b, ());
+        }
+    }
+
+    graph
+}
+
+/// Whether rooms `a` and `b` are next to each other on `main_path`.
+fn along_main_path(main_path: &[usize], a: usize, b: usize) -> bool {
+    main_path
+        .windows(2)
+        .any(|w| (w[0], w[1]) == (a, b) || (w[1], w[0]) == (a, b))
+}
 
 /// Returns vec of room indices.
+/// With `whole_path`, the main path runs between the two ends of the longest path in `graph`,
+/// which is the spanning tree, or the whole room graph if it keeps loops.
 fn choose_main_path(
     desired_len: usize,
-    mst: &StableGraph<usize, (), Undirected>,
+    whole_path: bool,
+    graph: &StableGraph<usize, (), Undirected>,
 ) -> Option<Vec<usize>> {
-    let path = longest_path_in_tree(mst);
+    let path = longest_simple_path(graph);
 
-    if path.len() >= desired_len {
-        Some(path[0..desired_len - 1].iter().map(|n| mst[*n]).collect())
-    } else {
+    if path.len() < desired_len {
         None
+    } else if whole_path {
+        Some(path.iter().map(|n| graph[*n]).collect())
+    } else {
+        Some(path[0..desired_len - 1].iter().map(|n| graph[*n]).collect())
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampling::NormalDistSpec, Voxel, VoxelFlags, VoxelLayer};
+
+    fn graph(rooms: usize, edges: &[(usize, usize)]) -> StableGraph<usize, (), Undirected> {
+        let mut graph = StableGraph::default();
+        let nodes: Vec<NodeIndex> = (0..rooms).map(|r| graph.add_node(r)).collect();
+        for (a, b) in edges {
+            graph.add_edge(nodes[*a], nodes[*b], ());
+        }
+
+        graph
+    }
+
+    fn room_edges(graph: &StableGraph<usize, (), Undirected>) -> Vec<(usize, usize)> {
+        let mut edges: Vec<_> = graph
+            .edge_references()
+            .map(|e| {
+                let (a, b) = (graph[e.source()], graph[e.target()]);
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        edges.sort_unstable();
+
+        edges
+    }
+
+    #[test]
+    fn test_keep_loops_adds_to_the_pruned_tree() {
+        let spec = DungeonMapSpec::default();
+        let all = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+        let connected_graph = graph(4, &all);
+        let path = [(0, 1), (1, 2), (2, 3)];
+        let main_path = [3, 2];
+
+        let mut room_graph = graph(4, &path);
+        spec.keep_loops(
+            0.0,
+            &connected_graph,
+            &room_graph.clone(),
+            &main_path,
+            &mut room_graph,
+            &mut small_rng([1; 4]),
+        );
+        assert_eq!(room_edges(&room_graph), path.to_vec());
+
+        let mut room_graph = graph(4, &path);
+        spec.keep_loops(
+            1.0,
+            &connected_graph,
+            &room_graph.clone(),
+            &main_path,
+            &mut room_graph,
+            &mut small_rng([1; 4]),
+        );
+        assert_eq!(room_edges(&room_graph), all.to_vec());
+
+        let mut room_graph = graph(4, &path);
+        spec.keep_loops(
+            0.5,
+            &connected_graph,
+            &room_graph.clone(),
+            &main_path,
+            &mut room_graph,
+            &mut small_rng([1; 4]),
+        );
+        let edges = room_edges(&room_graph);
+        assert_eq!(edges.len(), 5);
+        assert!(path.iter().all(|e| edges.contains(e)));
+    }
+
+    #[test]
+    fn test_keep_loops_cuts_every_door_down_to_the_spanning_tree() {
+        let spec = DungeonMapSpec::default();
+        let all = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+        let connected_graph = graph(4, &all);
+        let spanning_tree = graph(4, &[(0, 1), (0, 2), (0, 3)]);
+
+        let mut room_graph = graph(4, &all);
+        spec.keep_loops(
+            0.0,
+            &connected_graph,
+            &spanning_tree,
+            &[3, 2],
+            &mut room_graph,
+            &mut small_rng([1; 4]),
+        );
+        assert_eq!(room_edges(&room_graph), vec![(0, 1), (0, 2), (2, 3)]);
+    }
+
+    /// The last voxel and flags written at each point.
+    #[derive(Default)]
+    struct MapRecorder {
+        voxels: FnvHashMap<(VoxelLayer, Point), (u8, u32)>,
+        flags: FnvHashMap<Point, VoxelFlags>,
+    }
+
+    impl VoxelEncoder for MapRecorder {
+        fn encode_voxel(&mut self, point: &Point, data: &Voxel) {
+            self.encode_layer_voxel(VoxelLayer::Structure, point, data);
+        }
+
+        fn encode_layer_voxel(&mut self, layer: VoxelLayer, point: &Point, data: &Voxel) {
+            self.voxels
+                .insert((layer, *point), (data.voxel_type, data.distance.to_bits()));
+            self.flags.remove(point);
+        }
+
+        fn encode_flags(&mut self, point: &Point, flags: VoxelFlags) {
+            self.flags.insert(*point, flags);
+        }
+    }
+
+    fn six_room_spec() -> DungeonMapSpec {
+        DungeonMapSpec {
+            seed: [5, 6, 7, 8],
+            room_graph: RoomGraphSpec {
+                num_rooms: 6,
+                entrance_to_objective_path_length: 3,
+                ..Default::default()
+            },
+            room_dist: RoomDistributionSpec {
+                location: LatticeUniformDistSpec {
+                    x: (-40, 40),
+                    y: (-5, 5),
+                    z: (-40, 40),
+                },
+                size: LatticeNormalDistSpec {
+                    x: NormalDistSpec {
+                        mean: 22.0,
+                        std_dev: 4.0,
+                    },
+                    y: NormalDistSpec {
+                        mean: 24.0,
+                        std_dev: 2.0,
+                    },
+                    z: NormalDistSpec {
+                        mean: 22.0,
+                        std_dev: 4.0,
+                    },
+                },
+                min_center_separation: 4.0,
+                ..Default::default()
+            },
+            min_room_dim: 14,
+            max_room_dim: 32,
+            min_door_dim: 3,
+            max_door_dim: 5,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_generate_in_chunks_writes_what_generate_writes() {
+        let spec = DungeonMapSpec {
+            // Both reach past the extents of the rooms.
+            door_frames: Some(DoorFrameSpec {
+                thickness: 2,
+                voxel_type: 9,
+            }),
+            balconies: Some(BalconySpec {
+                min_room_height: 20,
+                depth: 3,
+                thickness: 1,
+                railing_height: 2,
+                step_rise: 1,
+            }),
+            ..six_room_spec()
+        };
+
+        let mut whole = MapRecorder::default();
+        let meta = spec.generate(&mut small_rng(spec.seed), &mut whole);
+        let mut chunked = MapRecorder::default();
+        let chunked_meta =
+            spec.generate_in_chunks(&mut small_rng(spec.seed), [32, 32, 32].into(), &mut chunked);
+
+        assert_eq!(chunked_meta.layout, meta.layout);
+        assert_eq!(chunked_meta.bounds, meta.bounds);
+        let bounds = meta.bounds.unwrap();
+        assert!(whole.voxels.keys().all(|(_, p)| bounds.contains_world(p)));
+        assert!(whole.voxels == chunked.voxels);
+        assert!(whole.flags == chunked.flags);
+    }
+
+    #[test]
+    fn test_windows_follow_the_style_seed() {
+        let mut spec = DungeonMapSpec {
+            windows: Some(WindowSpec {
+                probability: 0.5,
+                max_size: 2,
+                min_height_above_floor: 2,
+            }),
+            ..six_room_spec()
+        };
+        let metas: Vec<DungeonMeta> = (0..6)
+            .map(|i| {
+                spec.style_seed = Some([i, 0, 0, 0]);
+                spec.generate(&mut small_rng(spec.seed), &mut NullEncoder)
+            })
+            .collect();
+
+        assert!(metas.iter().all(|m| m.layout == metas[0].layout));
+        assert!(metas.iter().any(|m| m.windows != metas[0].windows));
+    }
+
+    #[test]
+    fn test_spawn_faces_the_main_path_door_into_the_entrance() {
+        for seed in 0..6 {
+            let spec = DungeonMapSpec {
+                seed: [seed, 1, 1, 1],
+                corridors: Some(CorridorSpec {
+                    width: 2,
+                    height: 1,
+                    max_length: 80,
+                }),
+                ..six_room_spec()
+            };
+            let meta = spec.generate(&mut small_rng(spec.seed), &mut NullEncoder);
+
+            let entrance = &meta.rooms[meta.entrance_room];
+            let entrance_doors: Vec<Extent> = meta
+                .layout
+                .doors
+                .iter()
+                .map(|d| d.extent)
+                .chain(meta.corridors.iter().flat_map(|c| c.doors.iter().cloned()))
+                .filter(|d| entrance.parts.iter().any(|p| !p.intersection(d).is_empty()))
+                .collect();
+            let area = &meta.spawn_area;
+            let faced = entrance_doors.iter().any(|d| {
+                let toward = SpawnArea {
+                    valid_spawn_points: area.valid_spawn_points.clone(),
+                    facing: None,
+                }
+                .facing_toward(&extent_center(d));
+
+                toward.facing == area.facing
+            });
+            assert!(faced, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_encounter_capacity_excludes_spawn_safety_radius() {
+        // A 10x10 floor.
+        let room = Room::from(Extent::from_min_and_local_supremum(
+            [0, 0, 0].into(),
+            [20, 20, 20].into(),
+        ));
+        let spawn_area = SpawnArea {
+            valid_spawn_points: vec![[10, 5, 10].into()],
+            facing: None,
+        };
+        let capacity = |radius| encounter_capacity(&room, &[], &[], &spawn_area, radius);
+
+        assert_eq!(capacity(0.0), 99);
+        // The 13 columns within 2 of the spawn point.
+        assert_eq!(capacity(2.0), 87);
+
+        // Spawn points on another floor don't count.
+        let above = SpawnArea {
+            valid_spawn_points: vec![[10, 25, 10].into()],
+            facing: None,
+        };
+        assert_eq!(encounter_capacity(&room, &[], &[], &above, 2.0), 100);
     }
 }
@@ -0,0 +1,316 @@
+use crate::{
+    noise::{FbmSpec, ValueNoise2},
+    occupancy::OccupancyGrid,
+    room::{EMPTY_VOXEL, FLOOR_VOXEL},
+    sampling::{retry_with_derived_seeds, small_rng},
+    SpawnArea, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Island {
+    /// The center of the island's flat top, one voxel above the ground.
+    pub center: Point,
+    pub radius: i32,
+    /// How far the island's underside hangs below its top.
+    pub depth: i32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Bridge {
+    /// Indices into `IslandsMeta::islands`.
+    pub islands: (usize, usize),
+    /// The deck, made of runs of constant height. Consecutive runs differ by one voxel in height.
+    pub segments: Vec<Extent>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IslandsMeta {
+    pub spawn_area: SpawnArea,
+    pub islands: Vec<Island>,
+    pub bridges: Vec<Bridge>,
+}
+
+/// Generates islands floating in the sky, each a flat-topped half ellipsoid whose rim is shaped by
+/// noise. Nearby islands are connected by bridges, which form a spanning tree over the islands
+/// that are close enough to reach each other.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct IslandsMapSpec {
+    pub seed: [u32; 4],
+    /// The map occupies the extent from the origin to this supremum.
+    pub size: Point,
+    pub num_islands: usize,
+    pub min_radius: i32,
+    pub max_radius: i32,
+    /// An island hangs this many times its radius below its top.
+    pub depth_ratio: f32,
+    /// Islands' rims are at least this far apart horizontally.
+    pub min_gap: i32,
+    /// The rim moves out or in by up to this fraction of the radius.
+    pub noise_amplitude: f32,
+    pub noise: FbmSpec,
+    /// Islands whose rims are farther apart than this aren't bridged.
+    pub max_bridge_length: i32,
+    pub bridge_width: i32,
+}
+
+impl IslandsMapSpec {
+    fn extent(&self) -> Extent {
+        Extent::from_min_and_local_supremum([0, 0, 0].into(), self.size)
+    }
+
+    fn depth(&self, radius: i32) -> i32 {
+        (radius as f32 * self.depth_ratio).ceil() as i32
+    }
+
+    /// Samples islands until `num_islands` are placed or too many attempts fail.
+    fn place_islands(&self, rng: &mut impl Rng) -> Vec<Island> {
+        let mut islands: Vec<Island> = Vec::new();
+        let max_radius = self.max_radius.max(self.min_radius).max(1);
+        let reach = (max_radius as f32 * (1.0 + self.noise_amplitude.abs())).ceil() as i32;
+        for _ in 0..10 * self.num_islands {
+            if islands.len() >= self.num_islands {
+                break;
+            }
+
+            let radius = rng.gen_range(self.min_radius.max(1), max_radius + 1);
+            let depth = self.depth(radius);
+            let (lo, hi) = (
+                reach + 1,
+                [self.size.x - reach - 1, self.size.z - reach - 1],
+            );
+            let (y_lo, y_hi) = (depth + 1, self.size.y - 2);
+            if lo >= hi[0] || lo >= hi[1] || y_lo >= y_hi {
+                break;
+            }
+            let center: Point = [
+                rng.gen_range(lo, hi[0]),
+                rng.gen_range(y_lo, y_hi),
+                rng.gen_range(lo, hi[1]),
+            ]
+            .into();
+
+            let too_close = islands
+                .iter()
+                .any(|other| rim_gap(other, &center, radius) < self.min_gap as f32);
+            if !too_close {
+                islands.push(Island {
+                    center,
+                    radius,
+                    depth,
+                });
+            }
+        }
+
+        islands
+    }
+
+    fn fill_island(&self, island: &Island, noise: &ValueNoise2, grid: &mut OccupancyGrid) {
+        let reach = (island.radius as f32 * (1.0 + self.noise_amplitude.abs())).ceil() as i32;
+        let c = island.center;
+        for z in c.z - reach..=c.z + reach {
+            for x in c.x - reach..=c.x + reach {
+                let (dx, dz) = ((x - c.x) as f32, (z - c.z) as f32);
+                let rim = island.radius as f32
+                    * (1.0 + self.noise_amplitude * self.noise.sample(noise, x as f32, z as f32));
+                let h = (dx * dx + dz * dz) / (rim * rim).max(1.0);
+                if h > 1.0 {
+                    continue;
+                }
+
+                // The underside is an ellipsoid, so columns get shallower toward the rim.
+                let depth = (island.depth as f32 * (1.0 - h).sqrt()).ceil().max(1.0) as i32;
+                for y in c.y - depth..c.y {
+                    grid.set_solid(&[x, y, z].into(), true);
+                }
+            }
+        }
+    }
+
+    /// A bridge deck from the center of `a` along X, then along Z to the center of `b`, that
+    /// rises or falls evenly between the islands' tops. Returns `None` if the slope is too steep to
+    /// walk.
+    fn bridge_between(&self, a: &Island, b: &Island) -> Option<Vec<Extent>> {
+        let (from, to) = (a.center, b.center);
+        let (dx, dz) = (to.x - from.x, to.z - from.z);
+        let length = dx.abs() + dz.abs();
+        let rise = to.y - from.y;
+        // Rise at most one voxel for every two voxels of run.
+        if length == 0 || 2 * rise.abs() > length {
+            return None;
+        }
+
+        let width = self.bridge_width.max(1);
+        let half = width / 2;
+        let deck_y = |t: i32| from.y - 1 + (rise as f32 * t as f32 / length as f32).round() as i32;
+        // Each voxel along the path, with the axis it's moving along.
+        let path = (0..=dx.abs())
+            .map(|i| ([from.x + i * dx.signum(), from.z], 0))
+            .chain((1..=dz.abs()).map(|i| ([to.x, from.z + i * dz.signum()], 2)));
+
+        let mut segments: Vec<Extent> = Vec::new();
+        let mut run: Option<(Point, Point, usize)> = None;
+        for (t, ([x, z], axis)) in path.enumerate() {
+            let p: Point = [x, deck_y(t as i32), z].into();
+            run = match run {
+                Some((start, end, run_axis)) if run_axis == axis && end.y == p.y => {
+                    Some((start, p, run_axis))
+                }
+                Some((start, end, run_axis)) => {
+                    segments.push(deck_segment(start, end, run_axis, half, width));
+                    Some((p, p, axis))
+                }
+                None => Some((p, p, axis)),
+            };
+        }
+        if let Some((start, end, axis)) = run {
+            segments.push(deck_segment(start, end, axis, half, width));
+        }
+
+        Some(segments)
+    }
+
+    /// Connects islands with bridges in order of increasing rim gap, skipping pairs that are
+    /// already connected, so the bridges form a spanning forest.
+    fn plan_bridges(&self, islands: &[Island]) -> Vec<Bridge> {
+        let mut pairs = Vec::new();
+        for i in 0..islands.len() {
+            for j in i + 1..islands.len() {
+                let gap = rim_gap(&islands[i], &islands[j].center, islands[j].radius);
+                if gap <= self.max_bridge_length as f32 {
+                    pairs.push((gap, i, j));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut component: Vec<usize> = (0..islands.len()).collect();
+        let mut bridges = Vec::new();
+        for (_, i, j) in pairs.into_iter() {
+            let (ci, cj) = (component[i], component[j]);
+            if ci == cj {
+                continue;
+            }
+            if let Some(segments) = self.bridge_between(&islands[i], &islands[j]) {
+                for c in component.iter_mut() {
+                    if *c == cj {
+                        *c = ci;
+                    }
+                }
+                bridges.push(Bridge {
+                    islands: (i, j),
+                    segments,
+                });
+            }
+        }
+
+        bridges
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<IslandsMeta> {
+        log::debug!("Generating islands map");
+
+        let islands = self.place_islands(rng);
+        if islands.is_empty() {
+            return None;
+        }
+        let bridges = self.plan_bridges(&islands);
+        log::debug!(
+            "Placed {} islands and {} bridges",
+            islands.len(),
+            bridges.len()
+        );
+
+        let noise = ValueNoise2::new(rng.gen());
+        let mut grid = OccupancyGrid::new(self.extent(), false);
+        for island in islands.iter() {
+            self.fill_island(island, &noise, &mut grid);
+        }
+        for b in bridges.iter() {
+            for s in b.segments.iter() {
+                for p in s {
+                    grid.set_solid(&p, true);
+                }
+            }
+        }
+
+        // Spawn on top of the first island.
+        let first = &islands[0];
+        let valid_spawn_points: Vec<Point> = (first.center.z - first.radius
+            ..=first.center.z + first.radius)
+            .flat_map(|z| {
+                (first.center.x - first.radius..=first.center.x + first.radius)
+                    .map(move |x| [x, first.center.y, z].into())
+            })
+            .filter(|p: &Point| !grid.is_solid(p) && grid.is_solid(&(*p - [0, 1, 0].into())))
+            .collect();
+        if valid_spawn_points.is_empty() {
+            return None;
+        }
+
+        for p in &self.extent() {
+            if grid.is_solid(&p) {
+                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+                if !grid.is_solid(&(p + [0, 1, 0].into())) {
+                    encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+                }
+            } else {
+                encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            }
+        }
+
+        Some(IslandsMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points,
+                facing: None,
+            },
+            islands,
+            bridges,
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> IslandsMeta {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |_, seed| {
+            self.try_generate(&mut small_rng(seed), encoder)
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "Failed to generate islands map after {} tries",
+                MAX_GENERATE_TRIES
+            )
+        })
+    }
+}
+
+/// The horizontal distance between the rim of `island` and the rim of a circle with `center` and
+/// `radius`, ignoring noise.
+fn rim_gap(island: &Island, center: &Point, radius: i32) -> f32 {
+    let (dx, dz) = (
+        (island.center.x - center.x) as f32,
+        (island.center.z - center.z) as f32,
+    );
+
+    (dx * dx + dz * dz).sqrt() - (island.radius + radius) as f32
+}
+
+/// The deck voxels from `start` to `end` along `axis`, widened across the other horizontal axis.
+fn deck_segment(start: Point, end: Point, axis: usize, half: i32, width: i32) -> Extent {
+    let min: Point = [start.x.min(end.x), start.y, start.z.min(end.z)].into();
+    let (len_x, len_z) = ((start.x - end.x).abs() + 1, (start.z - end.z).abs() + 1);
+    if axis == 0 {
+        Extent::from_min_and_local_supremum(min - [0, 0, half].into(), [len_x, 1, width].into())
+    } else {
+        Extent::from_min_and_local_supremum(min - [half, 0, 0].into(), [width, 1, len_z].into())
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{
+    extent::{component, with_component},
+    occupancy::OccupancyGrid,
+    room::{CLIMBABLE_VOXEL, EMPTY_VOXEL, FLOOR_VOXEL},
+    sampling::{retry_with_derived_seeds, small_rng},
+    SpawnArea, Voxel, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+/// The timber frames that hold up the drifts.
+pub const BEAM_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 3,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MineMeta {
+    pub spawn_area: SpawnArea,
+    /// The floor height of each level, from the bottom up.
+    pub levels: Vec<i32>,
+    pub shafts: Vec<Extent>,
+    /// The carved part of each drift that's connected to the rest of the mine.
+    pub drifts: Vec<Extent>,
+    /// The voxels of every support frame, written as `BEAM_VOXEL`.
+    pub beams: Vec<Extent>,
+    /// The ladders that run up one wall of each shaft.
+    pub ladders: Vec<Extent>,
+}
+
+/// Generates a mine: vertical shafts that pass through every level, with horizontal drifts
+/// branching off of the shafts on each level. The shafts are chained together by drifts on the
+/// bottom level, and each shaft has a ladder, so the whole mine is one network.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct MineMapSpec {
+    pub seed: [u32; 4],
+    /// The map occupies the extent from the origin to this supremum.
+    pub size: Point,
+    pub num_shafts: usize,
+    /// The length of a shaft's square cross section.
+    pub shaft_width: i32,
+    /// The vertical distance between the floors of consecutive levels.
+    pub level_spacing: i32,
+    pub drift_width: i32,
+    pub drift_height: i32,
+    /// How many drifts branch off of the shafts on each level.
+    pub drifts_per_level: usize,
+    pub max_drift_length: i32,
+    /// The distance between support frames along a drift. Frames are left out if this is 0.
+    pub beam_spacing: i32,
+}
+
+impl MineMapSpec {
+    fn extent(&self) -> Extent {
+        Extent::from_min_and_local_supremum([0, 0, 0].into(), self.size)
+    }
+
+    fn interior(&self) -> Extent {
+        self.extent().radial_grow(-1)
+    }
+
+    /// Makes every cell in `extent` empty, except for the outer shell of the map. Returns the part
+    /// of `extent` that was carved.
+    fn carve(&self, grid: &mut OccupancyGrid, extent: &Extent) -> Extent {
+        let carved = extent.intersection(&self.interior());
+        for p in &carved {
+            grid.set_solid(&p, false);
+        }
+
+        carved
+    }
+
+    fn levels(&self) -> Vec<i32> {
+        let spacing = self.level_spacing.max(self.drift_height + 1);
+
+        (0..)
+            .map(|i| 1 + i * spacing)
+            .take_while(|y| y + self.drift_height < self.size.y)
+            .collect()
+    }
+
+    /// A drift that starts at `start` on its floor and runs `length` voxels along `axis`, centered
+    /// across its width.
+    fn drift_from(&self, start: Point, axis: usize, positive: bool, length: i32) -> Extent {
+        let across = 2 - axis;
+        let a_min = if positive {
+            component(&start, axis)
+        } else {
+            component(&start, axis) - length + 1
+        };
+        let min = with_component(
+            &with_component(&start, axis, a_min),
+            across,
+            component(&start, across) - self.drift_width / 2,
+        );
+        let sup = with_component(
+            &with_component(&[0, self.drift_height, 0].into(), axis, length),
+            across,
+            self.drift_width,
+        );
+
+        Extent::from_min_and_local_supremum(min, sup)
+    }
+
+    fn shaft_center(shaft: &Extent, y: i32) -> Point {
+        let (min, sup) = (shaft.get_minimum(), shaft.get_local_supremum());
+
+        [min.x + sup.x / 2, y, min.z + sup.z / 2].into()
+    }
+
+    /// The posts and cap of each support frame along `drifts`. Frames that would stick into a shaft
+    /// or another drift are left out, so they never block a junction.
+    fn support_beams(&self, drifts: &[Extent], shafts: &[Extent]) -> Vec<Extent> {
+        if self.beam_spacing <= 0 {
+            return Vec::new();
+        }
+
+        let mut beams = Vec::new();
+        for (i, drift) in drifts.iter().enumerate() {
+            let sup = drift.get_local_supremum();
+            let axis = if sup.x >= sup.z { 0 } else { 2 };
+            let across = 2 - axis;
+            let (a_min, a_len) = (component(drift.get_minimum(), axis), component(sup, axis));
+            let mut a = a_min + self.beam_spacing / 2;
+            while a < a_min + a_len {
+                let frame = Extent::from_min_and_local_supremum(
+                    with_component(drift.get_minimum(), axis, a),
+                    with_component(sup, axis, 1),
+                );
+                a += self.beam_spacing;
+
+                let blocks_junction = shafts
+                    .iter()
+                    .chain(
+                        drifts
+                            .iter()
+                            .enumerate()
+                            .filter(|(j, _)| *j != i)
+                            .map(|(_, d)| d),
+                    )
+                    .any(|e| !frame.intersection(e).is_empty());
+                if blocks_junction {
+                    continue;
+                }
+
+                let (f_min, f_sup) = (frame.get_minimum(), frame.get_local_supremum());
+                let cap_y = f_min.y + f_sup.y - 1;
+                beams.push(Extent::from_min_and_local_supremum(
+                    with_component(f_min, 1, cap_y),
+                    with_component(f_sup, 1, 1),
+                ));
+                // Posts would leave no room to walk in a narrow drift.
+                if component(f_sup, across) >= 3 {
+                    let post_sup =
+                        with_component(&with_component(f_sup, 1, f_sup.y - 1), across, 1);
+                    let far_side = component(f_min, across) + component(f_sup, across) - 1;
+                    beams.push(Extent::from_min_and_local_supremum(*f_min, post_sup));
+                    beams.push(Extent::from_min_and_local_supremum(
+                        with_component(f_min, across, far_side),
+                        post_sup,
+                    ));
+                }
+            }
+        }
+
+        beams
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<MineMeta> {
+        log::debug!("Generating mine map");
+
+        let levels = self.levels();
+        let top_level = *levels.last()?;
+        let interior = self.interior();
+        let int_min = interior.get_minimum();
+        let int_sup = interior.get_world_supremum();
+        // Leave room for the ladder in the wall on the -X side of each shaft.
+        let (x_lo, x_hi) = (int_min.x + 1, int_sup.x - self.shaft_width);
+        let (z_lo, z_hi) = (int_min.z, int_sup.z - self.shaft_width);
+        if self.num_shafts == 0 || self.shaft_width < 1 || x_hi <= x_lo || z_hi <= z_lo {
+            return None;
+        }
+
+        let mut grid = OccupancyGrid::new(self.extent(), true);
+        let shafts: Vec<Extent> = (0..self.num_shafts)
+            .map(|_| {
+                Extent::from_min_and_local_supremum(
+                    [rng.gen_range(x_lo, x_hi), 1, rng.gen_range(z_lo, z_hi)].into(),
+                    [
+                        self.shaft_width,
+                        top_level + self.drift_height - 1,
+                        self.shaft_width,
+                    ]
+                    .into(),
+                )
+            })
+            .collect();
+        for s in shafts.iter() {
+            self.carve(&mut grid, s);
+        }
+
+        // Chain the shafts together on the bottom level with L-shaped drifts.
+        let mut drifts = Vec::new();
+        for pair in shafts.windows(2) {
+            let (from, to) = (
+                Self::shaft_center(&pair[0], levels[0]),
+                Self::shaft_center(&pair[1], levels[0]),
+            );
+            let (dx, dz) = (to.x - from.x, to.z - from.z);
+            let half = self.drift_width / 2;
+            drifts.push(self.drift_from(from, 0, dx >= 0, dx.abs() + half + 1));
+            drifts.push(self.drift_from(
+                [to.x, levels[0], from.z].into(),
+                2,
+                dz >= 0,
+                dz.abs() + 1,
+            ));
+        }
+
+        // Branches that dead-end in the rock.
+        let max_length = self.max_drift_length.max(self.drift_width);
+        for y in levels.iter() {
+            for _ in 0..self.drifts_per_level {
+                let shaft = shafts.choose(rng)?;
+                let axis = if rng.gen() { 0 } else { 2 };
+                let length = rng.gen_range(self.drift_width, max_length + 1);
+                drifts.push(self.drift_from(
+                    Self::shaft_center(shaft, *y),
+                    axis,
+                    rng.gen(),
+                    length,
+                ));
+            }
+        }
+        let drifts: Vec<Extent> = drifts
+            .iter()
+            .map(|d| self.carve(&mut grid, d))
+            .filter(|d| !d.is_empty())
+            .collect();
+        log::debug!("Carved {} shafts and {} drifts", shafts.len(), drifts.len());
+
+        let open_points = grid.keep_largest_empty_component();
+        // Drifts that weren't connected to the rest of the mine have been filled back in. Each
+        // drift is a box of empty cells, so it's either all filled or not at all.
+        let drifts: Vec<Extent> = drifts
+            .into_iter()
+            .filter(|d| !grid.is_solid(d.get_minimum()))
+            .collect();
+        let beams = self.support_beams(&drifts, &shafts);
+        let valid_spawn_points: Vec<_> = grid
+            .floor_points(&open_points)
+            .filter(|p| !beams.iter().any(|b| b.contains_world(p)))
+            .collect();
+        if valid_spawn_points.is_empty() {
+            return None;
+        }
+
+        let ladders: Vec<Extent> = shafts
+            .iter()
+            .map(|s| {
+                let min = s.get_minimum();
+                Extent::from_min_and_local_supremum(
+                    [min.x - 1, min.y, min.z + s.get_local_supremum().z / 2].into(),
+                    [1, s.get_local_supremum().y, 1].into(),
+                )
+            })
+            .collect();
+
+        for p in &self.extent() {
+            if grid.is_solid(&p) {
+                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            } else {
+                encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            }
+        }
+        for b in beams.iter() {
+            for p in b {
+                encoder.encode_voxel(&p, &BEAM_VOXEL);
+            }
+        }
+        for l in ladders.iter() {
+            // Drifts may cut through the ladder's wall.
+            for p in l {
+                if grid.is_solid(&p) {
+                    encoder.encode_voxel(&p, &CLIMBABLE_VOXEL);
+                    encoder.encode_flags(&p, VoxelFlags::CLIMBABLE);
+                }
+            }
+        }
+        for p in valid_spawn_points.iter() {
+            encoder.encode_flags(&(*p - [0, 1, 0].into()), VoxelFlags::WALKABLE);
+        }
+
+        Some(MineMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points,
+                facing: None,
+            },
+            levels,
+            shafts,
+            drifts,
+            beams,
+            ladders,
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> MineMeta {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |_, seed| {
+            self.try_generate(&mut small_rng(seed), encoder)
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "Failed to generate mine map after {} tries",
+                MAX_GENERATE_TRIES
+            )
+        })
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct TypeRecorder {
+        types: HashMap<Point, u8>,
+    }
+
+    impl VoxelEncoder for TypeRecorder {
+        fn encode_voxel(&mut self, point: &Point, voxel: &Voxel) {
+            self.types.insert(*point, voxel.voxel_type);
+        }
+
+        fn encode_flags(&mut self, _point: &Point, _flags: VoxelFlags) {}
+    }
+
+    #[test]
+    fn test_listed_drifts_are_open() {
+        for seed in 0..5 {
+            let spec = MineMapSpec {
+                seed: [seed, 7, 7, 7],
+                size: [40, 30, 40].into(),
+                num_shafts: 2,
+                shaft_width: 3,
+                level_spacing: 8,
+                drift_width: 3,
+                drift_height: 4,
+                drifts_per_level: 4,
+                max_drift_length: 30,
+                beam_spacing: 0,
+            };
+            let mut encoder = TypeRecorder::default();
+            let meta = spec.generate(&mut small_rng(spec.seed), &mut encoder);
+
+            assert!(!meta.drifts.is_empty());
+            for d in meta.drifts.iter() {
+                assert!(d
+                    .into_iter()
+                    .all(|p| encoder.types[&p] == EMPTY_VOXEL.voxel_type));
+            }
+        }
+    }
+}
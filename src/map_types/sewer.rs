@@ -0,0 +1,253 @@
+use crate::{
+    channel::{channel_below, fill_map_with_grates, fill_map_with_water},
+    occupancy::OccupancyGrid,
+    room::{CLIMBABLE_VOXEL, EMPTY_VOXEL, FLOOR_VOXEL},
+    sampling::{retry_with_derived_seeds, small_rng},
+    SpawnArea, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::{prelude::*, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SewerMeta {
+    pub spawn_area: SpawnArea,
+    pub tunnels: Vec<Extent>,
+    /// The water-filled trenches along the middle of each tunnel.
+    pub channels: Vec<Extent>,
+    pub junctions: Vec<Extent>,
+    /// The shafts up to the surface. Each has a ladder in one corner and a grate on top.
+    pub manholes: Vec<Extent>,
+    pub ladders: Vec<Extent>,
+    pub grates: Vec<Extent>,
+}
+
+/// Generates a grid of long, straight sewer tunnels along X and Z, each with a water channel sunk
+/// into the middle of its floor. Some crossings are widened into junction chambers, and some have
+/// manholes up to the surface, which is the top of the map.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct SewerMapSpec {
+    pub seed: [u32; 4],
+    /// The map occupies the extent from the origin to this supremum.
+    pub size: Point,
+    /// The distance between parallel tunnels.
+    pub tunnel_spacing: i32,
+    pub tunnel_width: i32,
+    pub tunnel_height: i32,
+    pub channel_width: i32,
+    /// Channels hold water up to one voxel below the floor, so this should be at least 2.
+    pub channel_depth: i32,
+    /// The probability that a crossing is widened into a junction chamber.
+    pub junction_probability: f32,
+    /// Junctions extend this far horizontally from the crossing's center.
+    pub junction_radius: i32,
+    pub junction_height: i32,
+    pub num_manholes: usize,
+    pub manhole_width: i32,
+}
+
+impl SewerMapSpec {
+    fn extent(&self) -> Extent {
+        Extent::from_min_and_local_supremum([0, 0, 0].into(), self.size)
+    }
+
+    /// Makes every cell in `extent` empty, except for the outer shell of the map.
+    fn carve(&self, grid: &mut OccupancyGrid, extent: &Extent) {
+        for p in &extent.intersection(&self.extent().radial_grow(-1)) {
+            grid.set_solid(&p, false);
+        }
+    }
+
+    fn floor_y(&self) -> i32 {
+        1 + self.channel_depth
+    }
+
+    /// The centers of the tunnels along one horizontal axis of length `len`.
+    fn tunnel_centers(&self, len: i32) -> Vec<i32> {
+        let spacing = self.tunnel_spacing.max(self.tunnel_width + 1);
+
+        (0..)
+            .map(|i| spacing / 2 + i * spacing)
+            .take_while(|c| c + self.tunnel_width / 2 + 1 < len)
+            .collect()
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<SewerMeta> {
+        log::debug!("Generating sewer map");
+
+        let floor_y = self.floor_y();
+        if floor_y + self.tunnel_height.max(self.junction_height) >= self.size.y - 1 {
+            return None;
+        }
+        let (xs, zs) = (
+            self.tunnel_centers(self.size.x),
+            self.tunnel_centers(self.size.z),
+        );
+        if xs.is_empty() || zs.is_empty() {
+            return None;
+        }
+
+        let mut grid = OccupancyGrid::new(self.extent(), true);
+        let half = self.tunnel_width / 2;
+        let mut tunnels = Vec::new();
+        let mut channels = Vec::new();
+        for z in zs.iter() {
+            let tunnel = Extent::from_min_and_local_supremum(
+                [1, floor_y, z - half].into(),
+                [self.size.x - 2, self.tunnel_height, self.tunnel_width].into(),
+            );
+            channels.push(channel_below(
+                &tunnel,
+                0,
+                self.channel_width,
+                self.channel_depth,
+            ));
+            tunnels.push(tunnel);
+        }
+        for x in xs.iter() {
+            let tunnel = Extent::from_min_and_local_supremum(
+                [x - half, floor_y, 1].into(),
+                [self.tunnel_width, self.tunnel_height, self.size.z - 2].into(),
+            );
+            channels.push(channel_below(
+                &tunnel,
+                2,
+                self.channel_width,
+                self.channel_depth,
+            ));
+            tunnels.push(tunnel);
+        }
+        for e in tunnels.iter().chain(channels.iter()) {
+            self.carve(&mut grid, e);
+        }
+
+        let crossings: Vec<Point> = zs
+            .iter()
+            .flat_map(|z| xs.iter().map(move |x| [*x, floor_y, *z].into()))
+            .collect();
+        let r = self.junction_radius.max(half);
+        let junctions: Vec<Extent> = crossings
+            .iter()
+            .filter(|_| rng.gen::<f32>() < self.junction_probability)
+            .map(|c| {
+                Extent::from_min_and_local_supremum(
+                    *c - [r, 0, r].into(),
+                    [2 * r + 1, self.junction_height, 2 * r + 1].into(),
+                )
+            })
+            .collect();
+        for j in junctions.iter() {
+            self.carve(&mut grid, j);
+        }
+
+        // Manholes run from the tunnel up through the surface, where a grate covers them.
+        let w = self.manhole_width.max(1);
+        let surface_y = self.size.y - 1;
+        let manholes: Vec<Extent> = crossings
+            .choose_multiple(rng, self.num_manholes)
+            .map(|c| {
+                Extent::from_min_and_local_supremum(
+                    *c - [w / 2, 0, w / 2].into(),
+                    [w, surface_y - floor_y, w].into(),
+                )
+            })
+            .collect();
+        for m in manholes.iter() {
+            self.carve(&mut grid, m);
+        }
+        // The corner of the shaft stays clear of both channels if the shaft is wide enough.
+        let ladders: Vec<Extent> = manholes
+            .iter()
+            .map(|m| {
+                Extent::from_min_and_local_supremum(
+                    *m.get_minimum(),
+                    [1, m.get_local_supremum().y, 1].into(),
+                )
+            })
+            .collect();
+        for l in ladders.iter() {
+            for p in l {
+                grid.set_solid(&p, true);
+            }
+        }
+        let grates: Vec<Extent> = manholes
+            .iter()
+            .map(|m| {
+                let min = m.get_minimum();
+                Extent::from_min_and_local_supremum(
+                    [min.x, surface_y, min.z].into(),
+                    [w, 1, w].into(),
+                )
+            })
+            .collect();
+        log::debug!(
+            "Carved {} tunnels, {} junctions and {} manholes",
+            tunnels.len(),
+            junctions.len(),
+            manholes.len()
+        );
+
+        let open_points = grid.keep_largest_empty_component();
+        // Don't spawn at the bottom of a channel.
+        let valid_spawn_points: Vec<_> = grid
+            .floor_points(&open_points)
+            .filter(|p| !channels.iter().any(|c| c.contains_world(p)))
+            .collect();
+        if valid_spawn_points.is_empty() {
+            return None;
+        }
+
+        for p in &self.extent() {
+            if grid.is_solid(&p) {
+                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            } else {
+                encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            }
+        }
+        fill_map_with_water(&channels, encoder);
+        for l in ladders.iter() {
+            for p in l {
+                encoder.encode_voxel(&p, &CLIMBABLE_VOXEL);
+                encoder.encode_flags(&p, VoxelFlags::CLIMBABLE);
+            }
+        }
+        fill_map_with_grates(&grates, encoder);
+        for p in valid_spawn_points.iter() {
+            encoder.encode_flags(&(*p - [0, 1, 0].into()), VoxelFlags::WALKABLE);
+        }
+
+        Some(SewerMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points,
+                facing: None,
+            },
+            tunnels,
+            channels,
+            junctions,
+            manholes,
+            ladders,
+            grates,
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> SewerMeta {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |_, seed| {
+            self.try_generate(&mut small_rng(seed), encoder)
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "Failed to generate sewer map after {} tries",
+                MAX_GENERATE_TRIES
+            )
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    noise::{FbmSpec, ValueNoise2},
+    room::FLOOR_VOXEL,
+    SpawnArea, Voxel, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::Point;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TerrainMeta {
+    pub spawn_area: SpawnArea,
+}
+
+/// Generates heightmap terrain for outdoor maps.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct TerrainMapSpec {
+    pub seed: [u32; 4],
+    /// The terrain occupies the extent from the origin to this supremum.
+    pub size: Point,
+    pub base_height: f32,
+    /// The noise value in `[-1, 1]` is scaled by this and added to `base_height`.
+    pub amplitude: f32,
+    pub noise: FbmSpec,
+    /// Columns within this horizontal distance of the center are valid spawn locations.
+    pub spawn_radius: i32,
+}
+
+/// The distance is only exact within this many voxels of the surface. Farther voxels get this
+/// distance, which is all that meshing needs.
+const DISTANCE_BAND: i32 = 2;
+
+/// The distance from `p` to the surface through the tops of the columns, where neighboring tops
+/// along X and Z are joined by line segments, or `DISTANCE_BAND` if it's farther than that.
+fn distance_to_surface(p: [f32; 3], height_at: &impl Fn(i32, i32) -> f32) -> f32 {
+    let (x, z) = (p[0] as i32, p[2] as i32);
+    let mut min_distance = DISTANCE_BAND as f32;
+    for cz in z - DISTANCE_BAND..=z + DISTANCE_BAND {
+        for cx in x - DISTANCE_BAND..=x + DISTANCE_BAND {
+            let top = [cx as f32, height_at(cx, cz), cz as f32];
+            for (nx, nz) in [(cx + 1, cz), (cx, cz + 1)].iter().cloned() {
+                let next = [nx as f32, height_at(nx, nz), nz as f32];
+                min_distance = min_distance.min(distance_to_segment(p, top, next));
+            }
+        }
+    }
+
+    min_distance
+}
+
+fn distance_to_segment(p: [f32; 3], a: [f32; 3], b: [f32; 3]) -> f32 {
+    let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let ap = [p[0] - a[0], p[1] - a[1], p[2] - a[2]];
+    let len_sq = ab[0] * ab[0] + ab[1] * ab[1] + ab[2] * ab[2];
+    // Columns past the edge of the map are clamped to it, so a segment can be a single point.
+    let t = if len_sq > 0.0 {
+        ((ap[0] * ab[0] + ap[1] * ab[1] + ap[2] * ab[2]) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let d = [ap[0] - t * ab[0], ap[1] - t * ab[1], ap[2] - t * ab[2]];
+
+    (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+}
+
+impl TerrainMapSpec {
+    /// Writes every voxel in the map's extent into `encoder`.
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> TerrainMeta {
+        log::debug!("Generating terrain map");
+
+        let noise = ValueNoise2::new(rng.gen());
+        let center_x = self.size.x / 2;
+        let center_z = self.size.z / 2;
+        let (size_x, size_z) = (self.size.x.max(0) as usize, self.size.z.max(0) as usize);
+        let mut heights = Vec::with_capacity(size_x * size_z);
+        for z in 0..self.size.z {
+            for x in 0..self.size.x {
+                heights.push(
+                    self.base_height
+                        + self.amplitude * self.noise.sample(&noise, x as f32, z as f32),
+                );
+            }
+        }
+        let height_at = |x: i32, z: i32| {
+            let x = x.max(0).min(self.size.x - 1) as usize;
+            let z = z.max(0).min(self.size.z - 1) as usize;
+
+            heights[z * size_x + x]
+        };
+
+        let mut valid_spawn_points = Vec::new();
+        for z in 0..self.size.z {
+            for x in 0..self.size.x {
+                let height = height_at(x, z);
+
+                for y in 0..self.size.y {
+                    let above_surface = y as f32 - height;
+                    let solid = above_surface < 0.0;
+                    let unsigned = distance_to_surface([x as f32, y as f32, z as f32], &height_at);
+                    let distance = if solid { -unsigned } else { unsigned };
+                    let voxel = if solid {
+                        Voxel {
+                            distance,
+                            voxel_type: FLOOR_VOXEL.voxel_type,
+                        }
+                    } else {
+                        Voxel {
+                            distance,
+                            voxel_type: 0,
+                        }
+                    };
+                    encoder.encode_voxel(&[x, y, z].into(), &voxel);
+                    if solid && above_surface + 1.0 >= 0.0 {
+                        // The topmost solid voxel in the column.
+                        encoder.encode_flags(&[x, y, z].into(), VoxelFlags::WALKABLE);
+                    }
+                }
+
+                let (dx, dz) = (x - center_x, z - center_z);
+                if dx * dx + dz * dz <= self.spawn_radius * self.spawn_radius {
+                    // The first air voxel above the ground.
+                    let surface_y = (height.ceil() as i32).max(0).min(self.size.y - 1);
+                    valid_spawn_points.push([x, surface_y, z].into());
+                }
+            }
+        }
+
+        TerrainMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points,
+                facing: None,
+            },
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::small_rng;
+
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct DistanceRecorder {
+        distances: HashMap<Point, f32>,
+    }
+
+    impl VoxelEncoder for DistanceRecorder {
+        fn encode_voxel(&mut self, point: &Point, voxel: &Voxel) {
+            self.distances.insert(*point, voxel.distance);
+        }
+
+        fn encode_flags(&mut self, _point: &Point, _flags: VoxelFlags) {}
+    }
+
+    fn steep_terrain() -> TerrainMapSpec {
+        TerrainMapSpec {
+            seed: [1, 2, 3, 4],
+            size: [24, 48, 24].into(),
+            base_height: 24.0,
+            amplitude: 20.0,
+            noise: FbmSpec {
+                octaves: 2,
+                frequency: 0.2,
+                lacunarity: 2.0,
+                gain: 0.5,
+            },
+            spawn_radius: 6,
+        }
+    }
+
+    #[test]
+    fn test_distance_is_bounded_by_neighbor_distance_on_steep_slopes() {
+        let spec = steep_terrain();
+        let mut encoder = DistanceRecorder::default();
+        spec.generate(&mut small_rng(spec.seed), &mut encoder);
+
+        // Near the surface, a distance field changes by about the distance between samples, while
+        // the vertical distance to a steep surface jumps by several voxels from one column to the
+        // next.
+        let mut max_step: f32 = 0.0;
+        for (p, d) in encoder.distances.iter() {
+            let neighbor = [p.x + 1, p.y, p.z].into();
+            if let Some(n) = encoder.distances.get(&neighbor) {
+                if d.abs().min(n.abs()) < 1.0 {
+                    max_step = max_step.max((d - n).abs());
+                }
+            }
+        }
+        assert!(max_step < 1.5, "max step {}", max_step);
+    }
+
+    #[test]
+    fn test_spawn_points_are_the_first_air_voxels() {
+        let spec = steep_terrain();
+        let mut encoder = DistanceRecorder::default();
+        let meta = spec.generate(&mut small_rng(spec.seed), &mut encoder);
+
+        for p in meta.spawn_area.valid_spawn_points.iter() {
+            assert!(encoder.distances[p] >= 0.0);
+            assert!(encoder.distances[&[p.x, p.y - 1, p.z].into()] < 0.0);
+        }
+    }
+}
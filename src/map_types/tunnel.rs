@@ -0,0 +1,165 @@
+use crate::{
+    occupancy::OccupancyGrid,
+    room::{EMPTY_VOXEL, FLOOR_VOXEL},
+    sampling::{retry_with_derived_seeds, small_rng},
+    SpawnArea, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::{Extent, Point};
+use rand::{prelude::*, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+const HORIZONTAL_DIRECTIONS: [[i32; 3]; 4] = [[-1, 0, 0], [1, 0, 0], [0, 0, -1], [0, 0, 1]];
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TunnelMeta {
+    pub spawn_area: SpawnArea,
+    /// The extents of the chambers that were carved along the tunnels.
+    pub chambers: Vec<Extent>,
+}
+
+/// Generates winding tunnels by letting random walkers carve through solid rock. All walkers start
+/// in the center of the map, so the tunnels are connected.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct TunnelMapSpec {
+    pub seed: [u32; 4],
+    /// The map occupies the extent from the origin to this supremum.
+    pub size: Point,
+    pub num_walkers: usize,
+    pub steps_per_walker: usize,
+    /// Tunnels are `2 * tunnel_radius + 1` voxels wide and tall.
+    pub tunnel_radius: i32,
+    /// The probability that a walker picks a new horizontal direction on each step.
+    pub turn_probability: f32,
+    /// The probability that a step also moves up or down by one voxel, making a ramp.
+    pub slope_probability: f32,
+    /// The probability that a chamber is carved around a walker on each step.
+    pub chamber_probability: f32,
+    /// Chambers extend this far horizontally and upward from the walker.
+    pub chamber_radius: i32,
+    /// Attempts where the open space is a smaller fraction of the volume than this are rejected.
+    pub min_open_ratio: f32,
+}
+
+impl TunnelMapSpec {
+    fn extent(&self) -> Extent {
+        Extent::from_min_and_local_supremum([0, 0, 0].into(), self.size)
+    }
+
+    /// Makes every cell in `extent` empty, except for the outer shell of the map.
+    fn carve(&self, grid: &mut OccupancyGrid, extent: &Extent) {
+        let interior = self.extent().radial_grow(-1);
+        for p in &extent.intersection(&interior) {
+            grid.set_solid(&p, false);
+        }
+    }
+
+    fn walk(&self, grid: &mut OccupancyGrid, rng: &mut impl Rng, chambers: &mut Vec<Extent>) {
+        let r = self.tunnel_radius.max(0);
+        // Keep the walker far enough from the boundary that the tunnel doesn't touch the shell.
+        let walkable = self.extent().radial_grow(-(r + 1));
+        if walkable.is_empty() {
+            return;
+        }
+
+        let mut p: Point = [self.size.x / 2, self.size.y / 2, self.size.z / 2].into();
+        let mut dir = *HORIZONTAL_DIRECTIONS.choose(rng).unwrap();
+        for _ in 0..self.steps_per_walker {
+            if rng.gen::<f32>() < self.turn_probability {
+                dir = *HORIZONTAL_DIRECTIONS.choose(rng).unwrap();
+            }
+            let mut next = p + dir.into();
+            if rng.gen::<f32>() < self.slope_probability {
+                next = next + [0, if rng.gen() { 1 } else { -1 }, 0].into();
+            }
+            if !walkable.contains_world(&next) {
+                // Bounce off of the boundary.
+                dir = [-dir[0], 0, -dir[2]];
+                continue;
+            }
+            p = next;
+
+            self.carve(
+                grid,
+                &Extent::from_min_and_world_supremum(
+                    p - [r, r, r].into(),
+                    p + [r + 1, r + 1, r + 1].into(),
+                ),
+            );
+
+            if rng.gen::<f32>() < self.chamber_probability {
+                let cr = self.chamber_radius.max(r);
+                // The chamber shares the tunnel's floor.
+                let chamber = Extent::from_min_and_world_supremum(
+                    p - [cr, r, cr].into(),
+                    p + [cr + 1, cr + 1, cr + 1].into(),
+                )
+                .intersection(&self.extent().radial_grow(-1));
+                self.carve(grid, &chamber);
+                chambers.push(chamber);
+            }
+        }
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<TunnelMeta> {
+        log::debug!("Generating tunnel map");
+
+        let mut grid = OccupancyGrid::new(self.extent(), true);
+        let mut chambers = Vec::new();
+        for _ in 0..self.num_walkers {
+            self.walk(&mut grid, rng, &mut chambers);
+        }
+        log::debug!("Carved {} chambers", chambers.len());
+
+        let open_points = grid.keep_largest_empty_component();
+        let open_ratio = open_points.len() as f32 / grid.volume() as f32;
+        log::debug!("Open ratio = {}", open_ratio);
+        if open_points.is_empty() || open_ratio < self.min_open_ratio {
+            return None;
+        }
+
+        let valid_spawn_points: Vec<_> = grid.floor_points(&open_points).collect();
+        if valid_spawn_points.is_empty() {
+            return None;
+        }
+
+        for p in &self.extent() {
+            if grid.is_solid(&p) {
+                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            } else {
+                encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            }
+        }
+        for p in valid_spawn_points.iter() {
+            encoder.encode_flags(&(*p - [0, 1, 0].into()), VoxelFlags::WALKABLE);
+        }
+
+        Some(TunnelMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points,
+                facing: None,
+            },
+            chambers,
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> TunnelMeta {
+        retry_with_derived_seeds(rng, MAX_GENERATE_TRIES, |_, seed| {
+            self.try_generate(&mut small_rng(seed), encoder)
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "Failed to generate tunnel map after {} tries",
+                MAX_GENERATE_TRIES
+            )
+        })
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    room::EMPTY_VOXEL,
+    wfc::{solve, AdjacencyRules, MAX_TILES},
+    SpawnArea, Voxel, VoxelEncoder, VoxelFlags,
+};
+
+use ilattice3::Point;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_GENERATE_TRIES: usize = 200;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WfcMeta {
+    pub spawn_area: SpawnArea,
+}
+
+/// A small hand-made map that the generated map should locally resemble.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WfcExample {
+    pub size: Point,
+    /// The voxel type at each point of the example, with X varying fastest. Type 0 is empty space.
+    pub voxel_types: Vec<u8>,
+}
+
+/// Synthesizes a map where every pair of neighboring voxels also appears, in the same
+/// orientation, somewhere in the example.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WfcMapSpec {
+    pub seed: [u32; 4],
+    pub size: Point,
+    pub example: WfcExample,
+}
+
+impl WfcMapSpec {
+    /// Returns the rules learned from the example, along with the voxel type of each tile.
+    fn rules(&self) -> (AdjacencyRules, Vec<u8>) {
+        let mut palette: Vec<u8> = self.example.voxel_types.clone();
+        palette.sort_unstable();
+        palette.dedup();
+        assert!(
+            palette.len() <= MAX_TILES,
+            "WFC example has more than {} voxel types",
+            MAX_TILES
+        );
+
+        let tiles: Vec<usize> = self
+            .example
+            .voxel_types
+            .iter()
+            .map(|t| palette.binary_search(t).unwrap())
+            .collect();
+
+        (
+            AdjacencyRules::from_example(&tiles, self.example.size, palette.len()),
+            palette,
+        )
+    }
+
+    /// On success, returns `Some` and writes the generated voxels into `encoder`. Leaves the
+    /// encoder untouched on failure.
+    pub fn try_generate(
+        &self,
+        rng: &mut impl Rng,
+        encoder: &mut impl VoxelEncoder,
+    ) -> Option<WfcMeta> {
+        log::debug!("Generating WFC map");
+
+        let (rules, palette) = self.rules();
+        let tiles = solve(&rules, self.size, rng)?;
+        let voxel_type_at = |p: &Point| -> u8 {
+            let s = self.size;
+            if p.x < 0 || p.y < 0 || p.z < 0 || p.x >= s.x || p.y >= s.y || p.z >= s.z {
+                return 0;
+            }
+            let i = p.x + self.size.x * (p.y + self.size.y * p.z);
+
+            palette[tiles[i as usize]]
+        };
+
+        let mut valid_spawn_points = Vec::new();
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let p: Point = [x, y, z].into();
+                    if voxel_type_at(&p) == 0 && y > 0 && voxel_type_at(&[x, y - 1, z].into()) != 0
+                    {
+                        valid_spawn_points.push(p);
+                    }
+                }
+            }
+        }
+        if valid_spawn_points.is_empty() {
+            return None;
+        }
+
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let p: Point = [x, y, z].into();
+                    let voxel_type = voxel_type_at(&p);
+                    if voxel_type == 0 {
+                        encoder.encode_voxel(&p, &EMPTY_VOXEL);
+                    } else {
+                        encoder.encode_voxel(
+                            &p,
+                            &Voxel {
+                                distance: -1.0,
+                                voxel_type,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        for p in valid_spawn_points.iter() {
+            encoder.encode_flags(&(*p - [0, 1, 0].into()), VoxelFlags::WALKABLE);
+        }
+
+        Some(WfcMeta {
+            spawn_area: SpawnArea {
+                valid_spawn_points,
+                facing: None,
+            },
+        })
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng, encoder: &mut impl VoxelEncoder) -> WfcMeta {
+        for _ in 0..MAX_GENERATE_TRIES {
+            if let Some(meta) = self.try_generate(rng, encoder) {
+                return meta;
+            }
+        }
+
+        panic!(
+            "Failed to generate WFC map after {} tries",
+            MAX_GENERATE_TRIES
+        );
+    }
+}
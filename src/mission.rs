@@ -0,0 +1,275 @@
+use crate::graph::reachable_without_node;
+
+use petgraph::{graph::NodeIndex, stable_graph::StableGraph, Undirected};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::iter::FromIterator;
+
+/// Backtracking gives up after trying this many rooms for mission nodes.
+const MAX_MISSION_STEPS: usize = 10_000;
+
+/// What the player does at one step of a mission.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MissionNodeKind {
+    Entrance,
+    Combat,
+    Puzzle,
+    Boss,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MissionNode {
+    pub kind: MissionNodeKind,
+    /// Indices into `MissionGraph::nodes` of the steps that must be done before this one.
+    #[serde(default)]
+    pub after: Vec<usize>,
+}
+
+/// A designer's plan for the progression through a map, without any say in where the rooms are.
+/// The plan is realized by putting each step in its own room, so that the room of every step
+/// can only be reached from the entrance through the rooms of the steps it comes after.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MissionGraph {
+    pub nodes: Vec<MissionNode>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum MissionError {
+    /// There must be exactly one entrance, and it can't come after anything.
+    BadEntrance,
+    /// A node comes after a node that doesn't exist.
+    MissingNode { node: usize, after: usize },
+    /// The ordering of these nodes forms a cycle.
+    Cycle(Vec<usize>),
+    /// No assignment of rooms to nodes respects the ordering.
+    DoesNotFit,
+}
+
+impl MissionGraph {
+    /// The indices of `nodes` in an order where each comes after every node it must. Ties are
+    /// broken by index, so the order is deterministic.
+    pub fn order(&self) -> Result<Vec<usize>, MissionError> {
+        let entrances: Vec<&MissionNode> = self
+            .nodes
+            .iter()
+            .filter(|n| n.kind == MissionNodeKind::Entrance)
+            .collect();
+        if entrances.len() != 1 || !entrances[0].after.is_empty() {
+            return Err(MissionError::BadEntrance);
+        }
+
+        let mut num_unmet = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for a in node.after.iter() {
+                if *a >= self.nodes.len() {
+                    return Err(MissionError::MissingNode { node: i, after: *a });
+                }
+            }
+            num_unmet[i] = node.after.len();
+        }
+
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut done = vec![false; self.nodes.len()];
+        while order.len() < self.nodes.len() {
+            let next = match (0..self.nodes.len()).find(|i| !done[*i] && num_unmet[*i] == 0) {
+                Some(n) => n,
+                None => {
+                    let cycle = (0..self.nodes.len()).filter(|i| !done[*i]).collect();
+
+                    return Err(MissionError::Cycle(cycle));
+                }
+            };
+
+            done[next] = true;
+            order.push(next);
+            for (i, node) in self.nodes.iter().enumerate() {
+                num_unmet[i] -= node.after.iter().filter(|a| **a == next).count();
+            }
+        }
+
+        Ok(order)
+    }
+}
+
+/// Assigns a distinct node of `graph`, the rooms of a map, to each node of `mission`, so the room
+/// of every node can only be reached from `entrance` by passing through the rooms of the nodes it
+/// comes after. The entrance node goes in `entrance`, a boss node in `objective`, and every other
+/// node in any other room, tried in an order shuffled by `rng`. Returns the room of each node, in
+/// the order of `mission.nodes`.
+pub fn map_mission<N, E>(
+    graph: &StableGraph<N, E, Undirected>,
+    entrance: NodeIndex,
+    objective: NodeIndex,
+    mission: &MissionGraph,
+    rng: &mut impl Rng,
+) -> Result<Vec<NodeIndex>, MissionError> {
+    let order = mission.order()?;
+    let rooms: Vec<NodeIndex> = graph.node_indices().collect();
+    let behind = rooms
+        .iter()
+        .map(|r| {
+            // Everything is behind the entrance.
+            let reached = if *r == entrance {
+                HashSet::from_iter(std::iter::once(entrance))
+            } else {
+                reachable_without_node(graph, entrance, *r)
+            };
+
+            rooms
+                .iter()
+                .filter(|other| !reached.contains(other))
+                .cloned()
+                .collect()
+        })
+        .collect();
+    let mut search = MissionSearch {
+        mission,
+        rooms,
+        entrance,
+        objective,
+        behind,
+        assigned: vec![None; mission.nodes.len()],
+        steps: 0,
+    };
+
+    if search.assign(&order, rng) {
+        Ok(search.assigned.into_iter().map(|r| r.unwrap()).collect())
+    } else {
+        Err(MissionError::DoesNotFit)
+    }
+}
+
+struct MissionSearch<'a> {
+    mission: &'a MissionGraph,
+    rooms: Vec<NodeIndex>,
+    entrance: NodeIndex,
+    objective: NodeIndex,
+    /// `behind[i]` are the rooms that can't be reached from the entrance without passing through
+    /// `rooms[i]`.
+    behind: Vec<HashSet<NodeIndex>>,
+    assigned: Vec<Option<NodeIndex>>,
+    steps: usize,
+}
+
+impl<'a> MissionSearch<'a> {
+    fn candidates(&self, kind: MissionNodeKind) -> Vec<NodeIndex> {
+        match kind {
+            MissionNodeKind::Entrance => vec![self.entrance],
+            MissionNodeKind::Boss => vec![self.objective],
+            _ => self
+                .rooms
+                .iter()
+                .filter(|r| **r != self.entrance && **r != self.objective)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn allowed(&self, node: usize, room: NodeIndex) -> bool {
+        !self.assigned.contains(&Some(room))
+            && self.mission.nodes[node].after.iter().all(|a| {
+                let a_room = self.assigned[*a].expect("Assigned in order");
+                let i = self.rooms.iter().position(|r| *r == a_room).unwrap();
+
+                self.behind[i].contains(&room)
+            })
+    }
+
+    /// Assigns rooms to the nodes of `order`, backtracking when a node has no room left that it's
+    /// allowed in.
+    fn assign(&mut self, order: &[usize], rng: &mut impl Rng) -> bool {
+        let node = match order.first() {
+            Some(n) => *n,
+            None => return true,
+        };
+        let mut candidates = self.candidates(self.mission.nodes[node].kind);
+        candidates.shuffle(rng);
+        for room in candidates.into_iter() {
+            self.steps += 1;
+            if self.steps > MAX_MISSION_STEPS {
+                return false;
+            }
+            if !self.allowed(node, room) {
+                continue;
+            }
+            self.assigned[node] = Some(room);
+            if self.assign(&order[1..], rng) {
+                return true;
+            }
+            self.assigned[node] = None;
+        }
+
+        false
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sampling::small_rng;
+
+    fn node(kind: MissionNodeKind, after: &[usize]) -> MissionNode {
+        MissionNode {
+            kind,
+            after: after.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_mission_rooms_follow_the_ordering() {
+        // 0-1-2-3 with 4 off of 1.
+        let mut graph = StableGraph::<(), (), Undirected>::default();
+        let n: Vec<NodeIndex> = (0..5).map(|_| graph.add_node(())).collect();
+        for (a, b) in [(0, 1), (1, 2), (2, 3), (1, 4)].iter() {
+            graph.add_edge(n[*a], n[*b], ());
+        }
+        // The puzzle must be solved before the fight, and both before the boss.
+        let mission = MissionGraph {
+            nodes: vec![
+                node(MissionNodeKind::Entrance, &[]),
+                node(MissionNodeKind::Combat, &[2]),
+                node(MissionNodeKind::Puzzle, &[0]),
+                node(MissionNodeKind::Boss, &[1]),
+            ],
+        };
+
+        for seed in 0..8 {
+            let rooms = map_mission(
+                &graph,
+                n[0],
+                n[3],
+                &mission,
+                &mut small_rng([seed, 1, 2, 3]),
+            )
+            .unwrap();
+
+            assert_eq!(rooms, vec![n[0], n[2], n[1], n[3]]);
+        }
+    }
+
+    #[test]
+    fn test_mission_errors() {
+        let cycle = MissionGraph {
+            nodes: vec![
+                node(MissionNodeKind::Entrance, &[]),
+                node(MissionNodeKind::Combat, &[2]),
+                node(MissionNodeKind::Puzzle, &[1]),
+            ],
+        };
+        assert_eq!(cycle.order(), Err(MissionError::Cycle(vec![1, 2])));
+
+        let no_entrance = MissionGraph {
+            nodes: vec![node(MissionNodeKind::Boss, &[])],
+        };
+        assert_eq!(no_entrance.order(), Err(MissionError::BadEntrance));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{
+    extent::{component, touching_axis},
+    layout::Layout,
+    room::WALL_THICKNESS,
+};
+
+use ilattice3::Extent;
+use serde::{Deserialize, Serialize};
+
+/// What the host game's character controller can do. Generators that place stairs and ladders
+/// respect these limits, and `untraversable_doors` checks that a layout does too.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MovementSpec {
+    /// The tallest ledge the character can get onto without climbing, by stepping or jumping.
+    pub max_step_up: u32,
+    /// The farthest the character can fall without taking damage.
+    pub max_safe_drop: u32,
+    /// The least headroom the character can fit through.
+    pub crouch_height: u32,
+}
+
+impl MovementSpec {
+    /// The tallest ledge that can be both climbed onto and dropped off of without a ladder.
+    pub fn max_ledge_height(&self) -> u32 {
+        self.max_step_up.min(self.max_safe_drop)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraversalProblem {
+    /// The door is shorter than the character's crouch height.
+    LowHeadroom { door: usize },
+    /// The door can't be reached from the floor of the room, or the drop from the door to the
+    /// floor is too far.
+    UnreachableDoor { door: usize, room: usize },
+}
+
+/// Whether `a` and `b` overlap when projected onto `axis`.
+fn overlaps_along(a: &Extent, b: &Extent, axis: usize) -> bool {
+    component(a.get_minimum(), axis) < component(&b.get_world_supremum(), axis)
+        && component(b.get_minimum(), axis) < component(&a.get_world_supremum(), axis)
+}
+
+/// Whether the character can get between the floor of `room` and the bottom of `door`, either by
+/// walking up the `steps` below the door or by climbing one of the `climbable` strips. `axis` is
+/// the axis that the door passes through.
+fn door_reachable_from_room(
+    door: &Extent,
+    axis: usize,
+    room: &Extent,
+    steps: &[Extent],
+    climbable: &[Extent],
+    spec: &MovementSpec,
+) -> bool {
+    let floor_y = room.get_minimum().y + WALL_THICKNESS;
+    let door_y = door.get_minimum().y;
+    // Only things in the room that line up with the door can help.
+    let below_door = |e: &Extent| {
+        !e.intersection(room).is_empty()
+            && (axis == 0 || overlaps_along(e, door, 0))
+            && (axis == 2 || overlaps_along(e, door, 2))
+    };
+
+    let mut heights = vec![floor_y, door_y];
+    heights.extend(
+        steps
+            .iter()
+            .filter(|s| below_door(s))
+            .map(|s| s.get_world_supremum().y)
+            .filter(|y| *y > floor_y && *y < door_y),
+    );
+    heights.sort_unstable();
+    let max_ledge_height = spec.max_ledge_height() as i32;
+    if heights.windows(2).all(|w| w[1] - w[0] <= max_ledge_height) {
+        return true;
+    }
+
+    climbable
+        .iter()
+        .any(|c| below_door(c) && c.get_world_supremum().y >= door_y)
+}
+
+/// Checks every door in `layout` against the limits of `spec`. `steps` and `climbable` are the
+/// extents that were written to help the character reach raised doors, like the output of
+/// `door_sill_steps` and `climbable_strips`.
+pub fn untraversable_doors(
+    layout: &Layout,
+    steps: &[Extent],
+    climbable: &[Extent],
+    spec: &MovementSpec,
+) -> Vec<TraversalProblem> {
+    let mut problems = Vec::new();
+    for (i, door) in layout.doors.iter().enumerate() {
+        let (r1, r2) = (&layout.rooms[door.rooms.0], &layout.rooms[door.rooms.1]);
+        let (axis, r2_is_positive) = match touching_axis(r1, r2) {
+            Some(t) => t,
+            None => continue,
+        };
+
+        // Only doors in floors need to be reached from the room below.
+        let sides = if axis == 1 {
+            if r2_is_positive {
+                vec![door.rooms.0]
+            } else {
+                vec![door.rooms.1]
+            }
+        } else {
+            if door.extent.get_local_supremum().y < spec.crouch_height as i32 {
+                problems.push(TraversalProblem::LowHeadroom { door: i });
+            }
+
+            vec![door.rooms.0, door.rooms.1]
+        };
+        for room in sides.into_iter() {
+            if !door_reachable_from_room(
+                &door.extent,
+                axis,
+                &layout.rooms[room],
+                steps,
+                climbable,
+                spec,
+            ) {
+                problems.push(TraversalProblem::UnreachableDoor { door: i, room });
+            }
+        }
+    }
+
+    problems
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{layout::LayoutDoor, room::door_sill_steps};
+
+    #[test]
+    fn test_tall_sill_is_traversable_only_with_staircase() {
+        let r1 = Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 20, 20].into());
+        let r2 = Extent::from_min_and_local_supremum([20, 0, 0].into(), [20, 20, 20].into());
+        // Raised 4 voxels above both floors.
+        let door = Extent::from_min_and_local_supremum([19, 9, 8].into(), [2, 4, 4].into());
+        let layout = Layout {
+            rooms: vec![r1, r2],
+            doors: vec![LayoutDoor::new(0, 1, door)],
+        };
+        let spec = MovementSpec {
+            max_step_up: 2,
+            max_safe_drop: 3,
+            crouch_height: 4,
+        };
+
+        let single_step = door_sill_steps(&layout, 4, None);
+        assert_eq!(
+            untraversable_doors(&layout, &single_step, &[], &spec),
+            vec![
+                TraversalProblem::UnreachableDoor { door: 0, room: 0 },
+                TraversalProblem::UnreachableDoor { door: 0, room: 1 },
+            ]
+        );
+
+        let staircase = door_sill_steps(&layout, 4, Some(&spec));
+        assert!(untraversable_doors(&layout, &staircase, &[], &spec).is_empty());
+    }
+}
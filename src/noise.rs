@@ -0,0 +1,79 @@
+use fnv::FnvHasher;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+
+/// Deterministic 2D value noise in `[-1, 1]`, interpolated between hashed lattice values.
+pub struct ValueNoise2 {
+    seed: u64,
+}
+
+impl ValueNoise2 {
+    pub fn new(seed: u64) -> Self {
+        ValueNoise2 { seed }
+    }
+
+    fn lattice_value(&self, x: i32, z: i32) -> f32 {
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.seed);
+        hasher.write_i32(x);
+        hasher.write_i32(z);
+
+        (hasher.finish() as u32) as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (tx, tz) = (smoothstep(x - x0), smoothstep(z - z0));
+        let (x0, z0) = (x0 as i32, z0 as i32);
+
+        let v00 = self.lattice_value(x0, z0);
+        let v10 = self.lattice_value(x0 + 1, z0);
+        let v01 = self.lattice_value(x0, z0 + 1);
+        let v11 = self.lattice_value(x0 + 1, z0 + 1);
+
+        lerp(lerp(v00, v10, tx), lerp(v01, v11, tx), tz)
+    }
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Fractal Brownian motion: a sum of octaves of noise with increasing frequency and decreasing
+/// amplitude.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct FbmSpec {
+    pub octaves: usize,
+    /// The frequency of the first octave, in cycles per voxel.
+    pub frequency: f32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves.
+    pub gain: f32,
+}
+
+impl FbmSpec {
+    /// Returns a value in `[-1, 1]`.
+    pub fn sample(&self, noise: &ValueNoise2, x: f32, z: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut total_amplitude = 0.0;
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        for _ in 0..self.octaves {
+            sum += amplitude * noise.sample(x * frequency, z * frequency);
+            total_amplitude += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+
+        if total_amplitude > 0.0 {
+            sum / total_amplitude
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use ilattice3::{Extent, Point};
+use std::collections::VecDeque;
+
+const FACE_OFFSETS: [[i32; 3]; 6] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+];
+
+/// A dense grid of solid/empty cells covering an extent. Points outside of the extent are
+/// considered solid.
+#[derive(Clone)]
+pub struct OccupancyGrid {
+    extent: Extent,
+    solid: Vec<bool>,
+}
+
+impl OccupancyGrid {
+    pub fn new(extent: Extent, solid: bool) -> Self {
+        let sup = extent.get_local_supremum();
+        let volume = (sup.x * sup.y * sup.z).max(0) as usize;
+
+        OccupancyGrid {
+            extent,
+            solid: vec![solid; volume],
+        }
+    }
+
+    pub fn extent(&self) -> &Extent {
+        &self.extent
+    }
+
+    pub fn volume(&self) -> usize {
+        self.solid.len()
+    }
+
+    fn index(&self, p: &Point) -> usize {
+        let local = *p - *self.extent.get_minimum();
+        let sup = self.extent.get_local_supremum();
+
+        (local.x + sup.x * (local.y + sup.y * local.z)) as usize
+    }
+
+    pub fn is_solid(&self, p: &Point) -> bool {
+        !self.extent.contains_world(p) || self.solid[self.index(p)]
+    }
+
+    pub fn set_solid(&mut self, p: &Point, solid: bool) {
+        if self.extent.contains_world(p) {
+            let i = self.index(p);
+            self.solid[i] = solid;
+        }
+    }
+
+    /// Counts the solid cells in the 26-neighborhood of `p`.
+    pub fn count_solid_neighbors(&self, p: &Point) -> usize {
+        let mut count = 0;
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    if (x, y, z) != (0, 0, 0) && self.is_solid(&(*p + [x, y, z].into())) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        count
+    }
+
+    /// The empty points that share a face with `p`.
+    pub fn empty_face_neighbors(&self, p: &Point) -> impl Iterator<Item = Point> + '_ {
+        let p = *p;
+
+        FACE_OFFSETS
+            .iter()
+            .map(move |o| p + (*o).into())
+            .filter(move |n| !self.is_solid(n))
+    }
+
+    /// Returns the face-connected components of empty cells, largest first.
+    pub fn empty_components(&self) -> Vec<Vec<Point>> {
+        let mut visited = vec![false; self.solid.len()];
+        let mut components = Vec::new();
+        for p in &self.extent {
+            let i = self.index(&p);
+            if self.solid[i] || visited[i] {
+                continue;
+            }
+
+            let mut component = Vec::new();
+            let mut queue = VecDeque::new();
+            visited[i] = true;
+            queue.push_back(p);
+            while let Some(next) = queue.pop_front() {
+                component.push(next);
+                for n in self.empty_face_neighbors(&next) {
+                    let n_i = self.index(&n);
+                    if !visited[n_i] {
+                        visited[n_i] = true;
+                        queue.push_back(n);
+                    }
+                }
+            }
+            components.push(component);
+        }
+        components.sort_by_key(|c| std::cmp::Reverse(c.len()));
+
+        components
+    }
+
+    /// Fills every empty cell that isn't in the largest empty component, so the remaining empty
+    /// space is connected. Returns the points of the largest component.
+    pub fn keep_largest_empty_component(&mut self) -> Vec<Point> {
+        let mut components = self.empty_components().into_iter();
+        let largest = components.next().unwrap_or_default();
+        for component in components {
+            for p in component.iter() {
+                self.set_solid(p, true);
+            }
+        }
+
+        largest
+    }
+
+    /// Empty points that are directly above a solid cell.
+    pub fn floor_points<'a>(&'a self, points: &'a [Point]) -> impl Iterator<Item = Point> + 'a {
+        points
+            .iter()
+            .cloned()
+            .filter(move |p| self.is_solid(&(*p - [0, 1, 0].into())))
+    }
+}
@@ -0,0 +1,226 @@
+use fnv::FnvHashMap;
+
+/// A capability that a pass produces or depends on.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PassTag {
+    RoomFill,
+    Doors,
+    Sdf,
+    Decoration,
+    Traps,
+    Lighting,
+    /// Lets third-party passes coordinate with each other.
+    Custom(&'static str),
+}
+
+/// One step of a generation pipeline that operates on some context `C`. Passes declare the tags
+/// they require and provide so that they can be ordered automatically.
+pub trait Pass<C> {
+    fn name(&self) -> &str;
+
+    fn requires(&self) -> &[PassTag] {
+        &[]
+    }
+
+    fn provides(&self) -> &[PassTag] {
+        &[]
+    }
+
+    fn run(&self, context: &mut C);
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ScheduleError {
+    /// Two passes claim to provide the same tag.
+    DuplicateProvider {
+        tag: PassTag,
+        first: String,
+        second: String,
+    },
+    /// A pass requires a tag that nothing provides.
+    MissingProvider { pass: String, tag: PassTag },
+    /// The requirements of these passes form a cycle.
+    Cycle(Vec<String>),
+}
+
+/// Returns the indices of `passes` in an order that satisfies all requirements. `provided` are the
+/// tags that are satisfied before any of the passes run. Ties are broken by the order of `passes`,
+/// so the schedule is deterministic.
+pub fn schedule_passes<C>(
+    provided: &[PassTag],
+    passes: &[&dyn Pass<C>],
+) -> Result<Vec<usize>, ScheduleError> {
+    let mut providers = FnvHashMap::default();
+    for (i, pass) in passes.iter().enumerate() {
+        for tag in pass.provides().iter() {
+            if let Some(first) = providers.insert(*tag, i) {
+                return Err(ScheduleError::DuplicateProvider {
+                    tag: *tag,
+                    first: passes[first].name().to_string(),
+                    second: pass.name().to_string(),
+                });
+            }
+        }
+    }
+
+    // Edges point from a provider to the passes that require it.
+    let mut dependents = vec![Vec::new(); passes.len()];
+    let mut num_unmet = vec![0; passes.len()];
+    for (i, pass) in passes.iter().enumerate() {
+        for tag in pass.requires().iter() {
+            if let Some(p) = providers.get(tag) {
+                dependents[*p].push(i);
+                num_unmet[i] += 1;
+            } else if !provided.contains(tag) {
+                return Err(ScheduleError::MissingProvider {
+                    pass: pass.name().to_string(),
+                    tag: *tag,
+                });
+            }
+        }
+    }
+
+    let mut order = Vec::with_capacity(passes.len());
+    let mut scheduled = vec![false; passes.len()];
+    while order.len() < passes.len() {
+        let next = (0..passes.len()).find(|i| !scheduled[*i] && num_unmet[*i] == 0);
+        let next = if let Some(n) = next {
+            n
+        } else {
+            let cycle = (0..passes.len())
+                .filter(|i| !scheduled[*i])
+                .map(|i| passes[i].name().to_string())
+                .collect();
+
+            return Err(ScheduleError::Cycle(cycle));
+        };
+
+        scheduled[next] = true;
+        order.push(next);
+        for d in dependents[next].iter() {
+            num_unmet[*d] -= 1;
+        }
+    }
+
+    Ok(order)
+}
+
+/// Schedules `passes` and runs them on `context`. Nothing runs if the passes can't be scheduled.
+pub fn run_passes<C>(
+    provided: &[PassTag],
+    passes: &[&dyn Pass<C>],
+    context: &mut C,
+) -> Result<(), ScheduleError> {
+    let order = schedule_passes(provided, passes)?;
+    for i in order.into_iter() {
+        log::debug!("Running pass {}", passes[i].name());
+        passes[i].run(context);
+    }
+
+    Ok(())
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestPass {
+        name: &'static str,
+        requires: Vec<PassTag>,
+        provides: Vec<PassTag>,
+    }
+
+    impl Pass<Vec<&'static str>> for TestPass {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn requires(&self) -> &[PassTag] {
+            &self.requires
+        }
+
+        fn provides(&self) -> &[PassTag] {
+            &self.provides
+        }
+
+        fn run(&self, context: &mut Vec<&'static str>) {
+            context.push(self.name);
+        }
+    }
+
+    #[test]
+    fn test_run_passes_orders_by_requirements() {
+        let lighting = TestPass {
+            name: "lighting",
+            requires: vec![PassTag::Decoration, PassTag::Sdf],
+            provides: vec![PassTag::Lighting],
+        };
+        let decoration = TestPass {
+            name: "decoration",
+            requires: vec![PassTag::RoomFill],
+            provides: vec![PassTag::Decoration],
+        };
+        let sdf = TestPass {
+            name: "sdf",
+            requires: vec![PassTag::RoomFill, PassTag::Doors],
+            provides: vec![PassTag::Sdf],
+        };
+
+        let mut ran = Vec::new();
+        run_passes::<Vec<&'static str>>(
+            &[PassTag::RoomFill, PassTag::Doors],
+            &[&lighting, &decoration, &sdf],
+            &mut ran,
+        )
+        .unwrap();
+
+        assert_eq!(ran, vec!["decoration", "sdf", "lighting"]);
+    }
+
+    #[test]
+    fn test_schedule_passes_detects_conflicts() {
+        let a = TestPass {
+            name: "a",
+            requires: vec![PassTag::Custom("b")],
+            provides: vec![PassTag::Custom("a")],
+        };
+        let b = TestPass {
+            name: "b",
+            requires: vec![PassTag::Custom("a")],
+            provides: vec![PassTag::Custom("b")],
+        };
+        assert_eq!(
+            schedule_passes::<Vec<&'static str>>(&[], &[&a, &b]),
+            Err(ScheduleError::Cycle(vec!["a".to_string(), "b".to_string()]))
+        );
+
+        let also_a = TestPass {
+            name: "also_a",
+            requires: vec![],
+            provides: vec![PassTag::Custom("a")],
+        };
+        assert_eq!(
+            schedule_passes::<Vec<&'static str>>(&[], &[&a, &also_a]),
+            Err(ScheduleError::DuplicateProvider {
+                tag: PassTag::Custom("a"),
+                first: "a".to_string(),
+                second: "also_a".to_string(),
+            })
+        );
+
+        assert_eq!(
+            schedule_passes::<Vec<&'static str>>(&[], &[&b]),
+            Err(ScheduleError::MissingProvider {
+                pass: "b".to_string(),
+                tag: PassTag::Custom("a"),
+            })
+        );
+    }
+}
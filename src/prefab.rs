@@ -0,0 +1,192 @@
+use crate::{Voxel, VoxelEncoder, VoxelFlags};
+
+use ilattice3::{normal::Direction, Extent, Point};
+
+/// Where a door can connect to a prefab.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DoorSocket {
+    pub extent: Extent,
+    /// The direction a door through the socket leads out of the prefab.
+    pub facing: Direction,
+}
+
+/// A hand-made template of voxels that can be stamped into a map. Everything is in local
+/// coordinates, where the template occupies the extent from the origin to `size`.
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    pub size: Point,
+    pub voxels: Vec<(Point, Voxel, VoxelFlags)>,
+    pub sockets: Vec<DoorSocket>,
+}
+
+/// One of the 8 ways to orient a prefab without tilting it: a mirror flip across X, followed by
+/// counterclockwise quarter turns about Y as seen from above.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PrefabTransform {
+    pub mirror_x: bool,
+    /// Only the remainder mod 4 matters.
+    pub quarter_turns: u8,
+}
+
+impl PrefabTransform {
+    /// Every distinct orientation, starting with the identity.
+    pub fn all() -> Vec<PrefabTransform> {
+        [false, true]
+            .iter()
+            .flat_map(|mirror_x| {
+                (0..4).map(move |quarter_turns| PrefabTransform {
+                    mirror_x: *mirror_x,
+                    quarter_turns,
+                })
+            })
+            .collect()
+    }
+
+    /// The size of a template of `size` after the transform.
+    pub fn apply_to_size(&self, size: &Point) -> Point {
+        if self.quarter_turns.is_multiple_of(2) {
+            *size
+        } else {
+            [size.z, size.y, size.x].into()
+        }
+    }
+
+    /// Moves the voxel at `p` in a template of `size` to where it ends up after the transform, so
+    /// the transformed template still occupies the extent from the origin to its new size.
+    pub fn apply_to_point(&self, p: &Point, size: &Point) -> Point {
+        let mut p = *p;
+        let mut size = *size;
+        if self.mirror_x {
+            p.x = size.x - 1 - p.x;
+        }
+        for _ in 0..self.quarter_turns % 4 {
+            p = [size.z - 1 - p.z, p.y, p.x].into();
+            size = [size.z, size.y, size.x].into();
+        }
+
+        p
+    }
+
+    pub fn apply_to_extent(&self, extent: &Extent, size: &Point) -> Extent {
+        let a = self.apply_to_point(extent.get_minimum(), size);
+        let b = self.apply_to_point(&(extent.get_world_supremum() - [1, 1, 1].into()), size);
+
+        Extent::from_min_and_world_supremum(
+            [a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)].into(),
+            [a.x.max(b.x) + 1, a.y.max(b.y) + 1, a.z.max(b.z) + 1].into(),
+        )
+    }
+
+    pub fn apply_to_direction(&self, d: Direction) -> Direction {
+        let mut d = d;
+        if self.mirror_x && (d == Direction::PosX || d == Direction::NegX) {
+            d = d.negate();
+        }
+        for _ in 0..self.quarter_turns % 4 {
+            d = match d {
+                Direction::PosX => Direction::PosZ,
+                Direction::PosZ => Direction::NegX,
+                Direction::NegX => Direction::NegZ,
+                Direction::NegZ => Direction::PosX,
+                vertical => vertical,
+            };
+        }
+
+        d
+    }
+}
+
+impl Prefab {
+    /// A copy of the template in a different orientation, with its sockets facing the matching
+    /// directions.
+    pub fn transformed(&self, transform: &PrefabTransform) -> Prefab {
+        Prefab {
+            size: transform.apply_to_size(&self.size),
+            voxels: self
+                .voxels
+                .iter()
+                .map(|(p, voxel, flags)| (transform.apply_to_point(p, &self.size), *voxel, *flags))
+                .collect(),
+            sockets: self
+                .sockets
+                .iter()
+                .map(|s| DoorSocket {
+                    extent: transform.apply_to_extent(&s.extent, &self.size),
+                    facing: transform.apply_to_direction(s.facing),
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the template into `encoder` with its local origin at `origin`. Returns the sockets in
+    /// world coordinates.
+    pub fn stamp(&self, origin: &Point, encoder: &mut impl VoxelEncoder) -> Vec<DoorSocket> {
+        for (p, voxel, flags) in self.voxels.iter() {
+            let world = *p + *origin;
+            encoder.encode_voxel(&world, voxel);
+            if !flags.is_empty() {
+                encoder.encode_flags(&world, *flags);
+            }
+        }
+
+        self.sockets
+            .iter()
+            .map(|s| DoorSocket {
+                extent: s.extent + *origin,
+                facing: s.facing,
+            })
+            .collect()
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_follows_its_wall() {
+        // A socket in the middle of the +X wall of a 4x2x6 template.
+        let size: Point = [4, 2, 6].into();
+        let socket = DoorSocket {
+            extent: Extent::from_min_and_local_supremum([3, 0, 2].into(), [1, 2, 2].into()),
+            facing: Direction::PosX,
+        };
+        let turned = PrefabTransform {
+            mirror_x: false,
+            quarter_turns: 1,
+        };
+        let mirrored = PrefabTransform {
+            mirror_x: true,
+            quarter_turns: 0,
+        };
+
+        assert_eq!(turned.apply_to_size(&size), [6, 2, 4].into());
+        assert_eq!(
+            turned.apply_to_extent(&socket.extent, &size),
+            Extent::from_min_and_local_supremum([2, 0, 3].into(), [2, 2, 1].into())
+        );
+        assert_eq!(turned.apply_to_direction(socket.facing), Direction::PosZ);
+        assert_eq!(
+            mirrored.apply_to_extent(&socket.extent, &size),
+            Extent::from_min_and_local_supremum([0, 0, 2].into(), [1, 2, 2].into())
+        );
+        assert_eq!(mirrored.apply_to_direction(socket.facing), Direction::NegX);
+
+        // Four quarter turns are the identity.
+        let full_turn = PrefabTransform {
+            mirror_x: false,
+            quarter_turns: 4,
+        };
+        assert_eq!(
+            full_turn.apply_to_extent(&socket.extent, &size),
+            socket.extent
+        );
+    }
+}
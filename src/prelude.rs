@@ -0,0 +1,25 @@
+//! The types needed to generate a dungeon and read its metadata, so a first integration only needs
+//! `use ilattice3_procgen::prelude::*;`.
+
+pub use crate::{
+    encoder::{BufferedEncoder, NullEncoder, ShellEncoder},
+    layout::{Layout, LayoutComparison, LayoutDiff, LayoutDoor, LayoutSummary, RoomGraph},
+    map_types::dungeon::{
+        BestOfDungeons, CandidateBudgetSpec, Centrality, DeadEndSpec, DoorRecord, DungeonMapSpec,
+        DungeonMeta, GenerationState, HubSpec, RoomDistributionSpec, RoomGraphLayout,
+        RoomGraphSpec, RoomId, RoomMeta, RoomRole, SpanningTreeWeight,
+    },
+    mission::{MissionGraph, MissionNode, MissionNodeKind},
+    pass::ScheduleError,
+    room::{DoorSelectionSpec, DoorStyle, RoomOutline, RoomShape, RoomTheme, SurfaceVoxelTypes},
+    sampling::{
+        small_rng, LatticeNormalDistSpec, LatticeUniformDistSpec, NormalDistSpec,
+        PoissonDiskDistSpec, Variant,
+    },
+    stats::GenerationStats,
+    symmetric_map::SymmetricMap,
+    SpawnArea, SpawnConstraint, SpawnFacing, Voxel, VoxelEncoder, VoxelFlags, VoxelLayer,
+    VoxelSource,
+};
+
+pub use ilattice3::{normal::Direction, Extent, Point};
@@ -0,0 +1,165 @@
+use crate::layout::Layout;
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProgressionSpec {
+    /// Locks are placed on up to this many of the connections along the main path.
+    pub num_locks: usize,
+}
+
+/// Every door between a pair of rooms is locked until the key in `key_room` is picked up.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Lock {
+    /// Indices into `Layout::rooms`, smallest first.
+    pub rooms: (usize, usize),
+    /// Indices into `Layout::doors`.
+    pub doors: Vec<usize>,
+    pub key_room: usize,
+}
+
+/// Locks some of the connections along `main_path`, which runs from the entrance to the objective,
+/// and hides each key in a room off the main path that can be reached before the lock. A lock is
+/// only kept if the objective can still be reached, so locks whose keys have nowhere to go are
+/// left out.
+pub fn place_locks_and_keys(
+    layout: &Layout,
+    main_path: &[usize],
+    spec: &ProgressionSpec,
+    rng: &mut impl Rng,
+) -> Vec<Lock> {
+    let (entrance, objective) = match (main_path.first(), main_path.last()) {
+        (Some(e), Some(o)) => (*e, *o),
+        _ => return Vec::new(),
+    };
+    let steps: Vec<(usize, usize)> = main_path
+        .windows(2)
+        .map(|w| (w[0].min(w[1]), w[0].max(w[1])))
+        .collect();
+    let mut chosen: Vec<usize> = (0..steps.len()).collect();
+    chosen.shuffle(rng);
+    chosen.truncate(spec.num_locks);
+    chosen.sort_unstable();
+
+    let mut locks: Vec<Lock> = Vec::new();
+    for (i, step) in chosen.iter().enumerate() {
+        // Keys for earlier locks are picked up first, so only this lock and the later ones are
+        // still closed while looking for a place for this key.
+        let closed: Vec<(usize, usize)> = chosen[i..].iter().map(|s| steps[*s]).collect();
+        let reachable = reachable_rooms(layout, entrance, &closed);
+        let candidates: Vec<usize> = (0..layout.rooms.len())
+            .filter(|r| reachable[*r] && !main_path.contains(r))
+            .filter(|r| locks.iter().all(|l| l.key_room != *r))
+            .collect();
+        let key_room = match candidates.choose(rng) {
+            Some(r) => *r,
+            None => continue,
+        };
+
+        let rooms = steps[*step];
+        locks.push(Lock {
+            rooms,
+            doors: (0..layout.doors.len())
+                .filter(|d| layout.doors[*d].rooms == rooms)
+                .collect(),
+            key_room,
+        });
+        if !is_solvable(layout, entrance, objective, &locks) {
+            locks.pop();
+        }
+    }
+
+    locks
+}
+
+/// Whether `goal` can be reached from `start` by repeatedly picking up every key within reach and
+/// opening the locks they belong to.
+pub fn is_solvable(layout: &Layout, start: usize, goal: usize, locks: &[Lock]) -> bool {
+    let mut has_key = vec![false; locks.len()];
+    loop {
+        let closed: Vec<(usize, usize)> = locks
+            .iter()
+            .zip(has_key.iter())
+            .filter(|(_, k)| !**k)
+            .map(|(l, _)| l.rooms)
+            .collect();
+        let reachable = reachable_rooms(layout, start, &closed);
+        if reachable[goal] {
+            return true;
+        }
+
+        let mut found_key = false;
+        for (lock, k) in locks.iter().zip(has_key.iter_mut()) {
+            if !*k && reachable[lock.key_room] {
+                *k = true;
+                found_key = true;
+            }
+        }
+        if !found_key {
+            return false;
+        }
+    }
+}
+
+/// The rooms that can be reached from `start` without going between any of the `closed` pairs of
+/// rooms.
+fn reachable_rooms(layout: &Layout, start: usize, closed: &[(usize, usize)]) -> Vec<bool> {
+    let mut reachable = vec![false; layout.rooms.len()];
+    reachable[start] = true;
+    let mut stack = vec![start];
+    while let Some(room) = stack.pop() {
+        for door in layout.doors.iter().filter(|d| !closed.contains(&d.rooms)) {
+            let next = match door.rooms {
+                (a, b) if a == room => b,
+                (a, b) if b == room => a,
+                _ => continue,
+            };
+            if !reachable[next] {
+                reachable[next] = true;
+                stack.push(next);
+            }
+        }
+    }
+
+    reachable
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{layout::row_layout, sampling::small_rng};
+
+    #[test]
+    fn test_keys_are_reachable_before_their_locks() {
+        // A main path 0-1-2-3 with side rooms 4 off of 1 and 5 off of 2.
+        let layout = row_layout(6, &[(0, 1), (1, 2), (2, 3), (1, 4), (2, 5)]);
+        let spec = ProgressionSpec { num_locks: 3 };
+
+        for seed in 0..10 {
+            let locks =
+                place_locks_and_keys(&layout, &[0, 1, 2, 3], &spec, &mut small_rng([seed; 4]));
+            assert!(!locks.is_empty());
+            assert!(is_solvable(&layout, 0, 3, &locks));
+            for lock in locks.iter() {
+                assert!([4, 5].contains(&lock.key_room));
+                assert_eq!(lock.doors.len(), 1);
+            }
+        }
+
+        // A key behind its own lock can't be picked up.
+        let stuck = Lock {
+            rooms: (1, 2),
+            doors: vec![1],
+            key_room: 5,
+        };
+        assert!(!is_solvable(&layout, 0, 3, &[stuck]));
+    }
+}
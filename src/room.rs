@@ -1,15 +1,28 @@
-use crate::{sampling::sample_range, symmetric_map::SymmetricMap, SpawnArea, Voxel, VoxelEncoder};
+use crate::{
+    extent::{
+        bounding_extent, component, distance_squared, extent_center, subtract_extents,
+        touching_axis, wall_face_slabs, with_component,
+    },
+    layout::{Layout, LayoutDoor},
+    movement::MovementSpec,
+    sampling::SubspanSampler,
+    sdf::SdfShape,
+    symmetric_map::SymmetricMap,
+    SpawnArea, Voxel, VoxelEncoder, VoxelFlags, VoxelLayer, VoxelSource,
+};
 
+use fnv::FnvHashSet;
 use ilattice3::{
     normal::{Direction, DirectionIndex, Normal, PlaneSpanInfo, ALL_DIRECTIONS},
     Extent, Point,
 };
 use petgraph::{
     stable_graph::StableGraph,
-    visit::{EdgeRef, IntoEdgeReferences},
+    visit::{EdgeRef, IntoEdgeReferences, NodeIndexable},
     Undirected,
 };
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 pub const EMPTY_VOXEL: Voxel = Voxel {
     distance: std::f32::MAX,
@@ -21,23 +34,503 @@ pub const FLOOR_VOXEL: Voxel = Voxel {
     voxel_type: 1,
 };
 
-pub fn fill_map_with_rooms(rooms: &[Extent], encoder: &mut impl VoxelEncoder) {
-    let wall_thickness = 5;
+/// The kind of space a room represents, which decides how it is decorated.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RoomTheme {
+    #[default]
+    Hall,
+    Cave,
+}
+
+/// A coarse description of a room's geometry, so systems like audio and cameras can adapt per room.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RoomShape {
+    #[default]
+    Ordinary,
+    /// Long and narrow, with few connections.
+    Corridor,
+    /// Much taller than it is wide.
+    Shaft,
+    /// A wide open space.
+    Hall,
+    /// Small, with at most one connection.
+    Closet,
+}
+
+/// Interior dimensions at or below this make a dead-end room a closet.
+const CLOSET_MAX_DIM: i32 = 6;
+/// Interior dimensions at or above this make a room a hall.
+const HALL_MIN_DIM: i32 = 16;
+
+/// Classifies `room` by its interior dimensions and the number of doors it has.
+pub fn classify_room(room: &Extent, degree: usize) -> RoomShape {
+    let interior = *room.radial_grow(-WALL_THICKNESS).get_local_supremum();
+    let (wide, narrow) = (interior.x.max(interior.z), interior.x.min(interior.z));
+
+    if interior.y >= 2 * wide {
+        RoomShape::Shaft
+    } else if wide >= 3 * narrow && degree <= 2 {
+        RoomShape::Corridor
+    } else if wide <= CLOSET_MAX_DIM && degree <= 1 {
+        RoomShape::Closet
+    } else if narrow >= HALL_MIN_DIM {
+        RoomShape::Hall
+    } else {
+        RoomShape::Ordinary
+    }
+}
+
+/// A climbable wall or ladder.
+pub const CLIMBABLE_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 2,
+};
+
+/// The rungs of a ladder up the shaft through a door in a floor.
+pub const LADDER_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 12,
+};
+
+/// Fills the doors of secret rooms, so they look like walls until the game reveals them.
+pub const SECRET_WALL_VOXEL: Voxel = Voxel {
+    distance: -1.0,
+    voxel_type: 11,
+};
+
+/// The thickness of the walls, floor and ceiling that `fill_map_with_rooms` writes for each room.
+pub const WALL_THICKNESS: i32 = 5;
+
+/// The `voxel_type` written for each kind of surface around a room's interior.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SurfaceVoxelTypes {
+    pub floor: u8,
+    pub wall: u8,
+    pub ceiling: u8,
+}
+
+/// Every surface is `FLOOR_VOXEL`.
+impl Default for SurfaceVoxelTypes {
+    fn default() -> Self {
+        SurfaceVoxelTypes {
+            floor: FLOOR_VOXEL.voxel_type,
+            wall: FLOOR_VOXEL.voxel_type,
+            ceiling: FLOOR_VOXEL.voxel_type,
+        }
+    }
+}
+
+impl SurfaceVoxelTypes {
+    /// `FLOOR_VOXEL` for floors, and types of their own for walls and ceilings.
+    pub fn distinct() -> Self {
+        SurfaceVoxelTypes {
+            floor: FLOOR_VOXEL.voxel_type,
+            wall: 9,
+            ceiling: 10,
+        }
+    }
+
+    /// Classifies the voxel at `p` outside of `room` by the direction in which its distance to the
+    /// interior falls fastest. Voxels below the interior are floor, and voxels above it are
+    /// ceiling.
+    fn at(&self, room: &impl SdfShape, p: &Point) -> u8 {
+        let slope = |axis: Point| room.distance(&(*p + axis)) - room.distance(&(*p - axis));
+        let (dx, dy, dz) = (
+            slope([1, 0, 0].into()),
+            slope([0, 1, 0].into()),
+            slope([0, 0, 1].into()),
+        );
+
+        if dy.abs() < dx.abs().max(dz.abs()) {
+            self.wall
+        } else if dy < 0.0 {
+            self.floor
+        } else {
+            self.ceiling
+        }
+    }
+}
+
+/// Writes the walls of each room, which are the voxels up to `WALL_THICKNESS` outside of its
+/// interior. Wall voxels closer than one voxel to the interior carry their distance, so curved
+/// rooms have smooth surfaces.
+pub fn fill_map_with_rooms(rooms: &[impl SdfShape], encoder: &mut impl VoxelEncoder) {
+    fill_map_with_typed_rooms(rooms, &SurfaceVoxelTypes::default(), encoder);
+}
+
+/// Like `fill_map_with_rooms`, but floors, walls and ceilings get the types in `types`.
+pub fn fill_map_with_typed_rooms(
+    rooms: &[impl SdfShape],
+    types: &SurfaceVoxelTypes,
+    encoder: &mut impl VoxelEncoder,
+) {
     for r in rooms.iter() {
-        let r_interior = r.radial_grow(-wall_thickness);
-        for p in r {
-            if !r_interior.contains_world(&p) {
-                // TODO: check the plane of the wall to determine if it's a floor, ceiling, etc.
-                encoder.encode_voxel(&p, &FLOOR_VOXEL);
+        for p in &r.extent() {
+            let d = r.distance(&p);
+            if d > 0.0 && d <= WALL_THICKNESS as f32 {
+                encoder.encode_voxel(
+                    &p,
+                    &Voxel {
+                        distance: -d.min(1.0),
+                        voxel_type: types.at(r, &p),
+                    },
+                );
+                // The top layer of the floor, directly below the interior.
+                if r.distance(&(p + [0, 1, 0].into())) <= 0.0 {
+                    encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+                }
             }
         }
     }
 }
 
+/// Fills the space between rooms with rock.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BackfillSpec {
+    pub voxel_type: u8,
+    /// Defaults to the bounding extent of the rooms and corridors.
+    #[serde(default)]
+    pub bounds: Option<Extent>,
+}
+
+/// Writes solid voxels of `voxel_type` everywhere in `bounds` outside of `rooms`. The empty space
+/// is found by subtracting extents, so only the written voxels are visited.
+pub fn backfill_map(
+    bounds: &Extent,
+    rooms: &[Extent],
+    voxel_type: u8,
+    encoder: &mut impl VoxelEncoder,
+) {
+    let voxel = Voxel {
+        distance: -1.0,
+        voxel_type,
+    };
+    for piece in subtract_extents(bounds, rooms).iter() {
+        for p in piece {
+            encoder.encode_voxel(&p, &voxel);
+        }
+    }
+}
+
+/// The horizontal outline of a `Room`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RoomOutline {
+    #[default]
+    Box,
+    /// Two arms along the edges of the bounding extent that meet in a corner.
+    L,
+    /// An arm along one edge of the bounding extent, with a stem from its middle to the opposite
+    /// edge.
+    T,
+    /// Two arms through the middle of the bounding extent.
+    Cross,
+}
+
+/// A room made of one or more overlapping extents, so it doesn't have to be a box. Each part has
+/// its own walls, except where they would cut into the interior of another part.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Room {
+    pub outline: RoomOutline,
+    pub parts: Vec<Extent>,
+}
+
+impl From<Extent> for Room {
+    fn from(extent: Extent) -> Self {
+        Room {
+            outline: RoomOutline::Box,
+            parts: vec![extent],
+        }
+    }
+}
+
+impl Room {
+    /// A room with `outline` whose bounding extent is `extent`, with arms `arm_width` voxels wide
+    /// in a random orientation. Falls back to a box if the arms wouldn't leave a notch.
+    pub fn with_outline(
+        extent: &Extent,
+        outline: RoomOutline,
+        arm_width: i32,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let (min, sup) = (extent.get_minimum(), extent.get_local_supremum());
+        if outline == RoomOutline::Box || arm_width < 1 || arm_width >= sup.x.min(sup.z) {
+            return Room::from(*extent);
+        }
+
+        // An arm that spans `extent` along `axis`, starting `offset` voxels into it across the
+        // other horizontal axis.
+        let arm = |axis: usize, offset: i32| {
+            let across = 2 - axis;
+            Extent::from_min_and_local_supremum(
+                with_component(min, across, component(min, across) + offset),
+                with_component(sup, across, arm_width),
+            )
+        };
+        let edge = |axis: usize, far: bool| {
+            if far {
+                component(sup, 2 - axis) - arm_width
+            } else {
+                0
+            }
+        };
+        let middle = |axis: usize| (component(sup, 2 - axis) - arm_width) / 2;
+
+        let parts = match outline {
+            RoomOutline::Box => unreachable!(),
+            RoomOutline::L => vec![arm(0, edge(0, rng.gen())), arm(2, edge(2, rng.gen()))],
+            RoomOutline::T => {
+                let axis = if rng.gen() { 0 } else { 2 };
+                vec![
+                    arm(axis, edge(axis, rng.gen())),
+                    arm(2 - axis, middle(2 - axis)),
+                ]
+            }
+            RoomOutline::Cross => vec![arm(0, middle(0)), arm(2, middle(2))],
+        };
+
+        Room { outline, parts }
+    }
+
+    pub fn bounding_extent(&self) -> Extent {
+        bounding_extent(&self.parts).expect("Room must have at least one part")
+    }
+
+    pub fn contains_world(&self, p: &Point) -> bool {
+        self.parts.iter().any(|part| part.contains_world(p))
+    }
+
+    /// True iff `p` is inside the walls of any part.
+    pub fn interior_contains(&self, p: &Point) -> bool {
+        self.parts
+            .iter()
+            .any(|part| part.radial_grow(-WALL_THICKNESS).contains_world(p))
+    }
+}
+
+/// The union of the parts, so walls are only written where they don't cut into the interior of
+/// another part.
+impl SdfShape for Room {
+    fn extent(&self) -> Extent {
+        self.bounding_extent()
+    }
+
+    fn distance(&self, p: &Point) -> f32 {
+        self.parts
+            .iter()
+            .map(|part| part.distance(p))
+            .fold(f32::MAX, f32::min)
+    }
+}
+
+/// Closes each door with `SECRET_WALL_VOXEL`.
+pub fn fill_map_with_secret_doors(doors: &[Extent], encoder: &mut impl VoxelEncoder) {
+    for d in doors.iter() {
+        for p in d {
+            encoder.encode_voxel(&p, &SECRET_WALL_VOXEL);
+            encoder.encode_flags(&p, VoxelFlags::NONE);
+        }
+    }
+}
+
 pub fn fill_map_with_doors(doors: &[Extent], encoder: &mut impl VoxelEncoder) {
+    fill_map_with_styled_doors(doors, DoorStyle::Square, encoder);
+}
+
+/// The shape of the opening cut through the wall for a door. Doors in floors and ceilings are
+/// always square.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DoorStyle {
+    #[default]
+    Square,
+    /// The top corners of the opening are left in place.
+    Arch,
+    /// The top of the opening is a half circle.
+    Round,
+}
+
+pub fn fill_map_with_styled_doors(
+    doors: &[Extent],
+    style: DoorStyle,
+    encoder: &mut impl VoxelEncoder,
+) {
+    for d in doors.iter() {
+        for p in d {
+            if door_style_contains(d, style, &p) {
+                encoder.encode_voxel(&p, &EMPTY_VOXEL);
+                encoder.encode_flags(&p, VoxelFlags::NONE);
+            }
+        }
+    }
+}
+
+/// Empties every voxel in the opening of each door for `style` that holds something solid in any
+/// layer, and returns the number of voxels that were cleared. Doors are carved from their extents
+/// alone, so this catches walls and decorations that later passes wrote into an opening.
+pub fn recarve_blocked_doors(
+    doors: &[Extent],
+    style: DoorStyle,
+    encoder: &mut (impl VoxelEncoder + VoxelSource),
+) -> usize {
+    let layers = [
+        VoxelLayer::Structure,
+        VoxelLayer::Decoration,
+        VoxelLayer::Liquid,
+    ];
+    let mut blocked = Vec::new();
     for d in doors.iter() {
         for p in d {
-            encoder.encode_voxel(&p, &EMPTY_VOXEL);
+            if !door_style_contains(d, style, &p) {
+                continue;
+            }
+            for layer in layers.iter() {
+                if encoder
+                    .get_voxel(*layer, &p)
+                    .is_some_and(|v| v.distance < 0.0)
+                {
+                    blocked.push((*layer, p));
+                }
+            }
+        }
+    }
+
+    for (layer, p) in blocked.iter() {
+        encoder.encode_layer_voxel(*layer, p, &EMPTY_VOXEL);
+        encoder.encode_flags(p, VoxelFlags::NONE);
+    }
+
+    blocked.len()
+}
+
+/// Pulls the distance of each solid `VoxelLayer::Structure` voxel next to the opening of a door
+/// for `style` toward the surface, so smooth meshing makes clean jambs instead of the ragged edges
+/// of walls at full depth. Voxels with more faces on the opening get closer to the surface. Returns
+/// the number of voxels that were trimmed. Run after every pass that writes near doors.
+pub fn trim_door_jambs(
+    doors: &[Extent],
+    style: DoorStyle,
+    encoder: &mut (impl VoxelEncoder + VoxelSource),
+) -> usize {
+    let mut trimmed = Vec::new();
+    for d in doors.iter() {
+        let in_opening = |p: &Point| d.contains_world(p) && door_style_contains(d, style, p);
+        let band = Extent::from_min_and_world_supremum(
+            *d.get_minimum() - Point::from([1, 1, 1]),
+            d.get_world_supremum() + Point::from([1, 1, 1]),
+        );
+        for p in &band {
+            if in_opening(&p) {
+                continue;
+            }
+            let open_faces = ALL_DIRECTIONS
+                .iter()
+                .filter(|dir| in_opening(&(p + Point::from(Normal::Axis(**dir)))))
+                .count();
+            if open_faces == 0 {
+                continue;
+            }
+            if let Some(v) = encoder.get_voxel(VoxelLayer::Structure, &p) {
+                if v.distance < 0.0 {
+                    let distance = -1.0 / (1 + open_faces) as f32;
+                    trimmed.push((p, Voxel { distance, ..v }, encoder.get_flags(&p)));
+                }
+            }
+        }
+    }
+
+    // Encoding the voxel again resets its flags, so a walkable threshold would stop being walkable.
+    for (p, v, flags) in trimmed.iter() {
+        encoder.encode_voxel(p, v);
+        if let Some(flags) = flags {
+            encoder.encode_flags(p, *flags);
+        }
+    }
+
+    trimmed.len()
+}
+
+/// Trim around door openings.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DoorFrameSpec {
+    /// How far the frame reaches out from the opening, in the plane of the wall.
+    pub thickness: u32,
+    pub voxel_type: u8,
+}
+
+/// Writes a frame around the opening of each door for `style`, including the parts of the door
+/// that the style leaves in place. Frames of doors in walls don't reach below the bottom of the
+/// opening. Write the frames before carving the doors, so a frame can't fill a neighboring
+/// opening.
+pub fn fill_map_with_door_frames(
+    doors: &[Extent],
+    style: DoorStyle,
+    spec: &DoorFrameSpec,
+    encoder: &mut impl VoxelEncoder,
+) {
+    let trim = Voxel {
+        distance: -1.0,
+        voxel_type: spec.voxel_type,
+    };
+    for d in doors.iter() {
+        for p in &door_frame_extent(d, spec.thickness as i32) {
+            if !d.contains_world(&p) || !door_style_contains(d, style, &p) {
+                encoder.encode_voxel(&p, &trim);
+            }
+        }
+    }
+}
+
+/// `door` grown by `thickness` in the plane of its wall, floor or ceiling.
+fn door_frame_extent(door: &Extent, thickness: i32) -> Extent {
+    let (min, sup) = (door.get_minimum(), door.get_world_supremum());
+    let dims = door.get_local_supremum();
+    // Like `door_style_contains`, doors are 2 voxels thick along their normal.
+    let normal_axis = if dims.y <= 2 {
+        1
+    } else if dims.x <= dims.z {
+        0
+    } else {
+        2
+    };
+    let grow = |axis: usize| if axis == normal_axis { 0 } else { thickness };
+    let grow_down = if normal_axis == 1 { thickness } else { 0 };
+
+    Extent::from_min_and_world_supremum(
+        Point {
+            x: min.x - grow(0),
+            y: min.y - grow_down,
+            z: min.z - grow(2),
+        },
+        Point {
+            x: sup.x + grow(0),
+            y: sup.y + grow(1),
+            z: sup.z + grow(2),
+        },
+    )
+}
+
+/// True iff `p` in `door` is part of the opening for `style`.
+fn door_style_contains(door: &Extent, style: DoorStyle, p: &Point) -> bool {
+    let (min, sup) = (door.get_minimum(), door.get_local_supremum());
+    // Doors are 2 voxels thick along their normal, so a door this short is in a floor or ceiling.
+    if style == DoorStyle::Square || sup.y <= 2 {
+        return true;
+    }
+
+    let across = if sup.x <= sup.z { 2 } else { 0 };
+    let (u, width) = (
+        component(p, across) - component(min, across),
+        component(sup, across),
+    );
+    let (v, height) = (p.y - min.y, sup.y);
+    match style {
+        DoorStyle::Square => true,
+        DoorStyle::Arch => width < 3 || v < height - 1 || (u > 0 && u < width - 1),
+        DoorStyle::Round => {
+            let radius = width as f32 / 2.0;
+            let dv = v as f32 + 0.5 - (height as f32 - radius).max(0.0);
+            let du = u as f32 + 0.5 - radius;
+
+            dv <= 0.0 || du * du + dv * dv <= radius * radius
         }
     }
 }
@@ -61,45 +554,297 @@ pub fn get_door_able_extent_for_rooms(r1: &Extent, r2: &Extent) -> Option<(Exten
     let dir = zeroes[0];
     let neg_dir = dir.negate();
 
-    // Make sure the door doesn't eat up the boundaries of the wall.
-    let mut grow_by = DirectionIndex::zeroes();
-    for d in ALL_DIRECTIONS.iter() {
-        if *d != dir && *d != neg_dir {
-            *grow_by.get_mut(*d) = -1;
-        }
-    }
+    // The outer layer of the face of each room's wall that faces the other room. Faces stop short
+    // of the edges of the wall, so the door doesn't eat up the boundaries of the wall. A room that's
+    // one voxel thick along the normal only has a face on its negative side, and that face is the
+    // whole layer.
+    let face = |room: &Extent, facing: Direction| {
+        let faces = wall_face_slabs(room, 1);
+        let find = |d: Direction| faces.iter().find(|(f, _)| *f == d).map(|(_, f)| *f);
+
+        find(facing)
+            .filter(|f| !f.is_empty())
+            .or_else(|| find(facing.negate()))
+    };
 
-    // Grow the rooms into each other along the door normal so we can just get their
+    // Grow the faces into each other along the door normal so we can just get their
     // intersection as the door extent.
-    let mut r1_grow_by = grow_by;
+    let mut r1_grow_by = DirectionIndex::zeroes();
     *r1_grow_by.get_mut(neg_dir) = 1;
-    let mut r2_grow_by = grow_by;
+    let mut r2_grow_by = DirectionIndex::zeroes();
     *r2_grow_by.get_mut(dir) = 1;
 
-    let grown_r1 = r1.directional_grow(&r1_grow_by);
-    let grown_r2 = r2.directional_grow(&r2_grow_by);
+    let grown_r1 = face(r1, neg_dir)?.directional_grow(&r1_grow_by);
+    let grown_r2 = face(r2, dir)?.directional_grow(&r2_grow_by);
 
     Some((grown_r1.intersection(&grown_r2), dir))
 }
 
+/// The direction out of `room` through `door`, which sticks out of one side of one of its parts.
+/// Returns `None` if the door isn't in the boundary of the room.
+pub fn door_direction(door: &Extent, room: &Room) -> Option<Direction> {
+    room.parts.iter().find_map(|part| {
+        if door.is_subset(part) {
+            return None;
+        }
+
+        ALL_DIRECTIONS
+            .iter()
+            .find(|d| (*door - Point::from(Normal::Axis(**d))).is_subset(part))
+            .cloned()
+    })
+}
+
+/// Like `get_door_able_extent_for_rooms`, but between any parts of two composite rooms. Returns
+/// the largest extent if several pairs of parts share a wall.
+pub fn get_door_able_extent_for_composite_rooms(
+    r1: &Room,
+    r2: &Room,
+) -> Option<(Extent, Direction)> {
+    door_able_walls(r1, r2)
+        .first()
+        .map(|(extent, dir, _)| (*extent, *dir))
+}
+
+/// The door-able extent between each pair of parts of `r1` and `r2` that share a wall, with the
+/// `door_floor_y` of the pair, largest extent first.
+fn door_able_walls(r1: &Room, r2: &Room) -> Vec<(Extent, Direction, i32)> {
+    let size = |e: &Extent| {
+        let sup = e.get_local_supremum();
+        sup.x * sup.y * sup.z
+    };
+
+    let mut walls: Vec<_> = r1
+        .parts
+        .iter()
+        .flat_map(|a| {
+            r2.parts.iter().filter_map(move |b| {
+                let (extent, dir) = get_door_able_extent_for_rooms(a, b)?;
+
+                Some((extent, dir, door_floor_y(a, b)))
+            })
+        })
+        .collect();
+    // Stable, so ties keep the order of the parts.
+    walls.sort_by_key(|(extent, _, _)| std::cmp::Reverse(size(extent)));
+
+    walls
+}
+
+/// The height of the lowest empty voxel of the higher of the two rooms' interiors. Doors in the
+/// wall between the rooms rest on it.
+fn door_floor_y(r1: &Extent, r2: &Extent) -> i32 {
+    r1.get_minimum().y.max(r2.get_minimum().y) + WALL_THICKNESS
+}
+
+/// How to choose among the candidate doors sampled for a pair of rooms.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DoorScoringPolicy {
+    /// Accept the first candidate.
+    #[default]
+    First,
+    /// Prefer the door whose bottom is closest to the higher of the two rooms' floors. Doors in
+    /// walls rest on the floor of the parts they're between, so between composite rooms this
+    /// prefers walls between parts on the main floor over walls between raised parts.
+    ClosestToFloor,
+    /// Prefer the door closest to the center of the shared wall.
+    MostCentral,
+    /// Prefer the door farthest from all previously generated doors.
+    FarthestFromOtherDoors,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DoorSelectionSpec {
+    /// The number of candidate doors sampled for each pair of adjacent rooms.
+    pub num_candidates: usize,
+    pub policy: DoorScoringPolicy,
+    /// Doors in walls are raised this far, and `door_sill_steps` puts a step in front of them on
+    /// both sides.
+    #[serde(default)]
+    pub sill_height: u32,
+    /// Rooms that share a long wall get up to this many doors between them, as long as the doors
+    /// don't touch. Zero is the same as one.
+    #[serde(default)]
+    pub max_doors_per_pair: usize,
+}
+
+impl Default for DoorSelectionSpec {
+    fn default() -> Self {
+        DoorSelectionSpec {
+            num_candidates: 1,
+            policy: DoorScoringPolicy::First,
+            sill_height: 0,
+            max_doors_per_pair: 1,
+        }
+    }
+}
+
+/// `door_dims` samples the door's span along each axis of the wall plane. Doors in walls rest on
+/// the higher of the two rooms' floors.
 pub fn try_generate_door_big_enough_between_rooms(
-    min_door_dim: u32,
-    max_door_dim: u32,
+    door_dims: &SubspanSampler,
     r1: &Extent,
     r2: &Extent,
     rng: &mut impl Rng,
 ) -> Option<Extent> {
-    let (extent, dir) = if let Some((extent, dir)) = get_door_able_extent_for_rooms(r1, r2) {
-        (extent, dir)
-    } else {
+    let (extent, dir) = get_door_able_extent_for_rooms(r1, r2)?;
+
+    sample_door_in_extent(door_dims, &extent, dir, door_floor_y(r1, r2), rng)
+}
+
+/// Samples up to `selection.num_candidates` doors between `r1` and `r2` and returns the one that
+/// scores best under `selection.policy`. `other_doors` are the doors that have already been
+/// generated.
+pub fn try_select_door_between_rooms(
+    door_dims: &SubspanSampler,
+    selection: &DoorSelectionSpec,
+    other_doors: &[Extent],
+    r1: &Extent,
+    r2: &Extent,
+    rng: &mut impl Rng,
+) -> Option<Extent> {
+    let (extent, dir) = get_door_able_extent_for_rooms(r1, r2)?;
+    let floor_y = door_floor_y(r1, r2);
+
+    select_door_in_walls(
+        door_dims,
+        selection,
+        other_doors,
+        &[],
+        &[(extent, dir, floor_y)],
+        floor_y,
+        rng,
+    )
+}
+
+/// Like `try_select_door_between_rooms`, but the door can be between any parts of the rooms.
+pub fn try_select_door_between_composite_rooms(
+    door_dims: &SubspanSampler,
+    selection: &DoorSelectionSpec,
+    other_doors: &[Extent],
+    r1: &Room,
+    r2: &Room,
+    rng: &mut impl Rng,
+) -> Option<Extent> {
+    select_door_between_composite_rooms(door_dims, selection, other_doors, &[], r1, r2, rng)
+}
+
+/// Like `try_select_door_between_composite_rooms`, but candidates that touch any of `pair_doors`,
+/// the doors already placed between `r1` and `r2`, are rejected. The candidates take turns among
+/// the walls shared by pairs of parts, starting with the largest.
+pub fn select_door_between_composite_rooms(
+    door_dims: &SubspanSampler,
+    selection: &DoorSelectionSpec,
+    other_doors: &[Extent],
+    pair_doors: &[Extent],
+    r1: &Room,
+    r2: &Room,
+    rng: &mut impl Rng,
+) -> Option<Extent> {
+    let walls = door_able_walls(r1, r2);
+    let floor_y = door_floor_y(&r1.bounding_extent(), &r2.bounding_extent());
+
+    select_door_in_walls(
+        door_dims,
+        selection,
+        other_doors,
+        pair_doors,
+        &walls,
+        floor_y,
+        rng,
+    )
+}
+
+/// Candidate `i` is sampled in wall `i % walls.len()`, where each wall is a door-able extent, its
+/// direction and the `door_floor_y` that doors in it rest on. `floor_y` is the `door_floor_y` of
+/// the two rooms. Candidates that touch any of `pair_doors` are rejected.
+fn select_door_in_walls(
+    door_dims: &SubspanSampler,
+    selection: &DoorSelectionSpec,
+    other_doors: &[Extent],
+    pair_doors: &[Extent],
+    walls: &[(Extent, Direction, i32)],
+    floor_y: i32,
+    rng: &mut impl Rng,
+) -> Option<Extent> {
+    if walls.is_empty() {
         return None;
+    }
+
+    let candidates: Vec<_> = (0..selection.num_candidates.max(1))
+        .filter_map(|i| {
+            let (extent, dir, wall_floor_y) = &walls[i % walls.len()];
+            let door = sample_door_in_extent(door_dims, extent, *dir, *wall_floor_y, rng)?;
+
+            Some((
+                raise_door(&door, extent, *dir, selection.sill_height)?,
+                extent,
+            ))
+        })
+        .filter(|(door, _)| {
+            let grown = door.radial_grow(1);
+            pair_doors.iter().all(|d| grown.intersection(d).is_empty())
+        })
+        .collect();
+
+    let score = |door: &Extent, wall: &Extent| -> f32 {
+        match selection.policy {
+            DoorScoringPolicy::First => 0.0,
+            DoorScoringPolicy::ClosestToFloor => (door.get_minimum().y - floor_y).abs() as f32,
+            DoorScoringPolicy::MostCentral => {
+                distance_squared(&extent_center(door), &extent_center(wall))
+            }
+            DoorScoringPolicy::FarthestFromOtherDoors => {
+                let center = extent_center(door);
+                let nearest = other_doors
+                    .iter()
+                    .map(|d| distance_squared(&center, &extent_center(d)))
+                    .fold(f32::MAX, f32::min);
+
+                -nearest
+            }
+        }
     };
 
+    // Lower scores are better, and ties go to the earliest candidate.
+    candidates
+        .into_iter()
+        .map(|(d, wall)| (score(&d, wall), d))
+        .min_by(|(s1, _), (s2, _)| s1.partial_cmp(s2).expect("Door score must not be NaN"))
+        .map(|(_, d)| d)
+}
+
+/// Moves a door in a wall up by `sill_height`, or returns `None` if it would no longer fit in
+/// `extent`. Doors in floors and ceilings are unchanged.
+fn raise_door(door: &Extent, extent: &Extent, dir: Direction, sill_height: u32) -> Option<Extent> {
+    if sill_height == 0 || dir.positive() == Direction::PosY {
+        return Some(*door);
+    }
+
+    let raised = *door + [0, sill_height as i32, 0].into();
+    if raised.get_world_supremum().y > extent.get_world_supremum().y {
+        return None;
+    }
+
+    Some(raised)
+}
+
+/// Doors in walls have their bottom at `floor_y`, the height of the lowest empty voxel of the
+/// interior, so they don't float in mid-wall. Doors in floors and ceilings can be anywhere in
+/// `extent`.
+fn sample_door_in_extent(
+    door_dims: &SubspanSampler,
+    extent: &Extent,
+    dir: Direction,
+    floor_y: i32,
+    rng: &mut impl Rng,
+) -> Option<Extent> {
     if extent.is_empty() {
         return None;
     }
 
-    // Choose a random 2 x N x M door inside the given extent where there is space for a door.
+    // Choose a random 2 x N x M door inside the given extent where there is space for a door. The
+    // size is chosen before the position so that neither is biased by the other.
 
     let n = Normal::Axis(dir.positive());
     let PlaneSpanInfo { u, v } = n.get_plane_span_info();
@@ -108,7 +853,7 @@ pub fn try_generate_door_big_enough_between_rooms(
     let u_sup = sup.dot(&u);
     let v_sup = sup.dot(&v);
 
-    if (u_sup < min_door_dim as i32) || (v_sup < min_door_dim as i32) {
+    if (u_sup < door_dims.min_len()) || (v_sup < door_dims.min_len()) {
         return None;
     }
 
@@ -116,38 +861,144 @@ pub fn try_generate_door_big_enough_between_rooms(
     let u_min = min.dot(&u);
     let v_min = min.dot(&v);
 
-    // TODO: if the door is in a wall (not a ceiling or floor), then make the bottom of the
-    // door touch the floor
-    let (door_u_min, door_u_max) = sample_range(rng, u_min, u_min + u_sup - 1);
-    let (door_v_min, door_v_max) = sample_range(rng, v_min, v_min + v_sup - 1);
-    let door_u_sup = (1 + door_u_max - door_u_min)
-        .min(max_door_dim as i32)
-        .max(min_door_dim as i32);
-    let door_v_sup = (1 + door_v_max - door_v_min)
-        .min(max_door_dim as i32)
-        .max(min_door_dim as i32);
+    let (door_u_min, door_u_sup) = door_dims.sample(rng, u_min, u_sup);
+    let (door_v_min, door_v_sup) = door_dims.sample(rng, v_min, v_sup);
 
     let n = Point::from(n);
 
     let door_min = n * min.dot(&n) + u * door_u_min + v * door_v_min;
     let door_sup = n * 2 + u * door_u_sup + v * door_v_sup;
-    let door = Extent::from_min_and_local_supremum(door_min, door_sup);
+    let mut door = Extent::from_min_and_local_supremum(door_min, door_sup);
+    debug_assert!(door.is_subset(extent));
+
+    if dir.positive() != Direction::PosY {
+        door = door + [0, floor_y - door.get_minimum().y, 0].into();
+        if !door.is_subset(extent) {
+            return None;
+        }
+    }
+
+    Some(door)
+}
+
+/// Small openings cut high in the walls between rooms, so players can see and shoot between them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WindowSpec {
+    /// The chance that a pair of rooms connected through a shared wall gets a window.
+    pub probability: f32,
+    /// Windows are 1 or 2 voxels wide and tall, so values above 2 are treated as 2.
+    pub max_size: u32,
+    /// The bottom of a window is at least this far above the higher of the two floors.
+    pub min_height_above_floor: u32,
+}
 
-    // It's possible that the random door escapes the valid extent due to size clamping.
-    if door.is_subset(&extent) {
-        Some(door)
+/// Cuts a window between `r1` and `r2` through the wall that `doors` are in, without touching
+/// them, and with the interiors of both rooms on either side of it. Returns `None` if the chance
+/// fails, the doors aren't in a wall, or no window fits.
+pub fn try_generate_window_between_rooms(
+    r1: &Room,
+    r2: &Room,
+    doors: &[Extent],
+    spec: &WindowSpec,
+    rng: &mut impl Rng,
+) -> Option<Extent> {
+    if rng.gen::<f32>() >= spec.probability {
+        return None;
+    }
+    let (wall, dir) = get_door_able_extent_for_composite_rooms(r1, r2)?;
+    if dir.positive() == Direction::PosY || doors.iter().all(|d| d.intersection(&wall).is_empty()) {
+        return None;
+    }
+
+    let max_size = spec.max_size.clamp(1, 2) as i32;
+    let (width, height) = (
+        rng.gen_range(1, max_size + 1),
+        rng.gen_range(1, max_size + 1),
+    );
+    let floor_y = door_floor_y(&r1.bounding_extent(), &r2.bounding_extent());
+    let bottom = floor_y + spec.min_height_above_floor as i32;
+
+    let across = if dir.positive() == Direction::PosX {
+        2
     } else {
-        None
+        0
+    };
+    let (min, sup) = (wall.get_minimum(), wall.get_world_supremum());
+    // A window opens onto a room's interior if it goes through one of the room's wall faces, and
+    // not through the edges or corners where the faces meet.
+    let opens_into = |room: &Room, w: &Extent| {
+        room.parts
+            .iter()
+            .flat_map(|part| wall_face_slabs(part, WALL_THICKNESS))
+            .any(|(d, face)| {
+                let spans = |axis: usize| {
+                    let (w_min, f_min) = (
+                        component(w.get_minimum(), axis),
+                        component(face.get_minimum(), axis),
+                    );
+                    let (w_sup, f_sup) = (
+                        component(&w.get_world_supremum(), axis),
+                        component(&face.get_world_supremum(), axis),
+                    );
+
+                    w_min >= f_min && w_sup <= f_sup
+                };
+
+                d.positive() == dir.positive()
+                    && !face.intersection(w).is_empty()
+                    && spans(across)
+                    && spans(1)
+            })
+    };
+    let candidates: Vec<Extent> = (component(min, across)..=component(&sup, across) - width)
+        .flat_map(|u| (bottom..=sup.y - height).map(move |y| (u, y)))
+        .map(|(u, y)| {
+            let window_min = with_component(&with_component(min, across, u), 1, y);
+            let window_sup = with_component(
+                &with_component(wall.get_local_supremum(), across, width),
+                1,
+                height,
+            );
+
+            Extent::from_min_and_local_supremum(window_min, window_sup)
+        })
+        .filter(|w| {
+            let grown = w.radial_grow(1);
+            doors.iter().all(|d| grown.intersection(d).is_empty())
+        })
+        .filter(|w| opens_into(r1, w) && opens_into(r2, w))
+        .collect();
+    if candidates.is_empty() {
+        return None;
     }
+
+    Some(candidates[rng.gen_range(0, candidates.len())])
 }
 
 pub fn generate_door_graph(
     rooms: &[Extent],
     min_door_dim: u32,
     max_door_dim: u32,
+    selection: &DoorSelectionSpec,
+    rng: &mut impl Rng,
+    doors: &mut SymmetricMap<Vec<Extent>>,
+) -> StableGraph<usize, (), Undirected> {
+    let rooms: Vec<Room> = rooms.iter().map(|r| Room::from(*r)).collect();
+
+    generate_composite_door_graph(&rooms, min_door_dim, max_door_dim, selection, rng, doors)
+}
+
+/// Like `generate_door_graph`, but doors are placed on the boundaries of composite rooms.
+pub fn generate_composite_door_graph(
+    rooms: &[Room],
+    min_door_dim: u32,
+    max_door_dim: u32,
+    selection: &DoorSelectionSpec,
     rng: &mut impl Rng,
-    doors: &mut SymmetricMap<Extent>,
+    doors: &mut SymmetricMap<Vec<Extent>>,
 ) -> StableGraph<usize, (), Undirected> {
+    let door_dims = SubspanSampler::new(min_door_dim as i32, max_door_dim as i32);
+    let mut placed_doors = Vec::new();
     let mut graph = StableGraph::default();
     for i in 0..rooms.len() {
         graph.add_node(i);
@@ -164,16 +1015,34 @@ pub fn generate_door_graph(
             }
 
             // TODO: maybe retry?
-            if let Some(door) = try_generate_door_big_enough_between_rooms(
-                min_door_dim,
-                max_door_dim,
+            if let Some(door) = try_select_door_between_composite_rooms(
+                &door_dims,
+                selection,
+                &placed_doors,
                 &rooms[i_idx],
                 &rooms[j_idx],
                 rng,
             ) {
+                let mut pair_doors = vec![door];
+                while pair_doors.len() < selection.max_doors_per_pair {
+                    match select_door_between_composite_rooms(
+                        &door_dims,
+                        selection,
+                        &placed_doors,
+                        &pair_doors,
+                        &rooms[i_idx],
+                        &rooms[j_idx],
+                        rng,
+                    ) {
+                        Some(door) => pair_doors.push(door),
+                        None => break,
+                    }
+                }
+
                 // It seems like too much overhead to put the door extents into the graph edges,
                 // since we copy the graph elements a lot.
-                doors.insert(i_idx, j_idx, door);
+                placed_doors.extend(pair_doors.iter().cloned());
+                doors.insert(i_idx, j_idx, pair_doors);
                 graph.add_edge(*i, *j, ());
             }
         }
@@ -193,15 +1062,138 @@ pub fn collect_rooms_from_room_graph(
 }
 
 pub fn collect_doors_from_room_graph(
-    doors: &SymmetricMap<Extent>,
+    doors: &SymmetricMap<Vec<Extent>>,
     room_graph: &StableGraph<usize, (), Undirected>,
 ) -> Vec<Extent> {
     room_graph
         .edge_references()
-        .map(|e| *doors.get(room_graph[e.source()], room_graph[e.target()]))
+        .flat_map(|e| doors.get(room_graph[e.source()], room_graph[e.target()]))
+        .cloned()
         .collect()
 }
 
+/// Builds the layout of the rooms and doors in `room_graph`. Room indices in the layout follow the
+/// order of `collect_rooms_from_room_graph`, and a pair of rooms gets a layout door for each of its
+/// doors.
+pub fn layout_from_room_graph(
+    room_candidates: &[Extent],
+    doors: &SymmetricMap<Vec<Extent>>,
+    room_graph: &StableGraph<usize, (), Undirected>,
+) -> Layout {
+    let mut layout_index = vec![0; room_graph.node_bound()];
+    for (i, n) in room_graph.node_indices().enumerate() {
+        layout_index[n.index()] = i;
+    }
+
+    Layout {
+        rooms: collect_rooms_from_room_graph(room_candidates, room_graph),
+        doors: room_graph
+            .edge_references()
+            .flat_map(|e| {
+                let (a, b) = (
+                    layout_index[e.source().index()],
+                    layout_index[e.target().index()],
+                );
+                doors
+                    .get(room_graph[e.source()], room_graph[e.target()])
+                    .iter()
+                    .map(move |d| LayoutDoor::new(a, b, *d))
+            })
+            .collect(),
+    }
+}
+
+/// The steps in front of each wall door in `layout`, on both sides, that lead up to a sill of
+/// `sill_height`. With a `movement` spec, a sill that's too tall to step onto gets a staircase that
+/// rises at most `max_ledge_height` per step, descending away from the wall.
+pub fn door_sill_steps(
+    layout: &Layout,
+    sill_height: u32,
+    movement: Option<&MovementSpec>,
+) -> Vec<Extent> {
+    if sill_height == 0 {
+        return Vec::new();
+    }
+
+    let rise = match movement {
+        Some(m) if m.max_ledge_height() > 0 => m.max_ledge_height() as i32,
+        _ => sill_height as i32,
+    };
+
+    let mut steps = Vec::new();
+    for door in layout.doors.iter() {
+        let (r1, r2) = (&layout.rooms[door.rooms.0], &layout.rooms[door.rooms.1]);
+        // Doors in floors and ceilings don't get steps.
+        let (axis, r2_is_positive) = match touching_axis(r1, r2) {
+            Some((axis, r2_is_positive)) if axis != 1 => (axis, r2_is_positive),
+            _ => continue,
+        };
+        let plane = if r2_is_positive {
+            component(&r1.get_world_supremum(), axis)
+        } else {
+            component(&r2.get_world_supremum(), axis)
+        };
+
+        // The first voxels of each room's interior in front of the wall, and which way is away
+        // from the wall.
+        for (a, away) in [
+            (plane - WALL_THICKNESS - 1, -1),
+            (plane + WALL_THICKNESS, 1),
+        ]
+        .iter()
+        {
+            let base_y = door.extent.get_minimum().y - sill_height as i32;
+            let mut height = sill_height as i32;
+            let mut a = *a;
+            while height > 0 {
+                let min = with_component(
+                    &with_component(door.extent.get_minimum(), axis, a),
+                    1,
+                    base_y,
+                );
+                let sup = with_component(
+                    &with_component(door.extent.get_local_supremum(), axis, 1),
+                    1,
+                    height,
+                );
+                steps.push(Extent::from_min_and_local_supremum(min, sup));
+                height -= rise;
+                a += away;
+            }
+        }
+    }
+
+    steps
+}
+
+pub fn fill_map_with_steps(steps: &[Extent], encoder: &mut impl VoxelEncoder) {
+    for s in steps.iter() {
+        let top = s.get_world_supremum().y - 1;
+        for p in s {
+            encoder.encode_voxel(&p, &FLOOR_VOXEL);
+            if p.y == top {
+                encoder.encode_flags(&p, VoxelFlags::WALKABLE);
+            }
+        }
+    }
+}
+
+/// Like `spawn_in_room`, but only in the parts of a composite room.
+pub fn spawn_in_composite_room(room: &Room) -> SpawnArea {
+    let mut seen = FnvHashSet::default();
+    let valid_spawn_points = room
+        .parts
+        .iter()
+        .flat_map(|part| spawn_in_room(part).valid_spawn_points)
+        .filter(|p| seen.insert(*p))
+        .collect();
+
+    SpawnArea {
+        valid_spawn_points,
+        facing: None,
+    }
+}
+
 /// The 1xNxM extent inside the room walls just above the floor.
 /// BUG: doesn't account for doors in the floor
 pub fn spawn_in_room(room: &Extent) -> SpawnArea {
@@ -213,6 +1205,7 @@ pub fn spawn_in_room(room: &Extent) -> SpawnArea {
 
     SpawnArea {
         valid_spawn_points: hero_spawn_area.into_iter().collect(),
+        facing: None,
     }
 }
 
@@ -248,4 +1241,242 @@ mod tests {
 
         assert_eq!(get_door_able_extent_for_rooms(&r1, &r2), None);
     }
+
+    #[test]
+    fn test_composite_room_doors_only_on_its_parts() {
+        let l_room = Room {
+            outline: RoomOutline::L,
+            parts: vec![
+                Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 12, 12].into()),
+                Extent::from_min_and_local_supremum([0, 0, 0].into(), [12, 12, 20].into()),
+            ],
+        };
+        let beside_arm = Room::from(Extent::from_min_and_local_supremum(
+            [20, 0, 8].into(),
+            [10, 12, 12].into(),
+        ));
+        let beside_notch = Room::from(Extent::from_min_and_local_supremum(
+            [20, 0, 12].into(),
+            [10, 12, 8].into(),
+        ));
+
+        assert!(l_room.interior_contains(&[6, 5, 6].into()));
+        assert!(!l_room.interior_contains(&[15, 5, 15].into()));
+        assert_eq!(
+            get_door_able_extent_for_composite_rooms(&l_room, &beside_arm).map(|(e, _)| e),
+            Some(Extent::from_min_and_local_supremum(
+                [19, 1, 9].into(),
+                [2, 10, 2].into()
+            )),
+        );
+        // The bounding extents share a wall, but the notch leaves no room for a door.
+        assert!(
+            get_door_able_extent_for_rooms(&l_room.bounding_extent(), &beside_notch.parts[0])
+                .is_some()
+        );
+        assert_eq!(
+            get_door_able_extent_for_composite_rooms(&l_room, &beside_notch),
+            None
+        );
+    }
+
+    #[test]
+    fn test_wall_door_rests_on_higher_floor() {
+        let r1 = Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 20, 20].into());
+        let r2 = Extent::from_min_and_local_supremum([20, 2, 0].into(), [20, 20, 20].into());
+        let door_dims = SubspanSampler::new(3, 5);
+        let mut rng = crate::sampling::small_rng([1, 2, 3, 4]);
+
+        for _ in 0..10 {
+            let door =
+                try_generate_door_big_enough_between_rooms(&door_dims, &r1, &r2, &mut rng).unwrap();
+            assert_eq!(door.get_minimum().y, 2 + WALL_THICKNESS);
+        }
+    }
+
+    #[test]
+    fn test_closest_to_floor_prefers_wall_between_parts_on_main_floor() {
+        let raised = Room {
+            outline: RoomOutline::L,
+            parts: vec![
+                Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 20, 20].into()),
+                Extent::from_min_and_local_supremum([0, 10, 16].into(), [20, 24, 24].into()),
+            ],
+        };
+        let beside = Room::from(Extent::from_min_and_local_supremum(
+            [20, 0, 0].into(),
+            [20, 40, 44].into(),
+        ));
+        let door_dims = SubspanSampler::new(3, 5);
+        let select = |policy| {
+            let selection = DoorSelectionSpec {
+                num_candidates: 2,
+                policy,
+                ..Default::default()
+            };
+            let mut rng = crate::sampling::small_rng([1, 2, 3, 4]);
+
+            try_select_door_between_composite_rooms(
+                &door_dims,
+                &selection,
+                &[],
+                &raised,
+                &beside,
+                &mut rng,
+            )
+            .unwrap()
+        };
+
+        // The first candidate is in the larger wall, which is between the raised part and the
+        // other room, so it rests on the raised floor.
+        assert_eq!(
+            select(DoorScoringPolicy::First).get_minimum().y,
+            10 + WALL_THICKNESS
+        );
+        assert_eq!(
+            select(DoorScoringPolicy::ClosestToFloor).get_minimum().y,
+            WALL_THICKNESS
+        );
+    }
+
+    #[test]
+    fn test_surface_types_of_box_room() {
+        let room = Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 20, 20].into());
+        let types = SurfaceVoxelTypes::distinct();
+
+        assert_eq!(types.at(&room, &[10, 2, 10].into()), types.floor);
+        assert_eq!(types.at(&room, &[10, 17, 10].into()), types.ceiling);
+        assert_eq!(types.at(&room, &[2, 10, 10].into()), types.wall);
+        assert_eq!(types.at(&room, &[10, 10, 18].into()), types.wall);
+    }
+
+    #[test]
+    fn test_door_frame_surrounds_arch() {
+        struct Writes(Vec<Point>);
+        impl VoxelEncoder for Writes {
+            fn encode_voxel(&mut self, point: &Point, _data: &Voxel) {
+                self.0.push(*point);
+            }
+        }
+
+        let door = Extent::from_min_and_local_supremum([0, 5, 10].into(), [2, 6, 4].into());
+        let spec = DoorFrameSpec {
+            thickness: 1,
+            voxel_type: 13,
+        };
+        let mut writes = Writes(Vec::new());
+        fill_map_with_door_frames(&[door], DoorStyle::Arch, &spec, &mut writes);
+
+        // A ring 1 voxel wide on the sides and top, plus the 2 corners the arch leaves in place,
+        // all 2 voxels thick.
+        assert_eq!(writes.0.len(), 2 * (2 * 6 + 6) + 2 * 2);
+        for p in writes.0.iter() {
+            assert!(p.y >= 5 && p.x >= 0 && p.x < 2);
+        }
+    }
+
+    #[test]
+    fn test_recarve_clears_what_was_written_into_door() {
+        use crate::encoder::{BufferedEncoder, NullEncoder};
+
+        let door = Extent::from_min_and_local_supremum([0, 5, 10].into(), [2, 4, 4].into());
+        let mut null = NullEncoder;
+        let mut staged = BufferedEncoder::with_read_back(&mut null);
+        fill_map_with_doors(&[door], &mut staged);
+        let column = Extent::from_min_and_local_supremum([0, 5, 11].into(), [1, 10, 1].into());
+        for p in &column {
+            staged.encode_layer_voxel(VoxelLayer::Decoration, &p, &FLOOR_VOXEL);
+        }
+
+        // Only the part of the column inside the opening is cleared.
+        assert_eq!(
+            recarve_blocked_doors(&[door], DoorStyle::Square, &mut staged),
+            4
+        );
+        let at = |y| staged.get_voxel(VoxelLayer::Decoration, &[0, y, 11].into());
+        assert_eq!(at(8).map(|v| v.voxel_type), Some(0));
+        assert_eq!(at(9).map(|v| v.voxel_type), Some(1));
+        assert_eq!(
+            recarve_blocked_doors(&[door], DoorStyle::Square, &mut staged),
+            0
+        );
+    }
+
+    #[test]
+    fn test_trim_door_jambs_only_touches_solid_neighbors_of_opening() {
+        use crate::encoder::{BufferedEncoder, NullEncoder};
+
+        let room = Extent::from_min_and_local_supremum([0, 0, 0].into(), [30, 20, 30].into());
+        let door = Extent::from_min_and_local_supremum([29, 6, 10].into(), [2, 4, 4].into());
+        let mut null = NullEncoder;
+        let mut staged = BufferedEncoder::with_read_back(&mut null);
+        fill_map_with_rooms(&[room], &mut staged);
+        fill_map_with_doors(&[door], &mut staged);
+
+        // The wall beside the opening, and its top edge, get closer to the surface.
+        assert!(trim_door_jambs(&[door], DoorStyle::Square, &mut staged) > 0);
+        let at = |p: [i32; 3]| staged.get_voxel(VoxelLayer::Structure, &p.into()).unwrap();
+        assert_eq!(at([29, 7, 9]).distance, -0.5);
+        assert_eq!(at([29, 10, 11]).distance, -0.5);
+        assert_eq!(at([29, 7, 10]).distance, EMPTY_VOXEL.distance);
+        assert_eq!(at([29, 7, 8]).distance, -1.0);
+        assert_eq!(at([29, 7, 9]).voxel_type, at([29, 7, 8]).voxel_type);
+    }
+
+    #[derive(Default)]
+    struct FlagRecorder {
+        flags: Vec<(Point, VoxelFlags)>,
+    }
+
+    impl VoxelEncoder for FlagRecorder {
+        fn encode_voxel(&mut self, _point: &Point, _data: &Voxel) {}
+
+        fn encode_flags(&mut self, point: &Point, flags: VoxelFlags) {
+            self.flags.push((*point, flags));
+        }
+    }
+
+    #[test]
+    fn test_trim_door_jambs_keeps_threshold_walkable() {
+        use crate::encoder::{BufferedEncoder, ShellEncoder};
+
+        let room = Extent::from_min_and_local_supremum([0, 0, 0].into(), [30, 20, 30].into());
+        let door = Extent::from_min_and_local_supremum([29, 6, 10].into(), [2, 4, 4].into());
+        let threshold: Point = [29, 5, 11].into();
+        let mut recorder = FlagRecorder::default();
+        let mut shell = ShellEncoder::new(&mut recorder);
+        let mut staged = BufferedEncoder::with_read_back(&mut shell);
+        fill_map_with_rooms(&[room], &mut staged);
+        fill_map_with_doors(&[door], &mut staged);
+        staged.encode_voxel(&threshold, &FLOOR_VOXEL);
+        staged.encode_flags(&threshold, VoxelFlags::WALKABLE);
+
+        assert!(trim_door_jambs(&[door], DoorStyle::Square, &mut staged) > 0);
+        assert!(
+            staged
+                .get_voxel(VoxelLayer::Structure, &threshold)
+                .unwrap()
+                .distance
+                > -1.0
+        );
+        staged.commit();
+        shell.finish();
+
+        assert_eq!(
+            recorder.flags.iter().rev().find(|(p, _)| *p == threshold),
+            Some(&(threshold, VoxelFlags::WALKABLE))
+        );
+    }
+
+    #[test]
+    fn test_classify_room() {
+        let room =
+            |x, y, z| Extent::from_min_and_local_supremum([0, 0, 0].into(), [x, y, z].into());
+
+        assert_eq!(classify_room(&room(14, 40, 14), 2), RoomShape::Shaft);
+        assert_eq!(classify_room(&room(40, 14, 14), 2), RoomShape::Corridor);
+        assert_eq!(classify_room(&room(40, 14, 14), 3), RoomShape::Ordinary);
+        assert_eq!(classify_room(&room(14, 14, 14), 1), RoomShape::Closet);
+        assert_eq!(classify_room(&room(30, 14, 30), 1), RoomShape::Hall);
+    }
 }
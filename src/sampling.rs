@@ -1,13 +1,153 @@
+use fnv::{FnvHashMap, FnvHasher};
 use ilattice3::{Extent, Point};
 use rand::{prelude::*, rngs::SmallRng};
 use rand_distr::{Distribution, Normal, Uniform};
 use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
 use std::mem;
 
 pub fn small_rng(seed: [u32; 4]) -> SmallRng {
     SmallRng::from_seed(unsafe { mem::transmute(seed) })
 }
 
+/// Wraps an RNG and counts the 32-bit words drawn from it.
+pub struct CountingRng<R> {
+    rng: R,
+    words: u64,
+}
+
+impl<R: RngCore> CountingRng<R> {
+    pub fn new(rng: R) -> Self {
+        CountingRng { rng, words: 0 }
+    }
+
+    pub fn words_drawn(&self) -> u64 {
+        self.words
+    }
+
+    /// Returns the number of words drawn since the last call.
+    pub fn take_words_drawn(&mut self) -> u64 {
+        mem::replace(&mut self.words, 0)
+    }
+}
+
+impl<R: RngCore> RngCore for CountingRng<R> {
+    fn next_u32(&mut self) -> u32 {
+        self.words += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.words += 2;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.words += (dest.len() as u64).div_ceil(4);
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.words += (dest.len() as u64).div_ceil(4);
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Deterministically derives an independent seed from `seed` and `salt`, e.g. one seed per room.
+pub fn derive_seed(seed: [u32; 4], salt: u64) -> [u32; 4] {
+    let mut derived = [0; 4];
+    for (i, words) in derived.chunks_mut(2).enumerate() {
+        let mut hasher = FnvHasher::default();
+        for s in seed.iter() {
+            hasher.write_u32(*s);
+        }
+        hasher.write_u64(salt);
+        hasher.write_usize(i);
+        let hash = hasher.finish();
+        words[0] = hash as u32;
+        words[1] = (hash >> 32) as u32;
+    }
+
+    derived
+}
+
+/// Calls `attempt` with the index and seed of each attempt until it returns `Some`, at most
+/// `max_attempts` times. The seeds are derived from one seed drawn from `rng` and the index of the
+/// attempt, so an attempt doesn't depend on how much earlier attempts drew, and can be reproduced
+/// on its own.
+pub fn retry_with_derived_seeds<T>(
+    rng: &mut impl Rng,
+    max_attempts: usize,
+    mut attempt: impl FnMut(usize, [u32; 4]) -> Option<T>,
+) -> Option<T> {
+    let base_seed: [u32; 4] = rng.gen();
+
+    (0..max_attempts).find_map(|i| attempt(i, derive_seed(base_seed, i as u64)))
+}
+
+/// Like `derive_seed`, but salted by a name, e.g. one seed per generation pass.
+pub fn named_seed(seed: [u32; 4], name: &str) -> [u32; 4] {
+    let mut hasher = FnvHasher::default();
+    hasher.write(name.as_bytes());
+
+    derive_seed(seed, hasher.finish())
+}
+
+/// A spec value that's either fixed or chosen from a weighted table of options when the map is
+/// generated, e.g. `OneOf(one_of: [Arch, Square, Round], weights: [2.0, 1.0, 1.0])`. Without
+/// weights, the options are equally likely.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Variant<T> {
+    Fixed(T),
+    OneOf {
+        one_of: Vec<T>,
+        #[serde(default)]
+        weights: Vec<f32>,
+    },
+}
+
+impl<T: Default> Default for Variant<T> {
+    fn default() -> Self {
+        Variant::Fixed(T::default())
+    }
+}
+
+impl<T: Clone> Variant<T> {
+    /// Chooses an option using a seed derived from `seed` and `name`, so each named variant is
+    /// stable for a given map seed regardless of which other variants the spec has.
+    pub fn resolve(&self, seed: [u32; 4], name: &str) -> T {
+        let (options, weights) = match self {
+            Variant::Fixed(value) => return value.clone(),
+            Variant::OneOf { one_of, weights } => (one_of, weights),
+        };
+        assert!(!options.is_empty(), "Variant {} has no options", name);
+
+        let mut rng = small_rng(named_seed(seed, name));
+
+        let total: f32 = weights.iter().take(options.len()).sum();
+        if weights.len() != options.len() || total <= 0.0 {
+            return options.choose(&mut rng).unwrap().clone();
+        }
+        let mut x = rng.gen::<f32>() * total;
+        for (option, w) in options.iter().zip(weights.iter()) {
+            if x < *w {
+                return option.clone();
+            }
+            x -= w;
+        }
+
+        // Rounding can leave a sliver past the last weight.
+        options
+            .iter()
+            .zip(weights.iter())
+            .rev()
+            .find(|(_, w)| **w > 0.0)
+            .unwrap()
+            .0
+            .clone()
+    }
+}
+
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct NormalDistSpec {
     pub mean: f32,
@@ -86,16 +226,167 @@ impl LatticeUniformDistSpec {
     }
 }
 
-/// Returns a random subrange of `[min, max]`,
-pub fn sample_range<R: Rng>(rng: &mut R, min: i32, max: i32) -> (i32, i32) {
-    let dist = Uniform::from(min..=max);
-    let c1 = dist.sample(rng);
-    let c2 = dist.sample(rng);
+/// Samples points of a lattice that are all at least `min_distance` apart, with Bridson's
+/// algorithm, so they're spread evenly without clumps or big gaps.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PoissonDiskDistSpec {
+    pub min_distance: f32,
+    /// How many points around an accepted point are tried before giving up on placing more near
+    /// it. Higher packs the points more tightly.
+    #[serde(default = "default_tries_per_point")]
+    pub tries_per_point: usize,
+}
+
+fn default_tries_per_point() -> usize {
+    30
+}
+
+impl PoissonDiskDistSpec {
+    /// Returns up to `num_points` points within `bounds` that are at least `min_distance` from each
+    /// other and from `existing`. Fewer are returned once no more fit.
+    pub fn sample(
+        &self,
+        bounds: &LatticeUniformDistSpec,
+        num_points: usize,
+        existing: &[Point],
+        rng: &mut impl Rng,
+    ) -> Vec<Point> {
+        let min_dist = self.min_distance.max(1.0);
+        let cell_size = min_dist.ceil() as i32;
+        let cell = |p: &Point| {
+            [
+                p.x.div_euclid(cell_size),
+                p.y.div_euclid(cell_size),
+                p.z.div_euclid(cell_size),
+            ]
+        };
+        let in_bounds = |p: &Point| {
+            (bounds.x.0..=bounds.x.1).contains(&p.x)
+                && (bounds.y.0..=bounds.y.1).contains(&p.y)
+                && (bounds.z.0..=bounds.z.1).contains(&p.z)
+        };
+
+        // Points within `min_dist` are at most one cell apart.
+        let mut grid: FnvHashMap<[i32; 3], Vec<Point>> = FnvHashMap::default();
+        let far_enough = |grid: &FnvHashMap<[i32; 3], Vec<Point>>, p: &Point| {
+            let [cx, cy, cz] = cell(p);
+            let mut neighbor_cells = (-1..=1).flat_map(|dx| {
+                (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [cx + dx, cy + dy, cz + dz]))
+            });
+
+            neighbor_cells.all(|c| {
+                grid.get(&c).into_iter().flatten().all(|q| {
+                    let d = *p - *q;
+                    ((d.x * d.x + d.y * d.y + d.z * d.z) as f32) >= min_dist * min_dist
+                })
+            })
+        };
+
+        // Existing points only keep new ones away. They may be out of bounds, e.g. after overlap
+        // resolution, so new points don't grow from them.
+        for p in existing.iter() {
+            grid.entry(cell(p)).or_default().push(*p);
+        }
+        let uniform = bounds.make();
+        let mut active = Vec::new();
+        let mut points = Vec::new();
+        while points.len() < num_points {
+            if active.is_empty() {
+                // Start growing from any point that fits, if there is one.
+                let seed = (0..self.tries_per_point)
+                    .map(|_| uniform.sample(rng))
+                    .find(|p| far_enough(&grid, p));
+                match seed {
+                    Some(p) => {
+                        grid.entry(cell(&p)).or_default().push(p);
+                        active.push(p);
+                        points.push(p);
+                    }
+                    None => break,
+                }
+                continue;
+            }
+
+            let i = rng.gen_range(0, active.len());
+            let center = active[i];
+            let mut accepted = None;
+            for _ in 0..self.tries_per_point {
+                // A random direction and a distance in [min_dist, 2 * min_dist).
+                let dir: [f32; 3] = loop {
+                    let v = [
+                        rng.gen_range(-1.0, 1.0),
+                        rng.gen_range(-1.0, 1.0),
+                        rng.gen_range(-1.0f32, 1.0),
+                    ];
+                    let len_sq = v[0] * v[0] + v[1] * v[1] + v[2] * v[2];
+                    if len_sq > 1e-6 && len_sq <= 1.0 {
+                        let len = len_sq.sqrt();
+                        break [v[0] / len, v[1] / len, v[2] / len];
+                    }
+                };
+                let r = rng.gen_range(min_dist, 2.0 * min_dist);
+                let p = center
+                    + Point::from([
+                        (dir[0] * r).round() as i32,
+                        (dir[1] * r).round() as i32,
+                        (dir[2] * r).round() as i32,
+                    ]);
+                if in_bounds(&p) && far_enough(&grid, &p) {
+                    accepted = Some(p);
+                    break;
+                }
+            }
+
+            match accepted {
+                Some(p) => {
+                    grid.entry(cell(&p)).or_default().push(p);
+                    active.push(p);
+                    points.push(p);
+                }
+                None => {
+                    active.swap_remove(i);
+                }
+            }
+        }
+
+        points
+    }
+}
+
+/// Samples subspans of a given span. The length is uniform in `[min_len, max_len]` (clamped to the
+/// span), then the position is uniform among the offsets where a subspan of that length fits.
+///
+/// The length distributions are built once for every possible clamped maximum, rather than on
+/// every call.
+pub struct SubspanSampler {
+    min_len: i32,
+    /// Indexed by the clamped maximum length minus `min_len`.
+    len_dists: Vec<Uniform<i32>>,
+}
+
+impl SubspanSampler {
+    pub fn new(min_len: i32, max_len: i32) -> Self {
+        let len_dists = (min_len..=max_len.max(min_len))
+            .map(|max| Uniform::new_inclusive(min_len, max))
+            .collect();
+
+        SubspanSampler { min_len, len_dists }
+    }
+
+    pub fn min_len(&self) -> i32 {
+        self.min_len
+    }
 
-    if c1 > c2 {
-        (c2, c1)
-    } else {
-        (c1, c2)
+    /// Returns the minimum and length of a subspan of `[min, min + span)`. Requires
+    /// `span >= self.min_len()`.
+    pub fn sample<R: Rng>(&self, rng: &mut R, min: i32, span: i32) -> (i32, i32) {
+        debug_assert!(span >= self.min_len);
+
+        let dist_index = ((span - self.min_len) as usize).min(self.len_dists.len() - 1);
+        let len = self.len_dists[dist_index].sample(rng);
+        let offset = rng.gen_range(0, span - len + 1);
+
+        (min + offset, len)
     }
 }
 
@@ -118,3 +409,101 @@ pub fn sample_extents(
 
     extents
 }
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subspan_sampler_stays_in_bounds() {
+        let mut rng = small_rng([1, 2, 3, 4]);
+        let sampler = SubspanSampler::new(2, 6);
+        for _ in 0..1000 {
+            let (min, len) = sampler.sample(&mut rng, -5, 10);
+            assert!((2..=6).contains(&len));
+            assert!(min >= -5 && min + len <= 5);
+        }
+
+        // The maximum length is clamped to the span.
+        let sampler = SubspanSampler::new(3, 6);
+        for _ in 0..100 {
+            assert_eq!(sampler.sample(&mut rng, 0, 3), (0, 3));
+        }
+    }
+
+    #[test]
+    fn test_retry_with_derived_seeds_seeds_attempts_independently() {
+        let mut seeds = Vec::new();
+        let result = retry_with_derived_seeds(&mut small_rng([1, 2, 3, 4]), 5, |i, seed| {
+            seeds.push(seed);
+            if i == 2 {
+                Some(i)
+            } else {
+                None
+            }
+        });
+        assert_eq!(result, Some(2));
+
+        let base_seed: [u32; 4] = small_rng([1, 2, 3, 4]).gen();
+        let expected: Vec<_> = (0..3).map(|i| derive_seed(base_seed, i)).collect();
+        assert_eq!(seeds, expected);
+
+        let mut attempts = 0;
+        let result: Option<()> =
+            retry_with_derived_seeds(&mut small_rng([1, 2, 3, 4]), 5, |_, _| {
+                attempts += 1;
+                None
+            });
+        assert_eq!((result, attempts), (None, 5));
+    }
+
+    #[test]
+    fn test_poisson_disk_points_are_spaced_and_in_bounds() {
+        let mut rng = small_rng([1, 2, 3, 4]);
+        let bounds = LatticeUniformDistSpec {
+            x: (-40, 40),
+            y: (0, 10),
+            z: (-40, 40),
+        };
+        let spec = PoissonDiskDistSpec {
+            min_distance: 12.0,
+            tries_per_point: 30,
+        };
+        let existing = [Point::from([0, 5, 0])];
+        let points = spec.sample(&bounds, 1000, &existing, &mut rng);
+
+        // The bounds fill up long before 1000 points.
+        assert!(points.len() > 20 && points.len() < 1000);
+        assert_poisson_disk_points(&points, &existing, &bounds, 12.0);
+
+        // Existing points outside of the bounds don't stop new points from being sampled.
+        let existing = [Point::from([100, 5, 0]), Point::from([-100, 5, 45])];
+        let points = spec.sample(&bounds, 10, &existing, &mut rng);
+        assert_eq!(points.len(), 10);
+        assert_poisson_disk_points(&points, &existing, &bounds, 12.0);
+    }
+
+    fn assert_poisson_disk_points(
+        points: &[Point],
+        existing: &[Point],
+        bounds: &LatticeUniformDistSpec,
+        min_distance: f32,
+    ) {
+        for (i, p) in points.iter().enumerate() {
+            assert!((bounds.x.0..=bounds.x.1).contains(&p.x));
+            assert!((bounds.y.0..=bounds.y.1).contains(&p.y));
+            assert!((bounds.z.0..=bounds.z.1).contains(&p.z));
+            for q in points[..i].iter().chain(existing.iter()) {
+                let d = *p - *q;
+                assert!(((d.x * d.x + d.y * d.y + d.z * d.z) as f32).sqrt() >= min_distance);
+            }
+        }
+    }
+}
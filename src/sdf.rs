@@ -0,0 +1,156 @@
+use crate::{extent::extent_center, room::WALL_THICKNESS};
+
+use ilattice3::{Extent, Point};
+use serde::{Deserialize, Serialize};
+
+/// A room whose interior is described by a signed distance function. `fill_map_with_rooms` writes
+/// the voxels within `WALL_THICKNESS` outside of the interior as walls.
+pub trait SdfShape {
+    /// Bounds the interior and its walls.
+    fn extent(&self) -> Extent;
+
+    /// The signed distance from the voxel at `p` to the surface of the interior, which is negative
+    /// inside.
+    fn distance(&self, p: &Point) -> f32;
+}
+
+/// A box room, whose interior is `self.radial_grow(-WALL_THICKNESS)`. Distances are measured
+/// along the axis of greatest separation, so every voxel of the extent outside of the interior is
+/// part of the wall.
+impl SdfShape for Extent {
+    fn extent(&self) -> Extent {
+        *self
+    }
+
+    fn distance(&self, p: &Point) -> f32 {
+        let (min, sup) = (self.get_minimum(), self.get_local_supremum());
+        let axes = [
+            (min.x, sup.x, p.x),
+            (min.y, sup.y, p.y),
+            (min.z, sup.z, p.z),
+        ];
+        let mut outside = 0;
+        let mut inside = i32::MAX;
+        for (min, sup, p) in axes.iter() {
+            let (lo, hi) = (min + WALL_THICKNESS, min + sup - 1 - WALL_THICKNESS);
+            outside = outside.max(lo - p).max(p - hi);
+            inside = inside.min(p - lo + 1).min(hi - p + 1);
+        }
+
+        if outside > 0 {
+            outside as f32
+        } else {
+            -inside as f32
+        }
+    }
+}
+
+/// A spherical chamber inscribed in the interior of `extent`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct SphereRoom {
+    pub extent: Extent,
+}
+
+impl SdfShape for SphereRoom {
+    fn extent(&self) -> Extent {
+        self.extent
+    }
+
+    fn distance(&self, p: &Point) -> f32 {
+        let sup = self.extent.get_local_supremum();
+        let radius = sup.x.min(sup.y).min(sup.z) as f32 / 2.0 - WALL_THICKNESS as f32;
+        let [dx, dy, dz] = offset_from_center(&self.extent, p);
+
+        (dx * dx + dy * dy + dz * dz).sqrt() - radius
+    }
+}
+
+/// A vertical cylinder inscribed in the interior of `extent`, with a flat floor and ceiling.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct CylinderRoom {
+    pub extent: Extent,
+}
+
+impl SdfShape for CylinderRoom {
+    fn extent(&self) -> Extent {
+        self.extent
+    }
+
+    fn distance(&self, p: &Point) -> f32 {
+        let sup = self.extent.get_local_supremum();
+        let radius = sup.x.min(sup.z) as f32 / 2.0 - WALL_THICKNESS as f32;
+        let half_height = sup.y as f32 / 2.0 - WALL_THICKNESS as f32;
+        let [dx, dy, dz] = offset_from_center(&self.extent, p);
+        let radial = (dx * dx + dz * dz).sqrt() - radius;
+        let vertical = dy.abs() - half_height;
+
+        let outside = (radial.max(0.0).powi(2) + vertical.max(0.0).powi(2)).sqrt();
+        let inside = radial.max(vertical).min(0.0);
+
+        outside + inside
+    }
+}
+
+/// A hemisphere resting on the floor of the interior of `extent`, as wide as fits horizontally or
+/// as tall as fits vertically.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct DomeRoom {
+    pub extent: Extent,
+}
+
+impl SdfShape for DomeRoom {
+    fn extent(&self) -> Extent {
+        self.extent
+    }
+
+    fn distance(&self, p: &Point) -> f32 {
+        let sup = self.extent.get_local_supremum();
+        let wall = WALL_THICKNESS as f32;
+        let radius = (sup.x.min(sup.z) as f32 / 2.0 - wall).min(sup.y as f32 - 2.0 * wall);
+        let [dx, dy, dz] = offset_from_center(&self.extent, p);
+        // Measured from the floor rather than the center.
+        let dy = dy + sup.y as f32 / 2.0 - wall;
+        let sphere = (dx * dx + dy * dy + dz * dz).sqrt() - radius;
+
+        sphere.max(-dy)
+    }
+}
+
+/// The offset from the center of `extent` to the center of the voxel at `p`.
+fn offset_from_center(extent: &Extent, p: &Point) -> [f32; 3] {
+    let c = extent_center(extent);
+
+    [
+        p.x as f32 + 0.5 - c[0],
+        p.y as f32 + 0.5 - c[1],
+        p.z as f32 + 0.5 - c[2],
+    ]
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_box_and_sphere_distances() {
+        let extent = Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 20, 20].into());
+
+        // Box walls are everything outside the interior, including the corners.
+        assert_eq!(extent.distance(&[10, 10, 10].into()), -5.0);
+        assert_eq!(extent.distance(&[4, 10, 10].into()), 1.0);
+        assert_eq!(extent.distance(&[0, 0, 0].into()), 5.0);
+
+        // A sphere's walls follow its surface, so the corners are too far away to be walls.
+        let sphere = SphereRoom { extent };
+        assert!(sphere.distance(&[10, 10, 10].into()) < 0.0);
+        assert!(sphere.distance(&[4, 10, 10].into()) > 0.0);
+        assert!(sphere.distance(&[0, 0, 0].into()) > WALL_THICKNESS as f32);
+    }
+}
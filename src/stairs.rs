@@ -0,0 +1,234 @@
+use crate::{
+    extent::{component, touching_axis, with_component},
+    layout::Layout,
+    movement::MovementSpec,
+    room::{fill_map_with_steps, WALL_THICKNESS},
+    Voxel, VoxelEncoder,
+};
+
+use ilattice3::Extent;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StairSpec {
+    /// The most each step rises above the one below it. With a `rise` and `run` of 1, the
+    /// staircase is a ramp.
+    pub rise: u32,
+    /// The length of each step along the staircase.
+    pub run: u32,
+    /// The height of the handrails on either side of the steps.
+    pub handrail_height: u32,
+}
+
+/// Steps that lead from the floor of a room up to a door in its ceiling.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Staircase {
+    /// Index into `Layout::doors`.
+    pub door: usize,
+    /// Index into `Layout::rooms` of the room below the door.
+    pub room: usize,
+    /// From the top step, which sits under the edge of the door, down to the floor.
+    pub steps: Vec<Extent>,
+    pub handrails: Vec<Extent>,
+}
+
+/// Builds a staircase below each door in a floor of `layout`, running away from the door in the
+/// first direction where it fits inside the room below and stays clear of the other doors. With a
+/// `movement` spec, steps rise at most `max_ledge_height`. Doors without space for a staircase are
+/// skipped.
+pub fn staircases(
+    layout: &Layout,
+    spec: &StairSpec,
+    movement: Option<&MovementSpec>,
+) -> Vec<Staircase> {
+    let rise = match movement {
+        Some(m) if m.max_ledge_height() > 0 => spec.rise.min(m.max_ledge_height()),
+        _ => spec.rise,
+    }
+    .max(1) as i32;
+    let run = spec.run.max(1) as i32;
+
+    let mut stairs: Vec<Staircase> = Vec::new();
+    for (i, door) in layout.doors.iter().enumerate() {
+        let (r1, r2) = (&layout.rooms[door.rooms.0], &layout.rooms[door.rooms.1]);
+        let room = match touching_axis(r1, r2) {
+            Some((1, true)) => door.rooms.0,
+            Some((1, false)) => door.rooms.1,
+            _ => continue,
+        };
+        let interior = layout.rooms[room].radial_grow(-WALL_THICKNESS);
+        let clear = |e: &Extent| {
+            let grown = e.radial_grow(1);
+            let fits = (0..3).filter(|a| *a != 1).all(|a| {
+                component(e.get_minimum(), a) >= component(interior.get_minimum(), a)
+                    && component(&e.get_world_supremum(), a)
+                        <= component(&interior.get_world_supremum(), a)
+            });
+            let blocks_door = layout
+                .doors
+                .iter()
+                .enumerate()
+                .any(|(j, d)| j != i && !grown.intersection(&d.extent).is_empty());
+            let blocks_stairs = stairs
+                .iter()
+                .flat_map(|s| s.steps.iter().chain(s.handrails.iter()))
+                .any(|s| !grown.intersection(s).is_empty());
+
+            fits && !blocks_door && !blocks_stairs
+        };
+
+        let directions = [(0, 1), (0, -1), (2, 1), (2, -1)];
+        let staircase = directions.iter().find_map(|(axis, away)| {
+            let (steps, handrails) = staircase_below(
+                &door.extent,
+                interior.get_minimum().y,
+                *axis,
+                *away,
+                rise,
+                run,
+                spec.handrail_height as i32,
+            );
+            if steps.is_empty() || !steps.iter().chain(handrails.iter()).all(clear) {
+                return None;
+            }
+
+            Some(Staircase {
+                door: i,
+                room,
+                steps,
+                handrails,
+            })
+        });
+        stairs.extend(staircase);
+    }
+
+    stairs
+}
+
+/// The steps and handrails from `floor_y` up to the bottom of `door`, with the top step under the
+/// edge of the door on the `away` side of `axis`.
+fn staircase_below(
+    door: &Extent,
+    floor_y: i32,
+    axis: usize,
+    away: i32,
+    rise: i32,
+    run: i32,
+    handrail_height: i32,
+) -> (Vec<Extent>, Vec<Extent>) {
+    let across = 2 - axis;
+    let (door_min, door_sup) = (door.get_minimum(), door.get_world_supremum());
+    let door_y = door_min.y;
+    let edge = if away > 0 {
+        component(&door_sup, axis) - 1
+    } else {
+        component(door_min, axis)
+    };
+    // The `run` rows of a step, starting from `first` and going away from the door.
+    let rows = |first: i32| {
+        let last = first + away * (run - 1);
+        (first.min(last), first.max(last) + 1)
+    };
+
+    let (mut steps, mut handrails) = (Vec::new(), Vec::new());
+    let mut height = door_y - floor_y;
+    let mut first = edge;
+    while height > 0 {
+        let (row_min, row_sup) = rows(first);
+        let span = |min: i32, sup: i32, y_min: i32, y_sup: i32| {
+            let min = with_component(&with_component(door_min, axis, row_min), across, min);
+            let sup = with_component(&with_component(&door_sup, axis, row_sup), across, sup);
+
+            Extent::from_min_and_world_supremum(
+                with_component(&min, 1, y_min),
+                with_component(&sup, 1, y_sup),
+            )
+        };
+        let (across_min, across_sup) = (component(door_min, across), component(&door_sup, across));
+
+        let top = floor_y + height;
+        steps.push(span(across_min, across_sup, floor_y, top));
+        let rail_top = (top + handrail_height).min(door_y);
+        if rail_top > top {
+            handrails.push(span(across_min - 1, across_min, top, rail_top));
+            handrails.push(span(across_sup, across_sup + 1, top, rail_top));
+        }
+
+        height -= rise;
+        first += away * run;
+    }
+
+    (steps, handrails)
+}
+
+/// Writes the steps of each staircase like `fill_map_with_steps`, and its handrails with
+/// `handrail_voxel_type`.
+pub fn fill_map_with_staircases(
+    stairs: &[Staircase],
+    handrail_voxel_type: u8,
+    encoder: &mut impl VoxelEncoder,
+) {
+    let handrail = Voxel {
+        distance: -1.0,
+        voxel_type: handrail_voxel_type,
+    };
+    for s in stairs.iter() {
+        fill_map_with_steps(&s.steps, encoder);
+        for h in s.handrails.iter() {
+            for p in h {
+                encoder.encode_voxel(&p, &handrail);
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::layout::LayoutDoor;
+
+    #[test]
+    fn test_steps_climb_to_the_door_in_the_ceiling() {
+        let lower = Extent::from_min_and_local_supremum([0, 0, 0].into(), [40, 20, 40].into());
+        let upper = Extent::from_min_and_local_supremum([0, 20, 0].into(), [40, 20, 40].into());
+        let door = Extent::from_min_and_local_supremum([10, 19, 18].into(), [4, 2, 4].into());
+        let layout = Layout {
+            rooms: vec![upper, lower],
+            doors: vec![LayoutDoor::new(0, 1, door)],
+        };
+        let spec = StairSpec {
+            rise: 4,
+            run: 2,
+            handrail_height: 2,
+        };
+        let movement = MovementSpec {
+            max_step_up: 3,
+            max_safe_drop: 10,
+            crouch_height: 2,
+        };
+
+        let stairs = staircases(&layout, &spec, Some(&movement));
+
+        assert_eq!(stairs.len(), 1);
+        let s = &stairs[0];
+        assert_eq!((s.door, s.room), (0, 1));
+        // The top step reaches the door from the floor at y = 5, and each step is at most 3 lower.
+        let tops: Vec<i32> = s.steps.iter().map(|e| e.get_world_supremum().y).collect();
+        assert_eq!(tops, vec![19, 16, 13, 10, 7]);
+        assert!(!(s.steps[0] + [0, 1, 0].into())
+            .intersection(&door)
+            .is_empty());
+        for e in s.steps.iter().chain(s.handrails.iter()) {
+            assert!(e.is_subset(&lower));
+        }
+        assert_eq!(s.handrails.len(), 2 * (tops.len() - 1));
+    }
+}
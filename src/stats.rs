@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PhaseRngDraws {
+    pub phase: String,
+    /// The number of 32-bit words drawn from the RNG.
+    pub words: u64,
+}
+
+/// Diagnostics gathered while generating a map.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GenerationStats {
+    /// RNG consumption of each phase, in the order that the phases first ran. Comparing these
+    /// between two runs shows where they started to diverge.
+    pub rng_draws: Vec<PhaseRngDraws>,
+    /// The index of the attempt that succeeded, for generators that retry.
+    #[serde(default)]
+    pub attempt: usize,
+    /// The seed of the RNG for the attempt that succeeded. Passing `small_rng(seed)` to the
+    /// generator's `try_generate` reproduces the map without the attempts that failed before it.
+    #[serde(default)]
+    pub attempt_seed: Option<[u32; 4]>,
+}
+
+impl GenerationStats {
+    /// Adds `words` to the count for `phase`.
+    pub fn record_rng_draws(&mut self, phase: &str, words: u64) {
+        log::debug!("Phase {} drew {} RNG words", phase, words);
+
+        if let Some(entry) = self.rng_draws.iter_mut().find(|e| e.phase == phase) {
+            entry.words += words;
+        } else {
+            self.rng_draws.push(PhaseRngDraws {
+                phase: phase.to_string(),
+                words,
+            });
+        }
+    }
+
+    pub fn total_rng_draws(&self) -> u64 {
+        self.rng_draws.iter().map(|e| e.words).sum()
+    }
+}
@@ -1,18 +1,29 @@
 use fnv::FnvHashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::hash::Hash;
 
-#[derive(Default)]
-pub struct SymmetricMap<T> {
-    map: FnvHashMap<(usize, usize), T>,
+/// A map keyed by unordered pairs, e.g. the doors between each pair of rooms. By default, the
+/// pairs are indices. Serialized as a list of `(pair, value)` entries ordered by pair, since
+/// formats like JSON only allow string keys.
+#[derive(Clone, Debug)]
+pub struct SymmetricMap<T, K: Copy + Eq + Hash + Ord = usize> {
+    map: FnvHashMap<(K, K), T>,
 }
 
-impl<T> SymmetricMap<T> {
-    pub fn new() -> Self {
+impl<T, K: Copy + Eq + Hash + Ord> Default for SymmetricMap<T, K> {
+    fn default() -> Self {
         SymmetricMap {
             map: FnvHashMap::default(),
         }
     }
+}
+
+impl<T, K: Copy + Eq + Hash + Ord> SymmetricMap<T, K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    fn order_indices(i1: usize, i2: usize) -> (usize, usize) {
+    fn order_indices(i1: K, i2: K) -> (K, K) {
         if i1 > i2 {
             (i2, i1)
         } else {
@@ -20,11 +31,84 @@ impl<T> SymmetricMap<T> {
         }
     }
 
-    pub fn get(&self, i1: usize, i2: usize) -> &T {
+    pub fn get(&self, i1: K, i2: K) -> &T {
         &self.map[&Self::order_indices(i1, i2)]
     }
 
-    pub fn insert(&mut self, i1: usize, i2: usize, value: T) {
+    pub fn try_get(&self, i1: K, i2: K) -> Option<&T> {
+        self.map.get(&Self::order_indices(i1, i2))
+    }
+
+    pub fn insert(&mut self, i1: K, i2: K, value: T) {
         self.map.insert(Self::order_indices(i1, i2), value);
     }
+
+    pub fn get_or_insert_with(&mut self, i1: K, i2: K, f: impl FnOnce() -> T) -> &mut T {
+        self.map
+            .entry(Self::order_indices(i1, i2))
+            .or_insert_with(f)
+    }
+
+    /// Each pair is smallest first.
+    pub fn iter(&self) -> impl Iterator<Item = (&(K, K), &T)> {
+        self.map.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<T: Serialize, K: Copy + Eq + Hash + Ord + Serialize> Serialize for SymmetricMap<T, K> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<_> = self.map.iter().collect();
+        entries.sort_by_key(|(pair, _)| **pair);
+
+        serializer.collect_seq(entries)
+    }
+}
+
+impl<'de, T, K> Deserialize<'de> for SymmetricMap<T, K>
+where
+    T: Deserialize<'de>,
+    K: Copy + Eq + Hash + Ord + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<((K, K), T)>::deserialize(deserializer)?;
+        let mut map = SymmetricMap::new();
+        for ((i1, i2), value) in entries.into_iter() {
+            map.insert(i1, i2, value);
+        }
+
+        Ok(map)
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_as_ordered_entries() {
+        let mut map = SymmetricMap::new();
+        map.insert(3, 1, "b".to_string());
+        map.insert(0, 2, "a".to_string());
+
+        let serialized = ron::ser::to_string(&map).unwrap();
+        assert_eq!(serialized, r#"[((0,2),"a"),((1,3),"b")]"#);
+        let map: SymmetricMap<String> = ron::de::from_str(&serialized).unwrap();
+        assert_eq!(map.get(2, 0), "a");
+        assert_eq!(map.get(1, 3), "b");
+    }
 }
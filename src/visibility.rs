@@ -0,0 +1,156 @@
+use crate::{
+    extent::extent_center,
+    room::{Room, WALL_THICKNESS},
+};
+
+use fnv::FnvHashSet;
+use ilattice3::{Extent, Point};
+use serde::{Deserialize, Serialize};
+
+/// Whether the segment from `from` to `to` only passes through voxels where `is_open` is true. The
+/// segment is sampled at quarter voxel steps, which is fine enough for the rooms this crate
+/// generates.
+pub fn line_of_sight(from: [f32; 3], to: [f32; 3], is_open: impl Fn(&Point) -> bool) -> bool {
+    let delta = [to[0] - from[0], to[1] - from[1], to[2] - from[2]];
+    let length = (delta[0] * delta[0] + delta[1] * delta[1] + delta[2] * delta[2]).sqrt();
+    let num_samples = (length * 4.0).ceil() as usize;
+
+    (0..=num_samples).all(|i| {
+        let t = i as f32 / num_samples.max(1) as f32;
+        let p = Point {
+            x: (from[0] + t * delta[0]).floor() as i32,
+            y: (from[1] + t * delta[1]).floor() as i32,
+            z: (from[2] + t * delta[2]).floor() as i32,
+        };
+
+        is_open(&p)
+    })
+}
+
+/// Where the objective of a map goes.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ObjectivePoint {
+    /// The empty voxel just above the floor.
+    pub point: Point,
+    /// False if every point of the floor can be seen from one of the doors.
+    pub hidden: bool,
+}
+
+/// Chooses the point just above the floor of `room` that can't be seen from just inside any of
+/// `doors`, so players have to enter the room to see it. Of the hidden points, the one farthest
+/// from the doors wins; if there are none, the farthest point is used anyway. Sight is blocked by
+/// the walls of the room and by `obstacles`, which no point is chosen inside of.
+pub fn hidden_objective_point(
+    room: &Room,
+    doors: &[Extent],
+    obstacles: &[Extent],
+) -> Option<ObjectivePoint> {
+    let blocked = |p: &Point| obstacles.iter().any(|o| o.contains_world(p));
+    let is_open = |p: &Point| room.interior_contains(p) && !blocked(p);
+    let eyes: Vec<[f32; 3]> = doors
+        .iter()
+        .filter_map(|d| eye_in_doorway(room, d))
+        .collect();
+    let voxel_center = |p: &Point| [p.x as f32 + 0.5, p.y as f32 + 0.5, p.z as f32 + 0.5];
+    let distance_to_doors = |p: &Point| {
+        let c = voxel_center(p);
+        eyes.iter()
+            .map(|e| (c[0] - e[0]).powi(2) + (c[1] - e[1]).powi(2) + (c[2] - e[2]).powi(2))
+            .fold(f32::MAX, f32::min)
+    };
+
+    let mut seen = FnvHashSet::default();
+    room.parts
+        .iter()
+        .flat_map(|part| {
+            let interior = part.radial_grow(-WALL_THICKNESS);
+            let floor_y = interior.get_minimum().y;
+
+            interior.into_iter().filter(move |p| p.y == floor_y)
+        })
+        .filter(|p| !blocked(p) && seen.insert(*p))
+        .map(|p| {
+            let target = voxel_center(&p);
+            let hidden = eyes
+                .iter()
+                .all(|e| !line_of_sight(*e, target, |q| *q == p || is_open(q)));
+
+            (hidden, distance_to_doors(&p), p)
+        })
+        .fold(
+            None,
+            |best: Option<(bool, f32, Point)>, candidate| match best {
+                Some(b) if (b.0, b.1) >= (candidate.0, candidate.1) => Some(b),
+                _ => Some(candidate),
+            },
+        )
+        .map(|(hidden, _, point)| ObjectivePoint { point, hidden })
+}
+
+/// The point of the interior of `room` nearest to the center of `door`, where someone looking
+/// through the door would stand.
+fn eye_in_doorway(room: &Room, door: &Extent) -> Option<[f32; 3]> {
+    let center = extent_center(door);
+
+    room.parts
+        .iter()
+        .map(|part| part.radial_grow(-WALL_THICKNESS))
+        .filter(|interior| !interior.is_empty())
+        .map(|interior| {
+            let (min, sup) = (interior.get_minimum(), interior.get_world_supremum());
+            let clamp = |c: f32, min: i32, sup: i32| c.clamp(min as f32 + 0.5, sup as f32 - 0.5);
+
+            [
+                clamp(center[0], min.x, sup.x),
+                clamp(center[1], min.y, sup.y),
+                clamp(center[2], min.z, sup.z),
+            ]
+        })
+        .map(|eye| {
+            let d: f32 = eye
+                .iter()
+                .zip(center.iter())
+                .map(|(e, c)| (e - c).abs())
+                .sum();
+            (d, eye)
+        })
+        .min_by(|(d1, _), (d2, _)| d1.partial_cmp(d2).expect("Distance must not be NaN"))
+        .map(|(_, eye)| eye)
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::RoomOutline;
+
+    #[test]
+    fn test_objective_is_around_the_corner_from_the_door() {
+        // An L with the door at the end of the arm along X.
+        let room = Room {
+            outline: RoomOutline::L,
+            parts: vec![
+                Extent::from_min_and_local_supremum([0, 0, 0].into(), [40, 15, 20].into()),
+                Extent::from_min_and_local_supremum([0, 0, 0].into(), [20, 15, 40].into()),
+            ],
+        };
+        let door = Extent::from_min_and_local_supremum([39, 5, 8].into(), [2, 4, 4].into());
+
+        let objective = hidden_objective_point(&room, &[door], &[]).unwrap();
+
+        assert!(objective.hidden);
+        assert_eq!(objective.point.y, 5);
+        // Only the far end of the other arm is out of sight.
+        assert!(objective.point.z >= 15);
+
+        // A box room has nowhere to hide.
+        let open = Room::from(room.parts[0]);
+        assert!(!hidden_objective_point(&open, &[door], &[]).unwrap().hidden);
+    }
+}
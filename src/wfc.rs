@@ -0,0 +1,219 @@
+//! A Wave Function Collapse solver over a 3D grid of tiles with 6-neighbor adjacency constraints.
+
+use ilattice3::Point;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// The solver represents each cell's remaining options as a bitmask.
+pub const MAX_TILES: usize = 64;
+
+/// Offsets to the face neighbors. Directions `2 * axis` and `2 * axis + 1` lie along `axis`.
+const OFFSETS: [[i32; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+/// Which tiles may be placed next to each other, and how often each tile should appear.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdjacencyRules {
+    pub weights: Vec<f32>,
+    /// `allowed[dir][tile]` is the mask of tiles that may be at the `dir` neighbor of `tile`.
+    pub allowed: [Vec<u64>; 6],
+}
+
+impl AdjacencyRules {
+    pub fn num_tiles(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Learns the rules from `example`, a grid of tile indices with dimensions `size`, stored with
+    /// X varying fastest. Tiles that never appear get zero weight. Along any axis where the example
+    /// is only 1 voxel thick, each tile may only neighbor itself, so a 2D example is extruded.
+    pub fn from_example(example: &[usize], size: Point, num_tiles: usize) -> Self {
+        assert!(num_tiles <= MAX_TILES);
+        assert_eq!(example.len(), (size.x * size.y * size.z) as usize);
+
+        let mut weights = vec![0.0; num_tiles];
+        let mut allowed: [Vec<u64>; 6] = Default::default();
+        for a in allowed.iter_mut() {
+            *a = vec![0; num_tiles];
+        }
+
+        let grid = Grid { size };
+        for (i, tile) in example.iter().enumerate() {
+            weights[*tile] += 1.0;
+            let p = grid.point(i);
+            for (dir, offset) in OFFSETS.iter().enumerate() {
+                if let Some(j) = grid.index(&(p + (*offset).into())) {
+                    allowed[dir][*tile] |= 1 << example[j];
+                }
+            }
+        }
+
+        let thickness = [size.x, size.y, size.z];
+        for (axis, len) in thickness.iter().enumerate() {
+            if *len != 1 {
+                continue;
+            }
+            for dir in [2 * axis, 2 * axis + 1].iter() {
+                for (tile, mask) in allowed[*dir].iter_mut().enumerate() {
+                    *mask = 1 << tile;
+                }
+            }
+        }
+
+        AdjacencyRules { weights, allowed }
+    }
+}
+
+struct Grid {
+    size: Point,
+}
+
+impl Grid {
+    fn len(&self) -> usize {
+        (self.size.x * self.size.y * self.size.z) as usize
+    }
+
+    fn index(&self, p: &Point) -> Option<usize> {
+        let s = self.size;
+        if p.x < 0 || p.y < 0 || p.z < 0 || p.x >= s.x || p.y >= s.y || p.z >= s.z {
+            return None;
+        }
+
+        Some((p.x + s.x * (p.y + s.y * p.z)) as usize)
+    }
+
+    fn point(&self, i: usize) -> Point {
+        let i = i as i32;
+        let s = self.size;
+
+        [i % s.x, (i / s.x) % s.y, i / (s.x * s.y)].into()
+    }
+}
+
+/// Fills a grid of dimensions `size` with tiles that satisfy `rules`. Returns the tile of each
+/// cell with X varying fastest, or `None` if the solver reached a contradiction.
+pub fn solve(rules: &AdjacencyRules, size: Point, rng: &mut impl Rng) -> Option<Vec<usize>> {
+    let grid = Grid { size };
+    let all_tiles = rules
+        .weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w > 0.0)
+        .fold(0u64, |mask, (t, _)| mask | (1 << t));
+    if all_tiles == 0 {
+        return None;
+    }
+    let mut domains = vec![all_tiles; grid.len()];
+
+    loop {
+        // Observe the undecided cell with the fewest options, breaking ties randomly.
+        let cell = domains
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.count_ones() > 1)
+            .map(|(i, d)| (d.count_ones() as f32 + rng.gen::<f32>() * 0.5, i))
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+            .map(|(_, i)| i);
+        let cell = match cell {
+            Some(c) => c,
+            None => break,
+        };
+
+        let tile = choose_weighted(rules, domains[cell], rng);
+        domains[cell] = 1 << tile;
+
+        if !propagate(rules, &grid, &mut domains, cell) {
+            return None;
+        }
+    }
+
+    Some(
+        domains
+            .into_iter()
+            .map(|d| d.trailing_zeros() as usize)
+            .collect(),
+    )
+}
+
+fn choose_weighted(rules: &AdjacencyRules, domain: u64, rng: &mut impl Rng) -> usize {
+    let options: Vec<_> = (0..rules.num_tiles())
+        .filter(|t| domain & (1 << t) != 0)
+        .collect();
+    let total: f32 = options.iter().map(|t| rules.weights[*t]).sum();
+    let mut x = rng.gen::<f32>() * total;
+    for t in options.iter() {
+        x -= rules.weights[*t];
+        if x <= 0.0 {
+            return *t;
+        }
+    }
+
+    *options.last().unwrap()
+}
+
+/// Removes the options that are no longer supported after `start` changed. Returns `false` on a
+/// contradiction.
+fn propagate(rules: &AdjacencyRules, grid: &Grid, domains: &mut [u64], start: usize) -> bool {
+    let mut stack = vec![start];
+    while let Some(i) = stack.pop() {
+        let p = grid.point(i);
+        for (dir, offset) in OFFSETS.iter().enumerate() {
+            let j = match grid.index(&(p + (*offset).into())) {
+                Some(j) => j,
+                None => continue,
+            };
+
+            let supported = (0..rules.num_tiles())
+                .filter(|t| domains[i] & (1 << t) != 0)
+                .fold(0, |mask, t| mask | rules.allowed[dir][t]);
+            let narrowed = domains[j] & supported;
+            if narrowed == domains[j] {
+                continue;
+            }
+            if narrowed == 0 {
+                return false;
+            }
+            domains[j] = narrowed;
+            stack.push(j);
+        }
+    }
+
+    true
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sampling::small_rng;
+
+    #[test]
+    fn test_solution_obeys_example_adjacency() {
+        // A checkerboard along X only allows alternating tiles.
+        let example = vec![0, 1, 0, 1];
+        let rules = AdjacencyRules::from_example(&example, [4, 1, 1].into(), 2);
+        let size: Point = [9, 2, 2].into();
+        let solution = solve(&rules, size, &mut small_rng([1, 2, 3, 4])).unwrap();
+
+        let grid = Grid { size };
+        for (i, tile) in solution.iter().enumerate() {
+            let p = grid.point(i);
+            if let Some(j) = grid.index(&(p + [1, 0, 0].into())) {
+                assert_ne!(*tile, solution[j]);
+            }
+        }
+    }
+}